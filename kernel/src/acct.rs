@@ -0,0 +1,41 @@
+use servos::lock::SpinLocked;
+use shared::sys::AcctRecord;
+
+use crate::{fs::vfs::Fd, klog, proc::Exited};
+
+/// Where records are appended, if accounting is on
+static FILE: SpinLocked<Option<Fd>> = SpinLocked::new(None);
+
+/// Append a record to `file` every time a process exits from now on, or stop with `None`
+pub fn set(file: Option<Fd>) {
+    *FILE.lock() = file;
+}
+
+/// Append the process' record. A failed write only loses it.
+pub fn process_exited(exit: &Exited) {
+    let rec = &exit.acct;
+    let file = FILE.lock();
+    let Some(file) = file.as_ref() else {
+        return;
+    };
+
+    // AcctRecord has no padding
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            (rec as *const AcctRecord).cast::<u8>(),
+            core::mem::size_of::<AcctRecord>(),
+        )
+    };
+    // something else may have written to the file too, so always append at the end
+    if let Err(err) = file
+        .stat()
+        .and_then(|stat| file.write(stat.size as u64, bytes))
+    {
+        klog!(
+            Warn,
+            Sched,
+            "couldn't write accounting record for PID {}: {err:?}",
+            rec.pid
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! Checks that a destroyed process didn't leave anything behind, enabled by `leak-check`. Pages
+//! and files are counted as they're released on each hart, so a process's teardown can be measured
+//! without other harts exiting processes at the same time getting mixed in.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use servos::riscv::r_tp;
+
+use crate::{
+    klog,
+    proc::{Process, MAX_HARTS},
+};
+
+pub const ENABLED: bool = cfg!(any(debug_assertions, feature = "leak-check"));
+
+#[derive(Clone, Copy)]
+pub enum Resource {
+    /// Page tables and the pages they own
+    Pages,
+    /// File descriptors, released once their filesystem's `close` has been called
+    Files,
+    /// Processes still blocked in `Sys::Waitpid` on the process
+    Waiters,
+    /// Entries in the scheduler's run queues
+    SchedNode,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Resource::Pages => "pages",
+            Resource::Files => "files",
+            Resource::Waiters => "waiters",
+            Resource::SchedNode => "scheduler nodes",
+        })
+    }
+}
+
+/// Indexed by hart, then `Resource::Pages` or `Resource::Files`
+static RELEASED: [[AtomicUsize; 2]; MAX_HARTS] =
+    [const { [const { AtomicUsize::new(0) }; 2] }; MAX_HARTS];
+
+fn counter(res: Resource) -> Option<&'static AtomicUsize> {
+    let i = match res {
+        Resource::Pages => 0,
+        Resource::Files => 1,
+        Resource::Waiters | Resource::SchedNode => return None,
+    };
+    RELEASED.get(r_tp()).map(|hart| &hart[i])
+}
+
+/// Count `n` of `res` as released by this hart
+pub fn released(res: Resource, n: usize) {
+    if ENABLED {
+        if let Some(counter) = counter(res) {
+            counter.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+}
+
+/// How many of `res` this hart has released since boot
+pub fn released_here(res: Resource) -> usize {
+    counter(res).map_or(0, |counter| counter.load(Ordering::Relaxed))
+}
+
+/// Log that `pid` left `leaked` of `res` behind, if it left any
+pub fn report(pid: u32, res: Resource, leaked: usize) {
+    if leaked != 0 {
+        klog!(Error, Sched, "PID {pid} leaked {leaked} {res}");
+    }
+}
+
+/// What a process held when its teardown started, and how much of it has been released since
+pub struct Teardown {
+    pid: u32,
+    pages: usize,
+    files: usize,
+}
+
+impl Teardown {
+    /// `files` is the number of descriptors taken out of the process's table, which doesn't
+    /// include its working directory
+    pub fn new(proc: &Process, files: usize) -> Self {
+        Self {
+            pid: proc.handle.pid(),
+            pages: if ENABLED {
+                proc.pagetable().pages_owned()
+            } else {
+                0
+            },
+            files: files + 1,
+        }
+    }
+
+    /// Run `f`, which frees some of what the process held. Only what's released on this hart
+    /// while it runs counts, so unrelated work between the steps of a teardown, like writing the
+    /// accounting record, can't hide a leak.
+    pub fn run<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        if !ENABLED {
+            return f();
+        }
+
+        let (pages, files) = (
+            released_here(Resource::Pages),
+            released_here(Resource::Files),
+        );
+        let res = f();
+        self.pages = self
+            .pages
+            .saturating_sub(released_here(Resource::Pages) - pages);
+        self.files = self
+            .files
+            .saturating_sub(released_here(Resource::Files) - files);
+        res
+    }
+
+    /// Report whatever hasn't been released by the time the process is gone
+    pub fn finish(self) {
+        if ENABLED {
+            report(self.pid, Resource::Pages, self.pages);
+            report(self.pid, Resource::Files, self.files);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+#[cfg(feature = "procfs")]
+use core::fmt::{self, Write};
+use core::{
+    ptr::{addr_of, addr_of_mut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use fdt_rs::{
+    base::DevTree,
+    prelude::{FallibleIterator, PropReader},
+};
+
+/// Arguments past this are dropped
+const MAX_LEN: usize = 256;
+
+static mut BUF: [u8; MAX_LEN] = [0; MAX_LEN];
+static LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Copy `/chosen/bootargs` out of the device tree, since it's overwritten once the heap is
+/// initialized.
+///
+/// # Safety
+/// Must be called once, before any other hart is started.
+pub unsafe fn init(dt: &DevTree) {
+    let Ok(Some(args)) = dt.nodes().find_map(|node| {
+        if node.name()? != "chosen" {
+            return Ok(None);
+        }
+        node.props()
+            .find(|prop| Ok(prop.name()? == "bootargs"))?
+            .map(|prop| prop.str())
+            .transpose()
+    }) else {
+        return;
+    };
+
+    let buf = unsafe { &mut *addr_of_mut!(BUF) };
+    let mut len = 0;
+    for arg in args.split_ascii_whitespace() {
+        let sep = (len != 0) as usize;
+        if len + sep + arg.len() > MAX_LEN {
+            break;
+        }
+        buf[len..][..sep].fill(b' ');
+        buf[len + sep..][..arg.len()].copy_from_slice(arg.as_bytes());
+        len += sep + arg.len();
+    }
+    LEN.store(len, Ordering::Release);
+}
+
+/// All of the arguments, separated by single spaces
+pub fn all() -> &'static str {
+    let len = LEN.load(Ordering::Acquire);
+    // only written by init, and only whole arguments of a str are copied
+    unsafe { core::str::from_utf8_unchecked(&(*addr_of!(BUF))[..len]) }
+}
+
+/// The value of the last `key=value` argument for `key`
+pub fn get(key: &str) -> Option<&'static str> {
+    all()
+        .split(' ')
+        .filter_map(|arg| arg.strip_prefix(key)?.strip_prefix('='))
+        .last()
+}
+
+/// Whether `flag` was passed on its own, without a value
+pub fn has(flag: &str) -> bool {
+    all().split(' ').any(|arg| arg == flag)
+}
+
+#[cfg(feature = "procfs")]
+pub fn show(w: &mut dyn Write) -> fmt::Result {
+    writeln!(w, "{}", all())
+}
@@ -1,8 +1,13 @@
-use core::mem::MaybeUninit;
+use core::{fmt::Write, mem::MaybeUninit};
 
-use servos::lock::SpinLocked;
+use alloc::vec::Vec;
+use servos::{drivers::Ns16550a, lock::SpinLocked};
+use shared::io::{ConsoleCtl, ModemControl};
 
-use crate::{fs::FsResult, print};
+use crate::{
+    fs::{FsError, FsResult, OpenId},
+    print,
+};
 
 use super::Device;
 
@@ -16,30 +21,156 @@ use super::Device;
  ^---re
 */
 
+/// Big enough to take a pasted script
+const LEN: usize = 4096;
+/// Past this the terminal is told to stop sending, and the line being edited is handed to readers
+/// as is
+const HIGH: usize = LEN / 4 * 3;
+/// The terminal is told to resume once readers have brought it down to this
+const LOW: usize = LEN / 4;
+
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+/// Enough for the echo of a pasted line that's typed at the end
+const OUTPUT_LEN: usize = 256;
+
+/// What handling a batch of input sends back to the terminal. It's written out in one go once the
+/// buffer is unlocked, instead of locking the UART for every byte.
+struct Output {
+    buf: [u8; OUTPUT_LEN],
+    len: usize,
+    echo: bool,
+}
+
+impl Output {
+    fn new(echo: bool) -> Self {
+        Self {
+            buf: [0; OUTPUT_LEN],
+            len: 0,
+            echo,
+        }
+    }
+
+    /// Only sent if the terminal doesn't show what's typed itself
+    fn echo(&mut self, s: &str) {
+        if self.echo {
+            self.send(s);
+        }
+    }
+
+    fn echo_ch(&mut self, ch: u8) {
+        if self.echo {
+            self.send_ch(ch);
+        }
+    }
+
+    fn send_ch(&mut self, ch: u8) {
+        self.send(char::from(ch).encode_utf8(&mut [0; 4]));
+    }
+
+    fn send(&mut self, s: &str) {
+        if self.len + s.len() > self.buf.len() {
+            self.flush();
+        }
+        if s.len() > self.buf.len() {
+            print!("{s}");
+        } else {
+            self.buf[self.len..][..s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+        // only whole strs are copied in
+        let s = unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) };
+        _ = crate::uart::lock().write_str(s);
+        self.len = 0;
+    }
+}
+
+/// Everything a descriptor can change about the console
+#[derive(Clone, Copy)]
+struct Settings {
+    bell: bool,
+    echo: bool,
+    /// CTS flow control and the modem lines, if the console is a UART we drive ourselves
+    uart: Option<(bool, ModemControl)>,
+}
+
 struct Buffer {
-    buf: [u8; 256],
+    buf: [u8; LEN],
     read: usize,
     write: usize,
     wend: usize,
     rend: usize,
     esc: Option<u8>,
+    /// XOFF was sent
+    stopped: bool,
+    /// Ring the bell when input is dropped
+    bell: bool,
+    /// Show what's typed
+    echo: bool,
+    /// Input dropped since `ConsoleCtl::TakeDropped` was last asked
+    dropped: usize,
+    /// The settings from before each descriptor first changed them, in the order they did
+    saved: Vec<(OpenId, Settings)>,
 }
 
 impl Buffer {
     const fn new() -> Self {
         Self {
-            buf: [0; 256],
+            buf: [0; LEN],
             read: 0,
             write: 0,
             wend: 0,
             rend: 0,
             esc: None,
+            stopped: false,
+            bell: true,
+            echo: true,
+            dropped: 0,
+            saved: Vec::new(),
+        }
+    }
+
+    fn settings(&self) -> Settings {
+        let uart = crate::uart::lock()
+            .uart()
+            .map(|uart| (uart.cts_flow(), uart.modem_control()));
+        Settings {
+            bell: self.bell,
+            echo: self.echo,
+            uart,
         }
     }
 
-    fn put(&mut self, ch: u8) -> bool {
+    fn apply(&mut self, settings: Settings) {
+        self.bell = settings.bell;
+        self.echo = settings.echo;
+        if let (Some((cts_flow, lines)), Some(uart)) = (settings.uart, crate::uart::lock().uart()) {
+            uart.set_cts_flow(cts_flow);
+            uart.set_modem_control(lines);
+        }
+    }
+
+    /// Remember the settings from before `open` first changes them
+    fn save(&mut self, open: OpenId) -> FsResult<()> {
+        if self.saved.iter().any(|&(id, _)| id == open) {
+            return Ok(());
+        }
+        self.saved.try_reserve(1).map_err(|_| FsError::NoMem)?;
+        let settings = self.settings();
+        self.saved.push((open, settings));
+        Ok(())
+    }
+
+    fn put(&mut self, ch: u8, out: &mut Output) -> bool {
         if let Some(esc) = self.esc {
-            self.handle_esc(esc, ch);
+            self.handle_esc(esc, ch, out);
             return true;
         }
 
@@ -53,7 +184,8 @@ impl Buffer {
                 self.write = self.wend;
                 self.push_ch(b'\n');
                 self.rend = self.wend;
-                print!("\r\n");
+                out.echo("\r\n");
+                self.throttle(out);
             }
             0x7f => {
                 if self.write > self.rend {
@@ -61,16 +193,16 @@ impl Buffer {
                     self.wend -= 1;
 
                     if self.wend == self.write {
-                        print!("\x08 \x08");
+                        out.echo("\x08 \x08");
                     } else {
-                        print!("\x08");
+                        out.echo("\x08");
                         for i in self.write..self.wend {
                             self.buf[i % self.buf.len()] = self.buf[(i + 1) % self.buf.len()];
-                            print!("{}", self.buf[i % self.buf.len()] as char);
+                            out.echo_ch(self.buf[i % self.buf.len()]);
                         }
-                        print!(" ");
+                        out.echo(" ");
                         for _ in self.write..=self.wend {
-                            print!("\x08");
+                            out.echo("\x08");
                         }
                     }
                 }
@@ -80,19 +212,37 @@ impl Buffer {
                     return false;
                 }
 
-                print!("{}", ch as char);
+                out.echo_ch(ch);
                 for i in self.write..self.wend {
-                    print!("{}", self.buf[i % self.buf.len()] as char);
+                    out.echo_ch(self.buf[i % self.buf.len()]);
                 }
                 for _ in self.write..self.wend {
-                    print!("\x08");
+                    out.echo("\x08");
                 }
+                self.throttle(out);
             }
         }
 
         true
     }
 
+    /// Only does anything for terminals that honor XON/XOFF, but a partial line is still better
+    /// than dropping what doesn't fit
+    fn throttle(&mut self, out: &mut Output) {
+        if self.wend - self.read < HIGH {
+            return;
+        }
+
+        // editing in the middle of a line can't happen during a paste
+        if self.write == self.wend {
+            self.rend = self.wend;
+        }
+        if !self.stopped {
+            self.stopped = true;
+            out.send_ch(XOFF);
+        }
+    }
+
     fn read<'a>(&mut self, buf: &'a mut [MaybeUninit<u8>]) -> &'a mut [u8] {
         let count = (self.rend - self.read).min(buf.len());
         let slice = &mut buf[..count];
@@ -101,10 +251,14 @@ impl Buffer {
         }
 
         self.read += count;
+        if self.stopped && self.wend - self.read <= LOW {
+            self.stopped = false;
+            print!("{}", XON as char);
+        }
         unsafe { MaybeUninit::slice_assume_init_mut(slice) }
     }
 
-    fn handle_esc(&mut self, esc: u8, ch: u8) {
+    fn handle_esc(&mut self, esc: u8, ch: u8, out: &mut Output) {
         // maybe makes sense to let applications handle escape sequences but whatever for now
         match (esc, ch) {
             (0x1b, b'[') | (b'[', b'1') | (b'1', b';') | (b';', b'5') => {
@@ -114,7 +268,7 @@ impl Buffer {
                 // LARROW
                 if self.write > self.rend {
                     self.write -= 1;
-                    print!("\x1b[D");
+                    out.echo("\x1b[D");
                 }
             }
             (b'5', b'D') => {
@@ -124,7 +278,7 @@ impl Buffer {
                 // RARROW
                 if self.write < self.wend {
                     self.write += 1;
-                    print!("\x1b[C");
+                    out.echo("\x1b[C");
                 }
             }
             (b'5', b'C') => {
@@ -159,8 +313,21 @@ impl Console {
         Self(SpinLocked::new(Buffer::new()))
     }
 
-    pub fn put(&self, ch: u8) -> bool {
-        self.0.lock().put(ch)
+    /// Handle everything the UART had for one interrupt, and echo it once the buffer is unlocked.
+    /// Returns how many bytes were dropped, in which case the bell should be rung if `bell` says
+    /// so.
+    pub fn put_all(&self, input: &[u8]) -> usize {
+        let mut buf = self.0.lock();
+        let mut out = Output::new(buf.echo);
+        let dropped = input.iter().filter(|&&ch| !buf.put(ch, &mut out)).count();
+        buf.dropped += dropped;
+        drop(buf);
+        out.flush();
+        dropped
+    }
+
+    pub fn bell(&self) -> bool {
+        self.0.lock().bell
     }
 }
 
@@ -169,9 +336,94 @@ impl Device for Console {
         Ok(self.0.lock().read(buf))
     }
 
+    /// Once a line has been entered, or the buffer is too full to wait for one
+    fn readable(&self) -> bool {
+        let buf = self.0.lock();
+        buf.rend > buf.read
+    }
+
     fn write(&self, _pos: u64, buf: &[u8]) -> FsResult<usize> {
-        let mut cons = crate::uart::CONS.lock();
+        let mut cons = crate::uart::lock();
         buf.iter().for_each(|&b| cons.put(b));
         Ok(buf.len())
     }
+
+    fn control(&self, open: OpenId, cmd: usize, arg: usize) -> FsResult<usize> {
+        let flag = |arg| match arg {
+            0 => Ok(Some(false)),
+            1 => Ok(Some(true)),
+            usize::MAX => Ok(None),
+            _ => Err(FsError::InvalidOp),
+        };
+        let cmd = ConsoleCtl::from_repr(cmd).ok_or(FsError::InvalidOp)?;
+        // a program that dies with echo off mustn't leave the shell that way
+        let changes = match cmd {
+            ConsoleCtl::Bell
+            | ConsoleCtl::Echo
+            | ConsoleCtl::ModemControl
+            | ConsoleCtl::CtsFlow => arg != usize::MAX,
+            ConsoleCtl::TakeDropped | ConsoleCtl::ModemStatus => false,
+        };
+        if changes {
+            self.0.lock().save(open)?;
+        }
+
+        match cmd {
+            ConsoleCtl::Bell => {
+                let mut buf = self.0.lock();
+                let prev = buf.bell;
+                if let Some(on) = flag(arg)? {
+                    buf.bell = on;
+                }
+                Ok(prev as usize)
+            }
+            ConsoleCtl::Echo => {
+                let mut buf = self.0.lock();
+                let prev = buf.echo;
+                if let Some(on) = flag(arg)? {
+                    buf.echo = on;
+                }
+                Ok(prev as usize)
+            }
+            ConsoleCtl::TakeDropped => Ok(core::mem::take(&mut self.0.lock().dropped)),
+            ConsoleCtl::ModemStatus => with_uart(|uart| Ok(uart.modem_status().bits() as usize)),
+            ConsoleCtl::ModemControl => with_uart(|uart| {
+                let prev = uart.modem_control();
+                if arg != usize::MAX {
+                    let lines = u8::try_from(arg)
+                        .ok()
+                        .and_then(ModemControl::from_bits)
+                        .ok_or(FsError::InvalidOp)?;
+                    uart.set_modem_control(lines);
+                }
+                Ok(prev.bits() as usize)
+            }),
+            ConsoleCtl::CtsFlow => with_uart(|uart| {
+                let prev = uart.cts_flow();
+                if let Some(on) = flag(arg)? {
+                    uart.set_cts_flow(on);
+                }
+                Ok(prev as usize)
+            }),
+        }
+    }
+
+    /// Put back the settings from before `open` changed them. If another descriptor changed them
+    /// after it did, they're handed to that one to put back once it's closed instead.
+    fn close(&self, open: OpenId) {
+        let mut buf = self.0.lock();
+        let Some(i) = buf.saved.iter().position(|&(id, _)| id == open) else {
+            return;
+        };
+        let (_, settings) = buf.saved.remove(i);
+        match buf.saved.get_mut(i) {
+            Some((_, later)) => *later = settings,
+            None => buf.apply(settings),
+        }
+    }
+}
+
+/// The modem lines only exist if the console is a UART we drive ourselves
+fn with_uart(f: impl FnOnce(&mut Ns16550a) -> FsResult<usize>) -> FsResult<usize> {
+    f(crate::uart::lock().uart().ok_or(FsError::Unsupported)?)
 }
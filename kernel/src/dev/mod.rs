@@ -1,12 +1,37 @@
 use core::mem::MaybeUninit;
 
-use crate::fs::FsResult;
+use crate::fs::{FsError, FsResult, OpenId};
 
 pub mod console;
 pub mod zero;
 pub mod null;
+pub mod random;
 
 pub trait Device {
     fn read<'a>(&self, pos: u64, buf: &'a mut [MaybeUninit<u8>]) -> FsResult<&'a mut [u8]>;
     fn write(&self, pos: u64, buf: &[u8]) -> FsResult<usize>;
+
+    /// Handle a device specific request made with `Sys::Ioctl` through the descriptor `open`
+    fn control(&self, _open: OpenId, _cmd: usize, _arg: usize) -> FsResult<usize> {
+        Err(FsError::Unsupported)
+    }
+
+    /// The descriptor `open` was closed
+    fn close(&self, _open: OpenId) {}
+
+    /// Whether a read would return anything right now. Devices that say no have to call
+    /// `poll::notify` once they would.
+    fn readable(&self) -> bool {
+        true
+    }
+
+    /// Whether each read or write is a whole message, which mustn't be split at page boundaries
+    fn datagram(&self) -> bool {
+        false
+    }
+
+    /// How many bytes the device holds, if it's a block device a filesystem can be read from
+    fn size(&self) -> Option<u64> {
+        None
+    }
 }
@@ -0,0 +1,28 @@
+use core::mem::MaybeUninit;
+
+use crate::{
+    fs::{FsError, FsResult},
+    random,
+};
+
+use super::Device;
+
+/// `urandom`, for programs that need numbers an outsider can't guess, like DNS query IDs. Like the
+/// rest of `random`, nothing it gives is fit for cryptography.
+pub struct RandomDevice;
+
+impl Device for RandomDevice {
+    fn read<'a>(&self, _pos: u64, buf: &'a mut [MaybeUninit<u8>]) -> FsResult<&'a mut [u8]> {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = random::next_u64().to_le_bytes();
+            for (b, &r) in chunk.iter_mut().zip(&bytes) {
+                b.write(r);
+            }
+        }
+        Ok(unsafe { MaybeUninit::slice_assume_init_mut(buf) })
+    }
+
+    fn write(&self, _pos: u64, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::InvalidOp)
+    }
+}
@@ -1,5 +1,5 @@
 mod ns16550;
 mod syscon;
 
-pub use ns16550::Ns16550a;
+pub use ns16550::{LineErrors, Ns16550a};
 pub use syscon::Syscon;
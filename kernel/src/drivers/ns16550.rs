@@ -13,6 +13,11 @@ base+6   MSR modem status      not used                | MSR modem status
 
 use core::ptr::NonNull;
 
+use bitflags::bitflags;
+use shared::io::{ModemControl, ModemStatus};
+
+use crate::riscv::{r_time, TIMEBASE_FREQ};
+
 #[allow(clippy::upper_case_acronyms)]
 #[allow(unused)]
 enum Read {
@@ -35,34 +40,89 @@ enum Write {
     MCR = 4,
 }
 
+/// 8 data bits, 1 stop bit, no parity
+const LCR_8N1: u8 = 0b011;
+/// Divisor latch access
+const LCR_DLAB: u8 = 1 << 7;
+/// How long `put` waits for CTS before sending anyway
+const CTS_TIMEOUT: usize = TIMEBASE_FREQ;
+
+bitflags! {
+    /// The error bits of LSR, which are cleared when it's read
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct LineErrors: u8 {
+        const Overrun = 1 << 1;
+        const Parity = 1 << 2;
+        const Framing = 1 << 3;
+        const Break = 1 << 4;
+    }
+}
+
 pub struct Ns16550a {
     base: NonNull<u8>,
+    clock_hz: u32,
+    /// Hold output while CTS is deasserted
+    cts_flow: bool,
+    /// CTS didn't come back within `CTS_TIMEOUT`, so output isn't held again until it does
+    cts_lost: bool,
 }
 
 impl Ns16550a {
-    /// Creates a new [`Ns16550a`].
+    /// Creates a new [`Ns16550a`] running at `baud`, or whatever the firmware left it at if the
+    /// divisor for `baud` can't be set.
     ///
     /// # Safety
     /// The `base` address must be a valid memory-mapped Ns16550a compliant UART controller.
     pub unsafe fn new(base: usize, clock_hz: u32, baud: u32) -> Ns16550a {
         unsafe {
-            let this = Ns16550a {
+            let mut this = Ns16550a {
                 base: NonNull::new_unchecked(base as *mut u8),
+                clock_hz,
+                cts_flow: false,
+                cts_lost: false,
             };
 
-            let divisor = clock_hz.div_ceil(baud * 16) as u16;
-            this.write_reg(Write::FCR, 1 << 7); // enable divisor latch access
-            this.write_reg(Write::THR /* DLL */, (divisor & 0xff) as u8);
-            this.write_reg(Write::IER /* DLM */, (divisor >> 8) as u8);
-
-            this.write_reg(Write::LCR, 0b011); // 8-bit data size, 1 stop bit, no parity, disable divisor latch
+            this.write_reg(Write::LCR, LCR_8N1);
+            this.set_baud(baud);
             this.write_reg(Write::FCR, 0b1); // enable FIFO
-            this.write_reg(Write::IER, 0b1); // enable receiver buffer interrupts
+            this.write_reg(Write::IER, 0b101); // enable receiver buffer and line status interrupts
+            this.set_modem_control(ModemControl::Dtr | ModemControl::Rts);
             this
         }
     }
 
+    /// Program the divisor latch for the closest rate to `baud` the input clock allows. Returns
+    /// false without changing anything if it can't get within 5%.
+    pub fn set_baud(&mut self, baud: u32) -> bool {
+        let Some(divisor) = self.divisor(baud) else {
+            return false;
+        };
+
+        // DLL and DLM replace THR and IER while the divisor latch access bit is set
+        self.write_reg(Write::LCR, LCR_DLAB | LCR_8N1);
+        self.write_reg(Write::THR /* DLL */, (divisor & 0xff) as u8);
+        self.write_reg(Write::IER /* DLM */, (divisor >> 8) as u8);
+        self.write_reg(Write::LCR, LCR_8N1);
+        true
+    }
+
+    fn divisor(&self, baud: u32) -> Option<u16> {
+        let (clock, baud) = (self.clock_hz as u64, baud as u64 * 16);
+        if baud == 0 {
+            return None;
+        }
+
+        let divisor = (clock + baud / 2) / baud;
+        let divisor = u16::try_from(divisor).ok().filter(|&d| d != 0)?;
+        let actual = clock / divisor as u64;
+        (actual.abs_diff(baud) * 20 <= baud).then_some(divisor)
+    }
+
     pub fn put(&mut self, byte: u8) {
+        if self.cts_flow {
+            self.wait_for_cts();
+        }
+
         // wait for THR to be empty
         while self.read_reg(Read::LSR) & (1 << 5) == 0 {
             core::hint::spin_loop();
@@ -71,11 +131,61 @@ impl Ns16550a {
         self.write_reg(Write::THR, byte);
     }
 
+    /// Wait for the other end to be ready for more. The FIFO can still hold more than a slow
+    /// adapter takes after it deasserts CTS, but it's only 16 bytes.
+    fn wait_for_cts(&mut self) {
+        let cts = |this: &mut Self| this.modem_status().contains(ModemStatus::Cts);
+        if self.cts_lost {
+            self.cts_lost = !cts(self);
+            return;
+        }
+
+        let deadline = r_time() + CTS_TIMEOUT;
+        while !cts(self) {
+            if r_time() >= deadline {
+                self.cts_lost = true;
+                return;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Whether `put` waits for CTS before sending. Returns the previous setting.
+    pub fn set_cts_flow(&mut self, on: bool) -> bool {
+        self.cts_lost = false;
+        core::mem::replace(&mut self.cts_flow, on)
+    }
+
+    pub fn cts_flow(&self) -> bool {
+        self.cts_flow
+    }
+
+    pub fn modem_control(&self) -> ModemControl {
+        ModemControl::from_bits_truncate(self.read_reg(Read::MCR))
+    }
+
+    pub fn set_modem_control(&mut self, lines: ModemControl) {
+        self.write_reg(Write::MCR, lines.bits());
+    }
+
+    /// Reading MSR clears the delta bits
+    pub fn modem_status(&mut self) -> ModemStatus {
+        ModemStatus::from_bits_retain(self.read_reg(Read::MSR))
+    }
+
     pub fn read(&mut self) -> Option<u8> {
-        if self.read_reg(Read::LSR) & 0b1 != 0 {
-            Some(self.read_reg(Read::RBR))
+        self.read_checked().0
+    }
+
+    /// Read a byte if there is one, along with any errors seen since LSR was last read. With a
+    /// parity or framing error, the byte is probably garbage.
+    pub fn read_checked(&mut self) -> (Option<u8>, LineErrors) {
+        let lsr = self.read_reg(Read::LSR);
+        let errors = LineErrors::from_bits_truncate(lsr);
+        if lsr & 0b1 != 0 {
+            (Some(self.read_reg(Read::RBR)), errors)
         } else {
-            None
+            (None, errors)
         }
     }
 
@@ -6,7 +6,6 @@ use fdt_rs::base::DevTree;
 use fdt_rs::error::Result as DevTreeResult;
 use fdt_rs::index::{DevTreeIndex, DevTreeIndexNode, DevTreeIndexProp};
 use fdt_rs::prelude::*;
-use fdt_rs::spec::fdt_reserve_entry;
 
 use crate::{print, println};
 
@@ -136,22 +135,25 @@ impl FdtDumper {
         println!("// size_dt_strings:\t{:#x}", fdt.size_dt_strings());
         println!("// size_dt_struct:\t{:#x}\n", fdt.size_dt_struct());
 
-        for rsv in fdt.reserved_entries() {
-            let rsv = unsafe {
-                (*(&rsv as *const _ as *const *const fdt_reserve_entry)).read_unaligned()
-            };
-
-            println!(
-                "/memreserve/ {:#x} {:#x};",
-                u64::from(rsv.address),
-                u64::from(rsv.size)
-            );
+        for (address, size) in reserved_entries(fdt.buf(), fdt.off_mem_rsvmap()) {
+            println!("/memreserve/ {address:#x} {size:#x};");
         }
 
         println!();
     }
 }
 
+/// The (address, size) pairs of the memory reservation block at `offset` in `fdt`, which ends at
+/// an all zero entry. They're read a byte at a time, so neither has to be aligned.
+pub fn reserved_entries(fdt: &[u8], offset: usize) -> impl Iterator<Item = (u64, u64)> + '_ {
+    let be64 = |entry: &[u8], at: usize| u64::from_be_bytes(entry[at..at + 8].try_into().unwrap());
+    fdt.get(offset..)
+        .unwrap_or_default()
+        .chunks_exact(16)
+        .map(move |entry| (be64(entry, 0), be64(entry, 8)))
+        .take_while(|&entry| entry != (0, 0))
+}
+
 #[allow(unused)]
 pub fn dump_tree(dt: DevTree<'_>) -> DevTreeResult<()> {
     let layout = DevTreeIndex::get_layout(&dt)?;
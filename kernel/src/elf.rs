@@ -1,7 +1,9 @@
-use core::{ffi::CStr, mem::size_of};
+use core::{ffi::CStr, marker::PhantomData, mem::size_of};
+
+use shared::sys::SysError;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct EHdr {
     pub ident: [u8; 16],
     pub typ: u16,
@@ -20,22 +22,22 @@ pub struct EHdr {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Shdr {
     pub name: u32,
     pub typ: u32,
-    pub flags: u32,
+    pub flags: u64,
     pub addr: u64,
     pub offset: u64,
-    pub size: u32,
+    pub size: u64,
     pub link: u32,
     pub info: u32,
-    pub addralign: u32,
-    pub entsize: u32,
+    pub addralign: u64,
+    pub entsize: u64,
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Phdr {
     pub typ: u32,
     pub flags: u32,
@@ -48,7 +50,7 @@ pub struct Phdr {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Sym {
     pub name: u32,
     pub info: u8,
@@ -80,6 +82,9 @@ pub const EI_DATA: usize = 5;
 pub const EI_VERSION: usize = 6;
 pub const EI_OSABI: usize = 7;
 
+pub const ET_EXEC: u16 = 2;
+pub const EM_RISCV: u16 = 243;
+
 pub const PN_XNUM: u16 = 0xffff;
 
 pub const SHN_LORESERVE: u16 = 0xff00;
@@ -92,31 +97,118 @@ pub const PF_R: u32 = 1;
 pub const PF_W: u32 = 2;
 pub const PF_X: u32 = 4;
 
+/// Why a file can't be loaded as an executable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// Doesn't start with the ELF magic, or is a version of the format we don't know
+    BadMagic,
+    /// Not 64-bit little endian
+    WrongClass,
+    /// Not for RISC-V
+    WrongMachine,
+    /// An object file or shared library instead of an executable
+    WrongType,
+    /// A header or segment is past the end of the file
+    Truncated,
+    /// A segment's address and offset disagree about its alignment
+    Misaligned,
+    /// Two loadable segments share a page
+    Overlapping,
+    /// A loadable segment is outside the address range executables are loaded at
+    TooBig,
+    /// A segment has more data in the file than room in memory
+    BadSegment,
+}
+
+impl From<ElfError> for SysError {
+    fn from(value: ElfError) -> Self {
+        match value {
+            ElfError::BadMagic => SysError::ExecNotElf,
+            ElfError::WrongClass | ElfError::WrongMachine => SysError::ExecWrongArch,
+            ElfError::WrongType => SysError::ExecWrongType,
+            ElfError::Truncated => SysError::ExecTruncated,
+            ElfError::Misaligned => SysError::ExecMisaligned,
+            ElfError::Overlapping => SysError::ExecOverlap,
+            ElfError::TooBig => SysError::ExecTooBig,
+            ElfError::BadSegment => SysError::ExecBadSegment,
+        }
+    }
+}
+
+/// Loadable segments can't share a page, since each page has one set of permissions
+const PAGE_SIZE: u64 = 0x1000;
+
+/// `len` headers of type `T` in a file. They're copied out one at a time, since nothing says the
+/// file is aligned in memory.
+#[derive(Clone, Copy)]
+pub struct Headers<'a, T> {
+    data: &'a [u8],
+    len: usize,
+    _pd: PhantomData<T>,
+}
+
+impl<'a, T: Copy + 'a> Headers<'a, T> {
+    fn new(data: &'a [u8], len: usize) -> Result<Self, ElfError> {
+        let data = size_of::<T>()
+            .checked_mul(len)
+            .and_then(|size| data.get(..size))
+            .ok_or(ElfError::Truncated)?;
+        Ok(Self {
+            data,
+            len,
+            _pd: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<T> {
+        read(self.data.get(i.checked_mul(size_of::<T>())?..)?)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = T> + Clone + 'a {
+        let this = *self;
+        (0..self.len).filter_map(move |i| this.get(i))
+    }
+}
+
 pub struct ElfFile<'a> {
-    pub ehdr: &'a EHdr,
-    pub sheaders: &'a [Shdr],
-    pub pheaders: &'a [Phdr],
+    pub ehdr: EHdr,
+    pub sheaders: Headers<'a, Shdr>,
+    pub pheaders: Headers<'a, Phdr>,
     pub strtable: Option<&'a [u8]>,
     pub raw: &'a [u8],
 }
 
 impl<'a> ElfFile<'a> {
-    pub fn new(file: &'a [u8]) -> Option<Self> {
-        let ehdr = as_struct::<EHdr>(file)?;
+    pub fn new(file: &'a [u8]) -> Result<Self, ElfError> {
+        // anything too short for the header can't be an ELF file at all
+        let ehdr = read::<EHdr>(file).ok_or(ElfError::BadMagic)?;
         if !matches!(&ehdr.ident[EI_MAG0..=EI_MAG3], b"\x7fELF")
-            || ehdr.ident[EI_CLASS] != 2 // is class 32 bit
-            || ehdr.ident[EI_DATA] != 1 // 2s complement, little endian
             || ehdr.ident[EI_VERSION] != 1
-            || ehdr.typ != 2  // ET_EXEC
-            || ehdr.machine != 243 // EM_RISCV
             || ehdr.version != 1
         {
-            return None;
+            return Err(ElfError::BadMagic);
+        }
+        // 64 bit, 2s complement little endian
+        if ehdr.ident[EI_CLASS] != 2 || ehdr.ident[EI_DATA] != 1 {
+            return Err(ElfError::WrongClass);
+        }
+        if ehdr.machine != EM_RISCV {
+            return Err(ElfError::WrongMachine);
+        }
+        if ehdr.typ != ET_EXEC {
+            return Err(ElfError::WrongType);
         }
 
-        let sh_zero = file
-            .get(ehdr.shoff as usize..)
-            .and_then(as_struct::<Shdr>)?;
+        let at = |offset: u64| file.get(offset as usize..).ok_or(ElfError::Truncated);
+        let sh_zero = read::<Shdr>(at(ehdr.shoff)?).ok_or(ElfError::Truncated)?;
         let phnum = if ehdr.phnum == PN_XNUM {
             sh_zero.info as usize
         } else {
@@ -128,12 +220,12 @@ impl<'a> ElfFile<'a> {
             ehdr.shnum as usize
         };
 
-        let sheaders = as_slice(file.get(ehdr.shoff as usize..)?, shnum)?;
-        Some(Self {
+        let sheaders = Headers::<Shdr>::new(at(ehdr.shoff)?, shnum)?;
+        Ok(Self {
             raw: file,
             ehdr,
             sheaders,
-            pheaders: as_slice(file.get(ehdr.phoff as usize..)?, phnum)?,
+            pheaders: Headers::new(at(ehdr.phoff)?, phnum)?,
             strtable: if ehdr.shstrndx == SHN_UNDEF {
                 None
             } else {
@@ -143,10 +235,54 @@ impl<'a> ElfFile<'a> {
                     ehdr.shstrndx as usize
                 };
 
-                Some(file.get(sheaders.get(idx)?.offset as usize..)?)
+                Some(at(sheaders.get(idx).ok_or(ElfError::Truncated)?.offset)?)
             },
         })
     }
+
+    /// The loadable segments, after checking that each one can be loaded on its own and that no
+    /// two of them would have to share a page
+    pub fn load_segments(&self) -> Result<impl Iterator<Item = Phdr> + 'a, ElfError> {
+        let pages = |phdr: &Phdr| {
+            if phdr.memsz == 0 {
+                return Some(0..0);
+            }
+            let end = phdr
+                .vaddr
+                .checked_add(phdr.memsz)?
+                .checked_add(PAGE_SIZE - 1)?;
+            Some(phdr.vaddr / PAGE_SIZE..end / PAGE_SIZE)
+        };
+
+        let segments = self.pheaders.iter().filter(|phdr| phdr.typ == PT_LOAD);
+        for (i, phdr) in segments.clone().enumerate() {
+            if phdr.memsz < phdr.filesz {
+                return Err(ElfError::BadSegment);
+            }
+            if phdr
+                .offset
+                .checked_add(phdr.filesz)
+                .map_or(true, |end| end > self.raw.len() as u64)
+            {
+                return Err(ElfError::Truncated);
+            }
+            if phdr.align > 1
+                && (!phdr.align.is_power_of_two()
+                    || phdr.vaddr % phdr.align != phdr.offset % phdr.align)
+            {
+                return Err(ElfError::Misaligned);
+            }
+
+            let mine = pages(&phdr).ok_or(ElfError::TooBig)?;
+            for other in segments.clone().take(i) {
+                let other = pages(&other).ok_or(ElfError::TooBig)?;
+                if mine.start < other.end && other.start < mine.end {
+                    return Err(ElfError::Overlapping);
+                }
+            }
+        }
+        Ok(segments)
+    }
 }
 
 impl Shdr {
@@ -157,20 +293,12 @@ impl Shdr {
     }
 }
 
-fn as_struct<T>(data: &[u8]) -> Option<&T> {
+/// Copy a `T` from the start of `data`, wherever it is in memory
+fn read<T: Copy>(data: &[u8]) -> Option<T> {
     if data.len() < size_of::<T>() {
         return None;
     }
 
-    assert!(data.as_ptr().is_aligned_to(core::mem::align_of::<T>()));
-    Some(unsafe { &*data.as_ptr().cast() })
-}
-
-fn as_slice<T>(data: &[u8], count: usize) -> Option<&[T]> {
-    if data.len() < size_of::<T>() * count {
-        return None;
-    }
-
-    assert!(data.as_ptr().is_aligned_to(core::mem::align_of::<T>()));
-    unsafe { Some(core::slice::from_raw_parts(data.as_ptr().cast(), count)) }
+    // Safety: every type read is plain old data made of integers, so any bytes are a valid value
+    Some(unsafe { data.as_ptr().cast::<T>().read_unaligned() })
 }
@@ -0,0 +1,86 @@
+use servos::lock::SpinLocked;
+use shared::sys::{FaultInfo, FAULT_BACKTRACE_LEN};
+
+use crate::{
+    proc::{ProcId, Process, Reg},
+    vmm::{User, VirtAddr},
+};
+
+/// How many faults are remembered for `Sys::FaultInfo`. Each one is only needed until whoever was
+/// waiting for the process has written its crash report.
+const FAULTS_LEN: usize = 8;
+
+struct Faults {
+    infos: [Option<FaultInfo>; FAULTS_LEN],
+    next: usize,
+}
+
+static FAULTS: SpinLocked<Faults> = SpinLocked::new(Faults {
+    infos: [None; FAULTS_LEN],
+    next: 0,
+});
+
+/// Remember why `proc` is being killed for the exception `cause` at `pc`, overwriting the oldest
+/// fault. The backtrace is read from the process's own stack, so it's only as good as its frame
+/// pointers.
+pub fn record(proc: &mut Process, cause: usize, pc: usize, stval: usize) {
+    let tf = proc.trapframe();
+    let (ra, sp, mut fp) = (tf[Reg::RA], tf[Reg::SP], tf[Reg::S0]);
+
+    // each frame stores the return address at fp - 8 and the caller's frame pointer at fp - 16.
+    // the stack grows down, so a caller's frame that isn't above this one means the chain is
+    // corrupt, and stopping there also guarantees the walk ends.
+    let mut backtrace = [0; FAULT_BACKTRACE_LEN];
+    for ret in backtrace.iter_mut() {
+        if fp < 16 || fp % core::mem::align_of::<usize>() != 0 {
+            break;
+        }
+        let Ok([prev_fp, ra]) = User::<[usize; 2]>::new(VirtAddr(fp - 16)).read(proc.pagetable())
+        else {
+            break;
+        };
+        if ra == 0 {
+            break;
+        }
+        *ret = ra as u64;
+        if prev_fp <= fp {
+            break;
+        }
+        fp = prev_fp;
+    }
+
+    let mut exe = [0; 128];
+    let path: &[u8] = proc.exe_path.as_ref().as_ref();
+    let len = path.len().min(exe.len());
+    exe[..len].copy_from_slice(&path[..len]);
+
+    let mut faults = FAULTS.lock();
+    let next = faults.next;
+    faults.infos[next] = Some(FaultInfo {
+        handle: proc.handle,
+        cause: cause as u64,
+        pc: pc as u64,
+        stval: stval as u64,
+        ra: ra as u64,
+        sp: sp as u64,
+        backtrace,
+        exe,
+    });
+    faults.next = (next + 1) % FAULTS_LEN;
+}
+
+/// The fault that killed the most recent process `id` refers to, if it's still remembered
+pub fn find(id: ProcId) -> Option<FaultInfo> {
+    let faults = FAULTS.lock();
+    // newest first, in case a bare PID has been used more than once
+    (0..FAULTS_LEN)
+        .map(|i| faults.infos[(faults.next + FAULTS_LEN - 1 - i) % FAULTS_LEN])
+        .find_map(|info| {
+            let info = info?;
+            match id {
+                ProcId::Pid(pid) => info.handle.pid() == pid,
+                ProcId::Handle(handle) => info.handle == handle,
+            }
+            .then_some(info)
+        })
+}
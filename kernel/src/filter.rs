@@ -0,0 +1,175 @@
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use servos::lock::SpinLocked;
+use shared::{
+    filter::{Field, Hook, Insn, Op, MAP_LEN, MAX_INSNS, REGS},
+    sys::{SysError, SYSCALL_MAX_ARGS},
+};
+
+/// What a program can see of the event it runs for
+pub struct Event {
+    pub syscall: usize,
+    pub args: [usize; SYSCALL_MAX_ARGS],
+    pub pid: u32,
+    pub result: Result<usize, SysError>,
+}
+
+impl Event {
+    fn field(&self, field: Field) -> u64 {
+        match field {
+            Field::Syscall => self.syscall as u64,
+            Field::Arg0 => self.args[0] as u64,
+            Field::Arg1 => self.args[1] as u64,
+            Field::Arg2 => self.args[2] as u64,
+            Field::Arg3 => self.args[3] as u64,
+            Field::Arg4 => self.args[4] as u64,
+            Field::Arg5 => self.args[5] as u64,
+            Field::Pid => self.pid as u64,
+            Field::Result => self.result.unwrap_or(0) as u64,
+            Field::Error => self.result.err().map_or(0, |err| err as u64),
+        }
+    }
+}
+
+/// A program that has passed `Program::new`'s checks, so running it can't go out of bounds or
+/// loop
+pub struct Program(Box<[Insn]>);
+
+impl Program {
+    pub fn new(insns: Vec<Insn>) -> Result<Self, SysError> {
+        if insns.is_empty() || insns.len() > MAX_INSNS {
+            return Err(SysError::BadArg);
+        }
+
+        for (pc, insn) in insns.iter().enumerate() {
+            let op = Op::from_repr(insn.op).ok_or(SysError::BadArg)?;
+            if insn.dst as usize >= REGS || insn.src as usize >= REGS {
+                return Err(SysError::BadArg);
+            }
+
+            match op {
+                Op::Load if Field::from_repr(insn.imm).is_none() => return Err(SysError::BadArg),
+                // falling off the end has to be impossible, so the target can't be past the last
+                // instruction, which has to return
+                Op::Jeq | Op::Jne | Op::Jlt | Op::Jge
+                    if pc + 1 + insn.imm as usize >= insns.len() =>
+                {
+                    return Err(SysError::BadArg)
+                }
+                _ => {}
+            }
+        }
+
+        if insns.last().map(|insn| insn.op) != Some(Op::Ret as u8) {
+            return Err(SysError::BadArg);
+        }
+        Ok(Self(insns.into()))
+    }
+
+    /// Run the program to completion. `map` is `None` for syscall filters.
+    pub fn run(&self, event: &Event, map: Option<&[AtomicU64; MAP_LEN]>) -> u64 {
+        let mut regs = [0u64; REGS];
+        let mut pc = 0;
+        loop {
+            let insn = self.0[pc];
+            let (dst, src) = (insn.dst as usize, insn.src as usize);
+            let (a, b) = (regs[dst], regs[src]);
+            pc += 1;
+            // verified by `new`
+            match Op::from_repr(insn.op).unwrap() {
+                Op::LoadImm => regs[dst] = insn.imm as u64,
+                Op::Load => regs[dst] = event.field(Field::from_repr(insn.imm).unwrap()),
+                Op::Mov => regs[dst] = b,
+                Op::Add => regs[dst] = a.wrapping_add(b),
+                Op::Sub => regs[dst] = a.wrapping_sub(b),
+                Op::Mul => regs[dst] = a.wrapping_mul(b),
+                Op::And => regs[dst] = a & b,
+                Op::Or => regs[dst] = a | b,
+                Op::Xor => regs[dst] = a ^ b,
+                Op::Shl => regs[dst] = a << (b % 64),
+                Op::Shr => regs[dst] = a >> (b % 64),
+                Op::Jeq | Op::Jne | Op::Jlt | Op::Jge => {
+                    let taken = match Op::from_repr(insn.op).unwrap() {
+                        Op::Jeq => a == b,
+                        Op::Jne => a != b,
+                        Op::Jlt => a < b,
+                        _ => a >= b,
+                    };
+                    if taken {
+                        pc += insn.imm as usize;
+                    }
+                }
+                Op::MapAdd => {
+                    if let Some(counter) = map.and_then(|map| map.get(a as usize)) {
+                        counter.fetch_add(b, Ordering::Relaxed);
+                    }
+                }
+                Op::Ret => return regs[0],
+            }
+        }
+    }
+}
+
+struct Filter {
+    id: u32,
+    hook: Hook,
+    prog: Program,
+    map: [AtomicU64; MAP_LEN],
+}
+
+static FILTERS: SpinLocked<Vec<Arc<Filter>>> = SpinLocked::new(Vec::new());
+/// Lets syscalls skip taking the lock when nothing is attached, which is almost always
+static ATTACHED: AtomicUsize = AtomicUsize::new(0);
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Run `prog` every time `hook` is reached. Returns the ID to read its map or detach it with.
+pub fn attach(hook: Hook, prog: Program) -> Result<u32, SysError> {
+    let filter = Arc::try_new(Filter {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        hook,
+        prog,
+        map: [const { AtomicU64::new(0) }; MAP_LEN],
+    })?;
+
+    let id = filter.id;
+    let mut filters = FILTERS.lock();
+    filters.try_reserve(1)?;
+    filters.push(filter);
+    ATTACHED.fetch_add(1, Ordering::Relaxed);
+    Ok(id)
+}
+
+pub fn detach(id: u32) -> Result<(), SysError> {
+    let mut filters = FILTERS.lock();
+    let pos = filters
+        .iter()
+        .position(|f| f.id == id)
+        .ok_or(SysError::NotFound)?;
+    filters.remove(pos);
+    ATTACHED.fetch_sub(1, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn read_map(id: u32) -> Result<[u64; MAP_LEN], SysError> {
+    let filters = FILTERS.lock();
+    let filter = filters
+        .iter()
+        .find(|f| f.id == id)
+        .ok_or(SysError::NotFound)?;
+    Ok(core::array::from_fn(|i| {
+        filter.map[i].load(Ordering::Relaxed)
+    }))
+}
+
+/// Run every filter attached to `hook`. Their results are only counted in their maps, so they're
+/// ignored.
+pub fn run(hook: Hook, event: &Event) {
+    if ATTACHED.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    for filter in FILTERS.lock().iter().filter(|f| f.hook == hook) {
+        filter.prog.run(event, Some(&filter.map));
+    }
+}
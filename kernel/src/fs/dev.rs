@@ -1,18 +1,28 @@
 use core::mem::MaybeUninit;
 
 use alloc::{sync::Arc, vec::Vec};
-use shared::io::{DirEntry, OpenFlags, Stat};
+use shared::io::{DeviceId, DirEntry, Mode, OpenFlags, Stat};
 
-use crate::dev::Device;
+use crate::{
+    dev::Device,
+    vmm::{PageTable, Pte, VirtAddr},
+};
 
 use super::{
     path::{OwnedPath, Path},
+    rw_va,
     vfs::MountError,
-    FileSystem, FsError, FsResult, VNode,
+    FileSystem, FsError, FsResult, OpenId, VNode,
 };
 
+struct Entry {
+    name: OwnedPath,
+    id: DeviceId,
+    dev: Arc<dyn Device>,
+}
+
 pub struct DeviceFs {
-    devices: Vec<(OwnedPath, Arc<dyn Device>)>,
+    devices: Vec<Entry>,
 }
 
 impl DeviceFs {
@@ -22,9 +32,15 @@ impl DeviceFs {
         }
     }
 
-    pub fn add_device(&mut self, name: OwnedPath, dev: Arc<dyn Device>) -> Result<(), MountError> {
+    /// Add `dev` as `/dev/<name>`. Its `id` is shown by `stat`, and must be unique too.
+    pub fn add_device(
+        &mut self,
+        name: OwnedPath,
+        id: DeviceId,
+        dev: Arc<dyn Device>,
+    ) -> Result<(), MountError> {
         assert!(name.components().count() == 1);
-        if self.find_device(&name).is_some() {
+        if self.find_device(&name).is_some() || self.devices.iter().any(|entry| entry.id == id) {
             return Err(MountError::AlreadyMounted);
         }
 
@@ -32,23 +48,36 @@ impl DeviceFs {
             return Err(MountError::NoMem);
         }
 
-        self.devices.push((name, dev));
+        self.devices.push(Entry { name, id, dev });
         Ok(())
     }
 
+    pub fn get(&self, name: &Path) -> Option<&Arc<dyn Device>> {
+        self.find_device(name).map(|i| &self.devices[i].dev)
+    }
+
     fn find_device(&self, name: &Path) -> Option<usize> {
-        self.devices.iter().position(|(dev, _)| dev == name)
+        self.devices.iter().position(|entry| &entry.name == name)
     }
 }
 
 impl FileSystem for DeviceFs {
-    fn open(&self, path: &Path, flags: OpenFlags, root: Option<&VNode>) -> FsResult<VNode> {
+    fn open(
+        &self,
+        path: &Path,
+        flags: OpenFlags,
+        _mode: Mode,
+        root: Option<&VNode>,
+    ) -> FsResult<VNode> {
         if !path.is_absolute() && root.is_some_and(|r| !r.directory) {
             return Err(FsError::PathNotFound);
         }
 
         let mut components = path.components();
         if components.next().is_none() {
+            if flags.intersects(OpenFlags::ReadWrite | OpenFlags::Truncate) {
+                return Err(FsError::ReadOnly);
+            }
             return Ok(VNode {
                 ino: 0,
                 directory: true,
@@ -58,7 +87,11 @@ impl FileSystem for DeviceFs {
             return Err(FsError::PathNotFound);
         }
 
+        // devices are only ever added by the kernel, and truncating one means nothing
         let Some(ino) = self.find_device(path) else {
+            if flags.intersects(OpenFlags::CreateDir | OpenFlags::CreateFile) {
+                return Err(FsError::ReadOnly);
+            }
             return Err(FsError::PathNotFound);
         };
         // if devices become removable this will have to change
@@ -75,56 +108,126 @@ impl FileSystem for DeviceFs {
         pos: u64,
         buf: &'a mut [MaybeUninit<u8>],
     ) -> FsResult<&'a mut [u8]> {
-        self.devices[vn.ino as usize].1.read(pos, buf)
+        self.devices[vn.ino as usize].dev.read(pos, buf)
     }
 
     fn write(&self, vn: &VNode, pos: u64, buf: &[u8]) -> FsResult<usize> {
-        self.devices[vn.ino as usize].1.write(pos, buf)
+        self.devices[vn.ino as usize].dev.write(pos, buf)
     }
 
-    fn close(&self, _vn: &VNode) -> FsResult<()> {
+    fn control(&self, vn: &VNode, open: OpenId, cmd: usize, arg: usize) -> FsResult<usize> {
+        if vn.directory {
+            return Err(FsError::InvalidOp);
+        }
+        self.devices[vn.ino as usize].dev.control(open, cmd, arg)
+    }
+
+    fn readable(&self, vn: &VNode) -> bool {
+        vn.directory || self.devices[vn.ino as usize].dev.readable()
+    }
+
+    fn close(&self, vn: &VNode, open: OpenId) -> FsResult<()> {
+        if !vn.directory {
+            self.devices[vn.ino as usize].dev.close(open);
+        }
         Ok(())
     }
 
-    fn readdir(&self, vn: &VNode, pos: usize) -> FsResult<Option<DirEntry>> {
+    fn readdir(&self, vn: &VNode, pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
         if !vn.directory {
             return Err(FsError::InvalidOp);
         }
 
-        if let Some((name, _)) = self.devices.get(pos) {
-            let name: &[u8] = name.as_ref().as_ref();
+        // devices are only ever added to the end, so indices stay put
+        if let Some(entry) = self.devices.get(pos) {
+            let name: &[u8] = entry.name.as_ref().as_ref();
             let mut dir = DirEntry {
                 name: [0; 256],
                 name_len: name.len(),
-                stat: Stat {
-                    directory: false,
-                    size: 0,
-                    readonly: false,
-                },
+                stat: device_stat(entry.id),
             };
             dir.name[..name.len()].copy_from_slice(name);
-            Ok(Some(dir))
+            Ok(Some((dir, pos + 1)))
         } else {
             Ok(None)
         }
     }
 
+    fn read_va(
+        &self,
+        vn: &VNode,
+        pos: u64,
+        pt: &PageTable,
+        buf: VirtAddr,
+        len: usize,
+    ) -> FsResult<usize> {
+        let dev = &self.devices[vn.ino as usize].dev;
+        if !dev.datagram() {
+            return rw_va(pos, pt, buf, len, Pte::U | Pte::W, |pos, buf| {
+                let buf =
+                    unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr().cast(), buf.len()) };
+                dev.read(pos, buf).map(|v| v.len())
+            });
+        }
+
+        let mut kbuf = Vec::try_with_capacity(len).map_err(|_| FsError::NoMem)?;
+        let read = dev.read(pos, &mut kbuf.spare_capacity_mut()[..len])?.len();
+        unsafe { kbuf.set_len(read) };
+        buf.copy_to(pt, &kbuf, None)?;
+        Ok(read)
+    }
+
+    fn write_va(
+        &self,
+        vn: &VNode,
+        pos: u64,
+        pt: &PageTable,
+        buf: VirtAddr,
+        len: usize,
+    ) -> FsResult<usize> {
+        let dev = &self.devices[vn.ino as usize].dev;
+        if !dev.datagram() {
+            return rw_va(pos, pt, buf, len, Pte::U | Pte::R, |pos, buf| {
+                dev.write(pos, buf)
+            });
+        }
+
+        let mut kbuf = Vec::try_with_capacity(len).map_err(|_| FsError::NoMem)?;
+        buf.copy_from(pt, &mut kbuf.spare_capacity_mut()[..len])?;
+        unsafe { kbuf.set_len(len) };
+        dev.write(pos, &kbuf)
+    }
+
     fn stat(&self, vn: &VNode) -> FsResult<Stat> {
         if vn.directory {
             Ok(Stat {
                 directory: true,
                 size: 0,
                 readonly: true,
+                executable: false,
+                atime: 0,
+                mtime: 0,
+                allocated: 0,
+                device: None,
             })
         } else {
-            Ok(Stat {
-                directory: false,
-                size: 0,
-                readonly: false,
-            })
+            Ok(device_stat(self.devices[vn.ino as usize].id))
         }
     }
 }
 
 unsafe impl Sync for DeviceFs {}
 unsafe impl Send for DeviceFs {}
+
+fn device_stat(id: DeviceId) -> Stat {
+    Stat {
+        directory: false,
+        size: 0,
+        readonly: false,
+        executable: false,
+        atime: 0,
+        mtime: 0,
+        allocated: 0,
+        device: Some(id),
+    }
+}
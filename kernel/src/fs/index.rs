@@ -0,0 +1,124 @@
+use alloc::vec::Vec;
+
+use super::{FsError, FsResult};
+
+/// Directories with more entries than this get an index the first time they're searched
+pub const INDEX_THRESHOLD: usize = 32;
+
+/// FNV-1a, which is plenty for file names
+fn hash(name: &[u8]) -> u64 {
+    name.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Finds entries of a directory by the hash of their name, so big directories don't have to be
+/// searched by comparing every name. Only stores inodes, so `name_of` has to be able to tell the
+/// name of each one.
+pub struct NameIndex {
+    /// `(hash, inode)`, sorted by hash
+    entries: Vec<(u64, u64)>,
+}
+
+impl NameIndex {
+    pub fn build<'a>(entries: impl Iterator<Item = (&'a [u8], u64)>) -> FsResult<Self> {
+        let mut index = Vec::new();
+        for (name, ino) in entries {
+            index.try_reserve(1).map_err(|_| FsError::NoMem)?;
+            index.push((hash(name), ino));
+        }
+        index.sort_unstable_by_key(|&(hash, _)| hash);
+        Ok(Self { entries: index })
+    }
+
+    pub fn find<'a>(&self, name: &[u8], name_of: impl Fn(u64) -> Option<&'a [u8]>) -> Option<u64> {
+        let hash = hash(name);
+        let start = self.entries.partition_point(|&(h, _)| h < hash);
+        self.entries[start..]
+            .iter()
+            .take_while(|&&(h, _)| h == hash)
+            .map(|&(_, ino)| ino)
+            .find(|&ino| name_of(ino) == Some(name))
+    }
+
+    pub fn insert(&mut self, name: &[u8], ino: u64) -> FsResult<()> {
+        let hash = hash(name);
+        self.entries.try_reserve(1).map_err(|_| FsError::NoMem)?;
+        let pos = self.entries.partition_point(|&(h, _)| h < hash);
+        self.entries.insert(pos, (hash, ino));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &[u8], ino: u64) {
+        let hash = hash(name);
+        let start = self.entries.partition_point(|&(h, _)| h < hash);
+        if let Some(pos) = self.entries[start..]
+            .iter()
+            .take_while(|&&(h, _)| h == hash)
+            .position(|&(_, i)| i == ino)
+        {
+            self.entries.remove(start + pos);
+        }
+    }
+}
+
+/// The inodes in a directory, in the order they were added. Indexed once there are enough of
+/// them. Inodes have to be added in increasing order, which they are by filesystems that never
+/// reuse them.
+#[derive(Default)]
+pub struct DirEntries {
+    inodes: Vec<u64>,
+    index: Option<NameIndex>,
+}
+
+impl DirEntries {
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.inodes.iter().copied()
+    }
+
+    /// The inodes numbered `ino` or higher. Since removing an entry doesn't renumber the rest, a
+    /// directory can be read from the inode after the last one returned even if it changed.
+    pub fn iter_from(&self, ino: u64) -> impl Iterator<Item = u64> + '_ {
+        let start = self.inodes.partition_point(|&i| i < ino);
+        self.inodes[start..].iter().copied()
+    }
+
+    pub fn find<'a>(
+        &mut self,
+        name: &[u8],
+        name_of: impl Fn(u64) -> Option<&'a [u8]>,
+    ) -> Option<u64> {
+        if self.index.is_none() && self.inodes.len() > INDEX_THRESHOLD {
+            // without the memory for an index, every name is compared like in a small directory
+            self.index =
+                NameIndex::build(self.iter().filter_map(|ino| Some((name_of(ino)?, ino)))).ok();
+        }
+
+        match &self.index {
+            Some(index) => index.find(name, name_of),
+            None => self.iter().find(|&ino| name_of(ino) == Some(name)),
+        }
+    }
+
+    pub fn insert(&mut self, name: &[u8], ino: u64) -> FsResult<()> {
+        debug_assert!(self.inodes.last().is_none_or(|&last| last < ino));
+        self.inodes.try_reserve(1).map_err(|_| FsError::NoMem)?;
+        if self
+            .index
+            .as_mut()
+            .is_some_and(|index| index.insert(name, ino).is_err())
+        {
+            // rebuilt the next time the directory is searched
+            self.index = None;
+        }
+        self.inodes.push(ino);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &[u8], ino: u64) {
+        self.inodes.retain(|&i| i != ino);
+        if let Some(index) = &mut self.index {
+            index.remove(name, ino);
+        }
+    }
+}
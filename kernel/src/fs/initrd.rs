@@ -1,15 +1,23 @@
 use core::mem::{size_of, MaybeUninit};
 
 use alloc::{boxed::Box, vec::Vec};
-use shared::io::{DirEntry, OpenFlags, Stat};
+use servos::lock::SpinLocked;
+use shared::io::{DirEntry, Mode, OpenFlags, Stat};
 
-use super::{path::Path, FileSystem, FsError, FsResult, VNode};
+use super::{
+    index::{NameIndex, INDEX_THRESHOLD},
+    path::Path,
+    FileSystem, FsError, FsResult, OpenId, VNode,
+};
 
 pub const INITRD_MAGIC: u32 = 0xce3fdefe;
 
 #[allow(unused)]
-pub const INODE_FILE: u16 = 0;
-pub const INODE_DIR: u16 = 1;
+pub const INODE_FILE: u8 = 0;
+pub const INODE_DIR: u8 = 1;
+
+/// Set in `INode::mode` for files that can be spawned
+pub const MODE_EXEC: u8 = 1 << 0;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -24,7 +32,8 @@ pub struct InitRdHeader {
 pub struct INode {
     name: [u8; 32],
     nlen: u16,
-    typ: u16,
+    typ: u8,
+    mode: u8,
     /// For a file, this is the size of the file. For a directory, this is the number of entries.
     size: u32,
     addr: u64,
@@ -32,44 +41,85 @@ pub struct INode {
 
 impl INode {
     pub fn name_eq(&self, name: &[u8]) -> bool {
-        if self.nlen as usize > self.name.len() {
-            return false;
-        }
+        self.name() == Some(name)
+    }
 
-        &self.name[..self.nlen as usize] == name
+    fn name(&self) -> Option<&[u8]> {
+        self.name.get(..self.nlen as usize)
     }
 }
 
 pub struct InitRd {
     inodes: Box<[INode]>,
     data: Box<[u8]>,
+    /// Built for big directories the first time they're searched, indexed by inode
+    indexes: SpinLocked<Box<[Option<NameIndex>]>>,
 }
 
 impl InitRd {
     pub fn new(data: &[u8]) -> Option<Self> {
-        assert!(data.as_ptr().is_aligned_to(align_of::<InitRdHeader>()));
-
+        // the image can be anywhere in memory, so nothing is read from it in place
         let (header, data) = data.split_at_checked(size_of::<InitRdHeader>())?;
-        let header = unsafe { &*header.as_ptr().cast::<InitRdHeader>() };
+        let header = unsafe { header.as_ptr().cast::<InitRdHeader>().read_unaligned() };
         if header.magic != INITRD_MAGIC {
             return None;
         }
 
-        let (inodes, files) =
-            data.split_at_checked(header.ninodes as usize * size_of::<INode>())?;
-        let inodes = try_vec_from_slice(unsafe {
-            core::slice::from_raw_parts(inodes.as_ptr().cast::<INode>(), header.ninodes as usize)
-        })?;
+        let len = usize::try_from(header.ninodes).ok()?;
+        let (raw_inodes, files) = data.split_at_checked(len.checked_mul(size_of::<INode>())?)?;
+        let mut inodes: Vec<INode> = Vec::try_with_capacity(len).ok()?;
+        inodes.extend(
+            raw_inodes
+                .chunks_exact(size_of::<INode>())
+                .map(|inode| unsafe { inode.as_ptr().cast::<INode>().read_unaligned() }),
+        );
         if !inodes.first().is_some_and(|i| i.typ == INODE_DIR) {
             return None;
         }
 
+        let mut indexes = Vec::try_with_capacity(inodes.len()).ok()?;
+        indexes.resize_with(inodes.len(), || None);
         Some(Self {
             inodes: inodes.into(),
             data: try_vec_from_slice(files)?.into(),
+            indexes: SpinLocked::new(indexes.into()),
         })
     }
 
+    /// Find `name` in the directory `dir`
+    fn lookup(&self, dir: usize, name: &[u8]) -> FsResult<Option<usize>> {
+        let inode = &self.inodes[dir];
+        let mut indexes = self.indexes.lock();
+        if inode.size as usize > INDEX_THRESHOLD && indexes[dir].is_none() {
+            let mut entries = (0..inode.size as usize).map(|i| self.dir_entry(inode, i));
+            if entries.any(|entry| entry.is_none()) {
+                return Err(FsError::CorruptedFs);
+            }
+
+            // without the memory for an index, every name is compared like in a small directory
+            indexes[dir] = NameIndex::build(
+                (0..inode.size as usize)
+                    .filter_map(|i| self.dir_entry(inode, i))
+                    .filter_map(|(ino, inode)| Some((inode.name()?, ino as u64))),
+            )
+            .ok();
+        }
+
+        if let Some(index) = &indexes[dir] {
+            let name_of = |ino| self.inodes.get(ino as usize).and_then(INode::name);
+            return Ok(index.find(name, name_of).map(|ino| ino as usize));
+        }
+        drop(indexes);
+
+        for i in 0..inode.size as usize {
+            let (ino, entry) = self.dir_entry(inode, i).ok_or(FsError::CorruptedFs)?;
+            if entry.name_eq(name) {
+                return Ok(Some(ino));
+            }
+        }
+        Ok(None)
+    }
+
     fn dir_entry(&self, dir: &INode, pos: usize) -> Option<(usize, &INode)> {
         const U64SZ: usize = size_of::<u64>();
         if pos >= dir.size as usize {
@@ -91,32 +141,46 @@ impl InitRd {
             size: inode.size as usize,
             readonly: true,
             directory: inode.typ == INODE_DIR,
+            executable: inode.typ != INODE_DIR && inode.mode & MODE_EXEC != 0,
+            atime: 0,
+            mtime: 0,
+            allocated: inode.size as usize,
+            device: None,
         }
     }
 }
 
 impl FileSystem for InitRd {
-    fn open(&self, path: &Path, _flags: OpenFlags, root: Option<&VNode>) -> FsResult<VNode> {
+    fn open(
+        &self,
+        path: &Path,
+        flags: OpenFlags,
+        _mode: Mode,
+        root: Option<&VNode>,
+    ) -> FsResult<VNode> {
         let mut ino = root
             .filter(|_| !path.is_absolute())
             .map(|r| r.ino as usize)
             .unwrap_or(0);
-        'outer: for component in path.components() {
+        let mut components = path.components().peekable();
+        while let Some(component) = components.next() {
             if self.inodes[ino].typ != INODE_DIR {
                 return Err(FsError::PathNotFound);
             }
 
-            for i in 0..self.inodes[ino].size {
-                let (entry_no, inode) = self
-                    .dir_entry(&self.inodes[ino], i as usize)
-                    .ok_or(FsError::CorruptedFs)?;
-                if inode.name_eq(component) {
-                    ino = entry_no;
-                    continue 'outer;
+            ino = match self.lookup(ino, component)? {
+                Some(ino) => ino,
+                None if components.peek().is_none()
+                    && flags.intersects(OpenFlags::CreateDir | OpenFlags::CreateFile) =>
+                {
+                    return Err(FsError::ReadOnly)
                 }
-            }
+                None => return Err(FsError::PathNotFound),
+            };
+        }
 
-            return Err(FsError::PathNotFound);
+        if flags.intersects(OpenFlags::ReadWrite | OpenFlags::Truncate) {
+            return Err(FsError::ReadOnly);
         }
 
         Ok(VNode {
@@ -156,11 +220,11 @@ impl FileSystem for InitRd {
         Err(FsError::Unsupported)
     }
 
-    fn close(&self, _vn: &VNode) -> FsResult<()> {
+    fn close(&self, _vn: &VNode, _open: OpenId) -> FsResult<()> {
         Ok(())
     }
 
-    fn readdir(&self, vn: &VNode, pos: usize) -> FsResult<Option<DirEntry>> {
+    fn readdir(&self, vn: &VNode, pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
         let dir = self.vnode_to_inode(vn)?;
         if dir.typ != INODE_DIR {
             return Err(FsError::InvalidOp);
@@ -177,7 +241,8 @@ impl FileSystem for InitRd {
         };
         entry.name[..inode.name.len()].copy_from_slice(&inode.name);
 
-        Ok(Some(entry))
+        // the image never changes, so the index of an entry is as stable as a position gets
+        Ok(Some((entry, pos + 1)))
     }
 
     fn stat(&self, vn: &VNode) -> FsResult<Stat> {
@@ -1,13 +1,25 @@
-use core::mem::MaybeUninit;
+use core::{
+    mem::MaybeUninit,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use path::Path;
-use shared::io::{DirEntry, OpenFlags, Stat};
+use servos::riscv::{r_time, TIMEBASE_FREQ};
+use shared::io::{DirEntry, Mode, OpenFlags, Stat};
 
-use crate::vmm::{PageTable, Pte, VirtAddr, VirtToPhysErr};
+use crate::{
+    bootargs,
+    vmm::{PageTable, Pte, VirtAddr, VirtToPhysErr},
+};
 
 pub mod dev;
+pub mod index;
 pub mod initrd;
+pub mod overlay;
 pub mod path;
+#[cfg(feature = "procfs")]
+pub mod proc;
+pub mod tmpfs;
 pub mod vfs;
 
 pub type FsResult<T> = Result<T, FsError>;
@@ -23,6 +35,13 @@ pub enum FsError {
     InvalidPerms,
     BadVa,
     Eof,
+    WouldBlock,
+    NotConnected,
+    ConnReset,
+    /// The filesystem a descriptor refers to has been unmounted
+    Stale,
+    /// Something of the wrong kind is already at the path being created
+    AlreadyExists,
 }
 
 impl From<VirtToPhysErr> for FsError {
@@ -38,17 +57,37 @@ pub struct VNode {
     pub readonly: bool,
 }
 
+/// Tells apart every `Fd`, even ones for the same file, so whatever was changed through one can be
+/// undone once it's closed
+pub type OpenId = u64;
+
 pub trait FileSystem {
-    fn open(&self, path: &Path, flags: OpenFlags, cwd: Option<&VNode>) -> FsResult<VNode>;
+    /// `mode` is only used if `flags` ask for the file to be created. The VFS has already rejected
+    /// combinations of `flags` that make no sense, and checks the result is the kind of file they
+    /// asked for.
+    fn open(
+        &self,
+        path: &Path,
+        flags: OpenFlags,
+        mode: Mode,
+        cwd: Option<&VNode>,
+    ) -> FsResult<VNode>;
     fn read<'a>(
         &self,
         vn: &VNode,
         pos: u64,
         buf: &'a mut [MaybeUninit<u8>],
     ) -> FsResult<&'a mut [u8]>;
+    /// Writing past the end of a file extends it, and the gap reads as zeroes. Filesystems that
+    /// can should leave the gap unallocated.
     fn write(&self, vn: &VNode, pos: u64, buf: &[u8]) -> FsResult<usize>;
-    fn close(&self, vn: &VNode) -> FsResult<()>;
-    fn readdir(&self, vn: &VNode, pos: usize) -> FsResult<Option<DirEntry>>;
+    fn close(&self, vn: &VNode, open: OpenId) -> FsResult<()>;
+    /// Read the entry of directory `vn` at `pos`, returning it with the position of the next one.
+    /// Positions are cookies only the filesystem understands, except that 0 is the start and the
+    /// position after an entry is never 0 or `usize::MAX`. An entry that stays in the directory
+    /// while it's being read is returned exactly once. Entries added or removed in the meantime
+    /// may or may not be.
+    fn readdir(&self, vn: &VNode, pos: usize) -> FsResult<Option<(DirEntry, usize)>>;
     fn stat(&self, vn: &VNode) -> FsResult<Stat>;
 
     fn read_va(
@@ -79,9 +118,60 @@ pub trait FileSystem {
         })
     }
 
+    /// Handle a request made with `Sys::Ioctl` on `vn`. Only devices have any.
+    fn control(&self, _vn: &VNode, _open: OpenId, _cmd: usize, _arg: usize) -> FsResult<usize> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Whether reading `vn` would return anything right now, for `Sys::Poll`
+    fn readable(&self, _vn: &VNode) -> bool {
+        true
+    }
+
+    /// The socket `vn` refers to, if this is the socket filesystem
+    #[cfg(feature = "net")]
+    fn socket_id(&self, _vn: &VNode) -> Option<u32> {
+        None
+    }
+
+    /// Remove the file or empty directory at `path`
+    fn remove(&self, _path: &Path, _cwd: Option<&VNode>) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    /// Update the access and/or modification time of `vn`. Filesystems that don't store times
+    /// ignore this.
+    fn set_times(&self, _vn: &VNode, _atime: Option<u64>, _mtime: Option<u64>) -> FsResult<()> {
+        Ok(())
+    }
+
     // fn rename(&self, vn: &VNode, abspath: &Path, mvdir: bool) -> FsResult<()>;
 }
 
+/// What `now` counts up from. There's no real time clock, so it's boot unless `epoch=` says
+/// otherwise.
+static EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Read the `epoch=` boot argument, in seconds, so timestamps don't depend on the host's clock
+pub fn init_clock() {
+    if let Some(secs) = bootargs::get("epoch").and_then(|secs| secs.parse::<u64>().ok()) {
+        EPOCH.store(secs.saturating_mul(1_000_000), Ordering::Relaxed);
+    }
+}
+
+/// The current time, in the unit of `Stat::atime` and `Stat::mtime`
+pub fn now() -> u64 {
+    EPOCH.load(Ordering::Relaxed) + (r_time() / (TIMEBASE_FREQ / 1_000_000)) as u64
+}
+
+/// `/proc/uptime`: seconds since boot, then the time at boot in the unit of `now`
+#[cfg(feature = "procfs")]
+pub fn show_uptime(w: &mut dyn core::fmt::Write) -> core::fmt::Result {
+    let epoch = EPOCH.load(Ordering::Relaxed);
+    let up = now() - epoch;
+    writeln!(w, "{}.{:02} {epoch}", up / 1_000_000, up / 10_000 % 100)
+}
+
 fn rw_va(
     mut pos: u64,
     pt: &PageTable,
@@ -0,0 +1,487 @@
+use core::mem::MaybeUninit;
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use servos::lock::SpinLocked;
+use shared::io::{DirEntry, Mode, OpenFlags, Stat};
+
+use super::{
+    index::DirEntries,
+    path::Path,
+    tmpfs::{Data, Kind, TmpFs},
+    FileSystem, FsError, FsResult, OpenId, VNode,
+};
+
+/// Set in positions in the lower layer. Those hold the upper layer's `next_ino` from when its
+/// entries ran out in bits 32-62, and the lower layer's own position in bits 0-31.
+const LOWER: usize = 1 << 63;
+
+struct Node {
+    parent: u64,
+    name: Box<[u8]>,
+    directory: bool,
+    /// Once set, this is used instead of `lower`
+    upper: Option<u64>,
+    lower: Option<VNode>,
+    /// The children that have been looked up so far
+    entries: DirEntries,
+}
+
+/// A writable view of a read-only filesystem. Changes are kept in a `TmpFs` layered on top:
+/// files are copied up the first time they're opened for writing, and removing a file from the
+/// lower layer leaves a whiteout in its place.
+///
+/// Inode 0 is the root directory, inode `n` is `nodes[n]`. A node is added for each path the first
+/// time it's looked up, and removed inodes are never reused.
+pub struct OverlayFs {
+    lower: Arc<dyn FileSystem>,
+    upper: TmpFs,
+    nodes: SpinLocked<Vec<Option<Node>>>,
+}
+
+impl OverlayFs {
+    pub fn new(lower: Arc<dyn FileSystem>) -> FsResult<Self> {
+        let mut nodes = Vec::new();
+        nodes.try_reserve(1).map_err(|_| FsError::NoMem)?;
+        nodes.push(Some(Node {
+            parent: 0,
+            name: Box::default(),
+            directory: true,
+            upper: Some(TmpFs::ROOT),
+            lower: Some(lower.open(Path::new(""), OpenFlags::empty(), Mode::empty(), None)?),
+            entries: DirEntries::default(),
+        }));
+
+        Ok(Self {
+            lower,
+            upper: TmpFs::new()?,
+            nodes: SpinLocked::new(nodes),
+        })
+    }
+
+    /// Find `name` in the directory `dir`, looking it up in both layers if it isn't known yet
+    fn child(&self, nodes: &mut Vec<Option<Node>>, dir: u64, name: &[u8]) -> FsResult<Option<u64>> {
+        let parent = node(nodes, dir)?;
+        match name {
+            b"." => return Ok(Some(dir)),
+            b".." => return Ok(Some(parent.parent)),
+            _ if !parent.directory => return Err(FsError::PathNotFound),
+            _ => {}
+        }
+
+        // taken out while searching, since building the index needs the names of the other nodes
+        let mut entries = core::mem::take(&mut node_mut(nodes, dir)?.entries);
+        let found = entries.find(name, |ino| node(nodes, ino).ok().map(|n| &*n.name));
+        node_mut(nodes, dir)?.entries = entries;
+        if found.is_some() {
+            return Ok(found);
+        }
+
+        let parent = node(nodes, dir)?;
+        let upper = parent.upper.and_then(|dir| self.upper.lookup(dir, name));
+        if upper.is_some_and(|ino| self.upper.is_whiteout(ino)) {
+            return Ok(None);
+        }
+
+        let upper_dir = upper.map(|ino| self.upper.is_dir(ino));
+        let lower = match (&parent.lower, upper_dir) {
+            // a file in the upper layer hides anything below it, and so does an opaque directory
+            (_, Some(false)) => None,
+            (_, Some(true)) if upper.is_some_and(|ino| self.upper.is_opaque(ino)) => None,
+            (Some(_), _) if parent.upper.is_some_and(|ino| self.upper.is_opaque(ino)) => None,
+            (Some(dir), _) => match self.lower.open(
+                Path::new(name),
+                OpenFlags::empty(),
+                Mode::empty(),
+                Some(dir),
+            ) {
+                // a directory can't be merged with a file
+                Ok(vn) if upper_dir.is_some_and(|dir| dir != vn.directory) => None,
+                Ok(vn) => Some(vn),
+                Err(FsError::PathNotFound) => None,
+                Err(err) => return Err(err),
+            },
+            (None, _) => None,
+        };
+
+        let directory = match (upper_dir, &lower) {
+            (Some(dir), _) => dir,
+            (None, Some(vn)) => vn.directory,
+            (None, None) => return Ok(None),
+        };
+        insert(
+            nodes,
+            Node {
+                parent: dir,
+                name: try_box(name)?,
+                directory,
+                upper,
+                lower,
+                entries: DirEntries::default(),
+            },
+        )
+        .map(Some)
+    }
+
+    /// Find the inode at `path`, creating the last component with `mode` if `flags` ask for it.
+    /// Returns whether it was created.
+    fn walk(
+        &self,
+        nodes: &mut Vec<Option<Node>>,
+        path: &Path,
+        flags: &OpenFlags,
+        mode: Mode,
+        cwd: Option<&VNode>,
+    ) -> FsResult<(u64, bool)> {
+        let mut ino = cwd.filter(|_| !path.is_absolute()).map_or(0, |vn| vn.ino);
+        let mut components = path.components().peekable();
+        while let Some(name) = components.next() {
+            ino = match self.child(nodes, ino, name)? {
+                Some(child) => child,
+                None if components.peek().is_some() => return Err(FsError::PathNotFound),
+                None if flags.contains(OpenFlags::CreateDir) => {
+                    return self
+                        .create(nodes, ino, name, true, mode)
+                        .map(|ino| (ino, true));
+                }
+                None if flags.contains(OpenFlags::CreateFile) => {
+                    return self
+                        .create(nodes, ino, name, false, mode)
+                        .map(|ino| (ino, true));
+                }
+                None => return Err(FsError::PathNotFound),
+            };
+        }
+
+        Ok((ino, false))
+    }
+
+    /// Anything only in the lower layer can be written to through a copy
+    fn writable(&self, nodes: &[Option<Node>], ino: u64) -> FsResult<bool> {
+        Ok(node(nodes, ino)?
+            .upper
+            .map_or(true, |upper| self.upper.mode(upper).contains(Mode::Write)))
+    }
+
+    fn create(
+        &self,
+        nodes: &mut Vec<Option<Node>>,
+        dir: u64,
+        name: &[u8],
+        directory: bool,
+        mode: Mode,
+    ) -> FsResult<u64> {
+        if !self.writable(nodes, dir)? {
+            return Err(FsError::ReadOnly);
+        }
+
+        let parent = self.copy_up(nodes, dir)?;
+        // a new directory in place of a removed one mustn't show what used to be inside it
+        let whiteout = self.upper.lookup(parent, name);
+        let kind = if directory {
+            Kind::Dir {
+                opaque: whiteout.is_some(),
+            }
+        } else {
+            Kind::File(Data::default())
+        };
+
+        let name = try_box(name)?;
+        if let Some(whiteout) = whiteout {
+            self.upper.unlink(whiteout);
+        }
+        let upper = self.upper.create(parent, &name, kind, mode)?;
+        insert(
+            nodes,
+            Node {
+                parent: dir,
+                name,
+                directory,
+                upper: Some(upper),
+                lower: None,
+                entries: DirEntries::default(),
+            },
+        )
+    }
+
+    /// Copy `ino` and its parent directories into the upper layer if they aren't there yet.
+    /// Directories are created empty, since their contents can still be found in the lower layer.
+    fn copy_up(&self, nodes: &mut Vec<Option<Node>>, ino: u64) -> FsResult<u64> {
+        let node = node(nodes, ino)?;
+        if let Some(upper) = node.upper {
+            return Ok(upper);
+        }
+
+        let (kind, mode) = match &node.lower {
+            Some(vn) if !vn.directory && self.lower.stat(vn)?.executable => {
+                (Kind::File(self.read_lower(vn)?), Mode::Write | Mode::Exec)
+            }
+            Some(vn) if !vn.directory => (Kind::File(self.read_lower(vn)?), Mode::Write),
+            _ => (Kind::Dir { opaque: false }, Mode::Write),
+        };
+        let (parent, name) = (node.parent, try_box(&node.name)?);
+        let parent = self.copy_up(nodes, parent)?;
+        let upper = self.upper.create(parent, &name, kind, mode)?;
+        if let Some(Some(node)) = nodes.get_mut(ino as usize) {
+            node.upper = Some(upper);
+        }
+        Ok(upper)
+    }
+
+    fn read_lower(&self, vn: &VNode) -> FsResult<Data> {
+        let size = self.lower.stat(vn)?.size;
+        let mut data = Vec::new();
+        data.try_reserve_exact(size).map_err(|_| FsError::NoMem)?;
+        while data.len() < size {
+            let pos = data.len() as u64;
+            match self.lower.read(vn, pos, data.spare_capacity_mut()) {
+                Ok(read) => {
+                    let read = read.len();
+                    unsafe { data.set_len(data.len() + read) };
+                }
+                Err(FsError::Eof) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Data::from_bytes(&data)
+    }
+
+    fn upper_vnode(ino: u64, directory: bool) -> VNode {
+        VNode {
+            ino,
+            directory,
+            readonly: false,
+        }
+    }
+}
+
+fn node(nodes: &[Option<Node>], ino: u64) -> FsResult<&Node> {
+    nodes
+        .get(ino as usize)
+        .and_then(Option::as_ref)
+        .ok_or(FsError::PathNotFound)
+}
+
+fn node_mut(nodes: &mut [Option<Node>], ino: u64) -> FsResult<&mut Node> {
+    nodes
+        .get_mut(ino as usize)
+        .and_then(Option::as_mut)
+        .ok_or(FsError::PathNotFound)
+}
+
+fn insert(nodes: &mut Vec<Option<Node>>, node: Node) -> FsResult<u64> {
+    nodes.try_reserve(1).map_err(|_| FsError::NoMem)?;
+    let ino = nodes.len() as u64;
+    node_mut(nodes, node.parent)?
+        .entries
+        .insert(&node.name, ino)?;
+    nodes.push(Some(node));
+    Ok(ino)
+}
+
+fn lower_pos(watermark: u64, pos: usize) -> FsResult<usize> {
+    if watermark >= 1 << 31 || pos >= u32::MAX as usize {
+        return Err(FsError::Unsupported);
+    }
+    Ok(LOWER | ((watermark as usize) << 32) | pos)
+}
+
+fn try_box(name: &[u8]) -> FsResult<Box<[u8]>> {
+    let mut owned = Vec::new();
+    owned
+        .try_reserve_exact(name.len())
+        .map_err(|_| FsError::NoMem)?;
+    owned.extend_from_slice(name);
+    Ok(owned.into())
+}
+
+impl FileSystem for OverlayFs {
+    fn open(
+        &self,
+        path: &Path,
+        flags: OpenFlags,
+        mode: Mode,
+        cwd: Option<&VNode>,
+    ) -> FsResult<VNode> {
+        let mut nodes = self.nodes.lock();
+        let (ino, created) = self.walk(&mut nodes, path, &flags, mode, cwd)?;
+        // whoever creates a file can write to it the first time, whatever its mode
+        if !created
+            && flags.intersects(OpenFlags::ReadWrite | OpenFlags::Truncate)
+            && !self.writable(&nodes, ino)?
+        {
+            return Err(FsError::ReadOnly);
+        }
+
+        let directory = node(&nodes, ino)?.directory;
+        if !directory && flags.intersects(OpenFlags::ReadWrite | OpenFlags::Truncate) {
+            let upper = self.copy_up(&mut nodes, ino)?;
+            if flags.contains(OpenFlags::Truncate) {
+                self.upper.truncate(upper)?;
+            }
+        }
+
+        Ok(VNode {
+            ino,
+            directory,
+            readonly: !flags.contains(OpenFlags::ReadWrite),
+        })
+    }
+
+    fn read<'a>(
+        &self,
+        vn: &VNode,
+        pos: u64,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> FsResult<&'a mut [u8]> {
+        let nodes = self.nodes.lock();
+        let node = node(&nodes, vn.ino)?;
+        match (node.upper, &node.lower) {
+            (Some(upper), _) => {
+                self.upper
+                    .read(&Self::upper_vnode(upper, node.directory), pos, buf)
+            }
+            (None, Some(lower)) => self.lower.read(lower, pos, buf),
+            (None, None) => Err(FsError::PathNotFound),
+        }
+    }
+
+    fn write(&self, vn: &VNode, pos: u64, buf: &[u8]) -> FsResult<usize> {
+        let mut nodes = self.nodes.lock();
+        let upper = self.copy_up(&mut nodes, vn.ino)?;
+        self.upper
+            .write(&Self::upper_vnode(upper, vn.directory), pos, buf)
+    }
+
+    fn close(&self, _vn: &VNode, _open: OpenId) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn readdir(&self, vn: &VNode, pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
+        let nodes = self.nodes.lock();
+        let dir = node(&nodes, vn.ino)?;
+        if !dir.directory {
+            return Err(FsError::InvalidOp);
+        }
+
+        let (watermark, mut pos) = match pos & LOWER {
+            0 => {
+                if let Some(upper) = dir.upper {
+                    let upper = Self::upper_vnode(upper, true);
+                    if let Some(res) = self.upper.readdir(&upper, pos)? {
+                        return Ok(Some(res));
+                    }
+                }
+                // whatever the upper layer gets from now on wasn't listed with it
+                (self.upper.next_ino(), 0)
+            }
+            _ => (((pos & !LOWER) >> 32) as u64, pos & u32::MAX as usize),
+        };
+
+        let Some(lower) = dir.lower.as_ref() else {
+            return Ok(None);
+        };
+        if dir.upper.is_some_and(|ino| self.upper.is_opaque(ino)) {
+            return Ok(None);
+        }
+
+        // skip anything that was listed from the upper layer, or removed there
+        while let Some((mut entry, next)) = self.lower.readdir(lower, pos)? {
+            pos = next;
+            let name = &entry.name[..entry.name_len];
+            if dir
+                .upper
+                .and_then(|dir| self.upper.lookup(dir, name))
+                .is_some_and(|ino| ino < watermark || self.upper.is_whiteout(ino))
+            {
+                continue;
+            }
+
+            // anything in the lower layer can be written to through a copy
+            entry.stat.readonly = false;
+            return Ok(Some((entry, lower_pos(watermark, next)?)));
+        }
+
+        Ok(None)
+    }
+
+    fn stat(&self, vn: &VNode) -> FsResult<Stat> {
+        let nodes = self.nodes.lock();
+        let node = node(&nodes, vn.ino)?;
+        match (node.upper, &node.lower) {
+            (Some(upper), _) => self.upper.stat(&Self::upper_vnode(upper, node.directory)),
+            (None, Some(lower)) => self.lower.stat(lower).map(|stat| Stat {
+                readonly: false,
+                ..stat
+            }),
+            (None, None) => Err(FsError::PathNotFound),
+        }
+    }
+
+    fn set_times(&self, vn: &VNode, atime: Option<u64>, mtime: Option<u64>) -> FsResult<()> {
+        let nodes = self.nodes.lock();
+        let node = node(&nodes, vn.ino)?;
+        match node.upper {
+            Some(upper) => {
+                self.upper
+                    .set_times(&Self::upper_vnode(upper, node.directory), atime, mtime)
+            }
+            // writes copy the file up first, and copying it up just for an access time is the
+            // kind of write amplification relatime is meant to avoid
+            None => Ok(()),
+        }
+    }
+
+    fn remove(&self, path: &Path, cwd: Option<&VNode>) -> FsResult<()> {
+        let mut nodes = self.nodes.lock();
+        let (ino, _) = self.walk(&mut nodes, path, &OpenFlags::empty(), Mode::empty(), cwd)?;
+        if ino == 0 {
+            return Err(FsError::InvalidOp);
+        } else if !self.writable(&nodes, node(&nodes, ino)?.parent)? {
+            return Err(FsError::ReadOnly);
+        }
+
+        if node(&nodes, ino)?.directory {
+            drop(nodes);
+            let vn = VNode {
+                ino,
+                directory: true,
+                readonly: true,
+            };
+            let mut pos = 0;
+            while let Some((entry, next)) = self.readdir(&vn, pos)? {
+                if !matches!(&entry.name[..entry.name_len], b"." | b"..") {
+                    return Err(FsError::InvalidOp);
+                }
+                pos = next;
+            }
+            nodes = self.nodes.lock();
+        }
+
+        let node = node(&nodes, ino)?;
+        let (parent, upper, lower) = (node.parent, node.upper, node.lower.is_some());
+        let name = try_box(&node.name)?;
+        let parent = if lower {
+            Some(self.copy_up(&mut nodes, parent)?)
+        } else {
+            None
+        };
+
+        if let Some(upper) = upper {
+            // an upper directory may still hold whiteouts for the lower one
+            self.upper.unlink(upper);
+        }
+        if let Some(node) = nodes[ino as usize].take() {
+            if let Ok(parent) = node_mut(&mut nodes, node.parent) {
+                parent.entries.remove(&node.name, ino);
+            }
+        }
+        if let Some(parent) = parent {
+            self.upper
+                .create(parent, &name, Kind::Whiteout, Mode::empty())?;
+        }
+        Ok(())
+    }
+}
+
+unsafe impl Sync for OverlayFs {}
+unsafe impl Send for OverlayFs {}
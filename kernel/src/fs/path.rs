@@ -44,6 +44,34 @@ impl Path {
     pub fn is_empty(&self) -> bool {
         self.components().next().is_none()
     }
+
+    /// Make `self` absolute by resolving it against the absolute path `cwd`, and remove any `.` and
+    /// `..` components. This is purely textual, so `..` always goes to the parent in the path even
+    /// across mount points, and `..` at the root stays there.
+    pub fn resolve(&self, cwd: &Path) -> Result<OwnedPath, TryReserveError> {
+        let mut parts: Vec<&[u8]> = Vec::new();
+        let cwd = (!self.is_absolute()).then(|| cwd.components());
+        for component in cwd.into_iter().flatten().chain(self.components()) {
+            match component {
+                b"." => {}
+                b".." => _ = parts.pop(),
+                component => {
+                    parts.try_reserve(1)?;
+                    parts.push(component);
+                }
+            }
+        }
+
+        let mut buf = Vec::try_with_capacity(parts.iter().map(|p| p.len() + 1).sum::<usize>() + 1)?;
+        if parts.is_empty() {
+            buf.push(b'/');
+        }
+        for part in parts {
+            buf.push(b'/');
+            buf.extend_from_slice(part);
+        }
+        Ok(OwnedPath(buf.into()))
+    }
 }
 
 impl PartialEq for Path {
@@ -0,0 +1,254 @@
+use core::{
+    fmt::{self, Write},
+    mem::MaybeUninit,
+};
+
+use alloc::vec::Vec;
+use shared::io::{DirEntry, Mode, OpenFlags, Stat};
+
+use crate::tunable::Tunable;
+
+use super::{path::Path, vfs::MountError, FileSystem, FsError, FsResult, OpenId, VNode};
+
+/// Writes the contents of a file. Called on every read, so readers always see the current state.
+pub type Show = fn(&mut dyn Write) -> fmt::Result;
+/// Parses and applies a value written to a file, without its trailing newline
+pub type Store = fn(&[u8]) -> FsResult<()>;
+
+enum Node {
+    Dir,
+    File(Show, Option<Store>),
+    Tunable(&'static Tunable),
+}
+
+struct Entry {
+    name: &'static [u8],
+    parent: u64,
+    node: Node,
+}
+
+/// A filesystem of generated files describing kernel state, a few of which can be written to change
+/// it. Inode 0 is the root
+/// directory, inode `n` is `entries[n - 1]`.
+pub struct ProcFs {
+    entries: Vec<Entry>,
+}
+
+impl ProcFs {
+    pub const ROOT: u64 = 0;
+
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add_dir(&mut self, parent: u64, name: &'static [u8]) -> Result<u64, MountError> {
+        self.add(parent, name, Node::Dir)
+    }
+
+    pub fn add_file(
+        &mut self,
+        parent: u64,
+        name: &'static [u8],
+        show: Show,
+    ) -> Result<u64, MountError> {
+        self.add(parent, name, Node::File(show, None))
+    }
+
+    /// Add a file that `store` is called with whatever is written to. A value has to be written
+    /// from the start of the file in one call.
+    pub fn add_writable(
+        &mut self,
+        parent: u64,
+        name: &'static [u8],
+        show: Show,
+        store: Store,
+    ) -> Result<u64, MountError> {
+        self.add(parent, name, Node::File(show, Some(store)))
+    }
+
+    pub fn add_tunable(
+        &mut self,
+        parent: u64,
+        name: &'static [u8],
+        tunable: &'static Tunable,
+    ) -> Result<u64, MountError> {
+        self.add(parent, name, Node::Tunable(tunable))
+    }
+
+    fn add(&mut self, parent: u64, name: &'static [u8], node: Node) -> Result<u64, MountError> {
+        assert!(self.is_dir(parent));
+        if self.child(parent, name).is_some() {
+            return Err(MountError::AlreadyMounted);
+        }
+
+        if self.entries.try_reserve(1).is_err() {
+            return Err(MountError::NoMem);
+        }
+
+        self.entries.push(Entry { name, parent, node });
+        Ok(self.entries.len() as u64)
+    }
+
+    fn entry(&self, ino: u64) -> Option<&Entry> {
+        self.entries.get((ino as usize).checked_sub(1)?)
+    }
+
+    fn writable(&self, ino: u64) -> bool {
+        self.entry(ino)
+            .is_some_and(|e| matches!(e.node, Node::File(_, Some(_)) | Node::Tunable(_)))
+    }
+
+    fn is_dir(&self, ino: u64) -> bool {
+        ino == Self::ROOT || self.entry(ino).is_some_and(|e| matches!(e.node, Node::Dir))
+    }
+
+    fn children(&self, dir: u64) -> impl Iterator<Item = (u64, &Entry)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(move |(_, e)| e.parent == dir)
+            .map(|(i, e)| (i as u64 + 1, e))
+    }
+
+    fn child(&self, dir: u64, name: &[u8]) -> Option<u64> {
+        self.children(dir)
+            .find(|(_, e)| e.name == name)
+            .map(|(ino, _)| ino)
+    }
+
+    fn stat_ino(&self, ino: u64) -> Stat {
+        Stat {
+            directory: self.is_dir(ino),
+            // the contents are generated on read, so the size isn't known ahead of time
+            size: 0,
+            readonly: !self.writable(ino),
+            executable: false,
+            atime: 0,
+            mtime: 0,
+            allocated: 0,
+            device: None,
+        }
+    }
+}
+
+/// Collects the output of a `Show` function without panicking on allocation failure
+struct Buffer(Vec<u8>);
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.try_reserve(s.len()).map_err(|_| fmt::Error)?;
+        self.0.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn open(
+        &self,
+        path: &Path,
+        flags: OpenFlags,
+        _mode: Mode,
+        cwd: Option<&VNode>,
+    ) -> FsResult<VNode> {
+        let mut ino = cwd
+            .filter(|_| !path.is_absolute())
+            .map(|vn| vn.ino)
+            .unwrap_or(Self::ROOT);
+        let mut components = path.components().peekable();
+        while let Some(component) = components.next() {
+            if !self.is_dir(ino) {
+                return Err(FsError::PathNotFound);
+            }
+            ino = match self.child(ino, component) {
+                Some(ino) => ino,
+                None if components.peek().is_none()
+                    && flags.intersects(OpenFlags::CreateDir | OpenFlags::CreateFile) =>
+                {
+                    return Err(FsError::ReadOnly)
+                }
+                None => return Err(FsError::PathNotFound),
+            };
+        }
+
+        // writing replaces a tunable's value, so there's nothing to truncate
+        if flags.intersects(OpenFlags::ReadWrite | OpenFlags::Truncate) && !self.writable(ino) {
+            return Err(FsError::ReadOnly);
+        }
+        Ok(VNode {
+            ino,
+            directory: self.is_dir(ino),
+            readonly: !flags.contains(OpenFlags::ReadWrite),
+        })
+    }
+
+    fn read<'a>(
+        &self,
+        vn: &VNode,
+        pos: u64,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> FsResult<&'a mut [u8]> {
+        let mut out = Buffer(Vec::new());
+        match self.entry(vn.ino).map(|e| &e.node) {
+            Some(Node::File(show, _)) => show(&mut out),
+            Some(Node::Tunable(tunable)) => tunable.show(&mut out),
+            _ => return Err(FsError::InvalidOp),
+        }
+        .map_err(|_| FsError::NoMem)?;
+
+        let Some(data) = out.0.get(pos as usize..).filter(|data| !data.is_empty()) else {
+            return Err(FsError::Eof);
+        };
+
+        let len = buf.len().min(data.len());
+        unsafe {
+            core::ptr::copy_nonoverlapping(data.as_ptr(), buf.as_mut_ptr().cast(), len);
+        }
+        Ok(unsafe { MaybeUninit::slice_assume_init_mut(&mut buf[..len]) })
+    }
+
+    fn write(&self, vn: &VNode, pos: u64, buf: &[u8]) -> FsResult<usize> {
+        // TODO: permission check, once there are users to check
+        if !self.writable(vn.ino) {
+            return Err(FsError::ReadOnly);
+        } else if pos != 0 {
+            return Err(FsError::InvalidOp);
+        }
+
+        let value = buf.strip_suffix(b"\n").unwrap_or(buf);
+        match self.entry(vn.ino).map(|e| &e.node) {
+            Some(Node::File(_, Some(store))) => store(value)?,
+            Some(Node::Tunable(tunable)) => tunable.store(value).map_err(|_| FsError::InvalidOp)?,
+            _ => unreachable!(),
+        }
+        Ok(buf.len())
+    }
+
+    fn close(&self, _vn: &VNode, _open: OpenId) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn readdir(&self, vn: &VNode, pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
+        if !vn.directory {
+            return Err(FsError::InvalidOp);
+        }
+
+        // the position is the next inode to look at, entries are only ever added with new ones
+        let Some((ino, entry)) = self.children(vn.ino).find(|&(ino, _)| ino >= pos as u64) else {
+            return Ok(None);
+        };
+
+        let mut dir = DirEntry {
+            name: [0; 256],
+            name_len: entry.name.len(),
+            stat: self.stat_ino(ino),
+        };
+        dir.name[..entry.name.len()].copy_from_slice(entry.name);
+        Ok(Some((dir, ino as usize + 1)))
+    }
+
+    fn stat(&self, vn: &VNode) -> FsResult<Stat> {
+        Ok(self.stat_ino(vn.ino))
+    }
+}
@@ -0,0 +1,476 @@
+use core::mem::MaybeUninit;
+
+use alloc::{boxed::Box, vec::Vec};
+use servos::lock::SpinLocked;
+use shared::io::{DirEntry, Mode, OpenFlags, Stat};
+
+use crate::vmm;
+
+use super::{index::DirEntries, now, path::Path, FileSystem, FsError, FsResult, OpenId, VNode};
+
+/// The longest name that fits in a `DirEntry`
+const MAX_NAME: usize = 0x100;
+/// File contents are allocated this many bytes at a time
+const BLOCK_SIZE: usize = 0x1000;
+
+/// The contents of a file. Blocks that have never been written to are holes, which take up no
+/// memory and read as zeroes.
+#[derive(Default)]
+pub struct Data {
+    len: usize,
+    blocks: Vec<Option<Box<[u8; BLOCK_SIZE]>>>,
+}
+
+impl Data {
+    /// Copy `bytes`, leaving blocks that are all zeroes as holes
+    pub fn from_bytes(bytes: &[u8]) -> FsResult<Self> {
+        let mut data = Self::default();
+        for (i, block) in bytes.chunks(BLOCK_SIZE).enumerate() {
+            if block.iter().any(|&b| b != 0) {
+                data.write(i * BLOCK_SIZE, block)?;
+            }
+        }
+        data.len = bytes.len();
+        Ok(data)
+    }
+
+    fn allocated(&self) -> usize {
+        self.blocks.iter().flatten().count() * BLOCK_SIZE
+    }
+
+    /// Returns how much was read, which is only zero at the end of the file
+    fn read(&self, pos: usize, buf: &mut [MaybeUninit<u8>]) -> usize {
+        let len = buf.len().min(self.len.saturating_sub(pos));
+        let mut done = 0;
+        while done < len {
+            let (block, offset) = ((pos + done) / BLOCK_SIZE, (pos + done) % BLOCK_SIZE);
+            let count = (BLOCK_SIZE - offset).min(len - done);
+            let dst = &mut buf[done..][..count];
+            match self.blocks.get(block) {
+                Some(Some(block)) => unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        block[offset..].as_ptr(),
+                        dst.as_mut_ptr().cast(),
+                        count,
+                    );
+                },
+                _ => dst.fill(MaybeUninit::new(0)),
+            }
+            done += count;
+        }
+        len
+    }
+
+    fn write(&mut self, pos: usize, buf: &[u8]) -> FsResult<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let end = pos.checked_add(buf.len()).ok_or(FsError::InvalidOp)?;
+        let blocks = end.div_ceil(BLOCK_SIZE);
+        if blocks > self.blocks.len() {
+            self.blocks
+                .try_reserve(blocks - self.blocks.len())
+                .map_err(|_| FsError::NoMem)?;
+            // anything skipped over stays a hole
+            self.blocks.resize_with(blocks, || None);
+        }
+
+        let mut done = 0;
+        while done < buf.len() {
+            let (block, offset) = ((pos + done) / BLOCK_SIZE, (pos + done) % BLOCK_SIZE);
+            let count = (BLOCK_SIZE - offset).min(buf.len() - done);
+            let block = match &mut self.blocks[block] {
+                Some(block) => block,
+                slot => {
+                    let mut block =
+                        Box::<[u8; BLOCK_SIZE]>::try_new_uninit().map_err(|_| FsError::NoMem)?;
+                    unsafe {
+                        vmm::zero(block.as_mut_ptr().cast(), BLOCK_SIZE);
+                        slot.insert(block.assume_init())
+                    }
+                }
+            };
+            block[offset..][..count].copy_from_slice(&buf[done..][..count]);
+            done += count;
+        }
+
+        self.len = self.len.max(end);
+        Ok(())
+    }
+}
+
+pub enum Kind {
+    Dir {
+        /// As an overlay's upper layer, hides the contents of the lower directory
+        opaque: bool,
+    },
+    File(Data),
+    /// As an overlay's upper layer, hides the lower file or directory of the same name. Never
+    /// listed by `readdir`.
+    Whiteout,
+}
+
+struct Node {
+    name: Box<[u8]>,
+    parent: u64,
+    kind: Kind,
+    mode: Mode,
+    atime: u64,
+    mtime: u64,
+    /// Always empty unless this is a directory
+    entries: DirEntries,
+}
+
+/// A writable filesystem kept in memory. Inode `n` is `nodes[n]`, and inode 0 is the root
+/// directory. Removed inodes are never reused, so an open file can't end up referring to
+/// another one.
+pub struct TmpFs {
+    nodes: SpinLocked<Vec<Option<Node>>>,
+}
+
+impl TmpFs {
+    pub const ROOT: u64 = 0;
+
+    pub fn new() -> FsResult<Self> {
+        let mut nodes = Vec::new();
+        nodes.try_reserve(1).map_err(|_| FsError::NoMem)?;
+        nodes.push(Some(Node {
+            name: Box::default(),
+            parent: Self::ROOT,
+            kind: Kind::Dir { opaque: false },
+            mode: Mode::Write,
+            atime: 0,
+            mtime: 0,
+            entries: DirEntries::default(),
+        }));
+        Ok(Self {
+            nodes: SpinLocked::new(nodes),
+        })
+    }
+
+    /// Find `name` in `dir`, including whiteouts
+    pub fn lookup(&self, dir: u64, name: &[u8]) -> Option<u64> {
+        child(&mut self.nodes.lock(), dir, name)
+    }
+
+    pub fn is_dir(&self, ino: u64) -> bool {
+        is_dir(&self.nodes.lock(), ino)
+    }
+
+    pub fn is_whiteout(&self, ino: u64) -> bool {
+        node(&self.nodes.lock(), ino).is_some_and(|n| matches!(n.kind, Kind::Whiteout))
+    }
+
+    /// The inode the next file created will get. Inodes below it are never handed out again.
+    pub fn next_ino(&self) -> u64 {
+        self.nodes.lock().len() as u64
+    }
+
+    pub fn is_opaque(&self, ino: u64) -> bool {
+        node(&self.nodes.lock(), ino).is_some_and(|n| matches!(n.kind, Kind::Dir { opaque: true }))
+    }
+
+    pub fn mode(&self, ino: u64) -> Mode {
+        mode(&self.nodes.lock(), ino)
+    }
+
+    /// Create `name` in `dir`, whether or not `dir` is writable
+    pub fn create(&self, dir: u64, name: &[u8], kind: Kind, mode: Mode) -> FsResult<u64> {
+        let mut nodes = self.nodes.lock();
+        create(&mut nodes, dir, name, kind, mode)
+    }
+
+    /// Remove `ino` along with anything inside it
+    pub fn unlink(&self, ino: u64) {
+        unlink(&mut self.nodes.lock(), ino);
+    }
+
+    pub fn truncate(&self, ino: u64) -> FsResult<()> {
+        match node_mut(&mut self.nodes.lock(), ino) {
+            Some(Node {
+                kind: Kind::File(data),
+                ..
+            }) => {
+                *data = Data::default();
+                Ok(())
+            }
+            _ => Err(FsError::InvalidOp),
+        }
+    }
+}
+
+fn node(nodes: &[Option<Node>], ino: u64) -> Option<&Node> {
+    nodes.get(ino as usize)?.as_ref()
+}
+
+fn node_mut(nodes: &mut [Option<Node>], ino: u64) -> Option<&mut Node> {
+    nodes.get_mut(ino as usize)?.as_mut()
+}
+
+fn mode(nodes: &[Option<Node>], ino: u64) -> Mode {
+    node(nodes, ino).map_or(Mode::empty(), |n| n.mode)
+}
+
+fn is_dir(nodes: &[Option<Node>], ino: u64) -> bool {
+    node(nodes, ino).is_some_and(|n| matches!(n.kind, Kind::Dir { .. }))
+}
+
+fn children(nodes: &[Option<Node>], dir: u64) -> impl Iterator<Item = (u64, &Node)> {
+    children_from(nodes, dir, 0)
+}
+
+fn children_from(nodes: &[Option<Node>], dir: u64, ino: u64) -> impl Iterator<Item = (u64, &Node)> {
+    node(nodes, dir)
+        .into_iter()
+        .flat_map(move |dir| dir.entries.iter_from(ino))
+        .filter_map(|ino| Some((ino, node(nodes, ino)?)))
+}
+
+fn child(nodes: &mut [Option<Node>], dir: u64, name: &[u8]) -> Option<u64> {
+    // taken out while searching, since building the index needs the names of the other nodes
+    let mut entries = core::mem::take(&mut node_mut(nodes, dir)?.entries);
+    let found = entries.find(name, |ino| node(nodes, ino).map(|n| &*n.name));
+    node_mut(nodes, dir)?.entries = entries;
+    found
+}
+
+fn create(
+    nodes: &mut Vec<Option<Node>>,
+    dir: u64,
+    name: &[u8],
+    kind: Kind,
+    mode: Mode,
+) -> FsResult<u64> {
+    if !is_dir(nodes, dir) {
+        return Err(FsError::PathNotFound);
+    } else if child(nodes, dir, name).is_some() || name.len() > MAX_NAME {
+        return Err(FsError::InvalidOp);
+    }
+
+    let mut owned = Vec::new();
+    owned
+        .try_reserve_exact(name.len())
+        .map_err(|_| FsError::NoMem)?;
+    owned.extend_from_slice(name);
+    nodes.try_reserve(1).map_err(|_| FsError::NoMem)?;
+
+    let ino = nodes.len() as u64;
+    let now = now();
+    let parent = node_mut(nodes, dir).unwrap();
+    parent.entries.insert(name, ino)?;
+    parent.mtime = now;
+    nodes.push(Some(Node {
+        name: owned.into(),
+        parent: dir,
+        kind,
+        mode,
+        atime: now,
+        mtime: now,
+        entries: DirEntries::default(),
+    }));
+    Ok(ino)
+}
+
+/// Remove `ino` and everything inside it. Removing an entry modifies its directory.
+fn unlink(nodes: &mut [Option<Node>], ino: u64) {
+    let Some(node) = nodes.get_mut(ino as usize).and_then(Option::take) else {
+        return;
+    };
+
+    if let Some(parent) = node_mut(nodes, node.parent) {
+        parent.entries.remove(&node.name, ino);
+        parent.mtime = now();
+    }
+    for child in node.entries.iter() {
+        unlink(nodes, child);
+    }
+}
+
+fn stat(nodes: &[Option<Node>], ino: u64) -> FsResult<Stat> {
+    let node = node(nodes, ino).ok_or(FsError::PathNotFound)?;
+    Ok(Stat {
+        size: match &node.kind {
+            Kind::File(data) => data.len,
+            _ => 0,
+        },
+        readonly: !node.mode.contains(Mode::Write),
+        directory: matches!(node.kind, Kind::Dir { .. }),
+        executable: matches!(node.kind, Kind::File(_)) && node.mode.contains(Mode::Exec),
+        atime: node.atime,
+        mtime: node.mtime,
+        allocated: match &node.kind {
+            Kind::File(data) => data.allocated(),
+            _ => 0,
+        },
+        device: None,
+    })
+}
+
+/// Find the inode at `path`, creating the last component with `mode` if `flags` ask for it.
+/// Returns whether it was created.
+fn walk(
+    nodes: &mut Vec<Option<Node>>,
+    path: &Path,
+    flags: &OpenFlags,
+    mode: Mode,
+    cwd: Option<&VNode>,
+) -> FsResult<(u64, bool)> {
+    let mut ino = cwd
+        .filter(|_| !path.is_absolute())
+        .map(|vn| vn.ino)
+        .unwrap_or(TmpFs::ROOT);
+    let mut components = path.components().peekable();
+    while let Some(component) = components.next() {
+        if !is_dir(nodes, ino) {
+            return Err(FsError::PathNotFound);
+        }
+
+        let kind = match component {
+            b"." => continue,
+            b".." => {
+                ino = node(nodes, ino).map_or(TmpFs::ROOT, |n| n.parent);
+                continue;
+            }
+            name => match child(nodes, ino, name) {
+                Some(child) => {
+                    ino = child;
+                    continue;
+                }
+                None if components.peek().is_some() => return Err(FsError::PathNotFound),
+                None if flags.contains(OpenFlags::CreateDir) => Kind::Dir { opaque: false },
+                None if flags.contains(OpenFlags::CreateFile) => Kind::File(Data::default()),
+                None => return Err(FsError::PathNotFound),
+            },
+        };
+
+        if !self::mode(nodes, ino).contains(Mode::Write) {
+            return Err(FsError::ReadOnly);
+        }
+        return create(nodes, ino, component, kind, mode).map(|ino| (ino, true));
+    }
+
+    Ok((ino, false))
+}
+
+impl FileSystem for TmpFs {
+    fn open(
+        &self,
+        path: &Path,
+        flags: OpenFlags,
+        mode: Mode,
+        cwd: Option<&VNode>,
+    ) -> FsResult<VNode> {
+        let mut nodes = self.nodes.lock();
+        let (ino, created) = walk(&mut nodes, path, &flags, mode, cwd)?;
+        let directory = is_dir(&nodes, ino);
+        // whoever creates a file can write to it the first time, whatever its mode
+        if !created
+            && flags.intersects(OpenFlags::ReadWrite | OpenFlags::Truncate)
+            && !self::mode(&nodes, ino).contains(Mode::Write)
+        {
+            return Err(FsError::ReadOnly);
+        }
+
+        if !directory && flags.contains(OpenFlags::Truncate) {
+            drop(nodes);
+            self.truncate(ino)?;
+        }
+
+        Ok(VNode {
+            ino,
+            directory,
+            readonly: !flags.contains(OpenFlags::ReadWrite),
+        })
+    }
+
+    fn read<'a>(
+        &self,
+        vn: &VNode,
+        pos: u64,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> FsResult<&'a mut [u8]> {
+        let nodes = self.nodes.lock();
+        let Some(Node {
+            kind: Kind::File(data),
+            ..
+        }) = node(&nodes, vn.ino)
+        else {
+            return Err(FsError::InvalidOp);
+        };
+
+        let pos = usize::try_from(pos).map_err(|_| FsError::Eof)?;
+        match data.read(pos, buf) {
+            0 if !buf.is_empty() => Err(FsError::Eof),
+            len => Ok(unsafe { MaybeUninit::slice_assume_init_mut(&mut buf[..len]) }),
+        }
+    }
+
+    fn write(&self, vn: &VNode, pos: u64, buf: &[u8]) -> FsResult<usize> {
+        let mut nodes = self.nodes.lock();
+        let Some(Node {
+            kind: Kind::File(data),
+            ..
+        }) = node_mut(&mut nodes, vn.ino)
+        else {
+            return Err(FsError::InvalidOp);
+        };
+
+        let pos = usize::try_from(pos).map_err(|_| FsError::InvalidOp)?;
+        data.write(pos, buf)?;
+        Ok(buf.len())
+    }
+
+    fn close(&self, _vn: &VNode, _open: OpenId) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn readdir(&self, vn: &VNode, pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
+        let nodes = self.nodes.lock();
+        if !is_dir(&nodes, vn.ino) {
+            return Err(FsError::InvalidOp);
+        }
+
+        // the position is the next inode to look at
+        let Some((ino, node)) = children_from(&nodes, vn.ino, pos as u64)
+            .find(|(_, n)| !matches!(n.kind, Kind::Whiteout))
+        else {
+            return Ok(None);
+        };
+
+        let mut entry = DirEntry {
+            name: [0; MAX_NAME],
+            name_len: node.name.len(),
+            stat: stat(&nodes, ino)?,
+        };
+        entry.name[..node.name.len()].copy_from_slice(&node.name);
+        Ok(Some((entry, ino as usize + 1)))
+    }
+
+    fn stat(&self, vn: &VNode) -> FsResult<Stat> {
+        stat(&self.nodes.lock(), vn.ino)
+    }
+
+    fn remove(&self, path: &Path, cwd: Option<&VNode>) -> FsResult<()> {
+        let mut nodes = self.nodes.lock();
+        let (ino, _) = walk(&mut nodes, path, &OpenFlags::empty(), Mode::empty(), cwd)?;
+        if ino == Self::ROOT || children(&nodes, ino).next().is_some() {
+            return Err(FsError::InvalidOp);
+        } else if !mode(&nodes, node(&nodes, ino).map_or(Self::ROOT, |n| n.parent))
+            .contains(Mode::Write)
+        {
+            return Err(FsError::ReadOnly);
+        }
+
+        unlink(&mut nodes, ino);
+        Ok(())
+    }
+
+    fn set_times(&self, vn: &VNode, atime: Option<u64>, mtime: Option<u64>) -> FsResult<()> {
+        let mut nodes = self.nodes.lock();
+        let node = node_mut(&mut nodes, vn.ino).ok_or(FsError::PathNotFound)?;
+        node.atime = atime.unwrap_or(node.atime);
+        node.mtime = mtime.unwrap_or(node.mtime);
+        Ok(())
+    }
+}
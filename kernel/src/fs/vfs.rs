@@ -1,80 +1,177 @@
-use core::{cell::Cell, mem::MaybeUninit};
+use core::{
+    cell::Cell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
 
 use alloc::{
-    collections::{btree_map::Entry, BTreeMap},
+    collections::{btree_map::Entry, BTreeMap, TryReserveError},
     sync::Arc,
+    vec::Vec,
 };
 use servos::lock::SpinLocked;
-use shared::io::Stat;
+use shared::io::{Mode, MountFlags, Stat};
 
 use crate::{
+    audit::{self, Resource},
     fs::FsError,
+    klog,
     vmm::{PageTable, VirtAddr},
 };
 
 use super::{
     path::{OwnedPath, Path},
-    DirEntry, FileSystem, FsResult, OpenFlags, VNode,
+    DirEntry, FileSystem, FsResult, OpenFlags, OpenId, VNode,
 };
 
-#[derive(Clone)]
+/// Don't bother updating an access time more often than this, in microseconds
+const ATIME_INTERVAL: u64 = 24 * 60 * 60 * 1_000_000;
+
+static NEXT_OPEN: AtomicU64 = AtomicU64::new(1);
+
+/// Shared by a mount and every `Fd` opened through it
+struct MountState {
+    open: AtomicUsize,
+    /// Set once the filesystem is unmounted. Only possible with open files if it was forced.
+    revoked: AtomicBool,
+    flags: MountFlags,
+}
+
+struct Mount {
+    fs: Arc<dyn FileSystem>,
+    /// The directory a bind mount exposes, instead of the root of `fs`
+    root: Option<Fd>,
+    state: Arc<MountState>,
+}
+
 pub struct Fd {
     node: VNode,
+    /// A clone gets its own, since closing it doesn't close the original
+    open: OpenId,
     dev: Arc<dyn FileSystem>,
+    /// `None` for filesystems that aren't in the mount table, like sockets
+    mount: Option<Arc<MountState>>,
     // right now file descriptors can't be shared but if/when they can this might need a spinlock
     pos: Cell<u64>,
 }
 
 impl Fd {
     pub unsafe fn new(node: VNode, dev: Arc<dyn FileSystem>) -> Self {
+        unsafe { Self::new_in(node, dev, None) }
+    }
+
+    unsafe fn new_in(
+        node: VNode,
+        dev: Arc<dyn FileSystem>,
+        mount: Option<Arc<MountState>>,
+    ) -> Self {
+        if let Some(mount) = &mount {
+            mount.open.fetch_add(1, Ordering::Relaxed);
+        }
+
         Self {
             node,
+            open: NEXT_OPEN.fetch_add(1, Ordering::Relaxed),
             dev,
+            mount,
             pos: Cell::new(0),
         }
     }
 
+    /// Fails if the filesystem was forcibly unmounted
+    fn check(&self) -> FsResult<()> {
+        match &self.mount {
+            Some(mount) if mount.revoked.load(Ordering::Relaxed) => Err(FsError::Stale),
+            _ => Ok(()),
+        }
+    }
+
     pub fn read<'a>(&self, pos: u64, buf: &'a mut [MaybeUninit<u8>]) -> FsResult<&'a mut [u8]> {
+        self.check()?;
         if self.node.directory {
             return Err(FsError::InvalidOp);
         }
 
-        self.exec_with_pos_raw(pos, |pos| {
-            let res = self.dev.read(&self.node, pos, buf)?;
-            Ok((res.len(), res))
-        })
-        .map(|p| p.1)
+        let res = self
+            .exec_with_pos_raw(pos, |pos| {
+                let res = self.dev.read(&self.node, pos, buf)?;
+                Ok((res.len(), res))
+            })
+            .map(|p| p.1)?;
+        self.accessed();
+        Ok(res)
     }
 
     pub fn write(&self, pos: u64, buf: &[u8]) -> FsResult<usize> {
+        self.check()?;
         if self.node.directory || self.node.readonly {
             return Err(FsError::ReadOnly);
         }
 
-        self.exec_with_pos(pos, |pos| self.dev.write(&self.node, pos, buf))
+        let res = self.exec_with_pos(pos, |pos| self.dev.write(&self.node, pos, buf))?;
+        self.modified();
+        Ok(res)
     }
 
     pub fn read_va(&self, pos: u64, pt: &PageTable, va: VirtAddr, len: usize) -> FsResult<usize> {
+        self.check()?;
         if self.node.directory {
             return Err(FsError::InvalidOp);
         }
 
-        self.exec_with_pos(pos, |pos| self.dev.read_va(&self.node, pos, pt, va, len))
+        let res = self.exec_with_pos(pos, |pos| self.dev.read_va(&self.node, pos, pt, va, len))?;
+        self.accessed();
+        Ok(res)
     }
 
     pub fn write_va(&self, pos: u64, pt: &PageTable, va: VirtAddr, len: usize) -> FsResult<usize> {
+        self.check()?;
         if self.node.directory || self.node.readonly {
             return Err(FsError::InvalidOp);
         }
 
-        self.exec_with_pos(pos, |pos| self.dev.write_va(&self.node, pos, pt, va, len))
+        let res = self.exec_with_pos(pos, |pos| self.dev.write_va(&self.node, pos, pt, va, len))?;
+        self.modified();
+        Ok(res)
     }
 
-    pub fn readdir(&self, cur: usize) -> FsResult<Option<DirEntry>> {
+    /// Update the access time after a read, unless the mount says not to. Like relatime, it's
+    /// only updated if it's older than the modification time or a day old, so reading a file
+    /// over and over doesn't turn into writes.
+    fn accessed(&self) {
+        let Some(mount) = &self.mount else {
+            return;
+        };
+        if mount.flags.contains(MountFlags::NoAtime) {
+            return;
+        }
+
+        let Ok(stat) = self.dev.stat(&self.node) else {
+            return;
+        };
+        let now = super::now();
+        if stat.atime <= stat.mtime || now.saturating_sub(stat.atime) >= ATIME_INTERVAL {
+            // the read itself succeeded, so a filesystem that can't store it isn't an error
+            _ = self.dev.set_times(&self.node, Some(now), None);
+        }
+    }
+
+    fn modified(&self) {
+        if self.mount.is_some() {
+            _ = self.dev.set_times(&self.node, None, Some(super::now()));
+        }
+    }
+
+    /// Read the entry at `cur`, or at the cursor if it's `usize::MAX`. Returns the entry with the
+    /// position of the next one, which the cursor is moved to.
+    pub fn readdir(&self, cur: usize) -> FsResult<Option<(DirEntry, usize)>> {
+        self.check()?;
         if cur == usize::MAX {
-            let res = self.dev.readdir(&self.node, self.pos.get() as usize);
-            self.pos.update(|pos| pos + 1);
-            res
+            let res = self.dev.readdir(&self.node, self.pos.get() as usize)?;
+            if let Some((_, next)) = res {
+                self.pos.set(next as u64);
+            }
+            Ok(res)
         } else {
             self.dev.readdir(&self.node, cur)
         }
@@ -85,9 +182,25 @@ impl Fd {
     }
 
     pub fn stat(&self) -> FsResult<Stat> {
+        self.check()?;
         self.dev.stat(&self.node)
     }
 
+    pub fn readable(&self) -> FsResult<bool> {
+        self.check()?;
+        Ok(self.dev.readable(&self.node))
+    }
+
+    pub fn control(&self, cmd: usize, arg: usize) -> FsResult<usize> {
+        self.check()?;
+        self.dev.control(&self.node, self.open, cmd, arg)
+    }
+
+    #[cfg(feature = "net")]
+    pub fn socket_id(&self) -> Option<u32> {
+        self.dev.socket_id(&self.node)
+    }
+
     fn exec_with_pos(&self, pos: u64, f: impl FnOnce(u64) -> FsResult<usize>) -> FsResult<usize> {
         self.exec_with_pos_raw(pos, |pos| Ok((f(pos)?, ())))
             .map(|v| v.0)
@@ -109,9 +222,21 @@ impl Fd {
     }
 }
 
+impl Clone for Fd {
+    fn clone(&self) -> Self {
+        let fd = unsafe { Self::new_in(self.node.clone(), self.dev.clone(), self.mount.clone()) };
+        fd.pos.set(self.pos.get());
+        fd
+    }
+}
+
 impl Drop for Fd {
     fn drop(&mut self) {
-        _ = self.dev.close(&self.node);
+        if let Some(mount) = &self.mount {
+            mount.open.fetch_sub(1, Ordering::Relaxed);
+        }
+        _ = self.dev.close(&self.node, self.open);
+        audit::released(Resource::Files, 1);
     }
 }
 
@@ -119,58 +244,433 @@ impl Drop for Fd {
 pub enum MountError {
     NoMem,
     AlreadyMounted,
+    NotMounted,
+    /// Files are still open on the filesystem, or another filesystem is mounted inside it
+    Busy,
+    /// The source of a bind mount couldn't be opened
+    Open(FsError),
+    NotDirectory,
+    /// A bind mount would be inside its own source
+    Loop,
+    /// `pivot_root` was asked to pivot onto `/`, or to put the old root outside the new one
+    BadPivot,
+}
+
+/// How many lookups `LookupCache` remembers
+const LOOKUP_CACHE_LEN: usize = 32;
+
+/// What was found at a path, or `None` if there was nothing there
+type Lookup = Option<(Arc<dyn FileSystem>, VNode, Arc<MountState>)>;
+
+/// The most recent plain lookups by absolute path, including the ones that found nothing, so
+/// things like searching `PATH` don't walk the same directories over and over. Cleared whenever
+/// anything is created, removed, mounted or unmounted.
+struct LookupCache {
+    /// Least recently used first
+    entries: Vec<(OwnedPath, Lookup)>,
+    /// Bumped on every clear, so a lookup that raced with one isn't cached
+    generation: u64,
+    hits: usize,
+    misses: usize,
+}
+
+impl LookupCache {
+    const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            generation: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn get(&mut self, path: &Path) -> Option<Lookup> {
+        let Some(pos) = self.entries.iter().position(|(p, _)| *p == *path) else {
+            self.misses += 1;
+            return None;
+        };
+
+        self.hits += 1;
+        let entry = self.entries.remove(pos);
+        let found = entry.1.clone();
+        self.entries.push(entry);
+        Some(found)
+    }
+
+    fn insert(&mut self, generation: u64, path: OwnedPath, found: Lookup) {
+        if generation != self.generation {
+            return;
+        }
+
+        if self.entries.len() >= LOOKUP_CACHE_LEN {
+            self.entries.remove(0);
+        } else if self.entries.try_reserve(1).is_err() {
+            return;
+        }
+        self.entries.push((path, found));
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.generation += 1;
+    }
 }
 
 pub struct Vfs {
-    mounts: BTreeMap<OwnedPath, Arc<dyn FileSystem>>,
+    mounts: BTreeMap<OwnedPath, Mount>,
+    cache: LookupCache,
 }
 
 impl Vfs {
     const fn new() -> Self {
         Self {
             mounts: BTreeMap::new(),
+            cache: LookupCache::new(),
+        }
+    }
+
+    pub fn mount(
+        &mut self,
+        path: OwnedPath,
+        fs: Arc<dyn FileSystem>,
+        flags: MountFlags,
+    ) -> Result<(), MountError> {
+        self.insert(path, fs, None, flags)
+    }
+
+    /// Make the directory at `src` reachable from `path` as well, with the same mount flags.
+    /// Anything mounted inside `src` is not carried along. Both paths must be absolute.
+    pub fn bind(src: &Path, path: OwnedPath) -> Result<(), MountError> {
+        let src = src.resolve(Path::new("/")).map_err(|_| MountError::NoMem)?;
+        // anything walking the tree by name would never reach the bottom
+        if path.starts_with(&src) {
+            return Err(MountError::Loop);
+        }
+
+        let root = Self::open(&src, OpenFlags::empty(), Mode::empty()).map_err(MountError::Open)?;
+        if !root.node.directory {
+            return Err(MountError::NotDirectory);
         }
+
+        let flags = root.mount.as_ref().map_or(MountFlags::empty(), |m| m.flags);
+        VFS.lock().insert(path, root.dev.clone(), Some(root), flags)
     }
 
-    pub fn mount(&mut self, path: OwnedPath, fs: Arc<dyn FileSystem>) -> Result<(), MountError> {
+    fn insert(
+        &mut self,
+        path: OwnedPath,
+        fs: Arc<dyn FileSystem>,
+        root: Option<Fd>,
+        flags: MountFlags,
+    ) -> Result<(), MountError> {
         // TODO: alloc failure
         match self.mounts.entry(path) {
             Entry::Vacant(entry) => {
-                entry.insert(fs);
+                self.cache.clear();
+                klog!(
+                    Debug,
+                    Fs,
+                    "mounted filesystem at '{}'",
+                    core::str::from_utf8(entry.key().as_ref().as_ref()).unwrap_or("?")
+                );
+                entry.insert(Mount {
+                    fs,
+                    root,
+                    state: Arc::try_new(MountState {
+                        open: AtomicUsize::new(0),
+                        revoked: AtomicBool::new(false),
+                        flags,
+                    })
+                    .map_err(|_| MountError::NoMem)?,
+                });
                 Ok(())
             }
             Entry::Occupied(_) => Err(MountError::AlreadyMounted),
         }
     }
 
-    pub fn unmount(&mut self, path: &Path) -> bool {
-        self.mounts.remove(path).is_some()
+    /// Unmount the filesystem at `path`. Fails if any files are open on it unless `force` is set,
+    /// in which case they are revoked and fail any further operations with `FsError::Stale`.
+    pub fn unmount(&mut self, path: &Path, force: bool) -> Result<(), MountError> {
+        let mount = self.mounts.get(path).ok_or(MountError::NotMounted)?;
+        if self
+            .mounts
+            .keys()
+            .any(|other| **other != *path && other.starts_with(path))
+        {
+            return Err(MountError::Busy);
+        }
+
+        if !force && mount.state.open.load(Ordering::Relaxed) != 0 {
+            return Err(MountError::Busy);
+        }
+
+        mount.state.revoked.store(true, Ordering::Relaxed);
+        self.mounts.remove(path);
+        self.cache.clear();
+        klog!(
+            Debug,
+            Fs,
+            "unmounted filesystem at '{}'",
+            core::str::from_utf8(path.as_ref()).unwrap_or("?")
+        );
+        Ok(())
+    }
+
+    /// Make the filesystem mounted at `new_root` the root, and move the old root to `put_old`,
+    /// which must be inside `new_root`. Mounts inside `new_root` move along with it, the rest stay
+    /// where they are, so `/dev` and `/proc` don't have to be mounted again. Both paths must have
+    /// been resolved.
+    ///
+    /// Files that are already open aren't affected, and the old root can be unmounted from
+    /// `put_old` once nothing is using it.
+    pub fn pivot_root(&mut self, new_root: &Path, put_old: &Path) -> Result<Pivot, MountError> {
+        let old_root = Path::new("/");
+        if *new_root == *old_root || !self.mounts.contains_key(old_root) {
+            return Err(MountError::BadPivot);
+        }
+        if !self.mounts.contains_key(new_root) {
+            return Err(MountError::NotMounted);
+        }
+        let put_old = put_old
+            .strip_prefix(new_root)
+            .ok_or(MountError::BadPivot)?
+            .resolve(old_root)
+            .map_err(|_| MountError::NoMem)?;
+
+        let mut pivot = Pivot {
+            new_root: new_root.try_into().map_err(|_| MountError::NoMem)?,
+            put_old,
+            kept: Vec::new(),
+        };
+        for at in self.mounts.keys() {
+            if **at != *old_root && !at.starts_with(new_root) {
+                pivot.kept.try_reserve(1).map_err(|_| MountError::NoMem)?;
+                pivot
+                    .kept
+                    .push((**at).try_into().map_err(|_| MountError::NoMem)?);
+            }
+        }
+
+        // in the same order as the mount table, None for the ones that stay put
+        let mut moved: Vec<Option<OwnedPath>> = Vec::new();
+        moved
+            .try_reserve_exact(self.mounts.len())
+            .map_err(|_| MountError::NoMem)?;
+        for at in self.mounts.keys() {
+            let to = pivot.moved(at).map_err(|_| MountError::NoMem)?;
+            if let Some(to) = &to {
+                // the old root could land on a mount inside the new one, or something inside the
+                // new root on a mount that stays put
+                if pivot.kept.contains(to) || moved.iter().flatten().any(|other| other == to) {
+                    return Err(MountError::AlreadyMounted);
+                }
+            }
+            moved.push(to);
+        }
+
+        for ((at, mount), to) in core::mem::take(&mut self.mounts).into_iter().zip(moved) {
+            self.mounts.insert(to.unwrap_or(at), mount);
+        }
+        self.cache.clear();
+        klog!(
+            Debug,
+            Fs,
+            "pivoted the root to '{}', the old root is at '{}'",
+            core::str::from_utf8(new_root.as_ref()).unwrap_or("?"),
+            core::str::from_utf8(pivot.put_old.as_ref().as_ref()).unwrap_or("?")
+        );
+        Ok(pivot)
+    }
+
+    /// Resolve `..` by name, since a filesystem's own parent entries could lead out of a bind
+    /// mount
+    fn resolve(path: &Path) -> FsResult<OwnedPath> {
+        path.resolve(Path::new("/")).map_err(|_| FsError::NoMem)
     }
 
-    pub fn open(path: impl AsRef<Path>, flags: OpenFlags) -> FsResult<Fd> {
-        fn open(path: &Path, flags: OpenFlags) -> FsResult<Fd> {
-            let Some((dev, path)) = VFS.lock().mounts.iter().rev().find_map(|(mount, dev)| {
-                let rest = path.strip_prefix(mount)?;
-                Some((dev.clone(), rest))
-            }) else {
+    /// Find the filesystem `path` is on, and call `f` with it, the directory the rest of `path`
+    /// is relative to, and the rest of `path`. `path` must have been resolved.
+    fn with_mount<T>(
+        path: &Path,
+        f: impl FnOnce(Arc<dyn FileSystem>, Option<VNode>, Arc<MountState>, &Path) -> FsResult<T>,
+    ) -> FsResult<T> {
+        // paths are ordered by component, so the first match from the end is the longest
+        let (dev, root, state, rest) = {
+            let vfs = VFS.lock();
+            let Some((mount, rest)) = vfs
+                .mounts
+                .iter()
+                .rev()
+                .find_map(|(at, mount)| Some((mount, path.strip_prefix(at)?)))
+            else {
                 return Err(FsError::PathNotFound);
             };
 
-            Ok(unsafe { Fd::new(dev.open(path, flags, None)?, dev) })
+            let root = match &mount.root {
+                Some(root) => root.check().map(|_| Some(root.node.clone()))?,
+                None => None,
+            };
+            (mount.fs.clone(), root, mount.state.clone(), rest)
+        };
+
+        f(dev, root, state, rest)
+    }
+
+    pub fn open(path: impl AsRef<Path>, flags: OpenFlags, mode: Mode) -> FsResult<Fd> {
+        let path = Self::resolve(path.as_ref())?;
+        let open = |flags| {
+            Self::with_mount(&path, |dev, root, state, rest| {
+                // if the filesystem is unmounted in the meantime, the Fd is stale from the start
+                let node = dev.open(rest, flags, mode, root.as_ref())?;
+                let fd = unsafe { Fd::new_in(node, dev, Some(state)) };
+                check_opened(&fd.node, flags)?;
+                Ok(fd)
+            })
+        };
+
+        // only plain lookups are cached, anything else may have to change the file
+        if !flags.is_empty() {
+            let created = check_flags(flags)?;
+            let res = open(flags);
+            if created && res.is_ok() {
+                VFS.lock().cache.clear();
+            }
+            return res;
         }
 
-        open(path.as_ref(), flags)
+        let generation = {
+            let mut vfs = VFS.lock();
+            match vfs.cache.get(&path) {
+                Some(Some((dev, node, state))) => {
+                    return Ok(unsafe { Fd::new_in(node, dev, Some(state)) })
+                }
+                Some(None) => return Err(FsError::PathNotFound),
+                None => vfs.cache.generation,
+            }
+        };
+
+        let res = open(OpenFlags::empty());
+        let found = match &res {
+            Ok(fd) => Some((fd.dev.clone(), fd.node.clone(), fd.mount.clone().unwrap())),
+            Err(FsError::PathNotFound) => None,
+            Err(_) => return res,
+        };
+        VFS.lock().cache.insert(generation, path, found);
+        res
+    }
+
+    pub fn remove(path: impl AsRef<Path>) -> FsResult<()> {
+        let path = Self::resolve(path.as_ref())?;
+        Self::with_mount(&path, |dev, root, _, rest| dev.remove(rest, root.as_ref()))?;
+        VFS.lock().cache.clear();
+        Ok(())
+    }
+
+    /// Open `path` relative to `cwd`, which must have been opened from `cwd_path`
+    pub fn open_in_cwd(
+        cwd: &Fd,
+        cwd_path: &Path,
+        path: impl AsRef<Path>,
+        flags: OpenFlags,
+        mode: Mode,
+    ) -> FsResult<Fd> {
+        assert!(cwd.node.directory);
+        let path = path.as_ref();
+        if path.is_absolute() {
+            Self::open(path, flags, mode)
+        } else if path.components().any(|c| c == b"..") {
+            Self::open(
+                path.resolve(cwd_path).map_err(|_| FsError::NoMem)?,
+                flags,
+                mode,
+            )
+        } else {
+            cwd.check()?;
+            let created = check_flags(flags)?;
+            let node = cwd.dev.open(path, flags, mode, Some(&cwd.node))?;
+            if created {
+                VFS.lock().cache.clear();
+            }
+            let fd = unsafe { Fd::new_in(node, cwd.dev.clone(), cwd.mount.clone()) };
+            check_opened(&fd.node, flags)?;
+            Ok(fd)
+        }
     }
 
-    pub fn open_in_cwd(cwd: &Fd, path: impl AsRef<Path>, flags: OpenFlags) -> FsResult<Fd> {
+    /// Remove `path` relative to `cwd`, which must have been opened from `cwd_path`
+    pub fn remove_in_cwd(cwd: &Fd, cwd_path: &Path, path: impl AsRef<Path>) -> FsResult<()> {
         assert!(cwd.node.directory);
         let path = path.as_ref();
         if path.is_absolute() {
-            Self::open(path, flags)
+            Self::remove(path)
+        } else if path.components().any(|c| c == b"..") {
+            Self::remove(path.resolve(cwd_path).map_err(|_| FsError::NoMem)?)
+        } else {
+            cwd.check()?;
+            cwd.dev.remove(path, Some(&cwd.node))?;
+            VFS.lock().cache.clear();
+            Ok(())
+        }
+    }
+}
+
+/// Reject combinations of `flags` that make no sense before any filesystem sees them. Returns
+/// whether they can create something, which makes cached lookups stale.
+fn check_flags(flags: OpenFlags) -> FsResult<bool> {
+    if flags.contains(OpenFlags::CreateDir)
+        && flags.intersects(OpenFlags::CreateFile | OpenFlags::Truncate)
+    {
+        return Err(FsError::InvalidOp);
+    }
+    Ok(flags.intersects(OpenFlags::CreateDir | OpenFlags::CreateFile))
+}
+
+/// Make sure what was opened is the kind of file `flags` asked for. Nothing has been truncated if
+/// this fails, since directories never are and `check_flags` doesn't let `CreateDir` truncate.
+fn check_opened(node: &VNode, flags: OpenFlags) -> FsResult<()> {
+    if node.directory && flags.contains(OpenFlags::CreateFile)
+        || !node.directory && flags.contains(OpenFlags::CreateDir)
+    {
+        Err(FsError::AlreadyExists)
+    } else if node.directory && flags.contains(OpenFlags::Truncate) {
+        Err(FsError::InvalidOp)
+    } else {
+        Ok(())
+    }
+}
+
+/// How `Vfs::pivot_root` moved things around
+pub struct Pivot {
+    new_root: OwnedPath,
+    /// Where the old root is now
+    put_old: OwnedPath,
+    /// Mounts outside of both roots, which stayed where they were
+    kept: Vec<OwnedPath>,
+}
+
+impl Pivot {
+    /// Where something that was at `path` before the pivot is now, or `None` if it didn't move.
+    /// `path` must have been resolved.
+    pub fn moved(&self, path: &Path) -> Result<Option<OwnedPath>, TryReserveError> {
+        if let Some(rest) = path.strip_prefix(&self.new_root) {
+            rest.resolve(Path::new("/")).map(Some)
+        } else if self.kept.iter().any(|at| path.starts_with(at)) {
+            Ok(None)
         } else {
-            Ok(unsafe { Fd::new(cwd.dev.open(path, flags, Some(&cwd.node))?, cwd.dev.clone()) })
+            let rest = path.strip_prefix(Path::new("/")).unwrap_or(path);
+            rest.resolve(&self.put_old).map(Some)
         }
     }
 }
 
 pub static VFS: SpinLocked<Vfs> = SpinLocked::new(Vfs::new());
+
+/// `/proc/lookups`: how well the lookup cache is doing
+#[cfg(feature = "procfs")]
+pub fn show_lookups(w: &mut dyn core::fmt::Write) -> core::fmt::Result {
+    let cache = &VFS.lock().cache;
+    writeln!(w, "hits:   {}", cache.hits)?;
+    writeln!(w, "misses: {}", cache.misses)?;
+    writeln!(w, "cached: {}", cache.entries.len())
+}
@@ -12,6 +12,11 @@ use crate::lock::SpinLocked;
 pub struct BlockAlloc {
     blocks: [Option<&'static mut Node>; BLOCK_SIZES.len()],
     fallback: Heap,
+    /// Bytes given to the allocator with `add_pages`, which are outside of `range`
+    added: usize,
+    /// The free page `scrub_pages` checked last, or 0 to start from the head of the list
+    #[cfg(feature = "page-poison")]
+    scrubbed: usize,
 }
 
 impl BlockAlloc {
@@ -19,6 +24,9 @@ impl BlockAlloc {
         Self {
             blocks: [const { None }; BLOCK_SIZES.len()],
             fallback: Heap::empty(),
+            added: 0,
+            #[cfg(feature = "page-poison")]
+            scrubbed: 0,
         }
     }
 
@@ -36,6 +44,14 @@ impl BlockAlloc {
             };
 
             self.blocks[i] = block.next.take();
+            #[cfg(feature = "page-poison")]
+            {
+                // the next page to scrub is the new head of the list
+                if self.scrubbed == block as *mut Node as usize {
+                    self.scrubbed = 0;
+                }
+                block.check_poison(BLOCK_SIZES[i]);
+            }
             block as *mut _ as *mut u8
         } else {
             self.fallback_alloc(layout)
@@ -52,12 +68,75 @@ impl BlockAlloc {
             // Safety: every block has sufficient size + alignment for a Node
             let block = unsafe { ptr.cast::<Node>().as_mut() };
             block.next = self.blocks[i].take();
+            #[cfg(feature = "page-poison")]
+            block.poison(BLOCK_SIZES[i]);
             self.blocks[i] = Some(block);
         } else {
             unsafe { self.fallback.deallocate(ptr, layout) };
         }
     }
 
+    /// Give the allocator every whole page in `mem`, which it hands out for page sized allocations.
+    /// Returns how many bytes that was.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must be mapped writable and never be used by anything else again.
+    pub unsafe fn add_pages(&mut self, mem: *mut [u8]) -> usize {
+        const PAGE: usize = BLOCK_SIZES[BLOCK_SIZES.len() - 1];
+
+        let start = mem.cast::<u8>();
+        let skip = start.align_offset(PAGE);
+        let pages = mem.len().saturating_sub(skip) / PAGE;
+        for i in 0..pages {
+            // Safety: PAGE is a power of two, and the caller promised the memory is unused
+            unsafe {
+                self.dealloc(
+                    NonNull::new_unchecked(start.add(skip + i * PAGE)),
+                    Layout::from_size_align_unchecked(PAGE, PAGE),
+                );
+            }
+        }
+        self.added += pages * PAGE;
+        pages * PAGE
+    }
+
+    /// Check that up to `count` free pages are still poisoned, carrying on from where the last call
+    /// stopped. A page that was written to is taken off the free list for good and returned with
+    /// the offset of the first changed byte. Otherwise returns how many pages were checked, which
+    /// is less than `count` once the end of the list is reached.
+    #[cfg(feature = "page-poison")]
+    pub fn scrub_pages(&mut self, count: usize) -> Result<usize, (*mut u8, usize)> {
+        const LIST: usize = BLOCK_SIZES.len() - 1;
+
+        for checked in 0..count {
+            let link = match self.scrubbed {
+                0 => &mut self.blocks[LIST],
+                // Safety: `alloc` resets `scrubbed` when it takes that block, so it's still free
+                prev => unsafe { &mut (*(prev as *mut Node)).next },
+            };
+            let Some(block) = link else {
+                self.scrubbed = 0;
+                return Ok(checked);
+            };
+
+            match block.changed(BLOCK_SIZES[LIST]) {
+                None => self.scrubbed = &mut **block as *mut Node as usize,
+                Some(offset) => {
+                    let block = link.take().unwrap();
+                    *link = block.next.take();
+                    return Err((block as *mut Node as *mut u8, offset));
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// How many bytes `add_pages` has given the allocator
+    pub fn added(&self) -> usize {
+        self.added
+    }
+
     pub fn range(&self) -> Range<*mut u8> {
         Range {
             start: self.fallback.bottom(),
@@ -88,6 +167,45 @@ struct Node {
     next: Option<&'static mut Node>,
 }
 
+/// Freed blocks at least this big are poisoned, which covers `Page` and `PageTable`
+#[cfg(feature = "page-poison")]
+const POISON_MIN_SIZE: usize = 0x1000;
+#[cfg(feature = "page-poison")]
+const POISON: u64 = 0xa5a5_a5a5_a5a5_a5a5;
+
+#[cfg(feature = "page-poison")]
+impl Node {
+    /// The rest of a free block of `size` bytes after the node, if blocks that size are poisoned
+    fn body(&mut self, size: usize) -> Option<&mut [u64]> {
+        let words = size / core::mem::size_of::<u64>();
+        (size >= POISON_MIN_SIZE).then(|| unsafe {
+            core::slice::from_raw_parts_mut((self as *mut Self).cast::<u64>().add(1), words - 1)
+        })
+    }
+
+    fn poison(&mut self, size: usize) {
+        if let Some(body) = self.body(size) {
+            body.fill(POISON);
+        }
+    }
+
+    /// The offset of the first word written to while this block was free, if it's poisoned
+    fn changed(&mut self, size: usize) -> Option<usize> {
+        let i = self.body(size)?.iter().position(|&w| w != POISON)?;
+        Some((i + 1) * core::mem::size_of::<u64>())
+    }
+
+    /// Panic if anything wrote to this block while it was free
+    fn check_poison(&mut self, size: usize) {
+        if let Some(offset) = self.changed(size) {
+            panic!(
+                "use after free: {:?} + {offset:#x} was written after the block was freed",
+                self as *mut Self
+            );
+        }
+    }
+}
+
 const BLOCK_SIZES: &[usize] = &[
     0x8, 0x10, 0x20, 0x40, 0x80, 0x100, 0x200, 0x400, 0x800, 0x1000,
 ];
@@ -0,0 +1,87 @@
+#[cfg(feature = "procfs")]
+use core::fmt::{self, Write};
+
+use servos::lock::SpinLocked;
+use shared::sys::{SysError, MAX_HOSTNAME_LEN};
+
+#[cfg(feature = "procfs")]
+use crate::fs::{FsError, FsResult};
+
+struct Hostname {
+    buf: [u8; MAX_HOSTNAME_LEN],
+    len: usize,
+}
+
+/// Until init reads `/etc/hostname`
+const DEFAULT: &[u8] = b"servos";
+
+static HOSTNAME: SpinLocked<Hostname> = SpinLocked::new({
+    let mut buf = [0; MAX_HOSTNAME_LEN];
+    let mut i = 0;
+    while i < DEFAULT.len() {
+        buf[i] = DEFAULT[i];
+        i += 1;
+    }
+    Hostname {
+        buf,
+        len: DEFAULT.len(),
+    }
+});
+
+/// Call `f` with the hostname. It's only letters, digits, `-` and `.`.
+pub fn with<T>(f: impl FnOnce(&[u8]) -> T) -> T {
+    let name = HOSTNAME.lock();
+    f(&name.buf[..name.len])
+}
+
+/// Copy the hostname into `buf`, so it can be printed without holding the lock
+pub fn copy(buf: &mut [u8; MAX_HOSTNAME_LEN]) -> &str {
+    let len = with(|name| {
+        buf[..name.len()].copy_from_slice(name);
+        name.len()
+    });
+    core::str::from_utf8(&buf[..len]).unwrap_or("?")
+}
+
+/// Like `copy`, but gives up if the lock is held, which might be by this hart if it's panicking
+pub fn try_copy(buf: &mut [u8; MAX_HOSTNAME_LEN]) -> Option<&str> {
+    let name = HOSTNAME.try_lock()?;
+    buf[..name.len].copy_from_slice(&name.buf[..name.len]);
+    let len = name.len;
+    drop(name);
+    Some(core::str::from_utf8(&buf[..len]).unwrap_or("?"))
+}
+
+/// Fails with `BadArg` unless `name` would be a valid DNS label sequence, so it can go anywhere
+/// the hostname is printed or sent without escaping
+pub fn set(name: &[u8]) -> Result<(), SysError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_HOSTNAME_LEN
+        && name.split(|&c| c == b'.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with(b"-")
+                && !label.ends_with(b"-")
+                && label
+                    .iter()
+                    .all(|c| c.is_ascii_alphanumeric() || *c == b'-')
+        });
+    if !valid {
+        return Err(SysError::BadArg);
+    }
+
+    let mut hostname = HOSTNAME.lock();
+    hostname.buf[..name.len()].copy_from_slice(name);
+    hostname.len = name.len();
+    Ok(())
+}
+
+/// `/proc/sys/kernel/hostname`
+#[cfg(feature = "procfs")]
+pub fn show(w: &mut dyn Write) -> fmt::Result {
+    with(|name| writeln!(w, "{}", core::str::from_utf8(name).unwrap_or("?")))
+}
+
+#[cfg(feature = "procfs")]
+pub fn store(value: &[u8]) -> FsResult<()> {
+    set(value).map_err(|_| FsError::InvalidOp)
+}
@@ -0,0 +1,91 @@
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    vec::Vec,
+};
+use servos::lock::SpinLocked;
+use shared::sys::{ProcHandle, SysError, MAX_MSG_LEN, MAX_NAME_LEN};
+
+use crate::proc::Exited;
+
+/// The maximum number of undelivered messages a process can have queued
+const MAILBOX_LEN: usize = 16;
+
+pub struct Message {
+    pub sender: ProcHandle,
+    pub len: usize,
+    pub data: [u8; MAX_MSG_LEN],
+}
+
+impl Message {
+    pub fn bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+#[derive(Default)]
+pub struct Mailbox {
+    queue: VecDeque<Message>,
+}
+
+impl Mailbox {
+    pub fn push(&mut self, msg: Message) -> Result<(), SysError> {
+        if self.queue.len() >= MAILBOX_LEN {
+            return Err(SysError::WouldBlock);
+        }
+
+        self.queue.try_reserve(1)?;
+        self.queue.push_back(msg);
+        Ok(())
+    }
+
+    pub fn peek(&self) -> Option<&Message> {
+        self.queue.front()
+    }
+
+    pub fn pop(&mut self) -> Option<Message> {
+        self.queue.pop_front()
+    }
+}
+
+/// Service names and the process that registered them. No other lock may be taken while this one
+/// is held.
+static NAMES: SpinLocked<BTreeMap<Vec<u8>, ProcHandle>> = SpinLocked::new(BTreeMap::new());
+
+pub fn register_name(name: &[u8], owner: ProcHandle) -> Result<(), SysError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(SysError::BadArg);
+    }
+
+    let mut key = Vec::try_with_capacity(name.len())?;
+    key.extend_from_slice(name);
+
+    let mut names = NAMES.lock();
+    if names.contains_key(&key) {
+        return Err(SysError::AlreadyExists);
+    }
+    // TODO: alloc failure
+    names.insert(key, owner);
+    Ok(())
+}
+
+/// Only the process that registered a name can remove it
+pub fn unregister_name(name: &[u8], owner: ProcHandle) -> Result<(), SysError> {
+    let mut names = NAMES.lock();
+    match names.get(name) {
+        Some(&handle) if handle == owner => {
+            names.remove(name);
+            Ok(())
+        }
+        Some(_) => Err(SysError::InvalidPerms),
+        None => Err(SysError::NotFound),
+    }
+}
+
+pub fn lookup_name(name: &[u8]) -> Option<ProcHandle> {
+    NAMES.lock().get(name).copied()
+}
+
+/// Remove every name registered by the process
+pub fn process_exited(exit: &Exited) {
+    NAMES.lock().retain(|_, &mut handle| handle != exit.handle);
+}
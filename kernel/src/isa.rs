@@ -0,0 +1,337 @@
+#[cfg(feature = "procfs")]
+use core::fmt::{self, Write};
+use core::{
+    arch::asm,
+    mem::MaybeUninit,
+    ptr::{addr_of, addr_of_mut},
+    sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering},
+};
+
+use bitflags::bitflags;
+use fdt_rs::{
+    base::{DevTree, DevTreeNode},
+    prelude::{FallibleIterator, PropReader},
+};
+use servos::{
+    riscv::{r_tp, w_scounteren, TIMEBASE_FREQ},
+    sbi,
+};
+use shared::{
+    layout,
+    sys::{Counters, SysInfo},
+};
+
+#[cfg(feature = "procfs")]
+use crate::stats;
+use crate::{
+    bootargs,
+    proc::MAX_HARTS,
+    vmm::{Page, PageTable, Pte, VirtAddr},
+};
+
+bitflags! {
+    /// The multi-letter extensions the kernel knows about. Names are matched against the ISA
+    /// string without regard to case.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub struct Extensions: u32 {
+        const Zicbom = 1 << 0;
+        const Zicboz = 1 << 1;
+        const Zicntr = 1 << 2;
+        const Zicsr = 1 << 3;
+        const Zifencei = 1 << 4;
+        const Zihintpause = 1 << 5;
+        const Zba = 1 << 6;
+        const Zbb = 1 << 7;
+        const Zbs = 1 << 8;
+        const Sstc = 1 << 9;
+        const Svpbmt = 1 << 10;
+    }
+}
+
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Mmu {
+    Unknown,
+    Bare,
+    Sv39,
+    Sv48,
+    Sv57,
+}
+
+impl Mmu {
+    fn parse(typ: &str) -> Self {
+        match typ {
+            "riscv,none" => Mmu::Bare,
+            "riscv,sv39" => Mmu::Sv39,
+            "riscv,sv48" => Mmu::Sv48,
+            "riscv,sv57" => Mmu::Sv57,
+            _ => Mmu::Unknown,
+        }
+    }
+}
+
+/// The single-letter extensions of each hart, bit 0 for `a`. Zero for harts that aren't in the
+/// device tree.
+static LETTERS: [AtomicU32; MAX_HARTS] = [const { AtomicU32::new(0) }; MAX_HARTS];
+static EXTENSIONS: [AtomicU32; MAX_HARTS] = [const { AtomicU32::new(0) }; MAX_HARTS];
+/// From `riscv,cboz-block-size`, only set once `probe` has seen that `cbo.zero` works
+static CBOZ_BLOCK: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+static DT_CBOZ_BLOCK: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+static MMUS: [AtomicU8; MAX_HARTS] = [const { AtomicU8::new(Mmu::Unknown as u8) }; MAX_HARTS];
+static TIMEBASES: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+/// `mvendorid`, `marchid` and `mimpid`, as reported by the SBI once the hart is running
+static IDS: [[AtomicUsize; 3]; MAX_HARTS] =
+    [const { [const { AtomicUsize::new(0) }; 3] }; MAX_HARTS];
+/// A whole page, so mapping it into processes doesn't show them anything else. Only written by
+/// `init`.
+static mut INFO_PAGE: Page = Page([MaybeUninit::new(0); Page::SIZE]);
+
+fn parse(isa: &str) -> Option<(u32, Extensions)> {
+    let mut parts = isa.split('_');
+    let base = parts.next()?;
+    if !base.get(..4)?.eq_ignore_ascii_case("rv64") {
+        return None;
+    }
+
+    let mut letters = 0;
+    let mut extensions = Extensions::empty();
+    for ch in base[4..].bytes().map(|ch| ch.to_ascii_lowercase()) {
+        match ch {
+            b'g' => {
+                letters |= [b'i', b'm', b'a', b'f', b'd']
+                    .iter()
+                    .fold(0, |bits, &ch| bits | 1 << (ch - b'a'));
+                extensions |= Extensions::Zicsr | Extensions::Zifencei;
+            }
+            b'a'..=b'z' => letters |= 1 << (ch - b'a'),
+            _ => return None,
+        }
+    }
+
+    for part in parts {
+        if let Some((_, ext)) = Extensions::all()
+            .iter_names()
+            .find(|(name, _)| name.eq_ignore_ascii_case(part))
+        {
+            extensions |= ext;
+        }
+    }
+    Some((letters, extensions))
+}
+
+fn prop_u32(node: &DevTreeNode, name: &str) -> Option<u32> {
+    node.props()
+        .find(|prop| Ok(prop.name()? == name))
+        .ok()
+        .flatten()
+        .and_then(|prop| prop.u32(0).ok())
+}
+
+/// Read what the device tree says about every hart. Must run before the heap is initialized over
+/// it, and before the other harts are started.
+pub fn init(dt: &DevTree) {
+    // usually only given once for all harts in /cpus, which comes before them
+    let mut timebase = TIMEBASE_FREQ;
+    let mut nodes = dt.nodes();
+    while let Ok(Some(node)) = nodes.next() {
+        if node.name() == Ok("cpus") {
+            if let Some(freq) = prop_u32(&node, "timebase-frequency") {
+                timebase = freq as usize;
+            }
+            continue;
+        }
+
+        let is_cpu = node
+            .props()
+            .find(|prop| Ok(prop.name()? == "device_type" && prop.str()? == "cpu"))
+            .is_ok_and(|prop| prop.is_some());
+        if !is_cpu {
+            continue;
+        }
+
+        let Some(hart) = prop_u32(&node, "reg").map(|reg| reg as usize) else {
+            continue;
+        };
+        let Some((letters, extensions)) = node
+            .props()
+            .find(|prop| Ok(prop.name()? == "riscv,isa"))
+            .ok()
+            .flatten()
+            .and_then(|prop| prop.str().ok())
+            .and_then(parse)
+        else {
+            continue;
+        };
+        if hart >= MAX_HARTS {
+            continue;
+        }
+
+        let mmu = node
+            .props()
+            .find(|prop| Ok(prop.name()? == "mmu-type"))
+            .ok()
+            .flatten()
+            .and_then(|prop| prop.str().ok())
+            .map_or(Mmu::Unknown, Mmu::parse);
+        MMUS[hart].store(mmu as u8, Ordering::Relaxed);
+        TIMEBASES[hart].store(
+            prop_u32(&node, "timebase-frequency").map_or(timebase, |freq| freq as usize),
+            Ordering::Relaxed,
+        );
+        LETTERS[hart].store(letters, Ordering::Relaxed);
+        EXTENSIONS[hart].store(extensions.bits(), Ordering::Relaxed);
+        // 64 bytes is the only size anything implements so far
+        let block = prop_u32(&node, "riscv,cboz-block-size").map_or(64, |size| size as usize);
+        if block.is_power_of_two() && block <= Page::SIZE {
+            DT_CBOZ_BLOCK[hart].store(block, Ordering::Relaxed);
+        }
+    }
+
+    // the cycle and instruction counters let a program time someone else's code closely enough to
+    // learn what it's doing, so they're only for when that doesn't matter
+    let mut counters = Counters::Time;
+    if bootargs::get("rdcycle") == Some("on") {
+        counters |= Counters::Cycle | Counters::Instret;
+    }
+    let timebase = TIMEBASES
+        .get(r_tp())
+        .map(|freq| freq.load(Ordering::Relaxed))
+        .filter(|&freq| freq != 0)
+        .unwrap_or(timebase);
+    let info = SysInfo {
+        timebase_freq: timebase as u64,
+        counters,
+    };
+    unsafe { addr_of_mut!(INFO_PAGE).cast::<SysInfo>().write(info) };
+}
+
+/// What every process can read at `layout::INFO`
+pub fn info() -> &'static SysInfo {
+    unsafe { &*addr_of!(INFO_PAGE).cast::<SysInfo>() }
+}
+
+pub fn map_info(pt: &mut PageTable) -> bool {
+    pt.map_pages(
+        addr_of!(INFO_PAGE).into(),
+        VirtAddr(layout::INFO.start),
+        Page::SIZE,
+        Pte::U | Pte::R,
+    )
+}
+
+pub fn extensions(hart: usize) -> Extensions {
+    EXTENSIONS.get(hart).map_or(Extensions::empty(), |ext| {
+        Extensions::from_bits_retain(ext.load(Ordering::Relaxed))
+    })
+}
+
+/// Zero the cache block at `addr`, returning false if `cbo.zero` isn't allowed on this hart or
+/// `addr` isn't mapped. An ISA string can't tell if the firmware enabled it for S-mode. The fault
+/// is only recovered from once `trap::install_vector` has run on this hart.
+pub unsafe fn cbo_zero_nofault(addr: *mut u8) -> bool {
+    unsafe { __cbo_zero_nofault(addr) == 0 }
+}
+
+#[naked]
+unsafe extern "C" fn __cbo_zero_nofault(_addr: *mut u8) -> usize {
+    unsafe {
+        asm!(
+            r#"
+            .option push
+            .option arch, +zicboz
+        2:  cbo.zero (a0)
+            .option pop
+            li   a0, 0
+            ret
+        3:  li   a0, 1
+            ret
+
+            .pushsection .ex_table, "a"
+            .balign 8
+            .dword 2b, 3b
+            .popsection
+            "#,
+            options(noreturn),
+        );
+    }
+}
+
+/// Check the extensions of the current hart that its ISA string isn't enough to use, before
+/// anything else runs on it
+pub fn probe() {
+    let hart = r_tp();
+    w_scounteren(info().counters.bits() as usize);
+    if let Some(ids) = IDS.get(hart) {
+        for (id, get) in ids.iter().zip([
+            sbi::base::get_mvendorid,
+            sbi::base::get_marchid,
+            sbi::base::get_mimpid,
+        ]) {
+            id.store(get().unwrap_or(0), Ordering::Relaxed);
+        }
+    }
+
+    let Some(block) = DT_CBOZ_BLOCK.get(hart).map(|b| b.load(Ordering::Relaxed)) else {
+        return;
+    };
+    if block == 0 || !extensions(hart).contains(Extensions::Zicboz) {
+        return;
+    }
+
+    let Ok(mut page) = Page::uninit() else {
+        return;
+    };
+    if unsafe { cbo_zero_nofault(page.0.as_mut_ptr().cast()) } {
+        CBOZ_BLOCK[hart].store(block, Ordering::Relaxed);
+    } else {
+        EXTENSIONS[hart].fetch_and(!Extensions::Zicboz.bits(), Ordering::Relaxed);
+    }
+}
+
+/// The block size `cbo.zero` can be used with on the current hart, or 0 if it can't be
+pub fn cboz_block() -> usize {
+    CBOZ_BLOCK
+        .get(r_tp())
+        .map_or(0, |block| block.load(Ordering::Relaxed))
+}
+
+/// `/proc/cpuinfo`: a paragraph for every hart in the device tree. The ISA leaves out Zicboz if
+/// the hart turned out not to be able to use it, and the IDs are only known once it's running.
+#[cfg(feature = "procfs")]
+pub fn show(w: &mut dyn Write) -> fmt::Result {
+    for (hart, letters) in LETTERS.iter().enumerate() {
+        let letters = letters.load(Ordering::Relaxed);
+        if letters == 0 {
+            continue;
+        }
+
+        writeln!(w, "hart:     {hart}")?;
+        write!(w, "isa:      rv64")?;
+        for ch in b'a'..=b'z' {
+            if letters & 1 << (ch - b'a') != 0 {
+                w.write_char(ch as char)?;
+            }
+        }
+        for (name, _) in extensions(hart).iter_names() {
+            w.write_char('_')?;
+            for ch in name.chars() {
+                w.write_char(ch.to_ascii_lowercase())?;
+            }
+        }
+        writeln!(w)?;
+
+        let mmu = Mmu::from_repr(MMUS[hart].load(Ordering::Relaxed)).unwrap_or(Mmu::Unknown);
+        writeln!(w, "mmu:      {mmu:?}")?;
+        writeln!(w, "timebase: {}", TIMEBASES[hart].load(Ordering::Relaxed))?;
+        let online = stats::is_online(hart);
+        if online {
+            let [vendor, arch, imp] = &IDS[hart];
+            let load = |id: &AtomicUsize| id.load(Ordering::Relaxed);
+            writeln!(w, "vendor:   {:#x}", load(vendor))?;
+            writeln!(w, "arch:     {:#x}", load(arch))?;
+            writeln!(w, "impl:     {:#x}", load(imp))?;
+        }
+        writeln!(w, "online:   {}\n", if online { "yes" } else { "no" })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use shared::{
+    log::{LogLevel, LogSubsystem},
+    sys::MAX_HOSTNAME_LEN,
+};
+
+use crate::{
+    hostname, println,
+    tunable::{Kind, Tunable},
+};
+
+/// Indexed by `LogLevel`
+pub static LEVEL: Tunable = Tunable::new(
+    LogLevel::Info as usize,
+    Kind::Names(&["error", "warn", "info", "debug"]),
+);
+static SUBSYSTEMS: AtomicU32 = AtomicU32::new(0);
+
+pub fn level() -> LogLevel {
+    LogLevel::from_repr(LEVEL.get()).unwrap_or(LogLevel::Info)
+}
+
+pub fn set_level(level: LogLevel) -> LogLevel {
+    LogLevel::from_repr(LEVEL.swap(level as usize)).unwrap_or(LogLevel::Info)
+}
+
+pub fn subsystems() -> LogSubsystem {
+    LogSubsystem::from_bits_truncate(SUBSYSTEMS.load(Ordering::Relaxed))
+}
+
+pub fn set_subsystems(enable: LogSubsystem, disable: LogSubsystem) {
+    SUBSYSTEMS.fetch_or(enable.bits(), Ordering::Relaxed);
+    SUBSYSTEMS.fetch_and(!disable.bits(), Ordering::Relaxed);
+}
+
+/// Debug messages are shown if the console level is `Debug` or their subsystem has been enabled,
+/// everything else only depends on the console level.
+pub fn enabled(level: LogLevel, subsys: LogSubsystem) -> bool {
+    level <= self::level() || (level == LogLevel::Debug && subsystems().intersects(subsys))
+}
+
+/// Messages are prefixed with the hostname, so logs from several machines can be told apart
+pub fn print(args: fmt::Arguments) {
+    let mut buf = [0; MAX_HOSTNAME_LEN];
+    let hostname = hostname::copy(&mut buf);
+    println!("{hostname}: {args}");
+}
+
+#[macro_export]
+macro_rules! klog {
+    ($level: ident, $subsys: ident, $($arg: tt)*) => ({
+        if $crate::klog::enabled(
+            shared::log::LogLevel::$level,
+            shared::log::LogSubsystem::$subsys,
+        ) {
+            $crate::klog::print(format_args!($($arg)*));
+        }
+    });
+}
@@ -0,0 +1,635 @@
+//! Self tests run on the boot hart before init is spawned, to catch miscompiles and platform
+//! quirks before anything in userland can trip over them
+
+use alloc::{format, string::String};
+use core::arch::asm;
+
+use servos::{
+    elf::{ElfFile, EM_RISCV, ET_EXEC, PT_LOAD},
+    riscv::{disable_intr, r_time, TIMEBASE_FREQ},
+};
+use shared::sys::ProcHandle;
+
+use crate::{
+    audit::{self, Resource},
+    dump_fdt,
+    fs::initrd::{InitRd, INITRD_MAGIC, INODE_DIR},
+    isa, power, print, println,
+    proc::Exited,
+    stats,
+    trap::{self, TrapCause},
+    vmm::{self, Page, PageTable, Pte, User, VirtAddr},
+};
+
+type TestResult = Result<(), String>;
+type Test = fn() -> TestResult;
+
+const TESTS: &[(&str, Test)] = &[
+    ("vmm map/translate/unmap", vmm_round_trip),
+    ("user copy", user_copy),
+    ("zero page sharing", zero_pages),
+    ("page table teardown", teardown),
+    ("trap round trip", trap_round_trip),
+    ("timer latency", timer_latency),
+    ("misaligned parsers", misaligned_parsers),
+    ("shutdown deadline", shutdown_deadline),
+    ("cbo.zero fixup", cbo_zero_fixup),
+    ("zero pool scrub", zero_pool_scrub),
+];
+
+/// An arbitrary user address for the scratch page tables
+const SCRATCH: VirtAddr = VirtAddr(0x4000_0000);
+
+/// Run all tests, printing the result of each. Panics if any of them failed.
+pub fn run() {
+    println!("Running kernel self tests");
+    let mut failed = 0;
+    for (name, test) in TESTS {
+        // printed first so a test that hangs can be identified
+        print!("  {name}: ");
+        match test() {
+            Ok(()) => println!("ok"),
+            Err(err) => {
+                println!("FAILED: {err}");
+                failed += 1;
+            }
+        }
+    }
+
+    if failed != 0 {
+        panic!("{failed} kernel self test(s) failed");
+    }
+}
+
+fn check(cond: bool, msg: &str) -> TestResult {
+    if cond {
+        Ok(())
+    } else {
+        Err(msg.into())
+    }
+}
+
+fn scratch_table() -> Result<alloc::boxed::Box<PageTable>, String> {
+    PageTable::try_alloc().map_err(|_| "out of memory".into())
+}
+
+fn vmm_round_trip() -> TestResult {
+    let mut pt = scratch_table()?;
+    let page = Page::zeroed().map_err(|_| "out of memory")?;
+    let pa = &*page as *const Page as usize;
+    let phys = |pt: &PageTable, va: VirtAddr, perms| va.to_phys(pt, perms).map(|pa| pa.0);
+
+    check(pt.map_owned_page(page, SCRATCH, Pte::Urw), "map failed")?;
+    check(
+        phys(&pt, SCRATCH + 0x123, Pte::Urw) == Ok(pa + 0x123),
+        "translated to the wrong address",
+    )?;
+    check(
+        phys(&pt, SCRATCH, Pte::X).is_err(),
+        "translated with missing permissions",
+    )?;
+    check(
+        phys(&pt, SCRATCH + Page::SIZE, Pte::empty()).is_err(),
+        "translated an unmapped page",
+    )?;
+    check(pt.unmap_page(SCRATCH), "unmap failed")?;
+    check(
+        phys(&pt, SCRATCH, Pte::empty()).is_err(),
+        "translated after unmap",
+    )?;
+    check(!pt.unmap_page(SCRATCH), "unmapped a page twice")?;
+
+    // an unaligned range that straddles two pages
+    let va = SCRATCH + Page::SIZE - 1;
+    check(pt.map_new_pages(va, 2, Pte::Urw, true), "map failed")?;
+    let mut ranges = va.iter_phys(&pt, 2, Pte::Urw);
+    for _ in 0..2 {
+        let range = ranges.next().ok_or("missing page")?;
+        let range = range.map_err(|_| "translation failed")?;
+        check(
+            range.end as usize - range.start as usize == 1,
+            "page split in the wrong place",
+        )?;
+    }
+    check(ranges.next().is_none(), "too many pages")?;
+    pt.unmap_pages(SCRATCH, va + 1);
+    check(
+        phys(&pt, va, Pte::empty()).is_err() && phys(&pt, va + 1, Pte::empty()).is_err(),
+        "translated after unmap",
+    )?;
+
+    check(
+        !pt.map_new_pages(VirtAddr::MAX - 1, 2, Pte::Urw, true),
+        "mapped past the end of the address space",
+    )
+}
+
+fn user_copy() -> TestResult {
+    let mut pt = scratch_table()?;
+    let rw = SCRATCH;
+    let ro = rw + 2 * Page::SIZE;
+    let kernel = ro + Page::SIZE;
+    let unmapped = kernel + Page::SIZE;
+    check(
+        pt.map_new_pages(rw, 2 * Page::SIZE, Pte::Urw, true),
+        "map failed",
+    )?;
+    check(
+        pt.map_new_pages(ro, Page::SIZE, Pte::U | Pte::R, true),
+        "map failed",
+    )?;
+    check(
+        pt.map_new_pages(kernel, Page::SIZE, Pte::Rw, true),
+        "map failed",
+    )?;
+
+    let mut data = [0u8; 64];
+    for (i, b) in data.iter_mut().enumerate() {
+        *b = (i * 7 + 3) as u8;
+    }
+
+    let boundary = rw + Page::SIZE - data.len() / 2;
+    boundary
+        .copy_to(&pt, &data, None)
+        .map_err(|_| "copy to user failed")?;
+    let read = User::<[u8; 64]>::new(boundary)
+        .read(&pt)
+        .map_err(|_| "copy from user failed")?;
+    check(read == data, "data changed across a page boundary")?;
+
+    let val = 0x0123_4567_89ab_cdefu64;
+    let user = User::<u64>::new(rw + Page::SIZE - 3);
+    user.write(&pt, &val).map_err(|_| "write failed")?;
+    check(user.read(&pt) == Ok(val), "unaligned value changed")?;
+
+    check(
+        ro.copy_to(&pt, &data, None).is_err(),
+        "wrote to a read only page",
+    )?;
+    check(
+        User::<u64>::new(kernel).read(&pt).is_err(),
+        "read a page without the user bit",
+    )?;
+    check(
+        User::<u64>::new(unmapped).read(&pt).is_err(),
+        "read an unmapped page",
+    )?;
+    check(
+        User::<[u8; 64]>::new(unmapped - 8).read(&pt).is_err(),
+        "read past the end of a mapping",
+    )
+}
+
+/// ABI names of the general purpose registers, indexed by register number
+fn zero_pages() -> TestResult {
+    let mut pt = scratch_table()?;
+    let rw = SCRATCH;
+    let ro = rw + 2 * Page::SIZE;
+    check(
+        pt.map_zero_pages(rw, 2 * Page::SIZE, Pte::Urw),
+        "map failed",
+    )?;
+    check(
+        pt.map_zero_pages(ro, Page::SIZE, Pte::U | Pte::R),
+        "map failed",
+    )?;
+    let phys = |pt: &PageTable, va: VirtAddr| va.to_phys(pt, Pte::U | Pte::R).map(|pa| pa.0);
+
+    check(
+        phys(&pt, rw) == phys(&pt, rw + Page::SIZE) && phys(&pt, rw) == phys(&pt, ro),
+        "untouched pages aren't shared",
+    )?;
+    check(
+        rw.to_phys(&pt, Pte::W).is_err(),
+        "the zero page is writable",
+    )?;
+    check(
+        User::<u64>::new(rw + 8).read(&pt) == Ok(0),
+        "untouched page isn't zero",
+    )?;
+
+    // the kernel writing gives the page its own copy, and leaves the rest alone
+    let val = 0x0123_4567_89ab_cdefu64;
+    User::<u64>::new(rw + 8)
+        .write(&pt, &val)
+        .map_err(|_| "write failed")?;
+    check(
+        User::<u64>::new(rw + 8).read(&pt) == Ok(val),
+        "write was lost",
+    )?;
+    check(
+        phys(&pt, rw) != phys(&pt, rw + Page::SIZE),
+        "written page is still shared",
+    )?;
+    check(
+        User::<u64>::new(rw + Page::SIZE + 8).read(&pt) == Ok(0),
+        "the zero page was written",
+    )?;
+
+    // as does a write fault from user mode, but only on writable mappings
+    check(pt.write_fault(rw + Page::SIZE), "write fault not handled")?;
+    check(
+        (rw + Page::SIZE).to_phys(&pt, Pte::Urw).is_ok(),
+        "page isn't writable after a write fault",
+    )?;
+    check(
+        !pt.write_fault(rw + Page::SIZE),
+        "handled a write fault twice",
+    )?;
+    check(
+        !pt.write_fault(ro),
+        "handled a write fault on a read only page",
+    )?;
+    check(
+        ro.copy_to(&pt, &[1], None).is_err(),
+        "wrote to a read only page",
+    )?;
+    check(
+        User::<u64>::new(ro).read(&pt) == Ok(0),
+        "read only page changed",
+    )
+}
+
+fn teardown() -> TestResult {
+    let mut pt = scratch_table()?;
+    check(
+        pt.map_new_pages(SCRATCH, 3 * Page::SIZE, Pte::Urw, true),
+        "map failed",
+    )?;
+    check(
+        pt.map_zero_pages(SCRATCH + 3 * Page::SIZE, Page::SIZE, Pte::Urw),
+        "map failed",
+    )?;
+    // the root, one table at each level below it and the three pages, but not the zero page
+    check(pt.pages_owned() == 6, "wrong number of owned pages")?;
+    check(
+        (SCRATCH + 3 * Page::SIZE).copy_to(&pt, &[1], None).is_ok(),
+        "write failed",
+    )?;
+    check(pt.pages_owned() == 7, "copied page isn't owned")?;
+
+    let before = audit::released_here(Resource::Pages);
+    drop(pt);
+    check(
+        !audit::ENABLED || audit::released_here(Resource::Pages) - before == 7,
+        "dropping the table didn't free every owned page",
+    )
+}
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+fn trap_round_trip() -> TestResult {
+    // nothing but the fault should interrupt the routine
+    let _token = disable_intr();
+    let faults = stats::traps(TrapCause::LoadPageFault);
+    let clobbered = unsafe { fault_with_pattern() };
+    check(
+        stats::traps(TrapCause::LoadPageFault) == faults + 1,
+        "the fault wasn't taken",
+    )?;
+    match clobbered {
+        0 => Ok(()),
+        reg => Err(format!("{} was clobbered", REG_NAMES[reg])),
+    }
+}
+
+/// Fill every register the kernel doesn't reserve with a pattern, take a page fault that
+/// `sv_trap_vec` recovers from through the exception table, and check the pattern survived.
+/// Returns the number of the first clobbered register, or 0 if they all came back intact.
+#[naked]
+unsafe extern "C" fn fault_with_pattern() -> usize {
+    unsafe {
+        asm!(
+            r#"
+            addi sp, sp, -112
+            sd   sp, 0(sp)
+            sd   ra, 8(sp)
+            sd   s0, 16(sp)
+            sd   s1, 24(sp)
+            sd   s2, 32(sp)
+            sd   s3, 40(sp)
+            sd   s4, 48(sp)
+            sd   s5, 56(sp)
+            sd   s6, 64(sp)
+            sd   s7, 72(sp)
+            sd   s8, 80(sp)
+            sd   s9, 88(sp)
+            sd   s10, 96(sp)
+            sd   s11, 104(sp)
+
+            li   ra, 0x701
+            li   t0, 0x705
+            li   t1, 0x706
+            li   t2, 0x707
+            li   s0, 0x708
+            li   s1, 0x709
+            li   a0, 0x70a
+            li   a1, 0x70b
+            li   a2, 0x70c
+            li   a3, 0x70d
+            li   a4, 0x70e
+            li   a5, 0x70f
+            li   a6, 0x710
+            li   a7, 0x711
+            li   s2, 0x712
+            li   s3, 0x713
+            li   s4, 0x714
+            li   s5, 0x715
+            li   s6, 0x716
+            li   s7, 0x717
+            li   s8, 0x718
+            li   s9, 0x719
+            li   s10, 0x71a
+            li   s11, 0x71b
+            li   t3, 0x71c
+            li   t4, 0x71d
+            li   t5, 0x71e
+            li   t6, 0x71f
+
+        2:  ld   a0, 0(zero)            # never mapped, the fault resumes at 3f
+        3:  addi t6, t6, -0x71f
+            beqz t6, 4f
+            li   t6, 31
+            j    5f
+
+            # t6 is free from here on, and holds the register being checked
+        4:  li   t6, 1
+            addi ra, ra, -0x701
+            bnez ra, 5f
+            li   t6, 5
+            addi t0, t0, -0x705
+            bnez t0, 5f
+            li   t6, 6
+            addi t1, t1, -0x706
+            bnez t1, 5f
+            li   t6, 7
+            addi t2, t2, -0x707
+            bnez t2, 5f
+            li   t6, 8
+            addi s0, s0, -0x708
+            bnez s0, 5f
+            li   t6, 9
+            addi s1, s1, -0x709
+            bnez s1, 5f
+            li   t6, 10
+            addi a0, a0, -0x70a
+            bnez a0, 5f
+            li   t6, 11
+            addi a1, a1, -0x70b
+            bnez a1, 5f
+            li   t6, 12
+            addi a2, a2, -0x70c
+            bnez a2, 5f
+            li   t6, 13
+            addi a3, a3, -0x70d
+            bnez a3, 5f
+            li   t6, 14
+            addi a4, a4, -0x70e
+            bnez a4, 5f
+            li   t6, 15
+            addi a5, a5, -0x70f
+            bnez a5, 5f
+            li   t6, 16
+            addi a6, a6, -0x710
+            bnez a6, 5f
+            li   t6, 17
+            addi a7, a7, -0x711
+            bnez a7, 5f
+            li   t6, 18
+            addi s2, s2, -0x712
+            bnez s2, 5f
+            li   t6, 19
+            addi s3, s3, -0x713
+            bnez s3, 5f
+            li   t6, 20
+            addi s4, s4, -0x714
+            bnez s4, 5f
+            li   t6, 21
+            addi s5, s5, -0x715
+            bnez s5, 5f
+            li   t6, 22
+            addi s6, s6, -0x716
+            bnez s6, 5f
+            li   t6, 23
+            addi s7, s7, -0x717
+            bnez s7, 5f
+            li   t6, 24
+            addi s8, s8, -0x718
+            bnez s8, 5f
+            li   t6, 25
+            addi s9, s9, -0x719
+            bnez s9, 5f
+            li   t6, 26
+            addi s10, s10, -0x71a
+            bnez s10, 5f
+            li   t6, 27
+            addi s11, s11, -0x71b
+            bnez s11, 5f
+            li   t6, 28
+            addi t3, t3, -0x71c
+            bnez t3, 5f
+            li   t6, 29
+            addi t4, t4, -0x71d
+            bnez t4, 5f
+            li   t6, 30
+            addi t5, t5, -0x71e
+            bnez t5, 5f
+            li   t6, 2
+            ld   t0, 0(sp)
+            bne  t0, sp, 5f
+            li   t6, 0
+
+        5:  mv   a0, t6
+            ld   ra, 8(sp)
+            ld   s0, 16(sp)
+            ld   s1, 24(sp)
+            ld   s2, 32(sp)
+            ld   s3, 40(sp)
+            ld   s4, 48(sp)
+            ld   s5, 56(sp)
+            ld   s6, 64(sp)
+            ld   s7, 72(sp)
+            ld   s8, 80(sp)
+            ld   s9, 88(sp)
+            ld   s10, 96(sp)
+            ld   s11, 104(sp)
+            addi sp, sp, 112
+            ret
+
+            .pushsection .ex_table, "a"
+            .balign 8
+            .dword 2b, 3b
+            .popsection
+            "#,
+            options(noreturn),
+        );
+    }
+}
+
+fn timer_latency() -> TestResult {
+    const DELAY: usize = TIMEBASE_FREQ / 100;
+    const LIMIT: usize = TIMEBASE_FREQ / 10;
+
+    let ticks = stats::traps(TrapCause::TimerIntr);
+    let deadline = r_time() + DELAY;
+    check(trap::set_timer(deadline), "set_timer failed")?;
+    loop {
+        // wfi still wakes with interrupts disabled, so one can't slip in between the check and
+        // the wait
+        let token = disable_intr();
+        if stats::traps(TrapCause::TimerIntr) != ticks {
+            break;
+        }
+        unsafe { asm!("wfi", options(nomem, nostack)) };
+        drop(token);
+    }
+
+    let now = r_time();
+    check(now >= deadline, "the interrupt arrived early")?;
+    let latency = now - deadline;
+    check(
+        latency <= LIMIT,
+        &format!("the interrupt arrived {latency} ticks late"),
+    )?;
+    print!("{}us, ", latency / (TIMEBASE_FREQ / 1_000_000));
+    Ok(())
+}
+
+#[repr(C, align(8))]
+struct Aligned([u8; 256]);
+
+/// Copy `data` to `shift` bytes past an 8 byte boundary
+fn misaligned<'a>(buf: &'a mut Aligned, data: &[u8], shift: usize) -> &'a [u8] {
+    let dst = &mut buf.0[shift..][..data.len()];
+    dst.copy_from_slice(data);
+    dst
+}
+
+fn misaligned_parsers() -> TestResult {
+    let put =
+        |buf: &mut [u8], at: usize, bytes: &[u8]| buf[at..][..bytes.len()].copy_from_slice(bytes);
+
+    // the smallest executable: a header, one program header and 4 bytes of code
+    let mut elf = [0; 64 + 56 + 4];
+    put(&mut elf, 0, b"\x7fELF\x02\x01\x01");
+    put(&mut elf, 0x10, &ET_EXEC.to_le_bytes());
+    put(&mut elf, 0x12, &EM_RISCV.to_le_bytes());
+    put(&mut elf, 0x14, &1u32.to_le_bytes());
+    put(&mut elf, 0x20, &64u64.to_le_bytes());
+    put(&mut elf, 0x38, &1u16.to_le_bytes());
+    let vaddr = 0x1_0000 + 120u64;
+    for (at, val) in [(8, 120), (16, vaddr), (32, 4), (40, 4), (48, 0x1000)] {
+        put(&mut elf, 64 + at, &u64::to_le_bytes(val));
+    }
+    put(&mut elf, 64, &PT_LOAD.to_le_bytes());
+
+    let mut initrd = [0; 16 + 48];
+    put(&mut initrd, 0, &INITRD_MAGIC.to_le_bytes());
+    put(&mut initrd, 8, &1u64.to_le_bytes());
+    put(&mut initrd, 16 + 34, &[INODE_DIR]);
+
+    let mut rsvmap = [0; 32];
+    put(&mut rsvmap, 0, &0x8000_0000u64.to_be_bytes());
+    put(&mut rsvmap, 8, &0x1000u64.to_be_bytes());
+
+    let mut buf = Aligned([0; 256]);
+    for shift in 1..8 {
+        {
+            let file = ElfFile::new(misaligned(&mut buf, &elf, shift))
+                .map_err(|err| format!("misaligned executable: {err:?}"))?;
+            let mut segments = file
+                .load_segments()
+                .map_err(|err| format!("misaligned segments: {err:?}"))?;
+            check(
+                segments.next().is_some_and(|phdr| phdr.vaddr == vaddr)
+                    && segments.next().is_none(),
+                "misaligned program header read wrong",
+            )?;
+        }
+
+        check(
+            InitRd::new(misaligned(&mut buf, &initrd, shift)).is_some(),
+            "misaligned initrd rejected",
+        )?;
+
+        let rsv = misaligned(&mut buf, &rsvmap, shift);
+        check(
+            dump_fdt::reserved_entries(rsv, 0).eq([(0x8000_0000, 0x1000)]),
+            "misaligned reserve entries read wrong",
+        )?;
+    }
+    Ok(())
+}
+
+fn shutdown_deadline() -> TestResult {
+    // no process has these handles, and nothing else can shut down before init is spawned
+    let (first, second) = (ProcHandle(u64::MAX - 1), ProcHandle(u64::MAX - 2));
+    let exited = |handle| Exited {
+        handle,
+        acct: unsafe { core::mem::zeroed() },
+    };
+
+    let (deadline, started) = power::begin_shutdown(first);
+    check(started, "first caller didn't start the shutdown")?;
+    check(
+        power::begin_shutdown(first) == (deadline, false),
+        "retrying restarted the shutdown",
+    )?;
+    check(
+        power::begin_shutdown(second) == (deadline, false),
+        "another caller restarted the shutdown",
+    )?;
+
+    // only the exit of the process that started it ends the shutdown
+    power::process_exited(&exited(second));
+    check(
+        power::begin_shutdown(second) == (deadline, false),
+        "another caller's exit ended the shutdown",
+    )?;
+    power::process_exited(&exited(first));
+    let (_, started) = power::begin_shutdown(second);
+    power::process_exited(&exited(second));
+    check(started, "the starter's exit didn't end the shutdown")
+}
+
+fn cbo_zero_fixup() -> TestResult {
+    // the null page is never mapped, so cbo.zero faults here whether or not the firmware enabled
+    // it, and has to come back through the same fixup `isa::probe` relies on
+    check(
+        !unsafe { isa::cbo_zero_nofault(core::ptr::null_mut()) },
+        "cbo.zero on an unmapped address didn't fault",
+    )?;
+
+    let block = isa::cboz_block();
+    if block == 0 {
+        return Ok(());
+    }
+    let mut page = Page::uninit().map_err(|_| "out of memory")?;
+    let bytes =
+        unsafe { core::slice::from_raw_parts_mut(page.0.as_mut_ptr().cast::<u8>(), Page::SIZE) };
+    bytes.fill(0xa5);
+    check(
+        unsafe { isa::cbo_zero_nofault(bytes.as_mut_ptr()) },
+        "cbo.zero faulted after the probe allowed it",
+    )?;
+    check(
+        bytes[..block].iter().all(|&b| b == 0) && bytes[block..].iter().all(|&b| b == 0xa5),
+        "cbo.zero cleared the wrong bytes",
+    )
+}
+
+fn zero_pool_scrub() -> TestResult {
+    let page = vmm::dirty_pool_page().ok_or("couldn't fill the zero pool")?;
+    let (found, errors) = vmm::scrub_state(page);
+    check(found, "the dirty page isn't in the pool")?;
+
+    vmm::scrub_now();
+    // idle harts may refill the pool right away, so look for the page rather than at the length
+    let (found, after) = vmm::scrub_state(page);
+    check(after == errors + 1, "the dirty page wasn't counted")?;
+    check(!found, "the dirty page is still in the pool")
+}
@@ -2,14 +2,30 @@ use core::{
     cell::UnsafeCell,
     mem::ManuallyDrop,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
-use crate::riscv::{disable_intr, InterruptToken};
+use crate::riscv::{disable_intr, r_tp, InterruptToken};
+
+const MIN_BACKOFF: usize = 4;
+const MAX_BACKOFF: usize = 1024;
+const NO_OWNER: usize = usize::MAX;
+
+/// How long to wait for a lock before assuming a deadlock with `debug-locks`
+#[cfg(any(debug_assertions, feature = "debug-locks"))]
+const DEADLOCK_TIMEOUT_MS: usize = 5000;
 
 pub struct SpinLocked<T> {
     data: UnsafeCell<T>,
     locked: AtomicBool,
+    owner: AtomicUsize,
+    contended: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LockStats {
+    /// Number of acquisitions that had to wait for another holder
+    pub contended: usize,
 }
 
 unsafe impl<T> Sync for SpinLocked<T> {}
@@ -19,30 +35,84 @@ impl<T> SpinLocked<T> {
         Self {
             data: UnsafeCell::new(data),
             locked: AtomicBool::new(false),
+            owner: AtomicUsize::new(NO_OWNER),
+            contended: AtomicUsize::new(0),
         }
     }
 
     pub fn lock(&self) -> Guard<T> {
         let token = disable_intr();
-        while self
-            .locked
-            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
-            .is_err()
-        {
+        if self.try_acquire() {
+            return Guard::new(token, self);
+        }
+
+        self.contended.fetch_add(1, Ordering::Relaxed);
+        #[cfg(any(debug_assertions, feature = "debug-locks"))]
+        let start = crate::riscv::r_time();
+        let mut backoff = MIN_BACKOFF;
+        loop {
             while self.locked.load(Ordering::Relaxed) {
-                core::hint::spin_loop();
+                for _ in 0..backoff {
+                    core::hint::spin_loop();
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+
+                #[cfg(any(debug_assertions, feature = "debug-locks"))]
+                if crate::riscv::r_time() - start
+                    > DEADLOCK_TIMEOUT_MS * (crate::riscv::TIMEBASE_FREQ / 1000)
+                {
+                    panic!(
+                        "possible deadlock: hart {} waited {DEADLOCK_TIMEOUT_MS}ms for lock {:?} \
+                         held by hart {}",
+                        r_tp(),
+                        self as *const Self,
+                        self.owner.load(Ordering::Relaxed) as isize,
+                    );
+                }
             }
-        }
 
-        Guard::new(token, self)
+            if self.try_acquire() {
+                return Guard::new(token, self);
+            }
+        }
     }
 
     pub fn try_lock(&self) -> Option<Guard<T>> {
         let token = disable_intr();
-        self.locked
+        self.try_acquire().then(|| Guard::new(token, self))
+    }
+
+    /// Spin for at most `attempts` tries to take the lock, backing off between each one.
+    pub fn try_lock_bounded(&self, attempts: usize) -> Option<Guard<T>> {
+        let mut backoff = MIN_BACKOFF;
+        for _ in 0..attempts {
+            if let Some(guard) = self.try_lock() {
+                return Some(guard);
+            }
+
+            for _ in 0..backoff {
+                core::hint::spin_loop();
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+        None
+    }
+
+    pub fn stats(&self) -> LockStats {
+        LockStats {
+            contended: self.contended.load(Ordering::Relaxed),
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        let success = self
+            .locked
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
-            .ok()
-            .map(|_| Guard::new(token, self))
+            .is_ok();
+        if success {
+            self.owner.store(r_tp(), Ordering::Relaxed);
+        }
+        success
     }
 
     pub fn with<U>(&self, f: impl FnOnce(Guard<T>) -> U) -> U {
@@ -50,6 +120,7 @@ impl<T> SpinLocked<T> {
     }
 
     unsafe fn unlock(&self) {
+        self.owner.store(NO_OWNER, Ordering::Relaxed);
         self.locked.store(false, Ordering::Release)
     }
 
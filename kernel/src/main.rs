@@ -14,7 +14,7 @@
 #![feature(maybe_uninit_as_bytes)]
 #![deny(unsafe_op_in_unsafe_fn)]
 
-use alloc::sync::Arc;
+use alloc::{format, sync::Arc};
 use core::{
     alloc::Allocator,
     arch::asm,
@@ -22,22 +22,21 @@ use core::{
     mem::MaybeUninit,
     ops::Range,
     ptr::{addr_of, addr_of_mut},
-    sync::atomic::AtomicUsize,
+    sync::atomic::{AtomicBool, AtomicU16, AtomicUsize, Ordering},
 };
-use dev::{console::Console, null::NullDevice, zero::ZeroDevice};
+use dev::{console::Console, null::NullDevice, random::RandomDevice, zero::ZeroDevice};
 use fdt_rs::{
     base::{DevTree, DevTreeNode},
     prelude::{FallibleIterator, PropReader},
 };
-use fs::{
-    dev::DeviceFs,
-    initrd::InitRd,
-    path::Path,
-    vfs::{Vfs, VFS},
-};
+#[cfg(feature = "procfs")]
+use fs::proc::ProcFs;
+use fs::{dev::DeviceFs, path::Path, vfs::VFS};
+#[cfg(feature = "net")]
+use net::{pcap::PcapDevice, tun::TunDevice};
 use power::{PowerManagement, POWER};
 use plic::PLIC;
-use proc::{Process, Scheduler, HART_FIRST_STACK, HART_STACK_LEN};
+use proc::{Scheduler, HART_FIRST_STACK, HART_STACK_LEN, HART_STACK_STRIDE, MAX_HARTS};
 use servos::{
     drivers::{Ns16550a, Syscon},
     heap::BlockAlloc,
@@ -46,23 +45,91 @@ use servos::{
     sbi::{self, hsm::HartState},
     Align16,
 };
-use shared::io::OpenFlags;
+use shared::{
+    io::{DeviceId, MountFlags},
+    sys::MAX_HOSTNAME_LEN,
+};
+use trap::TrapCause;
 use uart::{DebugIo, CONS};
 use vmm::{Page, PageTable, Pte};
 
+mod acct;
+mod audit;
+mod bootargs;
 mod dev;
 mod dump_fdt;
+mod fault;
+mod filter;
 mod fs;
+mod hostname;
+mod ipc;
+mod isa;
+mod klog;
+#[cfg(feature = "ktest")]
+mod ktest;
+#[cfg(feature = "net")]
+mod net;
 mod power;
 mod plic;
+mod poll;
 mod proc;
+mod random;
+mod rootfs;
+mod stats;
 mod sys;
 mod trap;
+mod tunable;
 mod uart;
 mod vmm;
 
 extern crate alloc;
 
+/// Optional kernel subsystems and debugging aids, listed at boot
+const FEATURES: &[(&str, bool)] = &[
+    ("net", cfg!(feature = "net")),
+    ("procfs", cfg!(feature = "procfs")),
+    (
+        "debug-locks",
+        cfg!(any(debug_assertions, feature = "debug-locks")),
+    ),
+    (
+        "leak-check",
+        cfg!(any(debug_assertions, feature = "leak-check")),
+    ),
+    ("page-poison", cfg!(feature = "page-poison")),
+    ("ktest", cfg!(feature = "ktest")),
+];
+
+/// `/proc/features`: the enabled features, one per line
+#[cfg(feature = "procfs")]
+fn show_features(w: &mut dyn core::fmt::Write) -> core::fmt::Result {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .try_for_each(|(name, _)| writeln!(w, "{name}"))
+}
+
+/// `/proc/locks`: how many times each of the busiest global locks had to be waited for since boot
+#[cfg(feature = "procfs")]
+fn show_locks(w: &mut dyn core::fmt::Write) -> core::fmt::Result {
+    let [proc_list, scheduler] = proc::lock_stats();
+    let locks = [
+        ("alloc", ALLOCATOR.stats()),
+        proc_list,
+        scheduler,
+        ("vfs", VFS.stats()),
+        ("console", CONS.stats()),
+        #[cfg(feature = "net")]
+        ("net", net::NET.stats()),
+    ];
+    locks
+        .iter()
+        .try_for_each(|(name, stats)| writeln!(w, "{name}: contended {}", stats.contended))
+}
+
+/// For the console UART, if neither the bootargs nor the device tree give a speed
+const DEFAULT_BAUD: u32 = 115200;
+
 static mut BOOT_STACK: Align16<MaybeUninit<[u8; HART_STACK_LEN]>> = Align16(MaybeUninit::uninit());
 
 #[global_allocator]
@@ -70,10 +137,22 @@ static ALLOCATOR: SpinLocked<BlockAlloc> = SpinLocked::new(BlockAlloc::new());
 
 static mut KPAGETABLE: PageTable = PageTable::new();
 
+#[repr(C, align(4096))]
+struct PageAligned<T: ?Sized>(T);
+
+/// Writable and page aligned so its pages can be given to the heap once the root filesystem is
+/// mounted, instead of taking up space in the kernel image for as long as it runs
+static mut INITRD: PageAligned<[u8; include_bytes!("../../initrd.img").len()]> =
+    PageAligned(*include_bytes!("../../initrd.img"));
+
 static mut CONSOLE_DEV: OnceCell<Arc<Console>> = OnceCell::new();
+/// Which serial port the console is, for its name in `/dev`
+static CONSOLE_INDEX: AtomicU16 = AtomicU16::new(0);
 
 static BOOT_HART: AtomicUsize = AtomicUsize::new(0);
 
+static PANICKING: [AtomicBool; MAX_HARTS] = [const { AtomicBool::new(false) }; MAX_HARTS];
+
 extern "C" {
     static _text_start: u8;
     static _rodata_start: u8;
@@ -90,9 +169,55 @@ extern "C" {
 
 #[panic_handler]
 fn on_panic(info: &core::panic::PanicInfo) -> ! {
+    use core::fmt::Write;
+
     let _ = disable_intr();
-    println!("[FATAL] panic: {info}");
+    let hart = r_tp();
+    if PANICKING[hart % MAX_HARTS].swap(true, Ordering::SeqCst) {
+        // the first panic happened while formatting or printing, so avoid doing either again
+        let mut buf = [0; 20];
+        let mut start = buf.len();
+        let mut n = hart;
+        loop {
+            start -= 1;
+            buf[start] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+
+        unsafe {
+            uart::raw_write(b"\n[FATAL] nested panic on hart ");
+            uart::raw_write(&buf[start..]);
+            uart::raw_write(b"\n");
+        }
+        halt();
+    }
 
+    let mut out = uart::EmergencyWriter;
+    _ = write!(out, "[FATAL] ");
+    // the lock might be held by this hart
+    let mut hostname = [0; MAX_HOSTNAME_LEN];
+    if let Some(hostname) = hostname::try_copy(&mut hostname) {
+        _ = write!(out, "{hostname}: ");
+    }
+    _ = write!(out, "panic on hart {hart}");
+    if let Some(pid) = proc::current_pid() {
+        _ = write!(out, " (PID {pid})");
+    }
+    _ = match TrapCause::current() {
+        Ok(cause) => write!(out, ", last trap {cause:?}"),
+        Err(cause) => write!(out, ", last trap {cause:#x}"),
+    };
+    _ = writeln!(out, ": {info}");
+    // the lock might be held by this hart, in which case nobody would ever flush the queue
+    unsafe { uart::force_flush() };
+
+    halt()
+}
+
+fn halt() -> ! {
     loop {
         unsafe {
             asm!("wfi", options(nomem, nostack));
@@ -143,10 +268,8 @@ extern "C" fn _start_hart(_hartid: usize, _satp: usize) -> ! {
 
             mv      tp, a0
 
-            # manual implementation of hart::stack_addr
-            li      t0, {stack_len}
-            li      t1, 0x1000
-            add     t0, t0, t1
+            # manual implementation of proc::hart_stack_top
+            li      t0, {stride}
             mul     t0, tp, t0
             li      sp, {first_stack}
             sub     sp, sp, t0
@@ -154,7 +277,7 @@ extern "C" fn _start_hart(_hartid: usize, _satp: usize) -> ! {
             tail    {init}",
 
             first_stack = const HART_FIRST_STACK.0,
-            stack_len = const HART_STACK_LEN,
+            stride = const HART_STACK_STRIDE,
 
             init = sym kinithart,
             options(noreturn),
@@ -171,6 +294,29 @@ unsafe fn find_reg_addr(node: &DevTreeNode) -> Option<usize> {
         .and_then(|prop| prop.u64(0).ok().map(|s| s as usize))
 }
 
+/// Find `node` in the device tree's `/aliases` and return the number after `prefix`, like 0 for
+/// `serial0`. Aliases are the board's own names for its devices, so unlike the order of the nodes
+/// they don't change between device trees.
+fn alias_index(dt: &DevTree, node: &DevTreeNode, prefix: &str) -> Option<u16> {
+    let name = node.name().ok()?;
+    let aliases = dt
+        .nodes()
+        .find(|node| node.name().map(|n| n == "aliases"))
+        .ok()
+        .flatten()?;
+    aliases
+        .props()
+        .iterator()
+        .filter_map(|prop| prop.ok())
+        .find_map(|prop| {
+            // the value is the path of the node it refers to
+            if prop.str().ok()?.rsplit('/').next() != Some(name) {
+                return None;
+            }
+            prop.name().ok()?.strip_prefix(prefix)?.parse().ok()
+        })
+}
+
 unsafe fn init_syscon(dt: &DevTree) -> Option<Syscon> {
     let node = dt
         .compatible_nodes("syscon")
@@ -216,8 +362,27 @@ unsafe fn init_uart(dt: &DevTree) -> Option<u32> {
         return None;
     };
 
-    println!("Found Ns16550a compatible device at address {base:#010x}");
-    *uart::CONS.lock() = uart::DebugIo::Ns16550a(unsafe { Ns16550a::new(base, clock, 76800) });
+    // console=ttyS0,115200n8 like Linux, then the speed the device tree says it's running at. A
+    // trailing r (115200n8r) turns on RTS/CTS flow control.
+    let options = bootargs::get("console").and_then(|console| Some(console.split_once(',')?.1));
+    let baud = options
+        .and_then(|options| options.split(|c: char| !c.is_ascii_digit()).next())
+        .and_then(|baud| baud.parse().ok())
+        .or_else(|| {
+            node.props()
+                .find(|prop| Ok(prop.name()? == "current-speed"))
+                .ok()
+                .flatten()
+                .and_then(|prop| prop.u32(0).ok())
+        })
+        .unwrap_or(DEFAULT_BAUD);
+
+    let index = alias_index(dt, &node, "serial").unwrap_or(0);
+    CONSOLE_INDEX.store(index, Ordering::Relaxed);
+    println!("Found Ns16550a compatible device at address {base:#010x} (ttyS{index}), {baud} baud");
+    let mut uart = unsafe { Ns16550a::new(base, clock, baud) };
+    uart.set_cts_flow(options.is_some_and(|options| options.ends_with('r')));
+    *uart::CONS.lock() = uart::DebugIo::Ns16550a(uart);
 
     Some(plic_irq)
 }
@@ -324,12 +489,15 @@ unsafe fn init_vmem(harts: usize) {
             continue;
         }
 
+        let stack = Global
+            .allocate(unsafe { Layout::from_size_align_unchecked(HART_STACK_LEN, 16) })
+            .expect("allocation failure allocating stack for a hart")
+            .cast::<u8>()
+            .as_ptr();
+        unsafe { stack.write_bytes(proc::HART_STACK_PAINT, HART_STACK_LEN) };
+        // nothing is mapped in the guard region below, see HART_STACK_STRIDE
         assert!(pt.map_pages(
-            Global
-                .allocate(unsafe { Layout::from_size_align_unchecked(HART_STACK_LEN, 16) })
-                .expect("allocation failure allocating stack for a hart")
-                .as_ptr()
-                .into(),
+            stack.into(),
             proc::hart_stack_top(i) - HART_STACK_LEN,
             HART_STACK_LEN,
             Pte::Rw
@@ -341,9 +509,12 @@ extern "C" fn kmain(hartid: usize, fdt: *const u8) -> ! {
     unsafe {
         println!("\n\n");
 
-        BOOT_HART.store(hartid, core::sync::atomic::Ordering::SeqCst);
+        BOOT_HART.store(hartid, Ordering::SeqCst);
 
         let dt = DevTree::from_raw_pointer(fdt).expect("Couldn't parse device tree from a1");
+        bootargs::init(&dt);
+        random::init();
+        fs::init_clock();
         let uart_plic_irq = init_uart(&dt);
         if uart_plic_irq.is_none() {
             println!("No Ns16550a node found in the device tree. Defaulting to SBI for I/O.");
@@ -358,6 +529,15 @@ extern "C" fn kmain(hartid: usize, fdt: *const u8) -> ! {
             addr_of!(BOOT_STACK),
             r_satp() as *const u8,
         );
+        print!("Features:");
+        for (name, _) in FEATURES.iter().filter(|(_, enabled)| *enabled) {
+            print!(" {name}");
+        }
+        println!();
+        let smp = !bootargs::has("nosmp");
+        if !smp {
+            println!("nosmp: starting the boot hart only");
+        }
 
         if let Some(syscon) = init_syscon(&dt) {
             println!("Syscon compatible device found at {:?}", syscon.addr());
@@ -365,10 +545,11 @@ extern "C" fn kmain(hartid: usize, fdt: *const u8) -> ! {
         }
 
         // TODO: maybe look in the device tree for hart count
-        const HARTS: usize = 64;
+        const HARTS: usize = MAX_HARTS;
 
         // note: the device tree lives somewhere in RAM outside the kernel area, it's potentially
         // invalidated once we initialize the heap over it
+        isa::init(&dt);
         init_heap(&dt);
         init_vmem(HARTS);
 
@@ -378,7 +559,7 @@ extern "C" fn kmain(hartid: usize, fdt: *const u8) -> ! {
         }
 
         let satp = PageTable::make_satp(addr_of!(KPAGETABLE));
-        for i in 0..HARTS {
+        for i in (0..HARTS).filter(|_| smp) {
             if matches!(sbi::hsm::hart_get_status(i), Ok(HartState::Stopped)) {
                 if let Err(err) = sbi::hsm::hart_start(i, _start_hart, satp) {
                     panic!("failed to start hart {i}: {err:?}");
@@ -391,39 +572,194 @@ extern "C" fn kmain(hartid: usize, fdt: *const u8) -> ! {
 }
 
 extern "C" fn kinithart(hartid: usize) -> ! {
+    // the probe may fault on firmware that hasn't enabled everything the hart supports
+    trap::install_vector();
+    isa::probe();
     println!(
         "Hello world from hart {hartid}: sp: {}",
         proc::hart_stack_top(hartid)
     );
 
-    if BOOT_HART.load(core::sync::atomic::Ordering::SeqCst) == hartid {
+    let boot_hart = BOOT_HART.load(Ordering::SeqCst) == hartid;
+    if boot_hart {
         let mut devices = DeviceFs::new();
         if let Some(cons) = unsafe { CONSOLE_DEV.get() } {
+            let index = CONSOLE_INDEX.load(Ordering::Relaxed);
+            devices
+                .add_device(
+                    Path::new(format!("ttyS{index}")).try_into().unwrap(),
+                    DeviceId::serial(index),
+                    cons.clone(),
+                )
+                .unwrap();
+            // so programs don't need to know which serial port it is
             devices
-                .add_device(Path::new("uart0").try_into().unwrap(), cons.clone())
+                .add_device(
+                    Path::new("console").try_into().unwrap(),
+                    DeviceId::CONSOLE,
+                    cons.clone(),
+                )
                 .unwrap();
         }
         devices
-            .add_device(Path::new("zero").try_into().unwrap(), Arc::new(ZeroDevice))
+            .add_device(
+                Path::new("zero").try_into().unwrap(),
+                DeviceId::ZERO,
+                Arc::new(ZeroDevice),
+            )
             .unwrap();
         devices
-            .add_device(Path::new("null").try_into().unwrap(), Arc::new(NullDevice))
+            .add_device(
+                Path::new("null").try_into().unwrap(),
+                DeviceId::NULL,
+                Arc::new(NullDevice),
+            )
             .unwrap();
+        devices
+            .add_device(
+                Path::new("urandom").try_into().unwrap(),
+                DeviceId::URANDOM,
+                Arc::new(RandomDevice),
+            )
+            .unwrap();
+        #[cfg(feature = "net")]
+        {
+            devices
+                .add_device(
+                    Path::new("pcap0").try_into().unwrap(),
+                    DeviceId::PCAP,
+                    Arc::new(PcapDevice),
+                )
+                .unwrap();
+            devices
+                .add_device(
+                    Path::new("tun0").try_into().unwrap(),
+                    DeviceId::TUN,
+                    Arc::new(TunDevice),
+                )
+                .unwrap();
+        }
+
+        #[cfg(feature = "procfs")]
+        let procfs = {
+            let mut procfs = ProcFs::new();
+            #[cfg(feature = "net")]
+            {
+                let procnet = procfs.add_dir(ProcFs::ROOT, b"net").unwrap();
+                procfs.add_file(procnet, b"arp", net::arp::show).unwrap();
+                procfs
+                    .add_file(procnet, b"route", net::route::show)
+                    .unwrap();
+            }
+            let procsys = procfs.add_dir(ProcFs::ROOT, b"sys").unwrap();
+            let procsys_kernel = procfs.add_dir(procsys, b"kernel").unwrap();
+            procfs
+                .add_writable(procsys_kernel, b"hostname", hostname::show, hostname::store)
+                .unwrap();
+            procfs
+                .add_tunable(procsys_kernel, b"log_level", &klog::LEVEL)
+                .unwrap();
+            procfs
+                .add_tunable(
+                    procsys_kernel,
+                    b"shutdown_grace_ms",
+                    &power::SHUTDOWN_GRACE_MS,
+                )
+                .unwrap();
+            let procsys_sched = procfs.add_dir(procsys_kernel, b"sched").unwrap();
+            for (name, quantum) in [
+                &b"interactive_quantum_ms"[..],
+                b"normal_quantum_ms",
+                b"batch_quantum_ms",
+                b"idle_quantum_ms",
+            ]
+            .into_iter()
+            .zip(&proc::QUANTA)
+            {
+                procfs.add_tunable(procsys_sched, name, quantum).unwrap();
+            }
+            let procsys_vm = procfs.add_dir(procsys, b"vm").unwrap();
+            procfs
+                .add_tunable(procsys_vm, b"scrub_interval_ms", &vmm::SCRUB_INTERVAL_MS)
+                .unwrap();
+            #[cfg(feature = "net")]
+            {
+                let procsys_net = procfs.add_dir(procsys, b"net").unwrap();
+                procfs
+                    .add_tunable(procsys_net, b"tcp_rcvbuf", &net::tcp::DEFAULT_RCVBUF)
+                    .unwrap();
+                procfs
+                    .add_tunable(procsys_net, b"tcp_sndbuf", &net::tcp::DEFAULT_SNDBUF)
+                    .unwrap();
+            }
+            procfs
+                .add_file(ProcFs::ROOT, b"cpuinfo", isa::show)
+                .unwrap();
+            procfs
+                .add_file(ProcFs::ROOT, b"interrupts", stats::show_interrupts)
+                .unwrap();
+            procfs
+                .add_file(ProcFs::ROOT, b"syscalls", stats::show_syscalls)
+                .unwrap();
+            procfs
+                .add_file(ProcFs::ROOT, b"stacks", stats::show_stacks)
+                .unwrap();
+            procfs
+                .add_file(ProcFs::ROOT, b"sched", stats::show_sched)
+                .unwrap();
+            procfs
+                .add_file(ProcFs::ROOT, b"latency", stats::show_latency)
+                .unwrap();
+            procfs
+                .add_file(ProcFs::ROOT, b"lookups", fs::vfs::show_lookups)
+                .unwrap();
+            procfs
+                .add_file(ProcFs::ROOT, b"meminfo", vmm::show_meminfo)
+                .unwrap();
+            procfs
+                .add_file(ProcFs::ROOT, b"cmdline", bootargs::show)
+                .unwrap();
+            procfs
+                .add_file(ProcFs::ROOT, b"uptime", fs::show_uptime)
+                .unwrap();
+            procfs
+                .add_file(ProcFs::ROOT, b"features", show_features)
+                .unwrap();
+            procfs.add_file(ProcFs::ROOT, b"locks", show_locks).unwrap();
+            procfs
+        };
 
-        static INITRD: &[u8] = include_bytes!("../../initrd.img");
         {
             let mut vfs = VFS.lock();
             vfs.mount(
                 Path::new("/").try_into().unwrap(),
-                Arc::new(InitRd::new(INITRD).unwrap()),
+                rootfs::select(&devices, unsafe { &*addr_of!(INITRD.0) }),
+                MountFlags::empty(),
+            )
+            .unwrap();
+            vfs.mount(
+                Path::new("/dev").try_into().unwrap(),
+                Arc::new(devices),
+                MountFlags::NoAtime,
+            )
+            .unwrap();
+            #[cfg(feature = "procfs")]
+            vfs.mount(
+                Path::new("/proc").try_into().unwrap(),
+                Arc::new(procfs),
+                MountFlags::NoAtime,
             )
             .unwrap();
-            vfs.mount(Path::new("/dev").try_into().unwrap(), Arc::new(devices))
-                .unwrap();
         }
 
-        let root = Vfs::open("/", OpenFlags::empty()).unwrap();
-        Process::spawn(Path::new("/bin/init"), root, &[]).expect("couldn't spawn init process");
+        // InitRd copies everything it needs, so the image is never read again
+        let reclaimed = unsafe { ALLOCATOR.lock().add_pages(addr_of_mut!(INITRD.0)) };
+        klog!(
+            Info,
+            Vmm,
+            "reclaimed {} KiB from the initrd image",
+            reclaimed / 1024
+        );
     }
 
     // ask for PLIC interrupts
@@ -435,5 +771,12 @@ extern "C" fn kinithart(hartid: usize) -> ! {
     // enable traps and install the trap handler
     trap::hart_install();
 
+    if boot_hart {
+        #[cfg(feature = "ktest")]
+        ktest::run();
+
+        rootfs::spawn_init();
+    }
+
     Scheduler::yield_hart()
 }
@@ -0,0 +1,79 @@
+use alloc::collections::BTreeMap;
+use core::{fmt, net::Ipv4Addr};
+
+use shared::{
+    net::{HwAddr, NeighborEntry},
+    sys::SysError,
+};
+
+use super::{NetResult, NetStack, NET};
+
+// TODO: resolve addresses with ARP requests once an interface has a link layer. Until then the
+// only neighbors are the ones added with `NetCtl::AddNeighbor`, which never expire.
+
+/// Link layer addresses of neighbors, keyed by interface and IP address
+pub struct ArpCache {
+    entries: BTreeMap<(u32, Ipv4Addr), HwAddr>,
+}
+
+impl ArpCache {
+    pub const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn lookup(&self, iface: u32, addr: Ipv4Addr) -> Option<HwAddr> {
+        self.entries.get(&(iface, addr)).copied()
+    }
+
+    pub fn add_permanent(&mut self, iface: u32, addr: Ipv4Addr, hwaddr: HwAddr) {
+        // TODO: alloc failure
+        self.entries.insert((iface, addr), hwaddr);
+    }
+
+    pub fn remove(&mut self, iface: u32, addr: Ipv4Addr) -> NetResult<()> {
+        self.entries
+            .remove(&(iface, addr))
+            .map(|_| ())
+            .ok_or(SysError::NotFound)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, Ipv4Addr, HwAddr)> + '_ {
+        self.entries
+            .iter()
+            .map(|(&(iface, addr), &hwaddr)| (iface, addr, hwaddr))
+    }
+}
+
+impl NetStack {
+    pub fn add_neighbor(&mut self, entry: NeighborEntry) -> NetResult<()> {
+        let iface = self.ifaces.get(entry.iface).ok_or(SysError::NotFound)?;
+        if iface.hwaddr().is_none() {
+            return Err(SysError::Unsupported);
+        }
+
+        self.arp
+            .add_permanent(entry.iface, Ipv4Addr::from(entry.addr), entry.hwaddr);
+        Ok(())
+    }
+
+    pub fn del_neighbor(&mut self, entry: NeighborEntry) -> NetResult<()> {
+        self.arp.remove(entry.iface, Ipv4Addr::from(entry.addr))
+    }
+}
+
+/// The contents of `/proc/net/arp`
+#[cfg(feature = "procfs")]
+pub fn show(w: &mut dyn fmt::Write) -> fmt::Result {
+    let net = NET.lock();
+    writeln!(w, "Address          HWaddress         Iface")?;
+    for (idx, addr, [a, b, c, d, e, f]) in net.arp.iter() {
+        let iface = net.ifaces.get(idx).map(|i| i.name()).unwrap_or("?");
+        writeln!(
+            w,
+            "{addr:<16} {a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{f:02x} {iface}"
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+use super::NetResult;
+
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+
+pub const HEADER_LEN: usize = 20;
+const VERSION_IHL: u8 = 0x45;
+const DEFAULT_TTL: u8 = 64;
+const FLAG_MF: u16 = 1 << 13;
+const FRAG_OFFSET_MASK: u16 = 0x1fff;
+
+pub struct Ipv4Header {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub proto: u8,
+}
+
+/// Where a packet's payload belongs in the datagram it was fragmented from
+pub struct Fragment {
+    pub id: u16,
+    /// The offset of the payload in bytes
+    pub offset: usize,
+    /// Whether more fragments follow this one
+    pub more: bool,
+}
+
+impl Fragment {
+    pub fn is_fragment(&self) -> bool {
+        self.more || self.offset != 0
+    }
+}
+
+/// The internet checksum of `data`, starting from the partial sum `initial`
+pub fn checksum(data: &[u8], initial: u32) -> u16 {
+    !fold(sum(data, initial as u64))
+}
+
+/// Fold a partial sum into 16 bits without complementing it
+pub fn fold(mut sum: u64) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// Add `data` to a partial ones' complement sum. Since 2^16 = 1 (mod 2^16 - 1), big endian 32-bit
+/// words can be summed into a 64-bit accumulator and folded at the end, which takes a quarter as
+/// many additions as summing 16-bit words one at a time and can't overflow for any packet size.
+fn sum(data: &[u8], mut sum: u64) -> u64 {
+    #[inline(always)]
+    fn word(b: &[u8]) -> u64 {
+        u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64
+    }
+
+    let mut chunks = data.chunks_exact(16);
+    for c in &mut chunks {
+        sum += word(&c[0..]) + word(&c[4..]) + word(&c[8..]) + word(&c[12..]);
+    }
+
+    let mut words = chunks.remainder().chunks_exact(4);
+    for w in &mut words {
+        sum += word(w);
+    }
+
+    match *words.remainder() {
+        [a, b, c] => sum + ((a as u64) << 24 | (b as u64) << 16 | (c as u64) << 8),
+        [a, b] => sum + ((a as u64) << 24 | (b as u64) << 16),
+        [a] => sum + ((a as u64) << 24),
+        _ => sum,
+    }
+}
+
+/// The partial checksum of the pseudo header used by TCP and UDP
+pub fn pseudo_header_sum(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, len: usize) -> u32 {
+    let (src, dst) = (src.octets(), dst.octets());
+    [
+        u16::from_be_bytes([src[0], src[1]]),
+        u16::from_be_bytes([src[2], src[3]]),
+        u16::from_be_bytes([dst[0], dst[1]]),
+        u16::from_be_bytes([dst[2], dst[3]]),
+        proto as u16,
+        len as u16,
+    ]
+    .iter()
+    .map(|&w| w as u32)
+    .sum()
+}
+
+/// Allocate a packet with a filled in IPv4 header followed by `payload_len` zeroed bytes
+pub fn packet(hdr: &Ipv4Header, id: u16, payload_len: usize) -> NetResult<Vec<u8>> {
+    let total = HEADER_LEN + payload_len;
+    let Ok(total_len) = u16::try_from(total) else {
+        return Err(shared::sys::SysError::BadArg);
+    };
+
+    let mut pkt = Vec::try_with_capacity(total)?;
+    pkt.resize(total, 0);
+    pkt[0] = VERSION_IHL;
+    pkt[2..4].copy_from_slice(&total_len.to_be_bytes());
+    pkt[4..6].copy_from_slice(&id.to_be_bytes());
+    pkt[8] = DEFAULT_TTL;
+    pkt[9] = hdr.proto;
+    pkt[12..16].copy_from_slice(&hdr.src.octets());
+    pkt[16..20].copy_from_slice(&hdr.dst.octets());
+    let sum = checksum(&pkt[..HEADER_LEN], 0);
+    pkt[10..12].copy_from_slice(&sum.to_be_bytes());
+    Ok(pkt)
+}
+
+/// Split a packet built by `packet` into fragments whose total length is at most `mtu`
+pub fn fragment(pkt: &[u8], mtu: usize) -> NetResult<Vec<Vec<u8>>> {
+    // every fragment but the last must carry a multiple of 8 bytes
+    let per_frag = (mtu - HEADER_LEN) & !7;
+    let payload = &pkt[HEADER_LEN..];
+
+    let mut frags = Vec::try_with_capacity(payload.len().div_ceil(per_frag))?;
+    for (i, chunk) in payload.chunks(per_frag).enumerate() {
+        let offset = i * per_frag;
+        let mut frag = Vec::try_with_capacity(HEADER_LEN + chunk.len())?;
+        frag.extend_from_slice(&pkt[..HEADER_LEN]);
+        frag.extend_from_slice(chunk);
+
+        let mut flags = (offset / 8) as u16;
+        if offset + chunk.len() < payload.len() {
+            flags |= FLAG_MF;
+        }
+        let total_len = (HEADER_LEN + chunk.len()) as u16;
+        frag[2..4].copy_from_slice(&total_len.to_be_bytes());
+        frag[6..8].copy_from_slice(&flags.to_be_bytes());
+        frag[10..12].fill(0);
+        let sum = checksum(&frag[..HEADER_LEN], 0);
+        frag[10..12].copy_from_slice(&sum.to_be_bytes());
+        frags.push(frag);
+    }
+    Ok(frags)
+}
+
+/// Validate an IPv4 packet and split it into its header, fragment information and payload
+pub fn parse(pkt: &[u8]) -> Option<(Ipv4Header, Fragment, &[u8])> {
+    let &[version_ihl, _, len_hi, len_lo, id_hi, id_lo, frag_hi, frag_lo, _, proto, ..] = pkt
+    else {
+        return None;
+    };
+
+    let header_len = (version_ihl & 0xf) as usize * 4;
+    let total_len = u16::from_be_bytes([len_hi, len_lo]) as usize;
+    if version_ihl >> 4 != 4
+        || header_len < HEADER_LEN
+        || total_len < header_len
+        || total_len > pkt.len()
+        || checksum(&pkt[..header_len], 0) != 0
+    {
+        return None;
+    }
+
+    let frag = u16::from_be_bytes([frag_hi, frag_lo]);
+    let addr = |i: usize| Ipv4Addr::new(pkt[i], pkt[i + 1], pkt[i + 2], pkt[i + 3]);
+    Some((
+        Ipv4Header {
+            src: addr(12),
+            dst: addr(16),
+            proto,
+        },
+        Fragment {
+            id: u16::from_be_bytes([id_hi, id_lo]),
+            offset: (frag & FRAG_OFFSET_MASK) as usize * 8,
+            more: frag & FLAG_MF != 0,
+        },
+        &pkt[header_len..total_len],
+    ))
+}
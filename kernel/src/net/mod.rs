@@ -0,0 +1,376 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{
+    net::Ipv4Addr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use arp::ArpCache;
+use bitflags::bitflags;
+use servos::{
+    lock::SpinLocked,
+    riscv::{r_time, TIMEBASE_FREQ},
+};
+use shared::{
+    frag::Reassembler,
+    net::{HwAddr, SockAddr, IFACE_LO, IFACE_TUN},
+    sys::SysError,
+};
+
+use crate::random;
+use ip::Ipv4Header;
+use loopback::Loopback;
+use pcap::Capture;
+use route::{Route, RouteTable};
+use tcp::TcpSocket;
+use tun::Tun;
+use udp::UdpSocket;
+
+pub mod arp;
+pub mod ip;
+pub mod loopback;
+pub mod pcap;
+pub mod route;
+pub mod socket;
+pub mod tcp;
+pub mod tun;
+pub mod udp;
+
+pub type NetResult<T> = Result<T, SysError>;
+
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// The most data that all sockets together may have buffered
+const MEM_LIMIT: usize = 1024 * 1024;
+/// The range of sizes for a socket's send and receive buffers
+pub const MIN_BUF: usize = 2048;
+pub const MAX_BUF: usize = 256 * 1024;
+/// How long to wait for the rest of a datagram after its first fragment arrives
+const REASSEMBLY_TIMEOUT: usize = 30 * TIMEBASE_FREQ;
+
+static BUFFERED: AtomicUsize = AtomicUsize::new(0);
+
+/// How much more data sockets may buffer before hitting the global limit
+fn mem_available() -> usize {
+    MEM_LIMIT.saturating_sub(BUFFERED.load(Ordering::Relaxed))
+}
+
+/// Account for `len` more buffered bytes. Callers check `mem_available` first, except for data the
+/// peer was already told it could send, since there's no way to take that back.
+fn charge(len: usize) {
+    BUFFERED.fetch_add(len, Ordering::Relaxed);
+}
+
+fn uncharge(len: usize) {
+    BUFFERED.fetch_sub(len, Ordering::Relaxed);
+}
+
+bitflags! {
+    #[derive(Clone, Copy)]
+    pub struct Offload: u32 {
+        /// The device fills in TCP and UDP checksums of transmitted packets
+        const TxChecksum = 1 << 0;
+        /// The device verifies TCP and UDP checksums of received packets
+        const RxChecksum = 1 << 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// Transmitted packets have a complete checksum, received packets must still be verified
+    None,
+    /// The checksum field `offset` bytes after `start` holds the pseudo header sum, and the device
+    /// must add the sum of everything from `start` onwards. Only for transmitted packets.
+    Partial { start: usize, offset: usize },
+    /// The device has verified the checksum. Only for received packets.
+    Verified,
+}
+
+pub struct Packet {
+    pub data: Vec<u8>,
+    pub csum: Checksum,
+    /// The link layer destination of a transmitted packet, if the interface has one
+    pub link_dst: Option<HwAddr>,
+}
+
+pub trait Interface {
+    fn name(&self) -> &'static str;
+    fn addr(&self) -> Ipv4Addr;
+    fn prefix_len(&self) -> u8;
+    /// The largest packet the interface can send, including the IP header
+    fn mtu(&self) -> usize;
+    /// The link layer address, or `None` if packets are sent without resolving neighbors
+    fn hwaddr(&self) -> Option<HwAddr>;
+    fn offload(&self) -> Offload;
+    fn transmit(&mut self, pkt: Packet) -> NetResult<()>;
+    fn receive(&mut self) -> Option<Packet>;
+
+    /// The on-link route to the network this interface is attached to
+    fn network(&self, iface: u32) -> Route {
+        let mut route = Route {
+            dest: self.addr(),
+            prefix_len: self.prefix_len(),
+            gateway: None,
+            iface,
+        };
+        route.dest = Ipv4Addr::from(u32::from(route.dest) & route.mask());
+        route
+    }
+}
+
+/// Every network interface, indexed by the numbers used in `RouteEntry` and `NeighborEntry`
+pub struct Interfaces {
+    lo: Loopback,
+    tun: Tun,
+}
+
+impl Interfaces {
+    const COUNT: u32 = 2;
+
+    pub fn get(&self, idx: u32) -> Option<&dyn Interface> {
+        match idx {
+            IFACE_LO => Some(&self.lo),
+            IFACE_TUN => Some(&self.tun),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, idx: u32) -> Option<&mut dyn Interface> {
+        match idx {
+            IFACE_LO => Some(&mut self.lo),
+            IFACE_TUN => Some(&mut self.tun),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &dyn Interface)> {
+        (0..Self::COUNT).filter_map(|idx| Some((idx, self.get(idx)?)))
+    }
+}
+
+pub enum Socket {
+    Udp(UdpSocket),
+    Tcp(TcpSocket),
+}
+
+impl Socket {
+    fn local(&self) -> Option<SockAddr> {
+        match self {
+            Socket::Udp(sock) => sock.local,
+            Socket::Tcp(sock) => sock.local,
+        }
+    }
+}
+
+pub struct NetStack {
+    sockets: BTreeMap<u32, Socket>,
+    next_socket: u32,
+    next_ip_id: u16,
+    ifaces: Interfaces,
+    routes: RouteTable,
+    arp: ArpCache,
+    capture: Capture,
+    reassembler: Reassembler,
+}
+
+/// Every socket and interface. No other lock may be taken while this one is held.
+pub static NET: SpinLocked<NetStack> = SpinLocked::new(NetStack::new());
+
+impl NetStack {
+    const fn new() -> Self {
+        Self {
+            sockets: BTreeMap::new(),
+            next_socket: 0,
+            next_ip_id: 0,
+            ifaces: Interfaces {
+                lo: Loopback::new(),
+                tun: Tun::new(),
+            },
+            routes: RouteTable::new(),
+            arp: ArpCache::new(),
+            capture: Capture::new(),
+            reassembler: Reassembler::new(REASSEMBLY_TIMEOUT),
+        }
+    }
+
+    fn add_socket(&mut self, sock: Socket) -> u32 {
+        let mut id = self.next_socket;
+        while self.sockets.contains_key(&id) {
+            id = id.wrapping_add(1);
+        }
+        self.next_socket = id.wrapping_add(1);
+        // TODO: alloc failure
+        self.sockets.insert(id, sock);
+        id
+    }
+
+    fn port_in_use(&self, tcp: bool, port: u16) -> bool {
+        self.sockets.values().any(|sock| {
+            matches!(sock, Socket::Tcp(_)) == tcp && sock.local().is_some_and(|l| l.port == port)
+        })
+    }
+
+    /// A free port, starting the search somewhere random so an outsider can't guess which port
+    /// the next connection or query will come from
+    fn ephemeral_port(&mut self, tcp: bool) -> NetResult<u16> {
+        let (first, count) = (*EPHEMERAL_PORTS.start(), EPHEMERAL_PORTS.len() as u64);
+        let offset = random::next_u64() % count;
+        (0..count)
+            .map(|i| first + ((offset + i) % count) as u16)
+            .find(|&port| !self.port_in_use(tcp, port))
+            .ok_or(SysError::AddrInUse)
+    }
+
+    /// The most specific route to `dst`, among the routing table and the networks the interfaces
+    /// are attached to
+    fn route(&self, dst: Ipv4Addr) -> NetResult<Route> {
+        let networks = self.ifaces.iter().map(|(idx, iface)| iface.network(idx));
+        route::longest_match(networks.chain(self.routes.iter().copied()), dst)
+            .ok_or(SysError::AddrNotAvailable)
+    }
+
+    /// The address of the interface used to reach `dst`
+    fn source_addr(&self, dst: Ipv4Addr) -> NetResult<Ipv4Addr> {
+        let route = self.route(dst)?;
+        Ok(self.ifaces.get(route.iface).unwrap().addr())
+    }
+
+    fn local_addr_valid(&self, addr: Ipv4Addr) -> bool {
+        // the whole loopback network is local, not just the address of `lo`
+        addr.is_unspecified()
+            || Loopback::handles(addr)
+            || self.ifaces.iter().any(|(_, iface)| iface.addr() == addr)
+    }
+
+    /// Build an IP packet with room for `payload_len` bytes, which `fill` must write, then send it.
+    /// `fill` must leave the checksum field at `csum_offset` zeroed, it is filled in here or by the
+    /// device.
+    fn send_ip(
+        &mut self,
+        hdr: Ipv4Header,
+        payload_len: usize,
+        csum_offset: usize,
+        fill: impl FnOnce(&mut [u8]),
+    ) -> NetResult<()> {
+        let route = self.route(hdr.dst)?;
+        let iface = self.ifaces.get(route.iface).unwrap();
+        let (offload, mtu) = (iface.offload(), iface.mtu());
+        let fragment = ip::HEADER_LEN + payload_len > mtu;
+
+        let id = self.next_ip_id;
+        self.next_ip_id = id.wrapping_add(1);
+
+        let mut data = ip::packet(&hdr, id, payload_len)?;
+        let seg = &mut data[ip::HEADER_LEN..];
+        fill(seg);
+
+        let pseudo = ip::pseudo_header_sum(hdr.src, hdr.dst, hdr.proto, seg.len());
+        // fragments can't have their checksum filled in by the device, since it only sees one
+        // piece of the segment at a time
+        let csum = if offload.contains(Offload::TxChecksum) && !fragment {
+            let partial = ip::fold(pseudo as u64);
+            seg[csum_offset..][..2].copy_from_slice(&partial.to_be_bytes());
+            Checksum::Partial {
+                start: ip::HEADER_LEN,
+                offset: csum_offset,
+            }
+        } else {
+            let sum = match ip::checksum(seg, pseudo) {
+                // zero means no checksum for UDP
+                0 if hdr.proto == ip::PROTO_UDP => 0xffff,
+                sum => sum,
+            };
+            seg[csum_offset..][..2].copy_from_slice(&sum.to_be_bytes());
+            Checksum::None
+        };
+
+        let next_hop = route.next_hop(hdr.dst);
+        if !fragment {
+            let pkt = Packet {
+                data,
+                csum,
+                link_dst: None,
+            };
+            return self.transmit(route.iface, next_hop, pkt);
+        }
+
+        for data in ip::fragment(&data, mtu)? {
+            let pkt = Packet {
+                data,
+                csum,
+                link_dst: None,
+            };
+            self.transmit(route.iface, next_hop, pkt)?;
+        }
+        Ok(())
+    }
+
+    /// Send `pkt` to `next_hop` over interface `idx`, looking up its link layer address first if
+    /// the interface needs one
+    fn transmit(&mut self, idx: u32, next_hop: Ipv4Addr, mut pkt: Packet) -> NetResult<()> {
+        let iface = self.ifaces.get_mut(idx).ok_or(SysError::AddrNotAvailable)?;
+        if iface.hwaddr().is_some() {
+            let hwaddr = self
+                .arp
+                .lookup(idx, next_hop)
+                .ok_or(SysError::AddrNotAvailable)?;
+            pkt.link_dst = Some(hwaddr);
+        }
+
+        self.capture.tap(&pkt.data);
+        iface.transmit(pkt)
+    }
+
+    /// Process every packet waiting on an interface, and age partially reassembled datagrams
+    pub fn poll(&mut self) {
+        for idx in 0..Interfaces::COUNT {
+            while let Some(pkt) = self.ifaces.get_mut(idx).and_then(|iface| iface.receive()) {
+                // looped back packets were already captured on the way out
+                if idx != IFACE_LO {
+                    self.capture.tap(&pkt.data);
+                }
+                self.receive(&pkt);
+            }
+        }
+
+        self.reassembler.expire(r_time());
+    }
+
+    fn receive(&mut self, pkt: &Packet) {
+        let Some((hdr, frag, payload)) = ip::parse(&pkt.data) else {
+            return;
+        };
+
+        if frag.is_fragment() {
+            let key = (hdr.src, hdr.dst, hdr.proto, frag.id);
+            if let Some(payload) =
+                self.reassembler
+                    .insert(key, frag.offset, frag.more, payload, r_time())
+            {
+                // a device can only vouch for the checksum of the fragment it received
+                self.deliver(&hdr, &payload, false);
+            }
+        } else {
+            self.deliver(&hdr, payload, pkt.csum == Checksum::Verified);
+        }
+    }
+
+    fn deliver(&mut self, hdr: &Ipv4Header, payload: &[u8], verified: bool) {
+        match hdr.proto {
+            ip::PROTO_UDP => self.udp_receive(hdr, payload, verified),
+            ip::PROTO_TCP => self.tcp_receive(hdr, payload, verified),
+            _ => {}
+        }
+    }
+}
+
+/// Whether the transport layer checksum of `segment` is correct
+fn checksum_valid(hdr: &Ipv4Header, segment: &[u8]) -> bool {
+    let pseudo = ip::pseudo_header_sum(hdr.src, hdr.dst, hdr.proto, segment.len());
+    ip::checksum(segment, pseudo) == 0
+}
+
+fn try_copy(data: &[u8]) -> NetResult<Vec<u8>> {
+    let mut buf = Vec::try_with_capacity(data.len())?;
+    buf.extend_from_slice(data);
+    Ok(buf)
+}
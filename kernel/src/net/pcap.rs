@@ -0,0 +1,119 @@
+use alloc::collections::VecDeque;
+use core::mem::MaybeUninit;
+
+use servos::riscv::{r_time, TIMEBASE_FREQ};
+
+use crate::{
+    dev::Device,
+    fs::{FsError, FsResult},
+};
+
+use super::NET;
+
+/// How much of each packet is kept
+const SNAPLEN: usize = 256;
+/// How many bytes of captured records are kept before the oldest are dropped
+const MAX_BUFFERED: usize = 64 * 1024;
+/// Packets are raw IPv4 without a link layer header
+const LINKTYPE_RAW: u32 = 101;
+const RECORD_HEADER_LEN: usize = 16;
+
+fn file_header() -> [u8; 24] {
+    let mut hdr = [0; 24];
+    hdr[0..4].copy_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+    hdr[4..6].copy_from_slice(&2u16.to_le_bytes());
+    hdr[6..8].copy_from_slice(&4u16.to_le_bytes());
+    // the timezone offset and timestamp accuracy are left zero
+    hdr[16..20].copy_from_slice(&(SNAPLEN as u32).to_le_bytes());
+    hdr[20..24].copy_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    hdr
+}
+
+/// Every packet the stack sends or receives, in pcap record format. The oldest records are dropped
+/// once `MAX_BUFFERED` is reached, unless a reader is partway through one.
+pub struct Capture {
+    buf: VecDeque<u8>,
+    /// The length of each record in `buf`
+    records: VecDeque<usize>,
+    /// How much of the first record has been read
+    front_read: usize,
+}
+
+impl Capture {
+    pub const fn new() -> Self {
+        Self {
+            buf: VecDeque::new(),
+            records: VecDeque::new(),
+            front_read: 0,
+        }
+    }
+
+    /// Record `pkt`. Transmitted packets may have checksums that are left to the device.
+    pub fn tap(&mut self, pkt: &[u8]) {
+        let incl = pkt.len().min(SNAPLEN);
+        let len = RECORD_HEADER_LEN + incl;
+        while self.buf.len() + len > MAX_BUFFERED && self.front_read == 0 {
+            let Some(front) = self.records.pop_front() else {
+                break;
+            };
+            self.buf.drain(..front);
+        }
+
+        if self.buf.len() + len > MAX_BUFFERED
+            || self.buf.try_reserve(len).is_err()
+            || self.records.try_reserve(1).is_err()
+        {
+            return;
+        }
+
+        let now = r_time();
+        let secs = (now / TIMEBASE_FREQ) as u32;
+        let usecs = ((now % TIMEBASE_FREQ) * 1_000_000 / TIMEBASE_FREQ) as u32;
+        for field in [secs, usecs, incl as u32, pkt.len() as u32] {
+            self.buf.extend(field.to_le_bytes());
+        }
+        self.buf.extend(&pkt[..incl]);
+        self.records.push_back(len);
+    }
+
+    fn read(&mut self, buf: &mut [MaybeUninit<u8>]) -> usize {
+        let len = buf.len().min(self.buf.len());
+        for (dst, src) in buf.iter_mut().zip(self.buf.drain(..len)) {
+            dst.write(src);
+        }
+
+        self.front_read += len;
+        while let Some(&front) = self.records.front().filter(|&&f| f <= self.front_read) {
+            self.front_read -= front;
+            self.records.pop_front();
+        }
+        len
+    }
+}
+
+/// `/dev/pcap0`. Reading from the start yields the pcap file header, then captured records are
+/// consumed as they're read, so concurrent readers each see only part of the capture.
+pub struct PcapDevice;
+
+impl Device for PcapDevice {
+    fn read<'a>(&self, pos: u64, buf: &'a mut [MaybeUninit<u8>]) -> FsResult<&'a mut [u8]> {
+        let mut len = 0;
+        let hdr = file_header();
+        if let Some(hdr) = hdr.get(pos as usize..) {
+            len = hdr.len().min(buf.len());
+            for (dst, &src) in buf.iter_mut().zip(&hdr[..len]) {
+                dst.write(src);
+            }
+        }
+
+        len += NET.lock().capture.read(&mut buf[len..]);
+        if len == 0 && !buf.is_empty() {
+            return Err(FsError::Eof);
+        }
+        Ok(unsafe { MaybeUninit::slice_assume_init_mut(&mut buf[..len]) })
+    }
+
+    fn write(&self, _pos: u64, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::InvalidOp)
+    }
+}
@@ -0,0 +1,139 @@
+use alloc::vec::Vec;
+use core::{fmt, net::Ipv4Addr};
+
+use shared::{net::RouteEntry, sys::SysError};
+
+use super::{NetResult, NetStack, NET};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub dest: Ipv4Addr,
+    pub prefix_len: u8,
+    /// The router to forward packets to, `None` if the destination is on-link
+    pub gateway: Option<Ipv4Addr>,
+    pub iface: u32,
+}
+
+impl Route {
+    pub fn mask(&self) -> u32 {
+        u32::MAX
+            .checked_shl(32 - self.prefix_len as u32)
+            .unwrap_or(0)
+    }
+
+    pub fn matches(&self, addr: Ipv4Addr) -> bool {
+        u32::from(addr) & self.mask() == u32::from(self.dest)
+    }
+
+    /// The address packets for `dst` are sent to on the link
+    pub fn next_hop(&self, dst: Ipv4Addr) -> Ipv4Addr {
+        self.gateway.unwrap_or(dst)
+    }
+}
+
+/// Routes added with `NetCtl::AddRoute`. Each interface also has an implicit on-link route to the
+/// network of its address, which `NetStack::route` considers alongside these.
+pub struct RouteTable {
+    routes: Vec<Route>,
+}
+
+impl RouteTable {
+    pub const fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    pub fn add(&mut self, route: Route) -> NetResult<()> {
+        if route.prefix_len > 32 || u32::from(route.dest) & !route.mask() != 0 {
+            return Err(SysError::BadArg);
+        }
+
+        if self.find(route.dest, route.prefix_len).is_some() {
+            return Err(SysError::AlreadyExists);
+        }
+
+        self.routes.try_reserve(1)?;
+        self.routes.push(route);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, dest: Ipv4Addr, prefix_len: u8) -> NetResult<()> {
+        let i = self.find(dest, prefix_len).ok_or(SysError::NotFound)?;
+        self.routes.remove(i);
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter()
+    }
+
+    fn find(&self, dest: Ipv4Addr, prefix_len: u8) -> Option<usize> {
+        self.routes
+            .iter()
+            .position(|r| r.dest == dest && r.prefix_len == prefix_len)
+    }
+}
+
+/// The most specific route in `routes` that matches `addr`. Earlier routes win ties.
+pub fn longest_match(routes: impl Iterator<Item = Route>, addr: Ipv4Addr) -> Option<Route> {
+    routes
+        .filter(|r| r.matches(addr))
+        .fold(None, |best: Option<Route>, r| match best {
+            Some(best) if best.prefix_len >= r.prefix_len => Some(best),
+            _ => Some(r),
+        })
+}
+
+impl NetStack {
+    pub fn add_route(&mut self, entry: RouteEntry) -> NetResult<()> {
+        let gateway = Ipv4Addr::from(entry.gateway);
+        let route = Route {
+            dest: Ipv4Addr::from(entry.dest),
+            prefix_len: entry.prefix_len,
+            gateway: (!gateway.is_unspecified()).then_some(gateway),
+            iface: entry.iface,
+        };
+        if self.ifaces.get(route.iface).is_none() {
+            return Err(SysError::NotFound);
+        }
+
+        // the gateway itself has to be reachable directly over the same interface
+        if let Some(gateway) = route.gateway {
+            match self.route(gateway) {
+                Ok(r) if r.gateway.is_none() && r.iface == route.iface => {}
+                _ => return Err(SysError::AddrNotAvailable),
+            }
+        }
+
+        self.routes.add(route)
+    }
+
+    pub fn del_route(&mut self, entry: RouteEntry) -> NetResult<()> {
+        self.routes
+            .remove(Ipv4Addr::from(entry.dest), entry.prefix_len)
+    }
+}
+
+/// The contents of `/proc/net/route`
+#[cfg(feature = "procfs")]
+pub fn show(w: &mut dyn fmt::Write) -> fmt::Result {
+    let net = NET.lock();
+    writeln!(w, "Destination      Prefix Gateway          Iface")?;
+
+    let networks = net.ifaces.iter().map(|(idx, iface)| iface.network(idx));
+    for route in networks.chain(net.routes.iter().copied()) {
+        let iface = net.ifaces.get(route.iface).map(|i| i.name()).unwrap_or("?");
+        match route.gateway {
+            Some(gateway) => writeln!(
+                w,
+                "{:<16} {:<6} {:<16} {iface}",
+                route.dest, route.prefix_len, gateway
+            )?,
+            None => writeln!(
+                w,
+                "{:<16} {:<6} {:<16} {iface}",
+                route.dest, route.prefix_len, "*"
+            )?,
+        }
+    }
+    Ok(())
+}
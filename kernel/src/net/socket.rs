@@ -0,0 +1,280 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::mem::MaybeUninit;
+
+use shared::{
+    io::{DirEntry, Mode, OpenFlags, Stat},
+    net::{SockAddr, SockOpt, SocketType},
+    sys::SysError,
+};
+
+use crate::{
+    fs::{path::Path, vfs::Fd, FileSystem, FsError, FsResult, OpenId, VNode},
+    vmm::{PageTable, VirtAddr},
+};
+
+use super::{tcp::TcpSocket, udp::UdpSocket, NetResult, NetStack, Socket, MAX_BUF, MIN_BUF, NET};
+
+impl NetStack {
+    fn socket(&mut self, id: u32) -> NetResult<&mut Socket> {
+        self.sockets.get_mut(&id).ok_or(SysError::BadFd)
+    }
+
+    pub fn create(&mut self, typ: SocketType) -> u32 {
+        self.add_socket(match typ {
+            SocketType::Udp => Socket::Udp(UdpSocket::new()),
+            SocketType::Tcp => Socket::Tcp(TcpSocket::new()),
+        })
+    }
+
+    pub fn bind(&mut self, id: u32, mut addr: SockAddr) -> NetResult<()> {
+        if !self.local_addr_valid(addr.ip()) {
+            return Err(SysError::AddrNotAvailable);
+        }
+
+        let tcp = match self.socket(id)? {
+            sock if sock.local().is_some() => return Err(SysError::InvalidOp),
+            sock => matches!(sock, Socket::Tcp(_)),
+        };
+        if addr.port == 0 {
+            addr.port = self.ephemeral_port(tcp)?;
+        } else if self.port_in_use(tcp, addr.port) {
+            return Err(SysError::AddrInUse);
+        }
+
+        match self.socket(id)? {
+            Socket::Udp(sock) => sock.local = Some(addr),
+            Socket::Tcp(sock) => sock.local = Some(addr),
+        }
+        Ok(())
+    }
+
+    pub fn connect(&mut self, id: u32, addr: SockAddr) -> NetResult<()> {
+        if let Socket::Udp(_) = self.socket(id)? {
+            self.source_addr(addr.ip())?;
+            if self.socket(id)?.local().is_none() {
+                self.bind(id, SockAddr::UNSPECIFIED)?;
+            }
+            if let Socket::Udp(sock) = self.socket(id)? {
+                sock.remote = Some(addr);
+            }
+            return Ok(());
+        }
+
+        self.tcp_connect(id, addr)
+    }
+
+    pub fn listen(&mut self, id: u32, backlog: usize) -> NetResult<()> {
+        match self.socket(id)? {
+            Socket::Udp(_) => Err(SysError::InvalidOp),
+            Socket::Tcp(_) => self.tcp_listen(id, backlog),
+        }
+    }
+
+    pub fn accept(&mut self, id: u32) -> NetResult<(u32, SockAddr)> {
+        match self.socket(id)? {
+            Socket::Udp(_) => Err(SysError::InvalidOp),
+            Socket::Tcp(_) => self.tcp_accept(id),
+        }
+    }
+
+    /// Send `data`, to `to` if the socket isn't connected. Stream sockets may not send all of it.
+    pub fn send_to(&mut self, id: u32, data: &[u8], to: Option<SockAddr>) -> NetResult<usize> {
+        let res = match self.socket(id)? {
+            Socket::Udp(sock) => {
+                let Some(to) = to.or(sock.remote) else {
+                    return Err(SysError::NotConnected);
+                };
+                if data.len() > sock.sndbuf {
+                    return Err(SysError::BadArg);
+                }
+                if sock.local.is_none() {
+                    self.bind(id, SockAddr::UNSPECIFIED)?;
+                }
+                let local = self.socket(id)?.local().unwrap();
+                self.udp_send(local, to, data).map(|_| data.len())
+            }
+            Socket::Tcp(_) => self.tcp_send(id, data),
+        };
+        self.poll();
+        res
+    }
+
+    /// Receive into `buf`. Returns the sender's address for datagram sockets.
+    pub fn recv_from(&mut self, id: u32, buf: &mut [u8]) -> NetResult<(usize, Option<SockAddr>)> {
+        let res = match self.socket(id)? {
+            Socket::Udp(sock) => sock.recv(buf).map(|(len, from)| (len, Some(from))),
+            Socket::Tcp(_) => self.tcp_recv(id, buf).map(|len| (len, None)),
+        };
+        self.poll();
+        res
+    }
+
+    /// Set a socket option. Buffer sizes are clamped to `MIN_BUF..=MAX_BUF`.
+    pub fn set_option(&mut self, id: u32, opt: SockOpt, val: usize) -> NetResult<()> {
+        let val = val.clamp(MIN_BUF, MAX_BUF);
+        let (rcvbuf, sndbuf) = match self.socket(id)? {
+            Socket::Udp(sock) => (&mut sock.rcvbuf, &mut sock.sndbuf),
+            Socket::Tcp(sock) => (&mut sock.rcvbuf, &mut sock.sndbuf),
+        };
+        match opt {
+            SockOpt::RecvBuf => *rcvbuf = val,
+            SockOpt::SendBuf => *sndbuf = val,
+        }
+
+        self.tcp_update_window(id);
+        self.poll();
+        Ok(())
+    }
+
+    pub fn get_option(&mut self, id: u32, opt: SockOpt) -> NetResult<usize> {
+        let (rcvbuf, sndbuf) = match self.socket(id)? {
+            Socket::Udp(sock) => (sock.rcvbuf, sock.sndbuf),
+            Socket::Tcp(sock) => (sock.rcvbuf, sock.sndbuf),
+        };
+        Ok(match opt {
+            SockOpt::RecvBuf => rcvbuf,
+            SockOpt::SendBuf => sndbuf,
+        })
+    }
+
+    /// The number of bytes that can be received without blocking
+    pub fn available(&mut self, id: u32) -> usize {
+        match self.socket(id) {
+            Ok(Socket::Udp(sock)) => sock.available(),
+            Ok(Socket::Tcp(sock)) => sock.available(),
+            Err(_) => 0,
+        }
+    }
+
+    pub fn close(&mut self, id: u32) {
+        match self.socket(id) {
+            Ok(Socket::Udp(_)) => _ = self.sockets.remove(&id),
+            Ok(Socket::Tcp(_)) => self.tcp_close(id),
+            Err(_) => {}
+        }
+        self.poll();
+    }
+}
+
+impl From<SysError> for FsError {
+    fn from(value: SysError) -> Self {
+        match value {
+            SysError::WouldBlock => FsError::WouldBlock,
+            SysError::NotConnected => FsError::NotConnected,
+            SysError::ConnReset | SysError::ConnRefused => FsError::ConnReset,
+            SysError::Eof => FsError::Eof,
+            SysError::NoMem => FsError::NoMem,
+            _ => FsError::InvalidOp,
+        }
+    }
+}
+
+/// Lets sockets live in the file descriptor table. Reading and writing a socket is the same as
+/// `recvfrom` and `sendto` without an address.
+pub struct SocketFs;
+
+impl SocketFs {
+    pub fn open(id: u32) -> FsResult<Fd> {
+        let Ok(fs) = Arc::try_new(SocketFs) else {
+            NET.lock().close(id);
+            return Err(FsError::NoMem);
+        };
+
+        let vn = VNode {
+            ino: id as u64,
+            directory: false,
+            readonly: false,
+        };
+        Ok(unsafe { Fd::new(vn, fs) })
+    }
+}
+
+impl FileSystem for SocketFs {
+    fn open(
+        &self,
+        _path: &Path,
+        _flags: OpenFlags,
+        _mode: Mode,
+        _cwd: Option<&VNode>,
+    ) -> FsResult<VNode> {
+        Err(FsError::Unsupported)
+    }
+
+    fn read<'a>(
+        &self,
+        vn: &VNode,
+        _pos: u64,
+        buf: &'a mut [MaybeUninit<u8>],
+    ) -> FsResult<&'a mut [u8]> {
+        buf.iter_mut().for_each(|b| _ = b.write(0));
+        let buf = unsafe { MaybeUninit::slice_assume_init_mut(buf) };
+        let (len, _) = NET.lock().recv_from(vn.ino as u32, buf)?;
+        Ok(&mut buf[..len])
+    }
+
+    fn write(&self, vn: &VNode, _pos: u64, buf: &[u8]) -> FsResult<usize> {
+        Ok(NET.lock().send_to(vn.ino as u32, buf, None)?)
+    }
+
+    fn close(&self, vn: &VNode, _open: OpenId) -> FsResult<()> {
+        NET.lock().close(vn.ino as u32);
+        Ok(())
+    }
+
+    fn readdir(&self, _vn: &VNode, _pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
+        Err(FsError::InvalidOp)
+    }
+
+    fn stat(&self, vn: &VNode) -> FsResult<Stat> {
+        Ok(Stat {
+            size: NET.lock().available(vn.ino as u32),
+            readonly: false,
+            directory: false,
+            executable: false,
+            atime: 0,
+            mtime: 0,
+            allocated: 0,
+            device: None,
+        })
+    }
+
+    // datagrams must not be split at page boundaries, so copy through a kernel buffer
+
+    fn read_va(
+        &self,
+        vn: &VNode,
+        pos: u64,
+        pt: &PageTable,
+        buf: VirtAddr,
+        len: usize,
+    ) -> FsResult<usize> {
+        let mut kbuf = Vec::try_with_capacity(len).map_err(|_| FsError::NoMem)?;
+        let read = self
+            .read(vn, pos, &mut kbuf.spare_capacity_mut()[..len])?
+            .len();
+        unsafe { kbuf.set_len(read) };
+        buf.copy_to(pt, &kbuf, None)?;
+        Ok(read)
+    }
+
+    fn write_va(
+        &self,
+        vn: &VNode,
+        pos: u64,
+        pt: &PageTable,
+        buf: VirtAddr,
+        len: usize,
+    ) -> FsResult<usize> {
+        let mut kbuf = Vec::try_with_capacity(len).map_err(|_| FsError::NoMem)?;
+        buf.copy_from(pt, &mut kbuf.spare_capacity_mut()[..len])?;
+        unsafe { kbuf.set_len(len) };
+        self.write(vn, pos, &kbuf)
+    }
+
+    fn socket_id(&self, vn: &VNode) -> Option<u32> {
+        Some(vn.ino as u32)
+    }
+}
+
+unsafe impl Sync for SocketFs {}
+unsafe impl Send for SocketFs {}
@@ -0,0 +1,658 @@
+use alloc::collections::VecDeque;
+
+use shared::{net::SockAddr, sys::SysError};
+
+use super::{
+    charge, checksum_valid,
+    ip::{self, Ipv4Header},
+    mem_available, uncharge, NetResult, NetStack, Socket, MAX_BUF, MIN_BUF,
+};
+use crate::{
+    random,
+    tunable::{Kind, Tunable},
+};
+
+const HEADER_LEN: usize = 20;
+const CSUM_OFFSET: usize = 16;
+const FIN: u8 = 1 << 0;
+const SYN: u8 = 1 << 1;
+const RST: u8 = 1 << 2;
+const PSH: u8 = 1 << 3;
+const ACK: u8 = 1 << 4;
+
+pub const MSS: usize = 1460;
+/// The buffer sizes new sockets start with, in bytes
+pub static DEFAULT_RCVBUF: Tunable = Tunable::new(16 * 1024, Kind::Range(MIN_BUF, MAX_BUF));
+pub static DEFAULT_SNDBUF: Tunable = Tunable::new(16 * 1024, Kind::Range(MIN_BUF, MAX_BUF));
+const MAX_BACKLOG: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+}
+
+// TODO: retransmission and TIME-WAIT. Neither matters on the loopback interface, which never drops
+// or reorders packets.
+pub struct TcpSocket {
+    pub state: TcpState,
+    pub local: Option<SockAddr>,
+    pub remote: Option<SockAddr>,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u32,
+    rcv_nxt: u32,
+    /// The right edge of the receive window in the last segment sent
+    rcv_edge: u32,
+    pub rcvbuf: usize,
+    pub sndbuf: usize,
+    rx: VecDeque<u8>,
+    /// Sent but unacknowledged data followed by unsent data, starting at `snd_una`
+    tx: VecDeque<u8>,
+    fin_queued: bool,
+    fin_sent: bool,
+    fin_received: bool,
+    /// Connections that have completed the handshake but haven't been accepted yet
+    backlog: VecDeque<u32>,
+    max_backlog: usize,
+    /// The listening socket that created this connection, until it is accepted
+    listener: Option<u32>,
+    error: Option<SysError>,
+    /// The file descriptor was closed, so the socket is removed as soon as it reaches `Closed`
+    orphaned: bool,
+}
+
+struct Segment<'a> {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    payload: &'a [u8],
+}
+
+impl Segment<'_> {
+    /// The sequence space used by this segment
+    fn len(&self) -> u32 {
+        self.payload.len() as u32 + (self.flags & SYN != 0) as u32 + (self.flags & FIN != 0) as u32
+    }
+}
+
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+impl TcpSocket {
+    pub fn new() -> Self {
+        Self {
+            state: TcpState::Closed,
+            local: None,
+            remote: None,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            rcv_edge: 0,
+            rcvbuf: DEFAULT_RCVBUF.get(),
+            sndbuf: DEFAULT_SNDBUF.get(),
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+            fin_queued: false,
+            fin_sent: false,
+            fin_received: false,
+            backlog: VecDeque::new(),
+            max_backlog: 0,
+            listener: None,
+            error: None,
+            orphaned: false,
+        }
+    }
+
+    pub fn available(&self) -> usize {
+        self.rx.len()
+    }
+
+    fn start(&mut self, state: TcpState) {
+        let iss = random::next_u64() as u32;
+        self.state = state;
+        self.snd_una = iss;
+        self.snd_nxt = iss.wrapping_add(1);
+    }
+
+    fn window(&mut self) -> u16 {
+        let window = self
+            .rcvbuf
+            .saturating_sub(self.rx.len())
+            .min(mem_available())
+            .min(u16::MAX as usize);
+        self.rcv_edge = self.rcv_nxt.wrapping_add(window as u32);
+        window as u16
+    }
+
+    /// How much more data the peer was told it could send
+    fn promised(&self) -> usize {
+        if seq_lt(self.rcv_nxt, self.rcv_edge) {
+            self.rcv_edge.wrapping_sub(self.rcv_nxt) as usize
+        } else {
+            0
+        }
+    }
+
+    fn reset(&mut self, err: SysError) {
+        self.state = TcpState::Closed;
+        self.error = Some(err);
+        uncharge(self.tx.len());
+        self.tx.clear();
+    }
+
+    /// Called after receiving, since the peer might have stopped sending because the window was
+    /// full
+    fn needs_window_update(&self) -> bool {
+        self.promised() < MSS && !self.fin_received
+    }
+}
+
+impl Drop for TcpSocket {
+    fn drop(&mut self) {
+        uncharge(self.rx.len() + self.tx.len());
+    }
+}
+
+impl NetStack {
+    fn tcp(&mut self, id: u32) -> Option<&mut TcpSocket> {
+        match self.sockets.get_mut(&id) {
+            Some(Socket::Tcp(sock)) => Some(sock),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn tcp_send_segment(
+        &mut self,
+        local: SockAddr,
+        remote: SockAddr,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        window: u16,
+        payload: &[u8],
+    ) -> NetResult<()> {
+        let hdr = Ipv4Header {
+            src: local.ip(),
+            dst: remote.ip(),
+            proto: ip::PROTO_TCP,
+        };
+        self.send_ip(hdr, HEADER_LEN + payload.len(), CSUM_OFFSET, |seg| {
+            seg[0..2].copy_from_slice(&local.port.to_be_bytes());
+            seg[2..4].copy_from_slice(&remote.port.to_be_bytes());
+            seg[4..8].copy_from_slice(&seq.to_be_bytes());
+            seg[8..12].copy_from_slice(&ack.to_be_bytes());
+            seg[12] = ((HEADER_LEN / 4) as u8) << 4;
+            seg[13] = flags;
+            seg[14..16].copy_from_slice(&window.to_be_bytes());
+            seg[HEADER_LEN..].copy_from_slice(payload);
+        })
+    }
+
+    /// Send a segment with no data, using the socket's current sequence numbers
+    fn tcp_send_control(&mut self, id: u32, flags: u8) -> NetResult<()> {
+        let Some(sock) = self.tcp(id) else {
+            return Ok(());
+        };
+        let (Some(local), Some(remote)) = (sock.local, sock.remote) else {
+            return Ok(());
+        };
+
+        let seq = if flags & SYN != 0 {
+            sock.snd_una
+        } else {
+            sock.snd_nxt
+        };
+        let (ack, window) = (sock.rcv_nxt, sock.window());
+        self.tcp_send_segment(local, remote, seq, ack, flags, window, &[])
+    }
+
+    /// Send as much queued data (and the FIN, if queued) as the peer's window allows
+    fn tcp_output(&mut self, id: u32) {
+        let mut buf = [0; MSS];
+        loop {
+            let Some(sock) = self.tcp(id) else {
+                return;
+            };
+            let (Some(local), Some(remote)) = (sock.local, sock.remote) else {
+                return;
+            };
+            if !matches!(
+                sock.state,
+                TcpState::Established
+                    | TcpState::CloseWait
+                    | TcpState::FinWait1
+                    | TcpState::Closing
+                    | TcpState::LastAck
+            ) {
+                return;
+            }
+
+            let in_flight = sock.snd_nxt.wrapping_sub(sock.snd_una) as usize;
+            let unsent = sock.tx.len().saturating_sub(in_flight);
+            let window = (sock.snd_wnd as usize).saturating_sub(in_flight);
+            let len = unsent.min(window).min(MSS);
+            let fin = sock.fin_queued && !sock.fin_sent && len == unsent;
+            if len == 0 && !fin {
+                return;
+            }
+
+            for (dst, &b) in buf[..len].iter_mut().zip(sock.tx.iter().skip(in_flight)) {
+                *dst = b;
+            }
+            let mut flags = ACK;
+            if len != 0 {
+                flags |= PSH;
+            }
+            if fin {
+                flags |= FIN;
+            }
+
+            let (seq, ack, window) = (sock.snd_nxt, sock.rcv_nxt, sock.window());
+            if self
+                .tcp_send_segment(local, remote, seq, ack, flags, window, &buf[..len])
+                .is_err()
+            {
+                // nothing was sent, try again on the next ack or write
+                return;
+            }
+
+            let sock = self.tcp(id).unwrap();
+            sock.snd_nxt = seq.wrapping_add(len as u32 + fin as u32);
+            sock.fin_sent |= fin;
+        }
+    }
+
+    fn tcp_send_reset(&mut self, hdr: &Ipv4Header, seg: &Segment) {
+        if seg.flags & RST != 0 {
+            return;
+        }
+
+        let local = SockAddr::new(hdr.dst, seg.dst_port);
+        let remote = SockAddr::new(hdr.src, seg.src_port);
+        _ = if seg.flags & ACK != 0 {
+            self.tcp_send_segment(local, remote, seg.ack, 0, RST, 0, &[])
+        } else {
+            let ack = seg.seq.wrapping_add(seg.len());
+            self.tcp_send_segment(local, remote, 0, ack, RST | ACK, 0, &[])
+        };
+    }
+
+    /// Remove a socket, including from the backlog of the listener that created it
+    fn tcp_remove(&mut self, id: u32) {
+        let Some(Socket::Tcp(sock)) = self.sockets.remove(&id) else {
+            return;
+        };
+
+        if let Some(listener) = sock.listener.and_then(|l| self.tcp(l)) {
+            listener.backlog.retain(|&child| child != id);
+        }
+    }
+
+    fn tcp_remove_if_done(&mut self, id: u32) {
+        if self
+            .tcp(id)
+            .is_some_and(|s| s.state == TcpState::Closed && (s.orphaned || s.listener.is_some()))
+        {
+            self.tcp_remove(id);
+        }
+    }
+
+    pub(super) fn tcp_receive(&mut self, hdr: &Ipv4Header, seg: &[u8], verified: bool) {
+        if seg.len() < HEADER_LEN || (!verified && !checksum_valid(hdr, seg)) {
+            return;
+        }
+
+        let offset = (seg[12] >> 4) as usize * 4;
+        if offset < HEADER_LEN || offset > seg.len() {
+            return;
+        }
+
+        let be16 = |i: usize| u16::from_be_bytes([seg[i], seg[i + 1]]);
+        let be32 = |i: usize| u32::from_be_bytes([seg[i], seg[i + 1], seg[i + 2], seg[i + 3]]);
+        let seg = Segment {
+            src_port: be16(0),
+            dst_port: be16(2),
+            seq: be32(4),
+            ack: be32(8),
+            flags: seg[13],
+            window: be16(14),
+            payload: &seg[offset..],
+        };
+
+        let local = SockAddr::new(hdr.dst, seg.dst_port);
+        let remote = SockAddr::new(hdr.src, seg.src_port);
+        let mut listener = None;
+        let mut conn = None;
+        for (&id, sock) in self.sockets.iter() {
+            let Socket::Tcp(sock) = sock else {
+                continue;
+            };
+
+            if sock.state == TcpState::Listen {
+                let bound = sock.local.unwrap();
+                if bound.port == local.port
+                    && (bound.ip().is_unspecified() || bound.ip() == local.ip())
+                {
+                    listener = Some(id);
+                }
+            } else if sock.state != TcpState::Closed
+                && sock.local == Some(local)
+                && sock.remote == Some(remote)
+            {
+                conn = Some(id);
+                break;
+            }
+        }
+
+        match (conn, listener) {
+            (Some(id), _) => self.tcp_receive_conn(id, hdr, &seg),
+            (None, Some(id)) => self.tcp_receive_listen(id, hdr, &seg),
+            (None, None) => self.tcp_send_reset(hdr, &seg),
+        }
+    }
+
+    fn tcp_receive_listen(&mut self, id: u32, hdr: &Ipv4Header, seg: &Segment) {
+        if seg.flags & RST != 0 {
+            return;
+        } else if seg.flags & ACK != 0 || seg.flags & SYN == 0 {
+            return self.tcp_send_reset(hdr, seg);
+        }
+
+        let pending = self
+            .sockets
+            .values()
+            .filter(|s| matches!(s, Socket::Tcp(s) if s.listener == Some(id)))
+            .count();
+        if pending >= self.tcp(id).unwrap().max_backlog {
+            return;
+        }
+
+        let mut child = TcpSocket::new();
+        child.start(TcpState::SynReceived);
+        child.local = Some(SockAddr::new(hdr.dst, seg.dst_port));
+        child.remote = Some(SockAddr::new(hdr.src, seg.src_port));
+        child.rcv_nxt = seg.seq.wrapping_add(1);
+        child.snd_wnd = seg.window as u32;
+        child.listener = Some(id);
+        let child = self.add_socket(Socket::Tcp(child));
+        _ = self.tcp_send_control(child, SYN | ACK);
+    }
+
+    fn tcp_receive_conn(&mut self, id: u32, hdr: &Ipv4Header, seg: &Segment) {
+        let sock = self.tcp(id).unwrap();
+        if sock.state == TcpState::SynSent {
+            let acceptable = seg.flags & ACK != 0 && seg.ack == sock.snd_nxt;
+            if seg.flags & ACK != 0 && !acceptable {
+                return self.tcp_send_reset(hdr, seg);
+            } else if seg.flags & RST != 0 {
+                if acceptable {
+                    sock.reset(SysError::ConnRefused);
+                }
+                return self.tcp_remove_if_done(id);
+            } else if seg.flags & SYN == 0 || !acceptable {
+                return;
+            }
+
+            sock.rcv_nxt = seg.seq.wrapping_add(1);
+            sock.snd_una = seg.ack;
+            sock.snd_wnd = seg.window as u32;
+            sock.state = TcpState::Established;
+            _ = self.tcp_send_control(id, ACK);
+            return self.tcp_output(id);
+        }
+
+        if seg.flags & RST != 0 {
+            if seg.seq == sock.rcv_nxt {
+                sock.reset(SysError::ConnReset);
+                self.tcp_remove_if_done(id);
+            }
+            return;
+        } else if seg.flags & SYN != 0 {
+            // retransmitted SYN or SYN-ACK, the peer must have missed our reply
+            let flags = if sock.state == TcpState::SynReceived {
+                SYN | ACK
+            } else {
+                ACK
+            };
+            _ = self.tcp_send_control(id, flags);
+            return;
+        } else if seg.flags & ACK == 0 {
+            return;
+        }
+
+        if sock.state == TcpState::SynReceived {
+            if seg.ack != sock.snd_nxt {
+                return self.tcp_send_reset(hdr, seg);
+            }
+
+            sock.state = TcpState::Established;
+            if let Some(listener) = sock.listener.and_then(|l| self.tcp(l)) {
+                // TODO: alloc failure
+                listener.backlog.push_back(id);
+            }
+        }
+
+        let sock = self.tcp(id).unwrap();
+        if seq_lt(sock.snd_una, seg.ack) && seq_le(seg.ack, sock.snd_nxt) {
+            let acked = (seg.ack.wrapping_sub(sock.snd_una) as usize).min(sock.tx.len());
+            sock.tx.drain(..acked);
+            uncharge(acked);
+            sock.snd_una = seg.ack;
+        }
+        if seq_le(seg.ack, sock.snd_nxt) {
+            sock.snd_wnd = seg.window as u32;
+        }
+
+        let fin_acked = sock.fin_sent && sock.snd_una == sock.snd_nxt;
+        match sock.state {
+            TcpState::FinWait1 if fin_acked => sock.state = TcpState::FinWait2,
+            TcpState::Closing | TcpState::LastAck if fin_acked => sock.state = TcpState::Closed,
+            _ => {}
+        }
+
+        let mut need_ack = false;
+        if !seg.payload.is_empty() || seg.flags & FIN != 0 {
+            need_ack = true;
+            let receiving = matches!(
+                sock.state,
+                TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+            );
+            if seg.seq == sock.rcv_nxt && receiving {
+                // anything past the window we advertised is dropped
+                let free = sock.rcvbuf.saturating_sub(sock.rx.len());
+                let len = seg.payload.len().min(free.max(sock.promised()));
+                if sock.rx.try_reserve(len).is_ok() {
+                    charge(len);
+                    sock.rx.extend(&seg.payload[..len]);
+                    sock.rcv_nxt = sock.rcv_nxt.wrapping_add(len as u32);
+
+                    if seg.flags & FIN != 0 && len == seg.payload.len() {
+                        sock.rcv_nxt = sock.rcv_nxt.wrapping_add(1);
+                        sock.fin_received = true;
+                        sock.state = match sock.state {
+                            TcpState::Established => TcpState::CloseWait,
+                            TcpState::FinWait1 => TcpState::Closing,
+                            _ => TcpState::Closed,
+                        };
+                    }
+                }
+            }
+        }
+
+        if need_ack {
+            _ = self.tcp_send_control(id, ACK);
+        }
+        self.tcp_output(id);
+        self.tcp_remove_if_done(id);
+    }
+
+    pub(super) fn tcp_connect(&mut self, id: u32, remote: SockAddr) -> NetResult<()> {
+        let sock = self.tcp(id).unwrap();
+        match sock.state {
+            TcpState::Closed => {}
+            TcpState::SynSent | TcpState::SynReceived => return Err(SysError::WouldBlock),
+            _ if sock.remote == Some(remote) => return Ok(()),
+            _ => return Err(SysError::AlreadyExists),
+        }
+        if let Some(err) = sock.error.take() {
+            return Err(err);
+        } else if remote.port == 0 || remote.ip().is_unspecified() {
+            return Err(SysError::BadArg);
+        }
+
+        let src = self.source_addr(remote.ip())?;
+        let local = match self.tcp(id).unwrap().local {
+            Some(local) if local.ip().is_unspecified() => SockAddr::new(src, local.port),
+            Some(local) => local,
+            None => SockAddr::new(src, self.ephemeral_port(true)?),
+        };
+
+        let sock = self.tcp(id).unwrap();
+        sock.local = Some(local);
+        sock.remote = Some(remote);
+        sock.start(TcpState::SynSent);
+        self.tcp_send_control(id, SYN)?;
+        self.poll();
+
+        let sock = self.tcp(id).unwrap();
+        match sock.state {
+            TcpState::SynSent => Err(SysError::WouldBlock),
+            TcpState::Closed => Err(sock.error.take().unwrap_or(SysError::ConnRefused)),
+            _ => Ok(()),
+        }
+    }
+
+    pub(super) fn tcp_listen(&mut self, id: u32, backlog: usize) -> NetResult<()> {
+        let sock = self.tcp(id).unwrap();
+        match sock.state {
+            TcpState::Closed if sock.local.is_some() => {}
+            TcpState::Listen => {}
+            _ => return Err(SysError::InvalidOp),
+        }
+
+        sock.state = TcpState::Listen;
+        sock.max_backlog = backlog.clamp(1, MAX_BACKLOG);
+        Ok(())
+    }
+
+    pub(super) fn tcp_accept(&mut self, id: u32) -> NetResult<(u32, SockAddr)> {
+        let sock = self.tcp(id).unwrap();
+        if sock.state != TcpState::Listen {
+            return Err(SysError::InvalidOp);
+        }
+
+        let child_id = sock.backlog.pop_front().ok_or(SysError::WouldBlock)?;
+        let child = self.tcp(child_id).unwrap();
+        child.listener = None;
+        Ok((child_id, child.remote.unwrap()))
+    }
+
+    pub(super) fn tcp_send(&mut self, id: u32, data: &[u8]) -> NetResult<usize> {
+        let sock = self.tcp(id).unwrap();
+        match sock.state {
+            TcpState::Established | TcpState::CloseWait => {}
+            TcpState::SynSent | TcpState::SynReceived => return Err(SysError::WouldBlock),
+            _ => return Err(sock.error.unwrap_or(SysError::NotConnected)),
+        }
+
+        let len = data
+            .len()
+            .min(sock.sndbuf.saturating_sub(sock.tx.len()))
+            .min(mem_available());
+        if len == 0 && !data.is_empty() {
+            return Err(SysError::WouldBlock);
+        }
+
+        sock.tx.try_reserve(len)?;
+        charge(len);
+        sock.tx.extend(&data[..len]);
+        self.tcp_output(id);
+        Ok(len)
+    }
+
+    pub(super) fn tcp_recv(&mut self, id: u32, buf: &mut [u8]) -> NetResult<usize> {
+        let sock = self.tcp(id).unwrap();
+        if sock.rx.is_empty() {
+            return Err(match sock.state {
+                _ if sock.fin_received => SysError::Eof,
+                _ if sock.error.is_some() => sock.error.unwrap(),
+                TcpState::Established
+                | TcpState::FinWait1
+                | TcpState::FinWait2
+                | TcpState::SynSent
+                | TcpState::SynReceived => SysError::WouldBlock,
+                _ => SysError::NotConnected,
+            });
+        }
+
+        let len = buf.len().min(sock.rx.len());
+        for (dst, src) in buf.iter_mut().zip(sock.rx.drain(..len)) {
+            *dst = src;
+        }
+        uncharge(len);
+
+        if sock.needs_window_update() {
+            _ = self.tcp_send_control(id, ACK);
+        }
+        Ok(len)
+    }
+
+    /// Tell the peer about a bigger receive window after the buffer size changes
+    pub(super) fn tcp_update_window(&mut self, id: u32) {
+        if self
+            .tcp(id)
+            .is_some_and(|s| s.state == TcpState::Established && s.needs_window_update())
+        {
+            _ = self.tcp_send_control(id, ACK);
+        }
+    }
+
+    pub(super) fn tcp_close(&mut self, id: u32) {
+        let sock = self.tcp(id).unwrap();
+        sock.orphaned = true;
+        match sock.state {
+            TcpState::Listen => {
+                // reset every connection that hasn't been accepted yet
+                while let Some(child) = self.sockets.iter().find_map(|(&child, s)| {
+                    matches!(s, Socket::Tcp(s) if s.listener == Some(id)).then_some(child)
+                }) {
+                    _ = self.tcp_send_control(child, RST | ACK);
+                    self.sockets.remove(&child);
+                }
+                self.sockets.remove(&id);
+            }
+            TcpState::Closed | TcpState::SynSent => {
+                self.sockets.remove(&id);
+            }
+            TcpState::Established | TcpState::CloseWait => {
+                sock.fin_queued = true;
+                sock.state = if sock.state == TcpState::Established {
+                    TcpState::FinWait1
+                } else {
+                    TcpState::LastAck
+                };
+                self.tcp_output(id);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::{mem::MaybeUninit, net::Ipv4Addr};
+
+use shared::{net::HwAddr, sys::SysError};
+
+use crate::{
+    dev::Device,
+    fs::{FsError, FsResult},
+};
+
+use super::{Checksum, Interface, NetResult, Offload, Packet, NET};
+
+/// The maximum number of packets waiting in either direction
+const MAX_QUEUED: usize = 256;
+
+/// The `tun0` interface. Packets the stack sends to it are read from `/dev/tun0`, and packets
+/// written to `/dev/tun0` are received by the stack, so a user program can act as a peer on the
+/// other end of a link.
+pub struct Tun {
+    /// Sent by the stack, waiting for a reader
+    outgoing: VecDeque<Vec<u8>>,
+    /// Written by a user program, waiting for `NetStack::poll`
+    incoming: VecDeque<Packet>,
+}
+
+impl Tun {
+    pub const ADDR: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    pub const MTU: usize = 1500;
+
+    pub const fn new() -> Self {
+        Self {
+            outgoing: VecDeque::new(),
+            incoming: VecDeque::new(),
+        }
+    }
+}
+
+impl Interface for Tun {
+    fn name(&self) -> &'static str {
+        "tun0"
+    }
+
+    fn addr(&self) -> Ipv4Addr {
+        Self::ADDR
+    }
+
+    fn prefix_len(&self) -> u8 {
+        24
+    }
+
+    fn mtu(&self) -> usize {
+        Self::MTU
+    }
+
+    fn hwaddr(&self) -> Option<HwAddr> {
+        None
+    }
+
+    fn offload(&self) -> Offload {
+        Offload::empty()
+    }
+
+    fn transmit(&mut self, pkt: Packet) -> NetResult<()> {
+        if self.outgoing.len() >= MAX_QUEUED {
+            return Err(SysError::WouldBlock);
+        }
+
+        self.outgoing.try_reserve(1)?;
+        self.outgoing.push_back(pkt.data);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Option<Packet> {
+        self.incoming.pop_front()
+    }
+}
+
+/// `/dev/tun0`. Each read returns one packet, truncated to fit, and each write sends one.
+pub struct TunDevice;
+
+impl Device for TunDevice {
+    fn read<'a>(&self, _pos: u64, buf: &'a mut [MaybeUninit<u8>]) -> FsResult<&'a mut [u8]> {
+        let Some(pkt) = NET.lock().ifaces.tun.outgoing.pop_front() else {
+            return Err(FsError::WouldBlock);
+        };
+
+        let len = pkt.len().min(buf.len());
+        for (dst, &src) in buf.iter_mut().zip(&pkt[..len]) {
+            dst.write(src);
+        }
+        Ok(unsafe { MaybeUninit::slice_assume_init_mut(&mut buf[..len]) })
+    }
+
+    fn write(&self, _pos: u64, buf: &[u8]) -> FsResult<usize> {
+        let mut net = NET.lock();
+        let tun = &mut net.ifaces.tun;
+        if tun.incoming.len() >= MAX_QUEUED {
+            return Err(FsError::WouldBlock);
+        }
+
+        let mut data = Vec::try_with_capacity(buf.len()).map_err(|_| FsError::NoMem)?;
+        data.extend_from_slice(buf);
+        tun.incoming.try_reserve(1).map_err(|_| FsError::NoMem)?;
+        tun.incoming.push_back(Packet {
+            data,
+            csum: Checksum::None,
+            link_dst: None,
+        });
+        net.poll();
+        Ok(buf.len())
+    }
+
+    fn datagram(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,143 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::mem::size_of;
+
+use shared::{net::SockAddr, sys::SysError};
+
+use super::{
+    charge, checksum_valid,
+    ip::{self, Ipv4Header},
+    mem_available, try_copy, uncharge, NetResult, NetStack, Socket,
+};
+
+const HEADER_LEN: usize = 8;
+const CSUM_OFFSET: usize = 6;
+const DEFAULT_RCVBUF: usize = 64 * 1024;
+pub const MAX_PAYLOAD: usize = u16::MAX as usize - ip::HEADER_LEN - HEADER_LEN;
+
+pub struct UdpSocket {
+    pub local: Option<SockAddr>,
+    pub remote: Option<SockAddr>,
+    pub rcvbuf: usize,
+    pub sndbuf: usize,
+    rx: VecDeque<(SockAddr, Vec<u8>)>,
+    /// The amount of `rcvbuf` used by `rx`
+    rx_used: usize,
+}
+
+/// Queued datagrams count against the receive buffer with some overhead, so a flood of empty
+/// datagrams can't use unlimited memory
+fn cost(data: &[u8]) -> usize {
+    data.len() + size_of::<(SockAddr, Vec<u8>)>()
+}
+
+impl UdpSocket {
+    pub const fn new() -> Self {
+        Self {
+            local: None,
+            remote: None,
+            rcvbuf: DEFAULT_RCVBUF,
+            sndbuf: MAX_PAYLOAD,
+            rx: VecDeque::new(),
+            rx_used: 0,
+        }
+    }
+
+    pub fn recv(&mut self, buf: &mut [u8]) -> NetResult<(usize, SockAddr)> {
+        let (from, data) = self.rx.pop_front().ok_or(SysError::WouldBlock)?;
+        self.rx_used -= cost(&data);
+        uncharge(cost(&data));
+        // the rest of the datagram is discarded
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, from))
+    }
+
+    pub fn available(&self) -> usize {
+        self.rx.front().map(|(_, data)| data.len()).unwrap_or(0)
+    }
+
+    fn accepts(&self, hdr: &Ipv4Header, src_port: u16, dst_port: u16) -> bool {
+        let Some(local) = self.local else {
+            return false;
+        };
+        local.port == dst_port
+            && (local.ip().is_unspecified() || local.ip() == hdr.dst)
+            && self
+                .remote
+                .map_or(true, |r| r == SockAddr::new(hdr.src, src_port))
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        uncharge(self.rx_used);
+    }
+}
+
+impl NetStack {
+    pub(super) fn udp_send(&mut self, local: SockAddr, to: SockAddr, data: &[u8]) -> NetResult<()> {
+        if data.len() > MAX_PAYLOAD {
+            return Err(SysError::BadArg);
+        }
+
+        let src = if local.ip().is_unspecified() {
+            self.source_addr(to.ip())?
+        } else {
+            local.ip()
+        };
+        let hdr = Ipv4Header {
+            src,
+            dst: to.ip(),
+            proto: ip::PROTO_UDP,
+        };
+        self.send_ip(hdr, HEADER_LEN + data.len(), CSUM_OFFSET, |seg| {
+            seg[0..2].copy_from_slice(&local.port.to_be_bytes());
+            seg[2..4].copy_from_slice(&to.port.to_be_bytes());
+            let len = seg.len() as u16;
+            seg[4..6].copy_from_slice(&len.to_be_bytes());
+            seg[HEADER_LEN..].copy_from_slice(data);
+        })
+    }
+
+    pub(super) fn udp_receive(&mut self, hdr: &Ipv4Header, seg: &[u8], verified: bool) {
+        let &[sp_hi, sp_lo, dp_hi, dp_lo, len_hi, len_lo, ck_hi, ck_lo, ..] = seg else {
+            return;
+        };
+
+        let len = u16::from_be_bytes([len_hi, len_lo]) as usize;
+        if len < HEADER_LEN || len > seg.len() {
+            return;
+        }
+
+        let seg = &seg[..len];
+        if !verified && u16::from_be_bytes([ck_hi, ck_lo]) != 0 && !checksum_valid(hdr, seg) {
+            return;
+        }
+
+        let (src_port, dst_port) = (
+            u16::from_be_bytes([sp_hi, sp_lo]),
+            u16::from_be_bytes([dp_hi, dp_lo]),
+        );
+        let Some(sock) = self.sockets.values_mut().find_map(|sock| match sock {
+            Socket::Udp(sock) if sock.accepts(hdr, src_port, dst_port) => Some(sock),
+            _ => None,
+        }) else {
+            return;
+        };
+
+        // datagrams that don't fit are dropped
+        let data = &seg[HEADER_LEN..];
+        if sock.rx_used + cost(data) > sock.rcvbuf
+            || cost(data) > mem_available()
+            || sock.rx.try_reserve(1).is_err()
+        {
+            return;
+        }
+        let Ok(data) = try_copy(data) else {
+            return;
+        };
+        charge(cost(&data));
+        sock.rx_used += cost(&data);
+        sock.rx.push_back((SockAddr::new(hdr.src, src_port), data));
+    }
+}
@@ -0,0 +1,60 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use servos::lock::SpinLocked;
+use shared::sys::ProcHandle;
+
+use crate::proc::{Exited, ProcId};
+
+/// How many exit codes are remembered for `Sys::Poll` to report once the process is gone
+const EXITS_LEN: usize = 32;
+
+/// Changed by everything `Sys::Poll` can wait for. A process blocked in it sleeps until this
+/// changes, then checks all of its entries again.
+static EVENTS: AtomicU64 = AtomicU64::new(0);
+
+struct Exits {
+    codes: [Option<(ProcHandle, usize)>; EXITS_LEN],
+    next: usize,
+}
+
+static EXITS: SpinLocked<Exits> = SpinLocked::new(Exits {
+    codes: [None; EXITS_LEN],
+    next: 0,
+});
+
+/// Read before checking if anything is ready, so whatever happens during the check still wakes
+/// the poller
+pub fn generation() -> u64 {
+    EVENTS.load(Ordering::Acquire)
+}
+
+/// Make every process blocked in `Sys::Poll` check its entries again. Doesn't take any locks, so
+/// it's safe to call from interrupt handlers.
+pub fn notify() {
+    EVENTS.fetch_add(1, Ordering::Release);
+}
+
+/// Remember the exit code, overwriting the oldest one. Pollers are only notified once the process
+/// is gone.
+pub fn process_exited(exit: &Exited) {
+    let mut exits = EXITS.lock();
+    let next = exits.next;
+    exits.codes[next] = Some((exit.handle, exit.acct.status as usize));
+    exits.next = (next + 1) % EXITS_LEN;
+}
+
+/// The exit code of the most recent process `id` refers to, if it's still remembered
+pub fn exit_code(id: ProcId) -> Option<usize> {
+    let exits = EXITS.lock();
+    // newest first, in case a bare PID has been used more than once
+    (0..EXITS_LEN)
+        .map(|i| exits.codes[(exits.next + EXITS_LEN - 1 - i) % EXITS_LEN])
+        .find_map(|code| {
+            let (handle, code) = code?;
+            match id {
+                ProcId::Pid(pid) => handle.pid() == pid,
+                ProcId::Handle(id) => handle == id,
+            }
+            .then_some(code)
+        })
+}
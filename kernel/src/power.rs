@@ -1,11 +1,46 @@
 use servos::{
     drivers::Syscon,
     lock::SpinLocked,
+    riscv::{r_time, TIMEBASE_FREQ},
     sbi::{
         self,
         sys_reset::{ResetReason, ResetType},
     },
 };
+use shared::sys::ProcHandle;
+
+use crate::{
+    proc::Exited,
+    tunable::{Kind, Tunable},
+};
+
+/// How long `Sys::Shutdown` gives other processes to exit before killing them, in milliseconds
+pub static SHUTDOWN_GRACE_MS: Tunable = Tunable::new(3000, Kind::Range(0, 60_000));
+
+/// The process that started the shutdown in progress, and when its grace period ends
+static SHUTDOWN: SpinLocked<Option<(ProcHandle, usize)>> = SpinLocked::new(None);
+
+/// Start a shutdown on behalf of `caller`, or join the one already in progress, which keeps its
+/// grace period. Returns when the grace period ends, and whether this call started it.
+pub fn begin_shutdown(caller: ProcHandle) -> (usize, bool) {
+    let mut shutdown = SHUTDOWN.lock();
+    if let Some((_, deadline)) = *shutdown {
+        return (deadline, false);
+    }
+
+    let deadline = r_time() + SHUTDOWN_GRACE_MS.get() * (TIMEBASE_FREQ / 1000);
+    *shutdown = Some((caller, deadline));
+    (deadline, true)
+}
+
+/// A shutdown ends with the process that started it, so one killed while waiting doesn't leave
+/// the next caller a grace period that's already over
+pub fn process_exited(exit: &Exited) {
+    let mut shutdown = SHUTDOWN.lock();
+    if shutdown.is_some_and(|(handle, _)| handle == exit.handle) {
+        *shutdown = None;
+    }
+}
 
 pub static POWER: SpinLocked<PowerManagement> =
     SpinLocked::new(PowerManagement::Sbi(SbiPowerManagement));
@@ -1,33 +1,90 @@
 use core::{
     ops::{Index, IndexMut},
     ptr::{addr_of, addr_of_mut, NonNull},
-    sync::atomic::{AtomicU32, Ordering},
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
 };
 
 use crate::{
+    acct,
+    audit::{self, Resource},
+    filter::Program,
     fs::{
-        path::Path,
+        self,
+        path::{OwnedPath, Path},
         vfs::{Fd, Vfs},
     },
-    trap::{self, USER_TRAP_VEC},
-    vmm::{Page, PageTable, Pte, VirtAddr},
+    ipc::{self, Mailbox},
+    isa, klog, poll, power, stats, trap,
+    tunable::{Kind, Tunable},
+    vmm::{self, page_number, Page, PageTable, Pte, VirtAddr},
+};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec,
 };
-use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
 use servos::{
     arr::HoleArray,
-    elf::{ElfFile, PF_W, PF_X, PT_LOAD},
+    elf::{ElfError, ElfFile, PF_W, PF_X},
     lock::{Guard, SpinLocked},
-    riscv::{enable_intr, r_tp},
+    riscv::{enable_intr, r_time, r_tp, TIMEBASE_FREQ},
+};
+use shared::{
+    io::{Mode, OpenFlags},
+    layout,
+    sys::{set_syscall_result, AcctRecord, ProcHandle, SchedClass, SysError},
 };
-use shared::{io::OpenFlags, sys::SysError};
 
-static NEXTPID: AtomicU32 = AtomicU32::new(0);
+/// The low 32 bits are the next PID, the high 32 bits count how many times PIDs have wrapped
+/// around. Starts at generation 1 so handles are never confused with bare PIDs.
+static NEXTID: AtomicU64 = AtomicU64::new(1 << 32);
+
+pub const MAX_HARTS: usize = 64;
+
+const NO_PID: u32 = u32::MAX;
+/// The PID of the process each hart is currently running or handling a trap for
+static CURRENT_PID: [AtomicU32; MAX_HARTS] = [const { AtomicU32::new(NO_PID) }; MAX_HARTS];
+
+pub fn current_pid() -> Option<u32> {
+    let pid = CURRENT_PID.get(r_tp())?.load(Ordering::Relaxed);
+    (pid != NO_PID).then_some(pid)
+}
+
+fn set_current_pid(pid: Option<u32>) {
+    if let Some(cur) = CURRENT_PID.get(r_tp()) {
+        cur.store(pid.unwrap_or(NO_PID), Ordering::Relaxed);
+    }
+}
 
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum ProcStatus {
     Idle,
     Running,
-    Waiting(u32),
+    /// Blocked in `Sys::Waitpid` until the process exits, or until the time given (in timer
+    /// ticks) passes. `usize::MAX` waits forever.
+    Waiting(ProcHandle, usize),
+    /// Blocked in `Sys::RecvMsg` until a message arrives
+    Receiving,
+    /// Blocked in `Sys::Poll` until the poll generation moves on from this one
+    Polling(u64),
+    /// Blocked in `Sys::Shutdown` until another process exits (the poll generation moves on) or
+    /// the grace period ends at the given time
+    ShuttingDown(u64, usize),
+}
+
+impl ProcStatus {
+    pub fn is_blocked(self) -> bool {
+        match self {
+            ProcStatus::Waiting(_, deadline) => r_time() < deadline,
+            ProcStatus::Receiving => true,
+            ProcStatus::Polling(gen) => gen == poll::generation(),
+            ProcStatus::ShuttingDown(gen, deadline) => {
+                gen == poll::generation() && r_time() < deadline
+            }
+            ProcStatus::Idle | ProcStatus::Running => false,
+        }
+    }
 }
 
 #[repr(usize)]
@@ -67,17 +124,24 @@ pub enum Reg {
     T6,
 }
 
-// return_to_user and user_trap_vec both rely on the exact layout of this struct
+// return_to_user and user_trap_vec find every field with offset_of!, but regs must still be
+// indexed by Reg
 #[repr(C, align(0x1000))]
 pub struct TrapFrame {
     pub regs: [usize; 32], // 0 is PC
     pub hartid: usize,
     pub ksatp: usize,
     pub ksp: *mut u8,
-    pub handle_trap: extern "C" fn(sepc: usize, proc: ProcessNode) -> !,
+    /// `entered_at` is the cycle counter as the trap was taken
+    pub handle_trap: extern "C" fn(sepc: usize, proc: ProcessNode, entered_at: usize) -> !,
     pub proc: ProcessNode,
 }
 
+const _: () = {
+    assert!(Reg::T6 as usize == 31);
+    assert!(core::mem::size_of::<TrapFrame>() == Page::SIZE);
+};
+
 impl Index<Reg> for TrapFrame {
     type Output = usize;
 
@@ -92,6 +156,23 @@ impl IndexMut<Reg> for TrapFrame {
     }
 }
 
+/// What subsystems are told about a process that has exited
+pub struct Exited {
+    pub handle: ProcHandle,
+    pub acct: AcctRecord,
+}
+
+/// Subsystems that keep state for a process outside of `Process` release it in these, in order.
+/// They're called once the process can no longer run and its files are closed, but before anything
+/// waiting for it is woken, so a parent never sees a half torn down child. No lock is held, but
+/// interrupts are off.
+const EXIT_HOOKS: &[fn(&Exited)] = &[
+    ipc::process_exited,
+    acct::process_exited,
+    poll::process_exited,
+    power::process_exited,
+];
+
 #[repr(transparent)]
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct ProcessNode(pub NonNull<SpinLocked<Process>>);
@@ -103,30 +184,56 @@ impl ProcessNode {
 
     /// # Safety
     /// The process must not be awaiting scheduling or running on any hart.
-    pub unsafe fn destroy(self, lock: Guard<Process>, ecode: usize) {
-        let mypid = lock.pid;
+    pub unsafe fn destroy(self, mut lock: Guard<Process>, ecode: usize) {
+        let me = lock.handle;
+        let mypid = me.pid();
         if mypid == 0 {
             panic!("return from the init process");
         }
 
-        let _token = Guard::forget_and_keep_token(lock);
-        let mut list = PROC_LIST.lock();
-        if let Some(i) = list.iter().position(|&rhs| rhs == self) {
-            list.swap_remove_back(i);
+        klog!(Debug, Sched, "PID {mypid} exited with code {ecode:#x}");
+        let exit = Exited {
+            handle: me,
+            acct: lock.acct_record(ecode),
+        };
+        // closing a file can do I/O or send packets, which shouldn't happen with PROC_LIST held
+        let files = core::mem::replace(&mut lock.files, HoleArray::empty());
+        let mut audit = audit::Teardown::new(&lock, files.iter().flatten().count());
+
+        // PROC_LIST must be taken before any process lock. Other harts can only reach this process
+        // through PROC_LIST, so once it has been removed nobody else can be holding its lock.
+        let _token = Guard::drop_and_keep_token(lock);
+        audit.run(|| drop(files));
+        for hook in EXIT_HOOKS {
+            hook(&exit);
         }
 
-        for proc in list.iter() {
+        let mut list = PROC_LIST.lock();
+        list.remove(&mypid);
+        poll::notify();
+
+        for proc in list.values() {
             unsafe {
                 proc.with(|mut proc| {
-                    if proc.status == ProcStatus::Waiting(mypid) {
-                        proc.status = ProcStatus::Idle;
-                        proc.trapframe()[Reg::A0] = ecode;
-                        proc.trapframe()[Reg::A1] = 0;
+                    if matches!(proc.status, ProcStatus::Waiting(handle, _) if handle == me) {
+                        proc.wake();
+                        set_syscall_result(&mut proc.trapframe().regs, Ok(ecode));
                     }
                 })
             }
         }
-        unsafe { self.free() };
+        drop(list);
+
+        // checked before the node is freed, since a new process could be allocated in its place
+        if audit::ENABLED {
+            let waiters = for_each_process(|proc| {
+                matches!(proc.status, ProcStatus::Waiting(handle, _) if handle == me).then_some(())
+            });
+            audit::report(mypid, Resource::Waiters, waiters.is_some() as usize);
+            audit::report(mypid, Resource::SchedNode, Scheduler::queued(self));
+        }
+        audit.run(|| unsafe { self.free() });
+        audit.finish();
     }
 
     unsafe fn free(self) {
@@ -135,48 +242,82 @@ impl ProcessNode {
 }
 
 pub struct Process {
-    pub pid: u32,
+    pub handle: ProcHandle,
     pub status: ProcStatus,
     pub files: HoleArray<Fd, 32>,
     pub cwd: Fd,
+    /// The absolute path `cwd` was opened with
+    pub cwd_path: OwnedPath,
+    /// The absolute path of the executable this process was spawned from
+    pub exe_path: OwnedPath,
+    /// Permissions removed from files and directories this process creates
+    pub umask: Mode,
+    /// Checked before every syscall, which fails without running if it returns nonzero.
+    /// Inherited by children and can't be removed once installed.
+    pub seccomp: Option<Arc<Program>>,
     pub brk: VirtAddr,
     pub killed: Option<usize>,
+    pub mailbox: Mailbox,
+    pub class: SchedClass,
+    /// When the process was spawned, in the unit of `fs::now`
+    start: u64,
+    /// Timer ticks spent running, counted each time the process traps
+    cpu_time: usize,
+    /// When the process was last resumed
+    resumed_at: usize,
+    /// When the process last became ready to run, for measuring how long it waits for a hart
+    ready_at: usize,
+    /// When the current time slice runs out
+    slice_end: usize,
     pagetable: *mut PageTable,
     trapframe: *mut TrapFrame,
 }
 
-pub const USER_TRAP_FRAME: VirtAddr = VirtAddr(USER_TRAP_VEC.0 - Page::SIZE);
+pub const USER_TRAP_FRAME: VirtAddr = VirtAddr(layout::TRAP_FRAME.start);
 pub const HART_STACK_LEN: usize = Page::SIZE * 4;
-pub const HART_FIRST_STACK: VirtAddr = VirtAddr(USER_TRAP_FRAME.0 - Page::SIZE);
+/// Each hart stack has an unmapped guard region of the same size below it, so the stacks are a
+/// power of two apart and the trap vector can tell if `sp` is in a guard region with one register
+pub const HART_STACK_STRIDE: usize = HART_STACK_LEN * 2;
+pub const HART_FIRST_STACK: VirtAddr = VirtAddr(layout::KERNEL_STACKS_TOP);
+/// Hart stacks are filled with this before they're used, so `hart_stack_used` can find how deep
+/// they've ever been
+pub const HART_STACK_PAINT: u8 = 0xa5;
 
-const USER_STACK_SZ: usize = 1024 * 1024;
+const _: () = assert!(HART_STACK_LEN.is_power_of_two());
 
 impl Process {
-    pub fn spawn(path: &Path, cwd: Fd, args: &[&[u8]]) -> Result<u32, SysError> {
-        let file = Vfs::open_in_cwd(&cwd, path, OpenFlags::empty())?;
+    /// Spawn the executable at `path` with the arguments `argv`, including `argv[0]`. If `argv`
+    /// is empty, `path` is used as `argv[0]`.
+    pub fn spawn(
+        path: &Path,
+        cwd: Fd,
+        cwd_path: OwnedPath,
+        umask: Mode,
+        seccomp: Option<Arc<Program>>,
+        argv: &[&[u8]],
+    ) -> Result<u32, SysError> {
+        let exe_path = path.resolve(&cwd_path)?;
+        let file = Vfs::open_in_cwd(&cwd, &cwd_path, path, OpenFlags::empty(), Mode::empty())?;
         let stat = file.stat()?;
+        if !stat.executable {
+            return Err(SysError::PermissionDenied);
+        }
+
         let mut buf = Vec::try_with_capacity(stat.size)?;
-        let Some(file) = ElfFile::new(file.read(0, buf.spare_capacity_mut())?) else {
-            return Err(SysError::BadArg);
-        };
+        let file = ElfFile::new(file.read(0, buf.spare_capacity_mut())?)?;
 
         let mut pt = PageTable::try_alloc()?;
         let mut trapframe_page = Page::zeroed()?;
         let trapframe = &mut *trapframe_page as *mut _ as *mut TrapFrame;
         if !trap::map_trap_code(&mut pt)
+            || !isa::map_info(&mut pt)
             || !pt.map_owned_page(trapframe_page, USER_TRAP_FRAME, Pte::Rw)
         {
             return Err(SysError::NoMem);
         }
 
         let mut highest_va = VirtAddr(0);
-        for phdr in file.pheaders.iter() {
-            if phdr.typ != PT_LOAD {
-                continue;
-            } else if phdr.memsz < phdr.filesz {
-                return Err(SysError::BadArg);
-            }
-
+        for phdr in file.load_segments()? {
             let mut perms = Pte::U | Pte::R;
             if phdr.flags & PF_W != 0 {
                 perms |= Pte::W;
@@ -185,11 +326,23 @@ impl Process {
                 perms |= Pte::X;
             }
             let base = VirtAddr(phdr.vaddr as usize);
-            if !pt.map_new_pages(base, phdr.memsz as usize, perms, false) {
+            let end = base.0.checked_add(phdr.memsz as usize);
+            if base.0 < layout::IMAGE.start || end.map_or(true, |end| end > layout::IMAGE.end) {
+                return Err(ElfError::TooBig.into());
+            }
+
+            // only the pages with data from the file need one of their own, the rest of the bss
+            // can share the zero page
+            let (filesz, end) = (phdr.filesz as usize, base + phdr.memsz as usize);
+            let file_end = VirtAddr(page_number(base.0 + filesz + Page::SIZE - 1)).min(end);
+            let file_len = file_end.0 - base.0;
+            if file_len != 0 && !pt.map_new_pages(base, file_len, perms, false) {
+                return Err(SysError::NoMem);
+            }
+            if end > file_end && !pt.map_zero_pages(file_end, end.0 - file_end.0, perms) {
                 return Err(SysError::NoMem);
             }
 
-            let filesz = phdr.filesz as usize;
             base.copy_to(
                 &pt,
                 &file.raw[phdr.offset as usize..][..filesz],
@@ -197,19 +350,21 @@ impl Process {
             )?;
 
             (base + filesz)
-                .iter_phys(&pt, (phdr.memsz - phdr.filesz) as usize, perms)
+                .iter_phys(&pt, file_len - filesz, Pte::empty())
                 .zero();
 
-            highest_va = highest_va.max(base + phdr.memsz as usize);
+            highest_va = highest_va.max(end);
         }
 
-        let mut sp = USER_TRAP_FRAME - Page::SIZE;
-        if !pt.map_new_pages(sp - USER_STACK_SZ, USER_STACK_SZ, Pte::Urw, true) {
+        let mut sp = VirtAddr(layout::STACK.end);
+        if !pt.map_zero_pages(VirtAddr(layout::STACK.start), layout::STACK_SIZE, Pte::Urw) {
             return Err(SysError::NoMem);
         }
 
-        let mut ptrs = Vec::try_with_capacity(args.len() + 1)?;
-        for arg in core::iter::once(path.as_ref()).chain(args.iter().cloned()) {
+        let path = [path.as_ref()];
+        let argv = if argv.is_empty() { &path[..] } else { argv };
+        let mut ptrs = Vec::try_with_capacity(argv.len())?;
+        for &arg in argv {
             // stack is already zeroed, so just add 1 for the null terminator
             sp.0 -= arg.len() + 1;
             sp.copy_to(&pt, arg, None)?;
@@ -222,18 +377,28 @@ impl Process {
             sp.copy_type_to(&pt, &arg)?;
         }
 
-        let pid = NEXTPID.fetch_add(1, Ordering::Relaxed);
         let proc = Box::try_new(SpinLocked::new(Process {
-            pid,
+            handle: ProcHandle(0), // assigned by enqueue_process
             pagetable: Box::into_raw(pt),
             trapframe,
             status: ProcStatus::Idle,
             killed: None,
+            mailbox: Mailbox::default(),
+            class: SchedClass::Normal,
+            start: fs::now(),
+            cpu_time: 0,
+            resumed_at: 0,
+            ready_at: r_time(),
+            slice_end: 0,
             files: HoleArray::empty(),
             cwd,
+            cwd_path,
+            exe_path,
+            umask,
+            seccomp,
             brk: highest_va,
         }))?;
-        let success = Self::enqueue_process(unsafe {
+        let pid = Self::enqueue_process(unsafe {
             let proc = ProcessNode(NonNull::new_unchecked(Box::into_raw(proc)));
 
             addr_of_mut!((*trapframe).proc).write(proc);
@@ -241,23 +406,38 @@ impl Process {
             (*trapframe).ksatp = PageTable::make_satp(addr_of!(crate::KPAGETABLE));
             (*trapframe)[Reg::PC] = file.ehdr.entry as usize;
             (*trapframe)[Reg::SP] = sp.0;
-            (*trapframe)[Reg::A0] = args.len() + 1;
+            (*trapframe)[Reg::A0] = argv.len();
             (*trapframe)[Reg::A1] = sp.0;
 
             proc
         });
 
-        success.then_some(pid).ok_or(SysError::NoMem)
+        if let Some(pid) = pid {
+            klog!(
+                Debug,
+                Sched,
+                "spawned PID {pid}, entry {:#x}",
+                file.ehdr.entry
+            );
+        }
+        pid.ok_or(SysError::NoMem)
     }
 
     pub unsafe fn resume(mut this: Guard<Process>) -> ! {
         this.status = ProcStatus::Running;
+        this.resumed_at = r_time();
+        set_current_pid(Some(this.pid()));
         this.trapframe().hartid = r_tp();
         this.trapframe().ksp = hart_stack_top(r_tp()).0 as *mut u8;
+        trap::set_timer(this.slice_end);
         let satp = PageTable::make_satp(this.pagetable());
         trap::return_to_user(Guard::drop_and_keep_token(this), satp)
     }
 
+    pub fn pid(&self) -> u32 {
+        self.handle.pid()
+    }
+
     pub fn trapframe(&mut self) -> &mut TrapFrame {
         // Safety: the pagetable owns the trapframe page
         unsafe { &mut *self.trapframe }
@@ -273,21 +453,71 @@ impl Process {
         unsafe { &mut *self.pagetable }
     }
 
+    /// Count the time since the process was resumed as its CPU time. Called once per trap from
+    /// user mode, after it has been handled.
+    /// Called whenever the process stops running. Unless it's blocked, it's ready to run again
+    /// from now on.
+    pub fn charge_cpu_time(&mut self) {
+        let now = r_time();
+        let ran = now.saturating_sub(self.resumed_at);
+        self.cpu_time += ran;
+        self.ready_at = now;
+        stats::busy(ran as u64);
+    }
+
+    /// Let a blocked process run again
+    pub fn wake(&mut self) {
+        self.status = ProcStatus::Idle;
+        self.ready_at = r_time();
+    }
+
+    fn acct_record(&self, ecode: usize) -> AcctRecord {
+        let mut name = [0; 40];
+        let exe = self.exe_path.components().last().unwrap_or_default();
+        let len = exe.len().min(name.len());
+        name[..len].copy_from_slice(&exe[..len]);
+        AcctRecord {
+            start: self.start,
+            cpu_time: (self.cpu_time / (TIMEBASE_FREQ / 1_000_000)) as u64,
+            status: ecode as u64,
+            pid: self.pid(),
+            uid: 0,
+            name,
+        }
+    }
+
     pub fn kill(&mut self, code: Option<usize>) {
         self.killed = Some(code.unwrap_or(usize::MAX));
+        self.interrupt();
+    }
+
+    /// Wake the process from whatever it's blocked in, failing the syscall with `Interrupted`.
+    /// Every status that `is_blocked` has to be safe to leave this way at any time.
+    pub fn interrupt(&mut self) {
+        if !self.status.is_blocked() {
+            return;
+        }
+
+        self.wake();
+        // a blocking syscall has already returned by the time the process sleeps, and whatever
+        // wakes it writes the result
+        set_syscall_result(&mut self.trapframe().regs, Err(SysError::Interrupted));
     }
 
-    fn enqueue_process(proc: ProcessNode) -> bool {
+    fn enqueue_process(proc: ProcessNode) -> Option<u32> {
         let mut proc_list = PROC_LIST.lock();
-        if !try_push_back(&mut proc_list, proc) {
+        let handle = alloc_handle(&proc_list);
+        let pid = handle.pid();
+        unsafe { proc.with(|mut proc| proc.handle = handle) };
+
+        // TODO: alloc failure
+        proc_list.insert(pid, proc);
+        if !Scheduler::take(proc, SchedClass::Normal, false) {
+            proc_list.remove(&pid);
             unsafe { proc.free() };
-            false
-        } else if !Scheduler::take(proc) {
-            proc_list.pop_back();
-            unsafe { proc.free() };
-            false
+            None
         } else {
-            true
+            Some(pid)
         }
     }
 }
@@ -298,47 +528,213 @@ impl Drop for Process {
     }
 }
 
+/// Call `f` on each process in ascending PID order until it returns `Some`. Only one process is
+/// locked at a time, and PROC_LIST is held throughout.
+///
+/// The lock order is PROC_LIST first, then processes in address order (see `with_two_processes`).
+/// PROC_LIST must never be taken while holding a process lock.
+pub fn for_each_process<T>(mut f: impl FnMut(&mut Process) -> Option<T>) -> Option<T> {
+    PROC_LIST
+        .lock()
+        .values()
+        .find_map(|proc| unsafe { proc.with(|mut proc| f(&mut proc)) })
+}
+
+/// Identifies a process by either a bare PID (whichever process currently has it) or a handle (only
+/// the exact process it was created for).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ProcId {
+    Pid(u32),
+    Handle(ProcHandle),
+}
+
+impl ProcId {
+    /// Interpret a syscall argument as a PID or handle
+    pub fn from_raw(raw: usize) -> ProcId {
+        let handle = ProcHandle(raw as u64);
+        if handle.generation() == 0 {
+            ProcId::Pid(handle.pid())
+        } else {
+            ProcId::Handle(handle)
+        }
+    }
+
+    pub fn pid(self) -> u32 {
+        match self {
+            ProcId::Pid(pid) => pid,
+            ProcId::Handle(handle) => handle.pid(),
+        }
+    }
+}
+
+/// Find the process identified by `id` and call `f` on it, unlocked. PROC_LIST is held throughout,
+/// so the process can't be destroyed until `f` returns. A stale handle finds nothing.
+pub fn find_process<T>(id: ProcId, f: impl FnOnce(&SpinLocked<Process>) -> T) -> Option<T> {
+    let list = PROC_LIST.lock();
+    let proc = unsafe { list.get(&id.pid())?.0.as_ref() };
+    if let ProcId::Handle(handle) = id {
+        if proc.lock().handle != handle {
+            return None;
+        }
+    }
+    Some(f(proc))
+}
+
+/// Lock two different processes in a globally consistent order, so two harts operating on the same
+/// pair can't deadlock.
+pub fn with_two_processes<T>(
+    a: &SpinLocked<Process>,
+    b: &SpinLocked<Process>,
+    f: impl FnOnce(&mut Process, &mut Process) -> T,
+) -> T {
+    assert!(
+        !core::ptr::eq(a, b),
+        "with_two_processes called with the same process"
+    );
+    if (a as *const SpinLocked<_>) < (b as *const SpinLocked<_>) {
+        let (mut a, mut b) = (a.lock(), b.lock());
+        f(&mut a, &mut b)
+    } else {
+        let (mut b, mut a) = (b.lock(), a.lock());
+        f(&mut a, &mut b)
+    }
+}
+
 // maybe this should be a semaphore or something
 static SCHEDULER: SpinLocked<Scheduler> = SpinLocked::new(Scheduler::new());
-pub static PROC_LIST: SpinLocked<VecDeque<ProcessNode>> = SpinLocked::new(VecDeque::new());
+static PROC_LIST: SpinLocked<BTreeMap<u32, ProcessNode>> = SpinLocked::new(BTreeMap::new());
+
+/// Contention on `PROC_LIST` and `SCHEDULER`, for `/proc/locks`
+#[cfg(feature = "procfs")]
+pub fn lock_stats() -> [(&'static str, servos::lock::LockStats); 2] {
+    [
+        ("proc_list", PROC_LIST.stats()),
+        ("scheduler", SCHEDULER.stats()),
+    ]
+}
+
+/// Must be called with PROC_LIST held
+fn alloc_handle(list: &BTreeMap<u32, ProcessNode>) -> ProcHandle {
+    // pids are recycled once they wrap around, so skip any that are still in use. The generation
+    // changes every time they wrap, so handles are never reused.
+    loop {
+        let handle = ProcHandle(NEXTID.fetch_add(1, Ordering::Relaxed));
+        if handle.pid() != NO_PID && !list.contains_key(&handle.pid()) {
+            return handle;
+        }
+    }
+}
+
+/// How long a process of each class runs before it's preempted, in milliseconds. Indexed by
+/// `SchedClass` minus one.
+pub static QUANTA: [Tunable; 4] = [
+    Tunable::new(10, Kind::Range(1, 10_000)),
+    Tunable::new(500, Kind::Range(1, 10_000)),
+    Tunable::new(2000, Kind::Range(1, 10_000)),
+    Tunable::new(100, Kind::Range(1, 10_000)),
+];
+
+pub fn quantum(class: SchedClass) -> usize {
+    QUANTA[class as usize - 1].get() * (TIMEBASE_FREQ / 1000)
+}
+
+/// Run queues, searched in this order. Normal and batch processes share a queue, and interactive
+/// processes join them once they've used up a whole time slice, so a busy interactive process
+/// can't starve everything else.
+const QUEUE_INTERACTIVE: usize = 0;
+const QUEUE_NORMAL: usize = 1;
+const QUEUE_IDLE: usize = 2;
 
 pub struct Scheduler {
-    awaiting: VecDeque<ProcessNode>,
+    queues: [VecDeque<ProcessNode>; 3],
 }
 
 impl Scheduler {
     pub const fn new() -> Self {
         Self {
-            awaiting: VecDeque::new(),
+            queues: [const { VecDeque::new() }; 3],
         }
     }
 
     pub fn try_find_execute() {
-        if let Some((next, mut sched)) = SCHEDULER
-            .try_lock()
-            .and_then(|mut s| s.awaiting.pop_front().zip(Some(s)))
-        {
-            unsafe {
-                next.with(|proc| {
-                    if !matches!(proc.status, ProcStatus::Waiting(_)) {
-                        drop(sched);
-                        Process::resume(proc);
-                    } else {
-                        sched.awaiting.push_back(next);
+        let Some(mut sched) = SCHEDULER.try_lock() else {
+            return;
+        };
+
+        let next = 'found: {
+            for queue in sched.queues.iter_mut() {
+                for _ in 0..queue.len() {
+                    let node = queue.pop_front().unwrap();
+                    let proc = unsafe { node.0.as_ref() }.lock();
+                    if !proc.status.is_blocked() {
+                        break 'found Some((node, proc));
                     }
-                });
+                    drop(proc);
+                    queue.push_back(node);
+                }
+            }
+            None
+        };
+
+        drop(sched);
+        if let Some((node, mut proc)) = next {
+            // killed while it was waiting to run, so don't let it run any more user code
+            if let Some(ecode) = proc.killed {
+                unsafe { node.destroy(proc, ecode) };
+                return;
             }
+
+            let now = r_time();
+            stats::dispatched(now.saturating_sub(proc.ready_at) as u64);
+            proc.slice_end = now + quantum(proc.class);
+            unsafe { Process::resume(proc) };
         }
     }
 
-    pub fn take(proc: ProcessNode) -> bool {
-        try_push_back(&mut SCHEDULER.lock().awaiting, proc)
+    /// Queue `proc` to run. `preempted` is true if it used up its time slice.
+    pub fn take(proc: ProcessNode, class: SchedClass, preempted: bool) -> bool {
+        let queue = match class {
+            SchedClass::Interactive if !preempted => QUEUE_INTERACTIVE,
+            SchedClass::Idle => QUEUE_IDLE,
+            _ => QUEUE_NORMAL,
+        };
+        try_push_back(&mut SCHEDULER.lock().queues[queue], proc)
+    }
+
+    /// How many times `proc` is in the run queues
+    pub fn queued(proc: ProcessNode) -> usize {
+        let sched = SCHEDULER.lock();
+        sched
+            .queues
+            .iter()
+            .flatten()
+            .filter(|&&node| node == proc)
+            .count()
+    }
+
+    /// Move interactive processes waiting behind others to the front, so whichever one is reading
+    /// the console gets to run soon after a key is pressed. Called from the interrupt handler, so
+    /// it gives up rather than wait long for the scheduler lock.
+    pub fn console_input() {
+        let Some(mut sched) = SCHEDULER.try_lock_bounded(64) else {
+            return;
+        };
+
+        let [interactive, normal, _] = &mut sched.queues;
+        normal.retain(|&node| {
+            let boost = unsafe { node.with(|proc| proc.class == SchedClass::Interactive) };
+            !(boost && try_push_back(interactive, node))
+        });
     }
 
     pub fn yield_hart() -> ! {
+        set_current_pid(None);
         unsafe { enable_intr() };
         loop {
             Self::try_find_execute();
+            // nothing to run, so get ahead on work that would otherwise slow down what runs next
+            vmm::fill_zero_pool();
+            vmm::scrub_zero_pool();
         }
     }
 }
@@ -353,6 +749,16 @@ fn try_push_back<T>(vec: &mut VecDeque<T>, item: T) -> bool {
 }
 
 pub const fn hart_stack_top(hart: usize) -> VirtAddr {
-    // extra Page::SIZE for guard page
-    VirtAddr(HART_FIRST_STACK.0 - (hart * (HART_STACK_LEN + Page::SIZE)))
+    VirtAddr(HART_FIRST_STACK.0 - hart * HART_STACK_STRIDE)
+}
+
+/// The most of `hart`'s stack that has ever been used, in bytes. The stack must be mapped.
+#[cfg(feature = "procfs")]
+pub fn hart_stack_used(hart: usize) -> usize {
+    let bottom = (hart_stack_top(hart) - HART_STACK_LEN).0 as *const u8;
+    // the hart might be pushing to its stack right now, so don't make a slice out of it
+    let unused = (0..HART_STACK_LEN)
+        .take_while(|&i| unsafe { bottom.add(i).read_volatile() } == HART_STACK_PAINT)
+        .count();
+    HART_STACK_LEN - unused
 }
@@ -0,0 +1,32 @@
+//! Numbers that only have to be hard to guess from outside the machine, like TCP initial sequence
+//! numbers. Nothing here is fit for cryptography.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    bootargs,
+    riscv::{r_cycle, r_time},
+};
+
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+/// Seed from the `seed=` boot argument if there is one, so every boot with the same seed makes
+/// the same choices in the same order. Otherwise the counters are as good as anything this early.
+pub fn init() {
+    let seed = bootargs::get("seed").and_then(|seed| seed.parse().ok());
+    STATE.store(
+        seed.unwrap_or_else(|| r_time() as u64 ^ (r_cycle() as u64).rotate_left(32)),
+        Ordering::Relaxed,
+    );
+}
+
+/// SplitMix64, which only needs one atomic add per number
+pub fn next_u64() -> u64 {
+    const GAMMA: u64 = 0x9e3779b97f4a7c15;
+    let mut z = STATE
+        .fetch_add(GAMMA, Ordering::Relaxed)
+        .wrapping_add(GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
@@ -4,6 +4,9 @@ pub const SIE_SEIE: usize = 1 << 9; // external
 pub const SIE_STIE: usize = 1 << 5; // timer
 pub const SIE_SSIE: usize = 1 << 1; // software
 
+// TODO: read timebase-frequency from the device tree
+pub const TIMEBASE_FREQ: usize = 10_000_000;
+
 pub const SSTATUS_SIE: usize = 1 << 1;
 pub const SSTATUS_SPIE: usize = 1 << 5;
 pub const SSTATUS_SPP: usize = 1 << 8;
@@ -53,9 +56,11 @@ status_reg_fns!(stvec);
 status_reg_fns!(sstatus);
 status_reg_fns!(sip);
 status_reg_fns!(sepc);
+status_reg_fns!(scounteren);
 read_register!(scause);
 read_register!(stval);
 read_register!(time);
+read_register!(cycle);
 
 #[must_use]
 #[inline(always)]
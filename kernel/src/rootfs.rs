@@ -0,0 +1,109 @@
+//! Choosing the root filesystem with `root=` and `rootfstype=`, and the first program with `init=`
+
+use alloc::{sync::Arc, vec::Vec};
+use shared::io::{Mode, OpenFlags};
+
+use crate::{
+    bootargs,
+    fs::{dev::DeviceFs, initrd::InitRd, overlay::OverlayFs, path::Path, vfs::Vfs, FileSystem},
+    klog,
+    proc::Process,
+};
+
+/// Run if `init=` isn't given, or the program it names can't be spawned
+const DEFAULT_INIT: &str = "/bin/init";
+/// Run if `DEFAULT_INIT` can't be spawned either. It doesn't start any services, but it's enough
+/// to look around a broken initrd.
+const FALLBACK_SHELL: &str = "/bin/sh";
+
+/// The filesystem to mount at `/`. `root=` names a block device in `/dev` to read it from, and
+/// `rootfstype=` what's on it, which defaults to an initrd image since that's the only format the
+/// kernel can read. If that fails, or `root=` isn't given, the built in `initrd` is used so there's
+/// still a system to fix things from.
+pub fn select(devices: &DeviceFs, initrd: &[u8]) -> Arc<dyn FileSystem> {
+    let lower = match bootargs::get("root") {
+        None | Some("initrd") => None,
+        Some(root) => match from_device(devices, root) {
+            Ok(fs) => {
+                klog!(Info, Fs, "mounted {root} as the root filesystem");
+                Some(fs)
+            }
+            Err(err) => {
+                klog!(
+                    Error,
+                    Fs,
+                    "couldn't mount root={root}: {err}, falling back to the initrd"
+                );
+                None
+            }
+        },
+    };
+    let lower = lower
+        .unwrap_or_else(|| Arc::new(InitRd::new(initrd).expect("the built in initrd is invalid")));
+
+    // images are read only, so changes are kept in memory on top
+    Arc::new(OverlayFs::new(lower).expect("couldn't create the root filesystem"))
+}
+
+fn from_device(devices: &DeviceFs, root: &str) -> Result<Arc<dyn FileSystem>, &'static str> {
+    let name = root.strip_prefix("/dev/").ok_or("not a path in /dev")?;
+    let dev = devices.get(Path::new(name)).ok_or("no such device")?;
+    let size = dev.size().ok_or("not a block device")?;
+    if bootargs::get("rootfstype").is_some_and(|typ| typ != "initrd") {
+        return Err("unsupported rootfstype");
+    }
+
+    // InitRd copies what it needs out of the image, so it's only needed until then
+    let size = usize::try_from(size).map_err(|_| "too big")?;
+    let mut buf = Vec::<u8>::new();
+    buf.try_reserve_exact(size).map_err(|_| "out of memory")?;
+    let image = &mut buf.spare_capacity_mut()[..size];
+    let mut pos = 0;
+    while pos < size {
+        let read = dev
+            .read(pos as u64, &mut image[pos..])
+            .map_err(|_| "read failed")?
+            .len();
+        if read == 0 {
+            return Err("device is smaller than its size");
+        }
+        pos += read;
+    }
+
+    let image = unsafe { core::slice::from_raw_parts(image.as_ptr().cast::<u8>(), size) };
+    Ok(Arc::new(InitRd::new(image).ok_or("not an initrd image")?))
+}
+
+/// Spawn the program `init=` names as PID 0, or `/bin/init` if it isn't given or can't be spawned,
+/// or `/bin/sh` as a last resort
+pub fn spawn_init() {
+    let spawn = |path: &str| {
+        let root = Vfs::open("/", OpenFlags::empty(), Mode::empty()).unwrap();
+        Process::spawn(
+            Path::new(path),
+            root,
+            Path::new("/").try_into().unwrap(),
+            Mode::empty(),
+            None,
+            &[],
+        )
+    };
+
+    let init = bootargs::get("init").unwrap_or(DEFAULT_INIT);
+    let candidates = [init, DEFAULT_INIT, FALLBACK_SHELL];
+    for (i, &path) in candidates.iter().enumerate() {
+        if candidates[..i].contains(&path) {
+            continue;
+        }
+
+        match spawn(path) {
+            Ok(_) if i == 0 => return,
+            Ok(_) => {
+                klog!(Warn, Sched, "fell back to {path} as init");
+                return;
+            }
+            Err(err) => klog!(Error, Sched, "couldn't spawn {path} as init: {err:?}"),
+        }
+    }
+    panic!("couldn't spawn any init process, see the log for why");
+}
@@ -0,0 +1,16 @@
+use super::raw::{sbicall_0, SbiResult};
+
+pub const EXTENSION_ID: i32 = 0x10;
+
+/// The `mvendorid` CSR of the calling hart, 0 if it isn't implemented
+pub fn get_mvendorid() -> SbiResult<usize> {
+    sbicall_0(EXTENSION_ID, 4).into_result(|r| r as usize)
+}
+
+pub fn get_marchid() -> SbiResult<usize> {
+    sbicall_0(EXTENSION_ID, 5).into_result(|r| r as usize)
+}
+
+pub fn get_mimpid() -> SbiResult<usize> {
+    sbicall_0(EXTENSION_ID, 6).into_result(|r| r as usize)
+}
@@ -1,3 +1,4 @@
+pub mod base;
 pub mod debug_console;
 pub mod hsm;
 mod raw;
@@ -0,0 +1,536 @@
+#[cfg(feature = "procfs")]
+use alloc::vec::Vec;
+#[cfg(feature = "procfs")]
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+#[cfg(feature = "procfs")]
+use servos::riscv::TIMEBASE_FREQ;
+use servos::{
+    drivers::LineErrors,
+    riscv::{r_time, r_tp},
+};
+use shared::sys::{
+    Sys, TrapStats, UartError, ALL_HARTS, MAX_EXCEPTIONS, MAX_IRQS, MAX_SYSCALLS, UART_ERRORS,
+};
+
+#[cfg(feature = "procfs")]
+use crate::{
+    plic::PLIC,
+    proc::{hart_stack_used, HART_STACK_LEN},
+};
+use crate::{proc::MAX_HARTS, trap::TrapCause};
+
+/// Counters are only incremented by the hart they belong to, so they don't need to be more than
+/// relaxed atomics. Readers may see a reset or increment on another hart late.
+struct HartStats {
+    online: AtomicBool,
+    timer: AtomicU64,
+    software: AtomicU64,
+    irqs: [AtomicU64; MAX_IRQS],
+    exceptions: [AtomicU64; MAX_EXCEPTIONS],
+    syscalls: [AtomicU64; MAX_SYSCALLS],
+    uart: [AtomicU64; UART_ERRORS],
+    /// Processes picked from the run queues
+    runs: AtomicU64,
+    /// Timer ticks spent running processes
+    busy: AtomicU64,
+    /// How long the most recently picked processes waited between becoming ready and running, in
+    /// timer ticks, indexed by `runs` modulo `WAIT_SAMPLES`
+    waits: [AtomicU64; WAIT_SAMPLES],
+    /// When the counters were last reset, in timer ticks
+    since: AtomicU64,
+}
+
+/// How many wait times each hart remembers for `/proc/sched`
+const WAIT_SAMPLES: usize = 256;
+
+impl HartStats {
+    const fn new() -> Self {
+        Self {
+            online: AtomicBool::new(false),
+            timer: AtomicU64::new(0),
+            software: AtomicU64::new(0),
+            irqs: [const { AtomicU64::new(0) }; MAX_IRQS],
+            exceptions: [const { AtomicU64::new(0) }; MAX_EXCEPTIONS],
+            syscalls: [const { AtomicU64::new(0) }; MAX_SYSCALLS],
+            uart: [const { AtomicU64::new(0) }; UART_ERRORS],
+            runs: AtomicU64::new(0),
+            busy: AtomicU64::new(0),
+            waits: [const { AtomicU64::new(0) }; WAIT_SAMPLES],
+            since: AtomicU64::new(0),
+        }
+    }
+
+    fn counters(&self) -> impl Iterator<Item = &AtomicU64> {
+        [&self.timer, &self.software]
+            .into_iter()
+            .chain(&self.irqs)
+            .chain(&self.exceptions)
+            .chain(&self.syscalls)
+            .chain(&self.uart)
+            .chain([&self.runs, &self.busy])
+            .chain(&self.waits)
+    }
+
+    fn add_to(&self, stats: &mut TrapStats) {
+        let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        stats.timer += load(&self.timer);
+        stats.software += load(&self.software);
+        for (dst, src) in stats.irqs.iter_mut().zip(&self.irqs) {
+            *dst += load(src);
+        }
+        for (dst, src) in stats.exceptions.iter_mut().zip(&self.exceptions) {
+            *dst += load(src);
+        }
+        for (dst, src) in stats.syscalls.iter_mut().zip(&self.syscalls) {
+            *dst += load(src);
+        }
+        for (dst, src) in stats.uart.iter_mut().zip(&self.uart) {
+            *dst += load(src);
+        }
+    }
+}
+
+static STATS: [HartStats; MAX_HARTS] = [const { HartStats::new() }; MAX_HARTS];
+
+/// Latencies are counted in power of two buckets of cycles, the first for anything under
+/// `1 << LATENCY_MIN_SHIFT` and the last for anything at least half of the one before it
+const LATENCY_BUCKETS: usize = 16;
+const LATENCY_MIN_SHIFT: u32 = 8;
+
+/// How many trap causes have their own latency histogram: the timer, software and external
+/// interrupts, then each exception
+const LATENCY_CAUSES: usize = 3 + MAX_EXCEPTIONS;
+
+struct Histogram([AtomicU64; LATENCY_BUCKETS]);
+
+impl Histogram {
+    const fn new() -> Self {
+        Self([const { AtomicU64::new(0) }; LATENCY_BUCKETS])
+    }
+
+    fn add(&self, cycles: u64) {
+        let bucket = (u64::BITS - cycles.leading_zeros()).saturating_sub(LATENCY_MIN_SHIFT);
+        let bucket = (bucket as usize).min(LATENCY_BUCKETS - 1);
+        self.0[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// these are shared by every hart rather than kept per hart like the counters, they'd take up too
+// much space otherwise
+/// From taking a trap in user mode to its handler starting, which is saving every register and
+/// switching to the kernel page table
+static ENTRY_LATENCY: Histogram = Histogram::new();
+/// From taking a trap in user mode to it having been handled, before the process is resumed or
+/// another one is picked. Indexed like `cause_latency`.
+static TRAP_LATENCY: [Histogram; LATENCY_CAUSES] = [const { Histogram::new() }; LATENCY_CAUSES];
+/// Spent in `handle_syscall`, by syscall number
+static SYSCALL_LATENCY: [Histogram; MAX_SYSCALLS] = [const { Histogram::new() }; MAX_SYSCALLS];
+
+fn cause_latency(cause: TrapCause) -> Option<&'static Histogram> {
+    match cause {
+        TrapCause::TimerIntr => Some(&TRAP_LATENCY[0]),
+        TrapCause::SoftwareIntr => Some(&TRAP_LATENCY[1]),
+        TrapCause::ExternalIntr => Some(&TRAP_LATENCY[2]),
+        TrapCause::CounterOverflowIntr => None,
+        ex => TRAP_LATENCY.get(3 + ex as usize),
+    }
+}
+
+fn this_hart() -> Option<&'static HartStats> {
+    STATS.get(r_tp())
+}
+
+fn bump(counter: Option<&AtomicU64>) {
+    if let Some(counter) = counter {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn hart_online() {
+    if let Some(stats) = this_hart() {
+        stats.since.store(r_time() as u64, Ordering::Relaxed);
+        stats.online.store(true, Ordering::Relaxed);
+    }
+}
+
+fn trap_counter(stats: &HartStats, cause: TrapCause) -> Option<&AtomicU64> {
+    match cause {
+        TrapCause::TimerIntr => Some(&stats.timer),
+        TrapCause::SoftwareIntr => Some(&stats.software),
+        TrapCause::ExternalIntr | TrapCause::CounterOverflowIntr => None,
+        ex => stats.exceptions.get(ex as usize),
+    }
+}
+
+/// Count a trap by its cause. External interrupts are counted by `irq` once they're claimed.
+pub fn trap(cause: TrapCause) {
+    bump(this_hart().and_then(|s| trap_counter(s, cause)));
+}
+
+/// How many traps with `cause` this hart has taken
+#[cfg(feature = "ktest")]
+pub fn traps(cause: TrapCause) -> u64 {
+    this_hart()
+        .and_then(|s| trap_counter(s, cause))
+        .map_or(0, |c| c.load(Ordering::Relaxed))
+}
+
+/// Count a PLIC claim, `None` if there was nothing to claim
+pub fn irq(num: Option<u32>) {
+    bump(this_hart().and_then(|s| s.irqs.get(num.unwrap_or(0) as usize)));
+}
+
+pub fn uart_error(err: UartError) {
+    bump(this_hart().map(|s| &s.uart[err as usize]));
+}
+
+/// Count every error in `errors`
+pub fn uart_errors(errors: LineErrors) {
+    for (flag, err) in [
+        (LineErrors::Overrun, UartError::Overrun),
+        (LineErrors::Parity, UartError::Parity),
+        (LineErrors::Framing, UartError::Framing),
+        (LineErrors::Break, UartError::Break),
+    ] {
+        if errors.contains(flag) {
+            uart_error(err);
+        }
+    }
+}
+
+/// Count a process being picked to run, after waiting `wait` timer ticks since it became ready
+pub fn dispatched(wait: u64) {
+    if let Some(stats) = this_hart() {
+        let run = stats.runs.fetch_add(1, Ordering::Relaxed);
+        stats.waits[run as usize % WAIT_SAMPLES].store(wait, Ordering::Relaxed);
+    }
+}
+
+/// Count `ticks` spent running a process
+pub fn busy(ticks: u64) {
+    if let Some(stats) = this_hart() {
+        stats.busy.fetch_add(ticks, Ordering::Relaxed);
+    }
+}
+
+/// Count the `cycles` it took from a trap being taken in user mode to its handler starting
+pub fn trap_entered(cycles: usize) {
+    ENTRY_LATENCY.add(cycles as u64);
+}
+
+/// Count the `cycles` it took from a trap being taken in user mode to it having been handled
+pub fn trap_handled(cause: TrapCause, cycles: usize) {
+    if let Some(histogram) = cause_latency(cause) {
+        histogram.add(cycles as u64);
+    }
+}
+
+/// Count the `cycles` it took to handle syscall `num`
+pub fn syscall_handled(num: usize, cycles: usize) {
+    let num = if Sys::from_repr(num).is_some() {
+        num
+    } else {
+        0
+    };
+    if let Some(histogram) = SYSCALL_LATENCY.get(num) {
+        histogram.add(cycles as u64);
+    }
+}
+
+pub fn syscall(num: usize) {
+    let num = if Sys::from_repr(num).is_some() {
+        num
+    } else {
+        0
+    };
+    bump(this_hart().and_then(|s| s.syscalls.get(num)));
+}
+
+/// Whether `hart` has started running and counting its traps
+#[cfg(feature = "procfs")]
+pub fn is_online(hart: usize) -> bool {
+    STATS
+        .get(hart)
+        .is_some_and(|s| s.online.load(Ordering::Relaxed))
+}
+
+fn online() -> impl Iterator<Item = (usize, &'static HartStats)> {
+    STATS
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.online.load(Ordering::Relaxed))
+}
+
+fn harts(hart: usize) -> Option<impl Iterator<Item = &'static HartStats>> {
+    if hart != ALL_HARTS && !STATS.get(hart)?.online.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    Some(
+        online()
+            .filter(move |&(i, _)| hart == ALL_HARTS || hart == i)
+            .map(|(_, s)| s),
+    )
+}
+
+/// The counters of `hart`, or the sum over all harts if it's `ALL_HARTS`. Returns `None` for a
+/// hart that isn't running.
+pub fn read(hart: usize) -> Option<TrapStats> {
+    let mut stats = TrapStats::new();
+    for hart in harts(hart)? {
+        hart.add_to(&mut stats);
+    }
+    Some(stats)
+}
+
+/// Reset the counters of `hart`, or every hart and the latency histograms if it's `ALL_HARTS`
+pub fn reset(hart: usize) -> Option<()> {
+    for hart in harts(hart)? {
+        for counter in hart.counters() {
+            counter.store(0, Ordering::Relaxed);
+        }
+        hart.since.store(r_time() as u64, Ordering::Relaxed);
+    }
+
+    if hart == ALL_HARTS {
+        let histograms = core::iter::once(&ENTRY_LATENCY)
+            .chain(&TRAP_LATENCY)
+            .chain(&SYSCALL_LATENCY);
+        for bucket in histograms.flat_map(|h| &h.0) {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+    Some(())
+}
+
+/// A fixed size buffer for row names, so they can be padded. Longer names are cut off.
+#[cfg(feature = "procfs")]
+struct Label([u8; 20], usize);
+
+#[cfg(feature = "procfs")]
+impl Label {
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.0[..self.1]).unwrap_or("?")
+    }
+}
+
+#[cfg(feature = "procfs")]
+impl Write for Label {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for ch in s.chars() {
+            if self.1 + ch.len_utf8() > self.0.len() {
+                return Err(fmt::Error);
+            }
+            self.1 += ch.encode_utf8(&mut self.0[self.1..]).len();
+        }
+        Ok(())
+    }
+}
+
+/// Write a table with a column per running hart, skipping rows that are zero on every hart unless
+/// `always` says otherwise
+#[cfg(feature = "procfs")]
+fn show_table(
+    w: &mut dyn Write,
+    rows: usize,
+    always: fn(usize) -> bool,
+    name: fn(&mut dyn Write, usize) -> fmt::Result,
+    counter: fn(&HartStats, usize) -> &AtomicU64,
+) -> fmt::Result {
+    write!(w, "{:<20}", "")?;
+    for (i, _) in online() {
+        let mut label = Label([0; 20], 0);
+        _ = write!(label, "hart{i}");
+        write!(w, " {:>10}", label.as_str())?;
+    }
+    writeln!(w)?;
+
+    for row in 0..rows {
+        let count = |stats| counter(stats, row).load(Ordering::Relaxed);
+        if !always(row) && online().all(|(_, stats)| count(stats) == 0) {
+            continue;
+        }
+
+        let mut label = Label([0; 20], 0);
+        _ = name(&mut label, row);
+        write!(w, "{:<20}", label.as_str())?;
+        for (_, stats) in online() {
+            write!(w, " {:>10}", count(stats))?;
+        }
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+/// `/proc/interrupts`: interrupts by source, console UART errors, and exceptions by cause
+#[cfg(feature = "procfs")]
+pub fn show_interrupts(w: &mut dyn Write) -> fmt::Result {
+    const UART_START: usize = 2 + MAX_IRQS;
+    const EXCEPTIONS_START: usize = UART_START + UART_ERRORS;
+    show_table(
+        w,
+        EXCEPTIONS_START + MAX_EXCEPTIONS,
+        |row| row < 2,
+        |w, row| match row {
+            0 => w.write_str("timer"),
+            1 => w.write_str("software"),
+            2 => w.write_str("spurious"),
+            row if row < UART_START => {
+                let irq = (row - 2) as u32;
+                if PLIC.get_uart0().is_some_and(|uart0| uart0.get() == irq) {
+                    write!(w, "irq{irq} (uart0)")
+                } else {
+                    write!(w, "irq{irq}")
+                }
+            }
+            row if row < EXCEPTIONS_START => {
+                write!(
+                    w,
+                    "uart0 {:?}",
+                    UartError::from_repr(row - UART_START).unwrap()
+                )
+            }
+            row => match TrapCause::from_repr(row - EXCEPTIONS_START) {
+                Some(cause) => write!(w, "{cause:?}"),
+                None => write!(w, "exception{}", row - EXCEPTIONS_START),
+            },
+        },
+        |stats, row| match row {
+            0 => &stats.timer,
+            1 => &stats.software,
+            row if row < UART_START => &stats.irqs[row - 2],
+            row if row < EXCEPTIONS_START => &stats.uart[row - UART_START],
+            row => &stats.exceptions[row - EXCEPTIONS_START],
+        },
+    )
+}
+
+/// `/proc/stacks`: the deepest each running hart's stack has been, for right-sizing
+/// `HART_STACK_LEN`
+#[cfg(feature = "procfs")]
+pub fn show_stacks(w: &mut dyn Write) -> fmt::Result {
+    for (i, _) in online() {
+        writeln!(w, "hart{i}: {} / {HART_STACK_LEN}", hart_stack_used(i))?;
+    }
+    Ok(())
+}
+
+/// `/proc/syscalls`: syscalls by number
+#[cfg(feature = "procfs")]
+pub fn show_syscalls(w: &mut dyn Write) -> fmt::Result {
+    show_table(
+        w,
+        MAX_SYSCALLS,
+        |_| false,
+        |w, row| match Sys::from_repr(row) {
+            Some(sys) => write!(w, "{sys:?}"),
+            None => w.write_str("invalid"),
+        },
+        |stats, row| &stats.syscalls[row],
+    )
+}
+
+/// `/proc/sched`: how many processes each hart has run and how much of its time that took since
+/// the counters were last reset, and percentiles of how long processes recently waited for a hart
+#[cfg(feature = "procfs")]
+pub fn show_sched(w: &mut dyn Write) -> fmt::Result {
+    let us = |ticks: u64| ticks / (TIMEBASE_FREQ / 1_000_000) as u64;
+    let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+    let now = r_time() as u64;
+
+    let mut waits = Vec::new();
+    waits
+        .try_reserve_exact(MAX_HARTS * WAIT_SAMPLES)
+        .map_err(|_| fmt::Error)?;
+    for (i, stats) in online() {
+        let runs = load(&stats.runs);
+        writeln!(
+            w,
+            "hart{i}: runs {runs} busy_us {} elapsed_us {}",
+            us(load(&stats.busy)),
+            us(now.saturating_sub(load(&stats.since))),
+        )?;
+        let samples = (runs as usize).min(WAIT_SAMPLES);
+        waits.extend(stats.waits[..samples].iter().map(load));
+    }
+
+    waits.sort_unstable();
+    let percentile = |p: usize| {
+        let i = (waits.len() * p / 100).min(waits.len().saturating_sub(1));
+        waits.get(i).map_or(0, |&ticks| us(ticks))
+    };
+    writeln!(w, "wait_samples: {}", waits.len())?;
+    writeln!(
+        w,
+        "wait_us: p50 {} p90 {} p99 {} max {}",
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        percentile(100),
+    )
+}
+
+/// `/proc/latency`: histograms of how many cycles it took to enter the kernel from user mode, to
+/// handle each kind of trap from user mode, and to handle each syscall. Rows that are all zero are
+/// skipped, except for entering the kernel.
+#[cfg(feature = "procfs")]
+pub fn show_latency(w: &mut dyn Write) -> fmt::Result {
+    fn row(
+        w: &mut dyn Write,
+        name: fmt::Arguments,
+        histogram: &Histogram,
+        always: bool,
+    ) -> fmt::Result {
+        let counts = histogram.0.each_ref().map(|c| c.load(Ordering::Relaxed));
+        if !always && counts.iter().all(|&c| c == 0) {
+            return Ok(());
+        }
+
+        let mut label = Label([0; 20], 0);
+        _ = label.write_fmt(name);
+        write!(w, "{:<20}", label.as_str())?;
+        for count in counts {
+            write!(w, " {count:>6}")?;
+        }
+        writeln!(w)
+    }
+
+    write!(w, "{:<20}", "cycles")?;
+    for bucket in 0..LATENCY_BUCKETS {
+        // the last bucket has no upper bound, so it's labelled with its lower one
+        let (op, shift) = match LATENCY_MIN_SHIFT + bucket as u32 {
+            shift if bucket == LATENCY_BUCKETS - 1 => (">=", shift - 1),
+            shift => ("<", shift),
+        };
+        let mut label = Label([0; 20], 0);
+        _ = match shift {
+            0..10 => write!(label, "{op}{}", 1u64 << shift),
+            10..20 => write!(label, "{op}{}K", 1u64 << (shift - 10)),
+            _ => write!(label, "{op}{}M", 1u64 << (shift - 20)),
+        };
+        write!(w, " {:>6}", label.as_str())?;
+    }
+    writeln!(w)?;
+
+    row(w, format_args!("entry"), &ENTRY_LATENCY, true)?;
+    for (i, histogram) in TRAP_LATENCY.iter().enumerate() {
+        match i {
+            0 => row(w, format_args!("TimerIntr"), histogram, false)?,
+            1 => row(w, format_args!("SoftwareIntr"), histogram, false)?,
+            2 => row(w, format_args!("ExternalIntr"), histogram, false)?,
+            i => match TrapCause::from_repr(i - 3) {
+                Some(cause) => row(w, format_args!("{cause:?}"), histogram, false)?,
+                None => row(w, format_args!("exception{}", i - 3), histogram, false)?,
+            },
+        }
+    }
+    for (num, histogram) in SYSCALL_LATENCY.iter().enumerate() {
+        match Sys::from_repr(num) {
+            Some(sys) => row(w, format_args!("sys {sys:?}"), histogram, false)?,
+            None => row(w, format_args!("sys invalid"), histogram, false)?,
+        }
+    }
+    Ok(())
+}
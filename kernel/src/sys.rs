@@ -1,16 +1,41 @@
-use alloc::vec::Vec;
-use servos::lock::SpinLocked;
+use alloc::{sync::Arc, vec::Vec};
+use core::mem::MaybeUninit;
+use servos::{
+    lock::{Guard, SpinLocked},
+    riscv::{r_cycle, r_time, TIMEBASE_FREQ},
+};
 use shared::{
-    io::{DirEntry, OpenFlags, Stat},
-    sys::{Sys, SysError as E},
+    filter::{Hook, Insn, MAP_LEN, MAX_INSNS},
+    io::{DirEntry, Mode, OpenFlags, Stat, UnmountFlags},
+    layout,
+    log::{LogLevel, LogSubsystem},
+    sys::{
+        set_syscall_result, FaultInfo, PollEntry, PollKind, ProcHandle, SchedClass, StatsOp, Sys,
+        SysError as E, SyscallRegs, TrapStats, MAX_HOSTNAME_LEN, MAX_MSG_LEN, MAX_NAME_LEN,
+        MAX_POLL, SHUTDOWN_MSG,
+    },
 };
 
+#[cfg(feature = "net")]
+use crate::net::{socket::SocketFs, NET};
 use crate::{
-    fs::{path::Path, vfs::Vfs, FsError},
-    power::POWER,
-    proc::{ProcStatus, Process, Reg, PROC_LIST},
+    acct, fault,
+    filter::{self, Event, Program},
+    fs::{
+        path::{OwnedPath, Path},
+        vfs::{Fd, MountError, Vfs, VFS},
+        FsError,
+    },
+    hostname,
+    ipc::{self, Message},
+    klog, poll,
+    power::{self, POWER},
+    proc::{self, ProcId, ProcStatus, Process},
+    stats,
     vmm::{Pte, User, VirtAddr},
 };
+#[cfg(feature = "net")]
+use shared::net::{NeighborEntry, NetCtl, RouteEntry, SockAddr, SockOpt, SocketType};
 
 impl From<FsError> for E {
     fn from(value: FsError) -> Self {
@@ -24,6 +49,24 @@ impl From<FsError> for E {
             FsError::InvalidPerms => E::InvalidPerms,
             FsError::BadVa => E::BadAddr,
             FsError::Eof => E::Eof,
+            FsError::WouldBlock => E::WouldBlock,
+            FsError::NotConnected => E::NotConnected,
+            FsError::ConnReset => E::ConnReset,
+            FsError::Stale => E::Stale,
+            FsError::AlreadyExists => E::AlreadyExists,
+        }
+    }
+}
+
+impl From<MountError> for E {
+    fn from(value: MountError) -> Self {
+        match value {
+            MountError::NoMem => E::NoMem,
+            MountError::AlreadyMounted => E::AlreadyExists,
+            MountError::NotMounted => E::PathNotFound,
+            MountError::Busy => E::Busy,
+            MountError::Open(err) => err.into(),
+            MountError::NotDirectory | MountError::Loop | MountError::BadPivot => E::BadArg,
         }
     }
 }
@@ -32,49 +75,113 @@ pub type SysResult = Result<usize, E>;
 
 type Proc = SpinLocked<Process>;
 
+/// Whether `Sys::Shutdown` waits for `proc` to exit. Init can't exit, and a process in waitpid is
+/// waiting on a child (often the one shutting down) rather than holding anything up itself.
+fn holds_up_shutdown(proc: &Process, caller: ProcHandle) -> bool {
+    proc.handle != caller
+        && proc.pid() != 0
+        && proc.killed.is_none()
+        && !matches!(proc.status, ProcStatus::Waiting(..))
+}
+
 // void shutdown(uint typ);
-fn sys_shutdown(_: &Proc, typ: usize) -> SysResult {
+fn sys_shutdown(proc: &Proc, typ: usize) -> SysResult {
     // TODO: permission check
+    if typ > 1 {
+        return Err(E::BadArg);
+    }
+
+    // an exit from here on wakes us up again
+    let gen = poll::generation();
+    let me = proc.lock().handle;
+    let (deadline, started) = power::begin_shutdown(me);
+    if started {
+        klog!(
+            Info,
+            Sched,
+            "shutdown: asking processes to exit, waiting up to {} ms",
+            power::SHUTDOWN_GRACE_MS.get()
+        );
+        proc::for_each_process(|proc| {
+            if proc.handle != me && proc.pid() != 0 {
+                let mut msg = Message {
+                    sender: me,
+                    len: SHUTDOWN_MSG.len(),
+                    data: [0; MAX_MSG_LEN],
+                };
+                msg.data[..msg.len].copy_from_slice(SHUTDOWN_MSG);
+                // a process that isn't reading its mailbox will be killed anyway
+                if proc.mailbox.push(msg).is_ok() && proc.status == ProcStatus::Receiving {
+                    proc.wake();
+                }
+            }
+            None::<()>
+        });
+    }
+
+    let expired = r_time() >= deadline;
+    let mut left = 0;
+    proc::for_each_process(|proc| {
+        if holds_up_shutdown(proc, me) {
+            left += 1;
+            let name = proc.exe_path.components().last().unwrap_or_default();
+            let name = core::str::from_utf8(name).unwrap_or("?");
+            if expired {
+                klog!(Warn, Sched, "shutdown: killing PID {} ({name})", proc.pid());
+                proc.kill(None);
+            } else if started {
+                klog!(
+                    Info,
+                    Sched,
+                    "shutdown: waiting for PID {} ({name})",
+                    proc.pid()
+                );
+            }
+        }
+        None::<()>
+    });
+
+    if left != 0 && !expired {
+        proc.with(|mut proc| proc.status = ProcStatus::ShuttingDown(gen, deadline));
+        return Err(E::WouldBlock);
+    }
+
     match typ {
         0 => POWER.lock().shutdown(),
-        1 => POWER.lock().restart(),
-        _ => Err(E::BadArg),
+        _ => POWER.lock().restart(),
     }
 }
 
-// void kill(u32 pid);
-fn sys_kill(_: &Proc, pid: usize) -> SysResult {
-    if pid == 0 {
+// void kill(u64 pid_or_handle);
+fn sys_kill(_: &Proc, id: usize) -> SysResult {
+    let id = ProcId::from_raw(id);
+    if id.pid() == 0 {
         return Err(E::BadArg);
     }
 
-    for proc in PROC_LIST.lock().iter() {
-        let success = unsafe {
-            proc.with(|mut proc| {
-                // TODO: permission check
-                if proc.pid as usize == pid {
-                    proc.kill(None);
-                    true
-                } else {
-                    false
-                }
-            })
-        };
-        if success {
-            return Ok(0);
-        }
-    }
-
-    Err(E::NotFound)
+    // TODO: permission check
+    proc::find_process(id, |proc| proc.lock().kill(None))
+        .map(|_| 0)
+        .ok_or(E::NotFound)
 }
 
 // u32 getpid(void);
 fn sys_getpid(proc: &Proc) -> SysResult {
-    Ok(proc.lock().pid as usize)
+    Ok(proc.lock().pid() as usize)
+}
+
+// u64 pidhandle(u32 pid);
+fn sys_pidhandle(_: &Proc, pid: usize) -> SysResult {
+    let Ok(pid) = u32::try_from(pid) else {
+        return Err(E::BadArg);
+    };
+
+    proc::find_process(ProcId::Pid(pid), |proc| proc.lock().handle.0 as usize).ok_or(E::NotFound)
 }
 
-// uint open(const u8 *path, uint pathlen, u32 flags);
-fn sys_open(proc: &Proc, path: VirtAddr, len: usize, flags: u32) -> SysResult {
+// uint open(const u8 *path, uint pathlen, u32 flags, Mode mode);
+fn sys_open(proc: &Proc, path: VirtAddr, len: usize, flags: u32, mode: usize) -> SysResult {
+    let mode = Mode::from_bits(u32::try_from(mode).map_err(|_| E::BadArg)?).ok_or(E::BadArg)?;
     let mut buf = Vec::try_with_capacity(len)?;
     proc.with(|mut proc| {
         path.copy_from(proc.pagetable(), buf.spare_capacity_mut())?;
@@ -82,16 +189,53 @@ fn sys_open(proc: &Proc, path: VirtAddr, len: usize, flags: u32) -> SysResult {
             buf.set_len(len);
         }
 
-        let file = Vfs::open_in_cwd(&proc.cwd, &buf[..], OpenFlags::from_bits_truncate(flags))?;
+        let file = Vfs::open_in_cwd(
+            &proc.cwd,
+            &proc.cwd_path,
+            &buf[..],
+            OpenFlags::from_bits_truncate(flags),
+            mode & !proc.umask,
+        )?;
         proc.files.push(file).map(|v| v.0).map_err(|_| E::NoMem)
     })
 }
 
+// void remove(const u8 *path, uint pathlen);
+fn sys_remove(proc: &Proc, path: VirtAddr, len: usize) -> SysResult {
+    let mut buf = Vec::try_with_capacity(len)?;
+    proc.with(|proc| {
+        path.copy_from(proc.pagetable(), buf.spare_capacity_mut())?;
+        unsafe {
+            buf.set_len(len);
+        }
+
+        Vfs::remove_in_cwd(&proc.cwd, &proc.cwd_path, &buf[..])?;
+        Ok(0)
+    })
+}
+
 // void close(uint fd);
 fn sys_close(proc: &Proc, fd: usize) -> SysResult {
     proc.with(|mut proc| proc.files.remove(fd).ok_or(E::BadFd).map(|_| 0))
 }
 
+// uint close_range(uint first, uint last);
+fn sys_close_range(proc: &Proc, first: usize, last: usize) -> SysResult {
+    if first > last {
+        return Err(E::BadArg);
+    }
+
+    proc.with(|mut proc| {
+        Ok(proc
+            .files
+            .iter_mut()
+            .take(last.saturating_add(1))
+            .skip(first)
+            .filter_map(Option::take)
+            .count())
+    })
+}
+
 // uint read(uint fd, u64 pos, u8 *buf, uint buflen);
 fn sys_read(proc: &Proc, fd: usize, pos: usize, buf: VirtAddr, buflen: usize) -> SysResult {
     proc.with(|proc| {
@@ -116,15 +260,16 @@ fn sys_write(proc: &Proc, fd: usize, pos: usize, buf: VirtAddr, buflen: usize) -
     })
 }
 
-// bool readdir(uint fd, uint pos, DirEntry *entry);
+// uint readdir(uint fd, uint pos, DirEntry *entry);
+// returns the position of the next entry, or 0 if there are no more
 fn sys_readdir(proc: &Proc, fd: usize, pos: usize, entry: User<DirEntry>) -> SysResult {
     proc.with(|proc| {
-        let Some(ent) = proc.files.get(fd).ok_or(E::BadFd)?.readdir(pos)? else {
+        let Some((ent, next)) = proc.files.get(fd).ok_or(E::BadFd)?.readdir(pos)? else {
             return Ok(0);
         };
 
         entry.write(proc.pagetable(), &ent)?;
-        Ok(1)
+        Ok(next)
     })
 }
 
@@ -140,6 +285,17 @@ fn sys_stat(proc: &Proc, fd: usize, stat: User<Stat>) -> SysResult {
 }
 
 // void chdir(const u8 *path, uint len);
+/// Open `path` as a new working directory for `proc`. The directory is opened again from its
+/// absolute path, so a cwd can always be reached from the root like its path says.
+fn open_cwd(proc: &Process, path: &Path) -> Result<(Fd, OwnedPath), E> {
+    let path = path.resolve(&proc.cwd_path)?;
+    let cwd = Vfs::open(&path, OpenFlags::empty(), Mode::empty())?;
+    if !cwd.vnode().directory {
+        return Err(E::BadArg);
+    }
+    Ok((cwd, path))
+}
+
 fn sys_chdir(proc: &Proc, path: VirtAddr, len: usize) -> SysResult {
     let mut buf = Vec::try_with_capacity(len)?;
     proc.with(|mut proc| {
@@ -148,13 +304,125 @@ fn sys_chdir(proc: &Proc, path: VirtAddr, len: usize) -> SysResult {
             buf.set_len(len);
         }
 
-        let cwd = Vfs::open_in_cwd(&proc.cwd, &buf[..], OpenFlags::empty())?;
-        if !cwd.vnode().directory {
+        (proc.cwd, proc.cwd_path) = open_cwd(&proc, Path::new(&buf))?;
+        Ok(0)
+    })
+}
+
+// uint getcwd(u8 *buf, uint buflen);
+fn sys_getcwd(proc: &Proc, buf: VirtAddr, buflen: usize) -> SysResult {
+    proc.with(|proc| {
+        let path: &[u8] = proc.cwd_path.as_ref().as_ref();
+        if path.len() > buflen {
             return Err(E::BadArg);
         }
 
-        proc.cwd = cwd;
-        Ok(0)
+        buf.copy_to(proc.pagetable(), path, None)?;
+        Ok(path.len())
+    })
+}
+
+// void unmount(const u8 *path, uint pathlen, u32 flags);
+fn sys_unmount(proc: &Proc, path: VirtAddr, len: usize, flags: usize) -> SysResult {
+    // TODO: permission check
+    let flags = UnmountFlags::from_bits(flags as u32).ok_or(E::BadArg)?;
+    let mut buf = Vec::try_with_capacity(len)?;
+    let path = proc.with(|proc| {
+        path.copy_from(proc.pagetable(), buf.spare_capacity_mut())?;
+        unsafe {
+            buf.set_len(len);
+        }
+
+        Ok::<_, E>(Path::new(&buf).resolve(&proc.cwd_path)?)
+    })?;
+
+    VFS.lock()
+        .unmount(&path, flags.contains(UnmountFlags::Force))?;
+    Ok(0)
+}
+
+// void bind_mount(const u8 *src, uint srclen, const u8 *dst, uint dstlen);
+fn sys_bind_mount(
+    proc: &Proc,
+    src: VirtAddr,
+    srclen: usize,
+    dst: VirtAddr,
+    dstlen: usize,
+) -> SysResult {
+    // TODO: permission check
+    let mut srcbuf = Vec::try_with_capacity(srclen)?;
+    let mut dstbuf = Vec::try_with_capacity(dstlen)?;
+    let (src, dst) = proc.with(|proc| {
+        src.copy_from(proc.pagetable(), srcbuf.spare_capacity_mut())?;
+        dst.copy_from(proc.pagetable(), dstbuf.spare_capacity_mut())?;
+        unsafe {
+            srcbuf.set_len(srclen);
+            dstbuf.set_len(dstlen);
+        }
+
+        Ok::<_, E>((
+            Path::new(&srcbuf).resolve(&proc.cwd_path)?,
+            Path::new(&dstbuf).resolve(&proc.cwd_path)?,
+        ))
+    })?;
+
+    Vfs::bind(&src, dst)?;
+    Ok(0)
+}
+
+// void pivot_root(const u8 *new_root, uint new_rootlen, const u8 *put_old, uint put_oldlen);
+fn sys_pivot_root(
+    proc: &Proc,
+    new_root: VirtAddr,
+    new_rootlen: usize,
+    put_old: VirtAddr,
+    put_oldlen: usize,
+) -> SysResult {
+    // TODO: permission check
+    let mut new_rootbuf = Vec::try_with_capacity(new_rootlen)?;
+    let mut put_oldbuf = Vec::try_with_capacity(put_oldlen)?;
+    let (new_root, put_old) = proc.with(|proc| {
+        new_root.copy_from(proc.pagetable(), new_rootbuf.spare_capacity_mut())?;
+        put_old.copy_from(proc.pagetable(), put_oldbuf.spare_capacity_mut())?;
+        unsafe {
+            new_rootbuf.set_len(new_rootlen);
+            put_oldbuf.set_len(put_oldlen);
+        }
+
+        Ok::<_, E>((
+            Path::new(&new_rootbuf).resolve(&proc.cwd_path)?,
+            Path::new(&put_oldbuf).resolve(&proc.cwd_path)?,
+        ))
+    })?;
+
+    let pivot = VFS.lock().pivot_root(&new_root, &put_old)?;
+    // working directories are still open on the same files, but the paths to them changed
+    proc::for_each_process(|proc| {
+        match pivot.moved(&proc.cwd_path) {
+            Ok(Some(path)) => proc.cwd_path = path,
+            Ok(None) => {}
+            Err(_) => klog!(
+                Warn,
+                Fs,
+                "pivot_root: out of memory moving the working directory of process {}",
+                proc.pid()
+            ),
+        }
+        None::<()>
+    });
+    Ok(0)
+}
+
+// uint getexepath(u8 *buf, uint buflen);
+fn sys_getexepath(proc: &Proc, buf: VirtAddr, buflen: usize) -> SysResult {
+    proc.with(|proc| {
+        let path: &[u8] = proc.exe_path.as_ref().as_ref();
+        if path.len() > buflen {
+            return Err(E::BadArg);
+        }
+
+        buf.copy_to(proc.pagetable(), path, None)?;
+        Ok(path.len())
     })
 }
 
@@ -165,23 +433,41 @@ struct KString {
     len: usize,
 }
 
-// u32 spawn(const u8 *path, uint pathlen, const struct KString **argv, uint nargs);
+// u32 spawn(const u8 *path, uint pathlen, const struct KString **argv, uint nargs,
+//           const u8 *cwd, uint cwdlen);
+// argv includes argv[0], which defaults to path if nargs is 0
 fn sys_spawn(
     proc: &Proc,
     path: VirtAddr,
     pathlen: usize,
     argv: User<KString>,
     nargs: usize,
+    cwd: VirtAddr,
+    cwdlen: usize,
 ) -> SysResult {
     let mut buf = Vec::try_with_capacity(pathlen)?;
+    let mut cwd_buf = Vec::new();
     let mut args = Vec::new();
     let mut arg_slices = Vec::try_with_capacity(nargs)?;
-    let cwd = proc.with(|proc| {
+    let (cwd, cwd_path, umask, seccomp) = proc.with(|proc| {
         path.copy_from(proc.pagetable(), buf.spare_capacity_mut())?;
         unsafe {
             buf.set_len(pathlen);
         }
 
+        // a null cwd means the child inherits ours
+        let cwd = if cwd.0 != 0 {
+            cwd_buf.try_reserve(cwdlen)?;
+            cwd.copy_from(proc.pagetable(), cwd_buf.spare_capacity_mut())?;
+            unsafe {
+                cwd_buf.set_len(cwdlen);
+            }
+
+            open_cwd(&proc, Path::new(&cwd_buf))?
+        } else {
+            (proc.cwd.clone(), proc.cwd_path.as_ref().try_into()?)
+        };
+
         for i in 0..nargs {
             let str = argv.read_nth(proc.pagetable(), i)?;
             args.try_reserve(str.len)?;
@@ -200,28 +486,373 @@ fn sys_spawn(
             buf = rest;
         }
 
-        Ok::<_, E>(proc.cwd.clone())
+        Ok::<_, E>((cwd.0, cwd.1, proc.umask, proc.seccomp.clone()))
+    })?;
+
+    Process::spawn(Path::new(&buf), cwd, cwd_path, umask, seccomp, &arg_slices)
+        .map(|pid| pid as usize)
+}
+
+// usize waitpid(u64 pid_or_handle);
+fn sys_waitpid(proc: &Proc, id: usize) -> SysResult {
+    wait_until(proc, id, usize::MAX).map(|_| 0)
+}
+
+// usize waitpid_timeout(u64 pid_or_handle, u64 timeout_ns);
+fn sys_waitpid_timeout(proc: &Proc, id: usize, timeout_ns: usize) -> SysResult {
+    let deadline = r_time().saturating_add(timeout_ns / (1_000_000_000 / TIMEBASE_FREQ));
+    // the exit code overwrites the error if the process exits first
+    match wait_until(proc, id, deadline)? {
+        true => Err(E::TimedOut),
+        false => Ok(0),
+    }
+}
+
+/// Block `proc` until the process `id` exits or the timer reaches `deadline`. Returns whether it
+/// blocked, which it doesn't if the process doesn't exist.
+fn wait_until(proc: &Proc, id: usize, deadline: usize) -> Result<bool, E> {
+    let id = ProcId::from_raw(id);
+    if proc.lock().pid() == id.pid() {
+        return Err(E::BadArg);
+    }
+
+    proc::find_process(id, |target| {
+        proc::with_two_processes(proc, target, |proc, target| {
+            // both processes would wait forever
+            if matches!(target.status, ProcStatus::Waiting(handle, _) if handle == proc.handle) {
+                return Err(E::BadArg);
+            }
+
+            // wait on the handle so a process that reuses the pid doesn't wake us
+            proc.status = ProcStatus::Waiting(target.handle, deadline);
+            Ok(())
+        })
+    })
+    .transpose()
+    .map(|blocked| blocked.is_some())
+}
+
+// void sendmsg(u64 pid_or_handle, const u8 *buf, uint buflen);
+fn sys_sendmsg(proc: &Proc, id: usize, buf: VirtAddr, len: usize) -> SysResult {
+    if len > MAX_MSG_LEN {
+        return Err(E::BadArg);
+    }
+
+    let mut data = MaybeUninit::<[u8; MAX_MSG_LEN]>::zeroed();
+    let sender = proc.with(|proc| {
+        buf.copy_from(proc.pagetable(), &mut data.as_bytes_mut()[..len])?;
+        Ok::<_, E>(proc.handle)
     })?;
+    let msg = Message {
+        sender,
+        len,
+        data: unsafe { data.assume_init() },
+    };
+
+    // the sender's lock must be released here, PROC_LIST is taken by find_process
+    proc::find_process(ProcId::from_raw(id), |target| {
+        let mut target = target.lock();
+        target.mailbox.push(msg)?;
+        if target.status == ProcStatus::Receiving {
+            target.wake();
+        }
+        Ok(0)
+    })
+    .ok_or(E::NotFound)?
+}
+
+// uint recvmsg(u8 *buf, uint buflen, u64 *sender, bool nonblock);
+fn sys_recvmsg(
+    proc: &Proc,
+    buf: VirtAddr,
+    len: usize,
+    sender: VirtAddr,
+    nonblock: bool,
+) -> SysResult {
+    proc.with(|mut proc| {
+        let Some(msg) = proc.mailbox.peek() else {
+            // the receiver has to retry once it's woken up, since the sender can't safely write
+            // this process' return registers
+            if !nonblock {
+                proc.status = ProcStatus::Receiving;
+            }
+            return Err(E::WouldBlock);
+        };
 
-    Process::spawn(Path::new(&buf), cwd, &arg_slices).map(|pid| pid as usize)
+        // the message stays queued if it can't be copied out, so a bad buffer doesn't lose it
+        let len = len.min(msg.len);
+        buf.copy_to(proc.pagetable(), &msg.bytes()[..len], None)?;
+        if sender != VirtAddr(0) {
+            User::new(sender).write(proc.pagetable(), &msg.sender)?;
+        }
+        proc.mailbox.pop();
+        Ok(len)
+    })
 }
 
-// usize waitpid(u32 pid);
-fn sys_waitpid(proc: &Proc, pid: usize) -> SysResult {
-    if proc.lock().pid as usize == pid {
+// uint poll(PollEntry *entries, uint count, bool nonblock);
+fn sys_poll(proc: &Proc, entries: User<PollEntry>, count: usize, nonblock: bool) -> SysResult {
+    if count > MAX_POLL {
         return Err(E::BadArg);
     }
 
-    for &rhs in PROC_LIST.lock().iter() {
-        if unsafe { rhs.with(|proc| proc.pid as usize == pid) } {
-            proc.lock().status = ProcStatus::Waiting(pid as u32);
-            break;
+    // anything that happens from here on wakes us up again
+    let gen = poll::generation();
+    let mut buf = [PollEntry::readable(0); MAX_POLL];
+    let polled = &mut buf[..count];
+    let me = proc.with(|proc| {
+        for (i, entry) in polled.iter_mut().enumerate() {
+            *entry = entries.read_nth(proc.pagetable(), i)?;
+        }
+        Ok::<_, E>(proc.pid())
+    })?;
+
+    // processes are checked first, since PROC_LIST can't be taken with our lock held
+    for entry in polled.iter_mut() {
+        entry.ready = 0;
+        match PollKind::from_repr(entry.kind).ok_or(E::BadArg)? {
+            PollKind::Readable => {}
+            PollKind::Exited => {
+                let id = ProcId::from_raw(entry.id as usize);
+                if id.pid() == me {
+                    return Err(E::BadArg);
+                }
+                if proc::find_process(id, |_| ()).is_none() {
+                    entry.ready = 1;
+                    entry.value = poll::exit_code(id).unwrap_or(usize::MAX);
+                }
+            }
         }
     }
 
+    proc.with(|mut proc| {
+        for entry in polled.iter_mut() {
+            if entry.kind == PollKind::Readable as usize {
+                let file = proc.files.get(entry.id as usize).ok_or(E::BadFd)?;
+                entry.ready = file.readable()? as usize;
+            }
+        }
+
+        let ready = polled.iter().filter(|e| e.is_ready()).count();
+        if ready == 0 && !nonblock {
+            // like recvmsg, the poll is tried again once something happens
+            proc.status = ProcStatus::Polling(gen);
+            return Err(E::WouldBlock);
+        }
+
+        for (i, entry) in polled.iter().enumerate() {
+            entries.write_nth(proc.pagetable(), i, entry)?;
+        }
+        Ok(ready)
+    })
+}
+
+// void faultinfo(u64 pid_or_handle, FaultInfo *info);
+fn sys_faultinfo(proc: &Proc, pid_or_handle: usize, info: User<FaultInfo>) -> SysResult {
+    let fault = fault::find(ProcId::from_raw(pid_or_handle)).ok_or(E::NotFound)?;
+    proc.with(|proc| info.write(proc.pagetable(), &fault))?;
+    Ok(0)
+}
+
+/// Copy a service name from user memory, returning its owner's handle as well
+fn read_name(
+    proc: &Proc,
+    name: VirtAddr,
+    len: usize,
+    buf: &mut [MaybeUninit<u8>; MAX_NAME_LEN],
+) -> Result<ProcHandle, E> {
+    if len > MAX_NAME_LEN {
+        return Err(E::BadArg);
+    }
+
+    proc.with(|proc| {
+        name.copy_from(proc.pagetable(), &mut buf[..len])?;
+        Ok(proc.handle)
+    })
+}
+
+// void registername(const u8 *name, uint len);
+fn sys_registername(proc: &Proc, name: VirtAddr, len: usize) -> SysResult {
+    let mut buf = [MaybeUninit::uninit(); MAX_NAME_LEN];
+    let owner = read_name(proc, name, len, &mut buf)?;
+    ipc::register_name(
+        unsafe { MaybeUninit::slice_assume_init_ref(&buf[..len]) },
+        owner,
+    )?;
+    Ok(0)
+}
+
+// void unregistername(const u8 *name, uint len);
+fn sys_unregistername(proc: &Proc, name: VirtAddr, len: usize) -> SysResult {
+    let mut buf = [MaybeUninit::uninit(); MAX_NAME_LEN];
+    let owner = read_name(proc, name, len, &mut buf)?;
+    ipc::unregister_name(
+        unsafe { MaybeUninit::slice_assume_init_ref(&buf[..len]) },
+        owner,
+    )?;
     Ok(0)
 }
 
+// u64 lookupname(const u8 *name, uint len);
+fn sys_lookupname(proc: &Proc, name: VirtAddr, len: usize) -> SysResult {
+    let mut buf = [MaybeUninit::uninit(); MAX_NAME_LEN];
+    read_name(proc, name, len, &mut buf)?;
+    ipc::lookup_name(unsafe { MaybeUninit::slice_assume_init_ref(&buf[..len]) })
+        .map(|handle| handle.0 as usize)
+        .ok_or(E::NotFound)
+}
+
+#[cfg(feature = "net")]
+fn socket_id(proc: &Process, fd: usize) -> Result<u32, E> {
+    proc.files
+        .get(fd)
+        .ok_or(E::BadFd)?
+        .socket_id()
+        .ok_or(E::InvalidOp)
+}
+
+#[cfg(feature = "net")]
+fn read_sockaddr(proc: &Process, addr: VirtAddr) -> Result<Option<SockAddr>, E> {
+    if addr == VirtAddr(0) {
+        return Ok(None);
+    }
+    Ok(Some(User::<SockAddr>::new(addr).read(proc.pagetable())?))
+}
+
+// uint socket(uint type);
+#[cfg(feature = "net")]
+fn sys_socket(proc: &Proc, typ: usize) -> SysResult {
+    let typ = SocketType::from_repr(typ).ok_or(E::BadArg)?;
+    let fd = SocketFs::open(NET.lock().create(typ))?;
+    proc.with(|mut proc| proc.files.push(fd).map(|v| v.0).map_err(|_| E::NoMem))
+}
+
+// void bind(uint fd, const struct SockAddr *addr);
+#[cfg(feature = "net")]
+fn sys_bind(proc: &Proc, fd: usize, addr: VirtAddr) -> SysResult {
+    proc.with(|proc| {
+        let id = socket_id(&proc, fd)?;
+        let addr = read_sockaddr(&proc, addr)?.ok_or(E::BadAddr)?;
+        NET.lock().bind(id, addr).map(|_| 0)
+    })
+}
+
+// void connect(uint fd, const struct SockAddr *addr);
+#[cfg(feature = "net")]
+fn sys_connect(proc: &Proc, fd: usize, addr: VirtAddr) -> SysResult {
+    proc.with(|proc| {
+        let id = socket_id(&proc, fd)?;
+        let addr = read_sockaddr(&proc, addr)?.ok_or(E::BadAddr)?;
+        NET.lock().connect(id, addr).map(|_| 0)
+    })
+}
+
+// void listen(uint fd, uint backlog);
+#[cfg(feature = "net")]
+fn sys_listen(proc: &Proc, fd: usize, backlog: usize) -> SysResult {
+    proc.with(|proc| {
+        let id = socket_id(&proc, fd)?;
+        NET.lock().listen(id, backlog).map(|_| 0)
+    })
+}
+
+// uint accept(uint fd, struct SockAddr *peer);
+#[cfg(feature = "net")]
+fn sys_accept(proc: &Proc, fd: usize, peer: VirtAddr) -> SysResult {
+    proc.with(|mut proc| {
+        let id = socket_id(&proc, fd)?;
+        let (conn, addr) = NET.lock().accept(id)?;
+        let conn = SocketFs::open(conn)?;
+        if peer != VirtAddr(0) {
+            User::new(peer).write(proc.pagetable(), &addr)?;
+        }
+        proc.files.push(conn).map(|v| v.0).map_err(|_| E::NoMem)
+    })
+}
+
+// uint sendto(uint fd, const u8 *buf, uint len, const struct SockAddr *addr);
+#[cfg(feature = "net")]
+fn sys_sendto(proc: &Proc, fd: usize, buf: VirtAddr, len: usize, addr: VirtAddr) -> SysResult {
+    let mut kbuf = Vec::try_with_capacity(len)?;
+    proc.with(|proc| {
+        let id = socket_id(&proc, fd)?;
+        let addr = read_sockaddr(&proc, addr)?;
+        buf.copy_from(proc.pagetable(), kbuf.spare_capacity_mut())?;
+        unsafe {
+            kbuf.set_len(len);
+        }
+
+        NET.lock().send_to(id, &kbuf, addr)
+    })
+}
+
+// uint recvfrom(uint fd, u8 *buf, uint len, struct SockAddr *addr);
+#[cfg(feature = "net")]
+fn sys_recvfrom(proc: &Proc, fd: usize, buf: VirtAddr, len: usize, addr: VirtAddr) -> SysResult {
+    let mut kbuf = Vec::try_with_capacity(len)?;
+    kbuf.resize(len, 0);
+    proc.with(|proc| {
+        let id = socket_id(&proc, fd)?;
+        let (read, from) = NET.lock().recv_from(id, &mut kbuf)?;
+        buf.copy_to(proc.pagetable(), &kbuf[..read], None)?;
+        if let (Some(from), true) = (from, addr != VirtAddr(0)) {
+            User::new(addr).write(proc.pagetable(), &from)?;
+        }
+        Ok(read)
+    })
+}
+
+// void setsockopt(uint fd, uint opt, uint val);
+#[cfg(feature = "net")]
+fn sys_setsockopt(proc: &Proc, fd: usize, opt: usize, val: usize) -> SysResult {
+    let opt = SockOpt::from_repr(opt).ok_or(E::BadArg)?;
+    proc.with(|proc| {
+        let id = socket_id(&proc, fd)?;
+        NET.lock().set_option(id, opt, val).map(|_| 0)
+    })
+}
+
+// uint getsockopt(uint fd, uint opt);
+#[cfg(feature = "net")]
+fn sys_getsockopt(proc: &Proc, fd: usize, opt: usize) -> SysResult {
+    let opt = SockOpt::from_repr(opt).ok_or(E::BadArg)?;
+    proc.with(|proc| {
+        let id = socket_id(&proc, fd)?;
+        NET.lock().get_option(id, opt)
+    })
+}
+
+// void netctl(NetCtl op, const void *entry);
+#[cfg(feature = "net")]
+fn sys_netctl(proc: &Proc, op: usize, entry: VirtAddr) -> SysResult {
+    // TODO: permission check
+    let op = NetCtl::from_repr(op).ok_or(E::BadArg)?;
+    proc.with(|proc| {
+        let pt = proc.pagetable();
+        match op {
+            NetCtl::AddRoute | NetCtl::DelRoute => {
+                let route = User::<RouteEntry>::new(entry).read(pt)?;
+                let mut net = NET.lock();
+                if op == NetCtl::AddRoute {
+                    net.add_route(route)
+                } else {
+                    net.del_route(route)
+                }
+            }
+            NetCtl::AddNeighbor | NetCtl::DelNeighbor => {
+                let neighbor = User::<NeighborEntry>::new(entry).read(pt)?;
+                let mut net = NET.lock();
+                if op == NetCtl::AddNeighbor {
+                    net.add_neighbor(neighbor)
+                } else {
+                    net.del_neighbor(neighbor)
+                }
+            }
+        }
+        .map(|_| 0)
+    })
+}
+
 // void *sbrk(sint inc);
 fn sys_sbrk(proc: &Proc, inc: isize) -> SysResult {
     let mut proc = proc.lock();
@@ -229,21 +860,29 @@ fn sys_sbrk(proc: &Proc, inc: isize) -> SysResult {
     let Some(new_brk) = cur_brk.0.checked_add_signed(inc).map(VirtAddr) else {
         return Err(E::BadArg);
     };
+    if new_brk.0 > layout::IMAGE.end {
+        return Err(E::NoMem);
+    }
 
     if !(new_brk.page() == cur_brk.page() || (inc == 1 && new_brk.page() != cur_brk.page())) {
         let pt = proc.pagetable_mut();
         if inc < 0 {
             pt.unmap_pages(new_brk.next_page(), cur_brk);
-        } else if !pt.map_new_pages(
+        } else if !pt.map_zero_pages(
             cur_brk.next_page(),
             new_brk.0 - cur_brk.next_page().0,
             Pte::Urw,
-            true,
         ) {
             return Err(E::NoMem);
         }
     }
 
+    klog!(
+        Debug,
+        Vmm,
+        "PID {} sbrk({inc}): {cur_brk} -> {new_brk}",
+        proc.pid()
+    );
     proc.brk = new_brk;
     Ok(proc.brk.0)
 }
@@ -254,41 +893,306 @@ fn sys_exit(proc: &Proc, ecode: usize) -> SysResult {
     Ok(0)
 }
 
-pub fn handle_syscall(proc: &Proc) {
-    let (syscall_no, a0, a1, a2, a3) = proc.with(|mut proc| {
-        let trapframe = proc.trapframe();
-        (
-            trapframe[Reg::A7],
-            trapframe[Reg::A0],
-            trapframe[Reg::A1],
-            trapframe[Reg::A2],
-            trapframe[Reg::A3],
-        )
+// LogLevel logctl(uint level, u32 enable, u32 disable);
+fn sys_logctl(_: &Proc, level: usize, enable: u32, disable: u32) -> SysResult {
+    // TODO: permission check
+    let level = match level {
+        usize::MAX => None,
+        level => Some(LogLevel::from_repr(level).ok_or(E::BadArg)?),
+    };
+
+    let prev = level.map(klog::set_level).unwrap_or_else(klog::level);
+    klog::set_subsystems(
+        LogSubsystem::from_bits_truncate(enable),
+        LogSubsystem::from_bits_truncate(disable),
+    );
+    Ok(prev as usize)
+}
+
+// SchedClass priority(u32 pid, SchedClass class);
+fn sys_priority(_: &Proc, id: usize, class: usize) -> SysResult {
+    let class = match class {
+        usize::MAX => None,
+        class => Some(SchedClass::from_repr(class).ok_or(E::BadArg)?),
+    };
+
+    // TODO: permission check
+    proc::find_process(ProcId::from_raw(id), |proc| {
+        let mut proc = proc.lock();
+        let prev = proc.class;
+        if let Some(class) = class {
+            proc.class = class;
+        }
+        prev as usize
+    })
+    .ok_or(E::NotFound)
+}
+
+// Mode umask(Mode mask);
+fn sys_umask(proc: &Proc, mask: usize) -> SysResult {
+    let mask = match mask {
+        usize::MAX => None,
+        mask => {
+            Some(Mode::from_bits(u32::try_from(mask).map_err(|_| E::BadArg)?).ok_or(E::BadArg)?)
+        }
+    };
+
+    let mut proc = proc.lock();
+    let prev = proc.umask;
+    if let Some(mask) = mask {
+        proc.umask = mask;
+    }
+    Ok(prev.bits() as usize)
+}
+
+// void stats(StatsOp op, usize hart, TrapStats *stats);
+fn sys_stats(proc: &Proc, op: usize, hart: usize, stats: User<TrapStats>) -> SysResult {
+    match StatsOp::from_repr(op).ok_or(E::BadArg)? {
+        StatsOp::Read => {
+            let counts = stats::read(hart).ok_or(E::NotFound)?;
+            proc.with(|proc| stats.write(proc.pagetable(), &counts))?;
+        }
+        StatsOp::Reset => {
+            // TODO: permission check
+            stats::reset(hart).ok_or(E::NotFound)?;
+        }
+    }
+    Ok(0)
+}
+
+fn read_program(proc: &Proc, insns: User<Insn>, count: usize) -> Result<Program, E> {
+    if count > MAX_INSNS {
+        return Err(E::BadArg);
+    }
+
+    let mut buf = Vec::try_with_capacity(count)?;
+    proc.with(|proc| {
+        for i in 0..count {
+            buf.push(insns.read_nth(proc.pagetable(), i)?);
+        }
+        Ok::<_, E>(())
+    })?;
+    Program::new(buf)
+}
+
+// u32 filter_attach(Hook hook, const Insn *insns, usize count);
+fn sys_filter_attach(proc: &Proc, hook: usize, insns: User<Insn>, count: usize) -> SysResult {
+    // TODO: permission check
+    let hook = Hook::from_repr(hook).ok_or(E::BadArg)?;
+    filter::attach(hook, read_program(proc, insns, count)?).map(|id| id as usize)
+}
+
+// void filter_detach(u32 id);
+fn sys_filter_detach(_: &Proc, id: usize) -> SysResult {
+    // TODO: permission check
+    filter::detach(u32::try_from(id).map_err(|_| E::BadArg)?).map(|_| 0)
+}
+
+// void filter_map(u32 id, u64 (*map)[MAP_LEN]);
+fn sys_filter_map(proc: &Proc, id: usize, map: User<[u64; MAP_LEN]>) -> SysResult {
+    let counts = filter::read_map(u32::try_from(id).map_err(|_| E::BadArg)?)?;
+    proc.with(|proc| map.write(proc.pagetable(), &counts))?;
+    Ok(0)
+}
+
+// void seccomp(const Insn *insns, usize count);
+fn sys_seccomp(proc: &Proc, insns: User<Insn>, count: usize) -> SysResult {
+    let prog = Arc::try_new(read_program(proc, insns, count)?)?;
+    let mut proc = proc.lock();
+    if proc.seccomp.is_some() {
+        return Err(E::AlreadyExists);
+    }
+    proc.seccomp = Some(prog);
+    Ok(0)
+}
+
+// uint ioctl(uint fd, uint cmd, uint arg);
+fn sys_ioctl(proc: &Proc, fd: usize, cmd: usize, arg: usize) -> SysResult {
+    proc.with(|proc| Ok(proc.files.get(fd).ok_or(E::BadFd)?.control(cmd, arg)?))
+}
+
+// void acct(const u8 *path, uint pathlen);
+fn sys_acct(proc: &Proc, path: VirtAddr, len: usize) -> SysResult {
+    // TODO: permission check
+    if len == 0 {
+        acct::set(None);
+        return Ok(0);
+    }
+
+    let mut buf = Vec::try_with_capacity(len)?;
+    proc.with(|proc| {
+        path.copy_from(proc.pagetable(), buf.spare_capacity_mut())?;
+        unsafe {
+            buf.set_len(len);
+        }
+
+        let file = Vfs::open_in_cwd(
+            &proc.cwd,
+            &proc.cwd_path,
+            &buf[..],
+            OpenFlags::CreateFile | OpenFlags::ReadWrite,
+            Mode::Write & !proc.umask,
+        )?;
+        if file.vnode().directory {
+            return Err(E::InvalidOp);
+        }
+        acct::set(Some(file));
+        Ok(0)
+    })
+}
+
+// void sethostname(const u8 *name, uint len);
+fn sys_sethostname(proc: &Proc, name: VirtAddr, len: usize) -> SysResult {
+    // TODO: permission check
+    if len > MAX_HOSTNAME_LEN {
+        return Err(E::BadArg);
+    }
+
+    let mut buf = [MaybeUninit::uninit(); MAX_HOSTNAME_LEN];
+    proc.with(|proc| name.copy_from(proc.pagetable(), &mut buf[..len]))?;
+    hostname::set(unsafe { MaybeUninit::slice_assume_init_ref(&buf[..len]) })?;
+    Ok(0)
+}
+
+// uint gethostname(u8 *buf, uint buflen);
+fn sys_gethostname(proc: &Proc, buf: VirtAddr, buflen: usize) -> SysResult {
+    let mut name = [0; MAX_HOSTNAME_LEN];
+    let len = hostname::with(|hostname| {
+        name[..hostname.len()].copy_from_slice(hostname);
+        hostname.len()
     });
+    if len > buflen {
+        return Err(E::BadArg);
+    }
 
-    let result = match Sys::from_repr(syscall_no) {
+    proc.with(|proc| buf.copy_to(proc.pagetable(), &name[..len], None))?;
+    Ok(len)
+}
+
+/// Run the syscall `proc` trapped with and write its result. Returns `proc` still locked from
+/// writing the result, so the trap can be finished without taking the lock again.
+pub fn handle_syscall(proc: &Proc) -> Guard<Process> {
+    let start = r_cycle();
+    let (call, pid, seccomp) = proc.with(|mut proc| {
+        let call = SyscallRegs::read(&proc.trapframe().regs);
+        (call, proc.pid(), proc.seccomp.clone())
+    });
+    let (syscall_no, [a0, a1, a2, a3, a4, a5]) = (call.nr, call.args);
+
+    stats::syscall(syscall_no);
+    let mut event = Event {
+        syscall: syscall_no,
+        args: call.args,
+        pid,
+        result: Ok(0),
+    };
+    filter::run(Hook::SyscallEntry, &event);
+
+    let denied = seccomp.is_some_and(|prog| prog.run(&event, None) != 0);
+    let sys = Sys::from_repr(syscall_no);
+    let result = match sys {
+        _ if denied => Err(E::PermissionDenied),
         Some(Sys::Shutdown) => sys_shutdown(proc, a0),
         Some(Sys::Kill) => sys_kill(proc, a0),
         Some(Sys::GetPid) => sys_getpid(proc),
-        Some(Sys::Open) => sys_open(proc, VirtAddr(a0), a1, (a2 & u32::MAX as usize) as u32),
+        Some(Sys::Open) => sys_open(proc, VirtAddr(a0), a1, (a2 & u32::MAX as usize) as u32, a3),
         Some(Sys::Close) => sys_close(proc, a0),
+        Some(Sys::CloseRange) => sys_close_range(proc, a0, a1),
         Some(Sys::Read) => sys_read(proc, a0, a1, VirtAddr(a2), a3),
         Some(Sys::Write) => sys_write(proc, a0, a1, VirtAddr(a2), a3),
         Some(Sys::Readdir) => sys_readdir(proc, a0, a1, VirtAddr(a2).into()),
         Some(Sys::Chdir) => sys_chdir(proc, VirtAddr(a0), a1),
-        Some(Sys::Spawn) => sys_spawn(proc, VirtAddr(a0), a1, VirtAddr(a2).into(), a3),
+        Some(Sys::Spawn) => sys_spawn(
+            proc,
+            VirtAddr(a0),
+            a1,
+            VirtAddr(a2).into(),
+            a3,
+            VirtAddr(a4),
+            a5,
+        ),
         Some(Sys::Stat) => sys_stat(proc, a0, VirtAddr(a1).into()),
         Some(Sys::Sbrk) => sys_sbrk(proc, a0 as isize),
         Some(Sys::Waitpid) => sys_waitpid(proc, a0),
+        Some(Sys::WaitpidTimeout) => sys_waitpid_timeout(proc, a0, a1),
         Some(Sys::Exit) => sys_exit(proc, a0),
+        Some(Sys::LogCtl) => sys_logctl(
+            proc,
+            a0,
+            (a1 & u32::MAX as usize) as u32,
+            (a2 & u32::MAX as usize) as u32,
+        ),
+        Some(Sys::PidHandle) => sys_pidhandle(proc, a0),
+        Some(Sys::SendMsg) => sys_sendmsg(proc, a0, VirtAddr(a1), a2),
+        Some(Sys::RecvMsg) => sys_recvmsg(proc, VirtAddr(a0), a1, VirtAddr(a2), a3 != 0),
+        Some(Sys::Poll) => sys_poll(proc, VirtAddr(a0).into(), a1, a2 != 0),
+        Some(Sys::FaultInfo) => sys_faultinfo(proc, a0, VirtAddr(a1).into()),
+        Some(Sys::RegisterName) => sys_registername(proc, VirtAddr(a0), a1),
+        Some(Sys::UnregisterName) => sys_unregistername(proc, VirtAddr(a0), a1),
+        Some(Sys::LookupName) => sys_lookupname(proc, VirtAddr(a0), a1),
+        #[cfg(feature = "net")]
+        Some(Sys::Socket) => sys_socket(proc, a0),
+        #[cfg(feature = "net")]
+        Some(Sys::Bind) => sys_bind(proc, a0, VirtAddr(a1)),
+        #[cfg(feature = "net")]
+        Some(Sys::Connect) => sys_connect(proc, a0, VirtAddr(a1)),
+        #[cfg(feature = "net")]
+        Some(Sys::Listen) => sys_listen(proc, a0, a1),
+        #[cfg(feature = "net")]
+        Some(Sys::Accept) => sys_accept(proc, a0, VirtAddr(a1)),
+        #[cfg(feature = "net")]
+        Some(Sys::SendTo) => sys_sendto(proc, a0, VirtAddr(a1), a2, VirtAddr(a3)),
+        #[cfg(feature = "net")]
+        Some(Sys::RecvFrom) => sys_recvfrom(proc, a0, VirtAddr(a1), a2, VirtAddr(a3)),
+        #[cfg(feature = "net")]
+        Some(Sys::SetSockOpt) => sys_setsockopt(proc, a0, a1, a2),
+        #[cfg(feature = "net")]
+        Some(Sys::GetSockOpt) => sys_getsockopt(proc, a0, a1),
+        #[cfg(feature = "net")]
+        Some(Sys::NetCtl) => sys_netctl(proc, a0, VirtAddr(a1)),
+        #[cfg(not(feature = "net"))]
+        Some(
+            Sys::Socket
+            | Sys::Bind
+            | Sys::Connect
+            | Sys::Listen
+            | Sys::Accept
+            | Sys::SendTo
+            | Sys::RecvFrom
+            | Sys::SetSockOpt
+            | Sys::GetSockOpt
+            | Sys::NetCtl,
+        ) => Err(E::Unsupported),
+        Some(Sys::Stats) => sys_stats(proc, a0, a1, VirtAddr(a2).into()),
+        Some(Sys::Priority) => sys_priority(proc, a0, a1),
+        Some(Sys::GetCwd) => sys_getcwd(proc, VirtAddr(a0), a1),
+        Some(Sys::Unmount) => sys_unmount(proc, VirtAddr(a0), a1, a2),
+        Some(Sys::BindMount) => sys_bind_mount(proc, VirtAddr(a0), a1, VirtAddr(a2), a3),
+        Some(Sys::Remove) => sys_remove(proc, VirtAddr(a0), a1),
+        Some(Sys::GetExePath) => sys_getexepath(proc, VirtAddr(a0), a1),
+        Some(Sys::Umask) => sys_umask(proc, a0),
+        Some(Sys::FilterAttach) => sys_filter_attach(proc, a0, VirtAddr(a1).into(), a2),
+        Some(Sys::FilterDetach) => sys_filter_detach(proc, a0),
+        Some(Sys::FilterMap) => sys_filter_map(proc, a0, VirtAddr(a1).into()),
+        Some(Sys::Seccomp) => sys_seccomp(proc, VirtAddr(a0).into(), a1),
+        Some(Sys::Ioctl) => sys_ioctl(proc, a0, a1, a2),
+        Some(Sys::Acct) => sys_acct(proc, VirtAddr(a0), a1),
+        Some(Sys::SetHostname) => sys_sethostname(proc, VirtAddr(a0), a1),
+        Some(Sys::GetHostname) => sys_gethostname(proc, VirtAddr(a0), a1),
+        Some(Sys::PivotRoot) => sys_pivot_root(proc, VirtAddr(a0), a1, VirtAddr(a2), a3),
         None => Err(E::BadSyscall),
     };
 
-    let (a0, a1) = match result {
-        Ok(res) => (res, 0),
-        Err(err) => (0, err as usize),
-    };
+    event.result = result;
+    filter::run(Hook::SyscallExit, &event);
+    stats::syscall_handled(syscall_no, r_cycle().wrapping_sub(start));
+
     let mut proc = proc.lock();
-    proc.trapframe()[Reg::A0] = a0;
-    proc.trapframe()[Reg::A1] = a1;
+    // the process being waited for can exit before we get here, in which case it has already
+    // written its exit code and woken us
+    if matches!(sys, Some(Sys::Waitpid | Sys::WaitpidTimeout)) && proc.status == ProcStatus::Idle {
+        return proc;
+    }
+    set_syscall_result(&mut proc.trapframe().regs, result);
+    proc
 }
@@ -1,17 +1,35 @@
+use core::{
+    fmt::Write,
+    mem::{offset_of, size_of},
+    ops::{Index, IndexMut, Range},
+    ptr::addr_of,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use servos::{
-    riscv::{r_sstatus, r_stval, r_tp, w_sstatus, SSTATUS_SPIE, SSTATUS_SPP},
-    sbi,
+    riscv::{r_sepc, r_sstatus, r_stval, r_tp, w_sstatus, SSTATUS_SPIE, SSTATUS_SPP},
+    sbi, Align16,
+};
+
+use shared::{
+    layout,
+    sys::{SchedClass, UartError},
 };
 
 use crate::{
+    fault, klog,
     plic::PLIC,
-    println,
-    proc::{ProcStatus, Process, ProcessNode, Reg, Scheduler, USER_TRAP_FRAME},
+    poll,
+    proc::{
+        Process, ProcessNode, Reg, Scheduler, TrapFrame, HART_FIRST_STACK, HART_STACK_LEN,
+        MAX_HARTS, USER_TRAP_FRAME,
+    },
     riscv::{
-        enable_intr, r_scause, r_time, w_sie, w_stvec, InterruptToken, SIE_SEIE, SIE_SSIE, SIE_STIE,
+        enable_intr, r_cycle, r_scause, r_time, w_sie, w_stvec, InterruptToken, SIE_SEIE, SIE_SSIE,
+        SIE_STIE, TIMEBASE_FREQ,
     },
-    sys,
-    uart::CONS,
+    stats, sys,
+    uart::{EmergencyWriter, CONS},
     vmm::{self, Page, PageTable, Pte, VirtAddr},
     CONSOLE_DEV,
 };
@@ -19,7 +37,7 @@ use crate::{
 const INTERRUPT_FLAG_BIT: usize = 1 << (usize::BITS - 1);
 
 #[repr(usize)]
-#[derive(strum::FromRepr, Debug)]
+#[derive(strum::FromRepr, Debug, Clone, Copy)]
 #[allow(clippy::enum_clike_unportable_variant)]
 pub enum TrapCause {
     // Interrupts
@@ -52,8 +70,48 @@ impl TrapCause {
     }
 }
 
-pub const USER_TRAP_VEC: VirtAddr = VirtAddr(VirtAddr::MAX.0 - Page::SIZE);
-pub const TIMER_INTERVAL: usize = 10_000_000 / 2;
+/// The offset of `reg` in a `TrapFrame`, for the trap assembly
+const fn frame_reg(reg: Reg) -> usize {
+    offset_of!(TrapFrame, regs) + reg as usize * size_of::<usize>()
+}
+
+/// The offset of `reg` in a `KernelTrapFrame`, for the trap assembly
+const fn kframe_reg(reg: Reg) -> usize {
+    offset_of!(KernelTrapFrame, regs) + reg as usize * size_of::<usize>()
+}
+
+// loads and stores only have a 12 bit signed offset, and the kernel frame must keep sp aligned
+const _: () = {
+    assert!(offset_of!(TrapFrame, proc) + size_of::<ProcessNode>() <= 2048);
+    assert!(frame_reg(Reg::T6) < offset_of!(TrapFrame, hartid));
+    assert!(size_of::<KernelTrapFrame>() % 16 == 0);
+    assert!(kframe_reg(Reg::T6) < size_of::<KernelTrapFrame>());
+};
+
+pub const USER_TRAP_VEC: VirtAddr = VirtAddr(layout::TRAP_VEC.start);
+pub const TIMER_INTERVAL: usize = TIMEBASE_FREQ / 2;
+
+/// What each hart's timer was last set to
+static TIMER_DEADLINE: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// Set this hart's timer to go off at `deadline`, unless it already will. Returns false if SBI
+/// doesn't support timers.
+///
+/// A process going back to user mode after a syscall usually still has the deadline it was
+/// resumed with, so this saves a call into SBI on every syscall. A timer that has gone off is
+/// always set to something new before the hart gets back to user mode, so it can't be skipped.
+pub fn set_timer(deadline: usize) -> bool {
+    let armed = &TIMER_DEADLINE[r_tp()];
+    if armed.load(Ordering::Relaxed) == deadline {
+        return true;
+    }
+
+    let ok = sbi::timer::set_timer(deadline).is_ok();
+    if ok {
+        armed.store(deadline, Ordering::Relaxed);
+    }
+    ok
+}
 
 #[naked]
 #[link_section = ".text.trap"]
@@ -63,44 +121,45 @@ extern "C" fn user_trap_vec() {
             r"
             .align 4
             csrrw t0, sscratch, t0
-
-            sd   ra, 0x08(t0)
-            sd   sp, 0x10(t0)
-            sd   gp, 0x18(t0)
-            sd   tp, 0x20(t0)
-
-            sd   t1, 0x30(t0)
-            sd   t2, 0x38(t0)
-            sd   s0, 0x40(t0)
-            sd   s1, 0x48(t0)
-            sd   a0, 0x50(t0)
-            sd   a1, 0x58(t0)
-            sd   a2, 0x60(t0)
-            sd   a3, 0x68(t0)
-            sd   a4, 0x70(t0)
-            sd   a5, 0x78(t0)
-            sd   a6, 0x80(t0)
-            sd   a7, 0x88(t0)
-            sd   s2, 0x90(t0)
-            sd   s3, 0x98(t0)
-            sd   s4, 0xa0(t0)
-            sd   s5, 0xa8(t0)
-            sd   s6, 0xb0(t0)
-            sd   s7, 0xb8(t0)
-            sd   s8, 0xc0(t0)
-            sd   s9, 0xc8(t0)
-            sd   s10, 0xd0(t0)
-            sd   s11, 0xd8(t0)
-            sd   t3, 0xe0(t0)
-            sd   t4, 0xe8(t0)
-            sd   t5, 0xf0(t0)
-            sd   t6, 0xf8(t0)
+            sd   a2, {a2}(t0)
+            csrr a2, cycle              # for the latency histograms, before anything is saved
+
+            sd   ra, {ra}(t0)
+            sd   sp, {sp}(t0)
+            sd   gp, {gp}(t0)
+            sd   tp, {tp}(t0)
+
+            sd   t1, {t1}(t0)
+            sd   t2, {t2}(t0)
+            sd   s0, {s0}(t0)
+            sd   s1, {s1}(t0)
+            sd   a0, {a0}(t0)
+            sd   a1, {a1}(t0)
+            sd   a3, {a3}(t0)
+            sd   a4, {a4}(t0)
+            sd   a5, {a5}(t0)
+            sd   a6, {a6}(t0)
+            sd   a7, {a7}(t0)
+            sd   s2, {s2}(t0)
+            sd   s3, {s3}(t0)
+            sd   s4, {s4}(t0)
+            sd   s5, {s5}(t0)
+            sd   s6, {s6}(t0)
+            sd   s7, {s7}(t0)
+            sd   s8, {s8}(t0)
+            sd   s9, {s9}(t0)
+            sd   s10, {s10}(t0)
+            sd   s11, {s11}(t0)
+            sd   t3, {t3}(t0)
+            sd   t4, {t4}(t0)
+            sd   t5, {t5}(t0)
+            sd   t6, {t6}(t0)
 
             csrr t1, sscratch
-            sd   t1, 0x28(t0)           # save t0 as well
+            sd   t1, {t0}(t0)           # save t0 as well
 
             csrr a0, sepc
-            sd   a0, 0x00(t0)           # load previous PC into TrapFrame::regs[0]
+            sd   a0, {pc}(t0)           # load previous PC into TrapFrame::regs[0]
 
             ld         a1, {proc}(t0)
             ld         tp, {hartid}(t0)          # load kernel hartid
@@ -113,11 +172,43 @@ extern "C" fn user_trap_vec() {
 
             jr ra
             ",
-            satp = const core::mem::offset_of!(crate::proc::TrapFrame, ksatp),
-            hartid = const core::mem::offset_of!(crate::proc::TrapFrame, hartid),
-            stack = const core::mem::offset_of!(crate::proc::TrapFrame, ksp),
-            handle = const core::mem::offset_of!(crate::proc::TrapFrame, handle_trap),
-            proc = const core::mem::offset_of!(crate::proc::TrapFrame, proc),
+            pc = const frame_reg(Reg::PC),
+            ra = const frame_reg(Reg::RA),
+            sp = const frame_reg(Reg::SP),
+            gp = const frame_reg(Reg::GP),
+            tp = const frame_reg(Reg::TP),
+            t0 = const frame_reg(Reg::T0),
+            t1 = const frame_reg(Reg::T1),
+            t2 = const frame_reg(Reg::T2),
+            s0 = const frame_reg(Reg::S0),
+            s1 = const frame_reg(Reg::S1),
+            a0 = const frame_reg(Reg::A0),
+            a1 = const frame_reg(Reg::A1),
+            a2 = const frame_reg(Reg::A2),
+            a3 = const frame_reg(Reg::A3),
+            a4 = const frame_reg(Reg::A4),
+            a5 = const frame_reg(Reg::A5),
+            a6 = const frame_reg(Reg::A6),
+            a7 = const frame_reg(Reg::A7),
+            s2 = const frame_reg(Reg::S2),
+            s3 = const frame_reg(Reg::S3),
+            s4 = const frame_reg(Reg::S4),
+            s5 = const frame_reg(Reg::S5),
+            s6 = const frame_reg(Reg::S6),
+            s7 = const frame_reg(Reg::S7),
+            s8 = const frame_reg(Reg::S8),
+            s9 = const frame_reg(Reg::S9),
+            s10 = const frame_reg(Reg::S10),
+            s11 = const frame_reg(Reg::S11),
+            t3 = const frame_reg(Reg::T3),
+            t4 = const frame_reg(Reg::T4),
+            t5 = const frame_reg(Reg::T5),
+            t6 = const frame_reg(Reg::T6),
+            satp = const offset_of!(TrapFrame, ksatp),
+            hartid = const offset_of!(TrapFrame, hartid),
+            stack = const offset_of!(TrapFrame, ksp),
+            handle = const offset_of!(TrapFrame, handle_trap),
+            proc = const offset_of!(TrapFrame, proc),
 
             options(noreturn),
         );
@@ -137,91 +228,422 @@ extern "C" fn __return_to_user(satp: usize) -> ! {
             csrw satp, a0
             sfence.vma zero, zero       # switch to user page table
 
-            ld   t1,  0x00(t0)
+            ld   t1,  {pc}(t0)
             csrw sepc, t1               # restore PC
 
-            ld   ra,  0x08(t0)
-            ld   sp,  0x10(t0)
-            ld   gp,  0x18(t0)
-            ld   tp,  0x20(t0)
-
-            ld   t1,  0x30(t0)
-            ld   t2,  0x38(t0)
-            ld   s0,  0x40(t0)
-            ld   s1,  0x48(t0)
-            ld   a0,  0x50(t0)
-            ld   a1,  0x58(t0)
-            ld   a2,  0x60(t0)
-            ld   a3,  0x68(t0)
-            ld   a4,  0x70(t0)
-            ld   a5,  0x78(t0)
-            ld   a6,  0x80(t0)
-            ld   a7,  0x88(t0)
-            ld   s2,  0x90(t0)
-            ld   s3,  0x98(t0)
-            ld   s4,  0xa0(t0)
-            ld   s5,  0xa8(t0)
-            ld   s6,  0xb0(t0)
-            ld   s7,  0xb8(t0)
-            ld   s8,  0xc0(t0)
-            ld   s9,  0xc8(t0)
-            ld   s10, 0xd0(t0)
-            ld   s11, 0xd8(t0)
-            ld   t3,  0xe0(t0)
-            ld   t4,  0xe8(t0)
-            ld   t5,  0xf0(t0)
-            ld   t6,  0xf8(t0)
-
-            ld   t0,  0x28(t0)
+            ld   ra,  {ra}(t0)
+            ld   sp,  {sp}(t0)
+            ld   gp,  {gp}(t0)
+            ld   tp,  {tp}(t0)
+
+            ld   t1,  {t1}(t0)
+            ld   t2,  {t2}(t0)
+            ld   s0,  {s0}(t0)
+            ld   s1,  {s1}(t0)
+            ld   a0,  {a0}(t0)
+            ld   a1,  {a1}(t0)
+            ld   a2,  {a2}(t0)
+            ld   a3,  {a3}(t0)
+            ld   a4,  {a4}(t0)
+            ld   a5,  {a5}(t0)
+            ld   a6,  {a6}(t0)
+            ld   a7,  {a7}(t0)
+            ld   s2,  {s2}(t0)
+            ld   s3,  {s3}(t0)
+            ld   s4,  {s4}(t0)
+            ld   s5,  {s5}(t0)
+            ld   s6,  {s6}(t0)
+            ld   s7,  {s7}(t0)
+            ld   s8,  {s8}(t0)
+            ld   s9,  {s9}(t0)
+            ld   s10, {s10}(t0)
+            ld   s11, {s11}(t0)
+            ld   t3,  {t3}(t0)
+            ld   t4,  {t4}(t0)
+            ld   t5,  {t5}(t0)
+            ld   t6,  {t6}(t0)
+
+            ld   t0,  {t0}(t0)
             sret
             ",
+            pc = const frame_reg(Reg::PC),
+            ra = const frame_reg(Reg::RA),
+            sp = const frame_reg(Reg::SP),
+            gp = const frame_reg(Reg::GP),
+            tp = const frame_reg(Reg::TP),
+            t0 = const frame_reg(Reg::T0),
+            t1 = const frame_reg(Reg::T1),
+            t2 = const frame_reg(Reg::T2),
+            s0 = const frame_reg(Reg::S0),
+            s1 = const frame_reg(Reg::S1),
+            a0 = const frame_reg(Reg::A0),
+            a1 = const frame_reg(Reg::A1),
+            a2 = const frame_reg(Reg::A2),
+            a3 = const frame_reg(Reg::A3),
+            a4 = const frame_reg(Reg::A4),
+            a5 = const frame_reg(Reg::A5),
+            a6 = const frame_reg(Reg::A6),
+            a7 = const frame_reg(Reg::A7),
+            s2 = const frame_reg(Reg::S2),
+            s3 = const frame_reg(Reg::S3),
+            s4 = const frame_reg(Reg::S4),
+            s5 = const frame_reg(Reg::S5),
+            s6 = const frame_reg(Reg::S6),
+            s7 = const frame_reg(Reg::S7),
+            s8 = const frame_reg(Reg::S8),
+            s9 = const frame_reg(Reg::S9),
+            s10 = const frame_reg(Reg::S10),
+            s11 = const frame_reg(Reg::S11),
+            t3 = const frame_reg(Reg::T3),
+            t4 = const frame_reg(Reg::T4),
+            t5 = const frame_reg(Reg::T5),
+            t6 = const frame_reg(Reg::T6),
             options(noreturn),
             trap_frame = const USER_TRAP_FRAME.0,
         )
     }
 }
 
-#[repr(align(4))]
-extern "riscv-interrupt-s" fn sv_trap_vec() {
-    // FIXME: if the kernel overruns its stack, it will cause a page fault that brings it to this
-    // routine. However, the first thing we do here (courtesy of the riscv-interrupt-s calling conv)
-    // is backup all registers on the stack, which will cause a double-fault in this case.
-    match TrapCause::current() {
-        Ok(TrapCause::ExternalIntr) => handle_external_intr(),
+/// Registers of the interrupted kernel code, saved on its stack by `sv_trap_vec`. Uses the same
+/// layout as `TrapFrame::regs`.
+#[repr(C)]
+pub struct KernelTrapFrame {
+    pub regs: [usize; 32],
+}
+
+impl Index<Reg> for KernelTrapFrame {
+    type Output = usize;
+
+    fn index(&self, index: Reg) -> &Self::Output {
+        &self.regs[index as usize]
+    }
+}
+
+impl IndexMut<Reg> for KernelTrapFrame {
+    fn index_mut(&mut self, index: Reg) -> &mut Self::Output {
+        &mut self.regs[index as usize]
+    }
+}
+
+const REG_NAMES: [&str; 32] = [
+    "pc", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5",
+    "t6",
+];
+
+impl KernelTrapFrame {
+    fn dump(&self, out: &mut impl Write) {
+        _ = writeln!(out, "stval: {:#018x}", r_stval());
+        for (i, (name, val)) in REG_NAMES.iter().zip(self.regs.iter()).enumerate() {
+            _ = write!(out, "{name:>4}: {val:#018x}");
+            _ = if i % 4 == 3 {
+                writeln!(out)
+            } else {
+                write!(out, " ")
+            };
+        }
+
+        // requires frame pointers, each frame stores the return address at fp - 8 and the
+        // previous frame pointer at fp - 16
+        _ = writeln!(out, "backtrace:");
+        _ = writeln!(out, "  0: {:#018x}", self[Reg::PC]);
+        let mut fp = self[Reg::S0];
+        for i in 1..32 {
+            let mut frame = [0usize; 2];
+            if fp == 0
+                || fp % core::mem::align_of::<usize>() != 0
+                || !unsafe {
+                    vmm::copy_nofault(
+                        frame.as_mut_ptr().cast(),
+                        (fp - 16) as *const u8,
+                        core::mem::size_of_val(&frame),
+                    )
+                }
+            {
+                break;
+            }
+
+            let [prev_fp, ra] = frame;
+            if ra == 0 {
+                break;
+            }
+            _ = writeln!(out, "{i:>3}: {ra:#018x}");
+            fp = prev_fp;
+        }
+    }
+}
+
+#[repr(C)]
+struct ExTableEntry {
+    fault: usize,
+    fixup: usize,
+}
+
+extern "C" {
+    static _ex_table_start: ExTableEntry;
+    static _ex_table_end: ExTableEntry;
+}
+
+/// Find the recovery address for a fault at `pc`. Entries are added to the `.ex_table` section by
+/// assembly routines that are allowed to fault, like `vmm::copy_nofault`.
+fn find_fixup(pc: usize) -> Option<usize> {
+    let table = unsafe {
+        core::slice::from_ptr_range(Range {
+            start: addr_of!(_ex_table_start),
+            end: addr_of!(_ex_table_end),
+        })
+    };
+    table
+        .iter()
+        .find(|ent| ent.fault == pc)
+        .map(|ent| ent.fixup)
+}
+
+const OVERFLOW_STACK_LEN: usize = Page::SIZE * 2;
+
+/// Where a hart goes to report that it overflowed its own stack
+static mut OVERFLOW_STACKS: [Align16<[u8; OVERFLOW_STACK_LEN]>; MAX_HARTS] =
+    [const { Align16([0; OVERFLOW_STACK_LEN]) }; MAX_HARTS];
+
+extern "C" fn handle_stack_overflow() -> ! {
+    panic!(
+        "kernel stack overflow (pc {:#x}, address {:#x})",
+        r_sepc(),
+        r_stval()
+    );
+}
+
+#[naked]
+extern "C" fn sv_trap_vec() {
+    // if the kernel overruns its stack, the fault brings it here, where saving the registers
+    // would fault again. check that the frame fits before saving anything, using only t0.
+    unsafe {
+        core::arch::asm!(
+            r"
+            .align 4
+            csrw sscratch, t0
+            addi sp, sp, -{size}
+
+            # sp is in a guard region if this bit of its distance below the first stack is set,
+            # see HART_STACK_STRIDE
+            li   t0, {first_stack} - 1
+            sub  t0, t0, sp
+            srli t0, t0, {stack_shift}
+            andi t0, t0, 1
+            bnez t0, 1f
+            csrr t0, sscratch
+
+            sd   ra, {ra}(sp)
+            sd   gp, {gp}(sp)
+            sd   tp, {tp}(sp)
+            sd   t0, {t0}(sp)
+            sd   t1, {t1}(sp)
+            sd   t2, {t2}(sp)
+            sd   s0, {s0}(sp)
+            sd   s1, {s1}(sp)
+            sd   a0, {a0}(sp)
+            sd   a1, {a1}(sp)
+            sd   a2, {a2}(sp)
+            sd   a3, {a3}(sp)
+            sd   a4, {a4}(sp)
+            sd   a5, {a5}(sp)
+            sd   a6, {a6}(sp)
+            sd   a7, {a7}(sp)
+            sd   s2, {s2}(sp)
+            sd   s3, {s3}(sp)
+            sd   s4, {s4}(sp)
+            sd   s5, {s5}(sp)
+            sd   s6, {s6}(sp)
+            sd   s7, {s7}(sp)
+            sd   s8, {s8}(sp)
+            sd   s9, {s9}(sp)
+            sd   s10, {s10}(sp)
+            sd   s11, {s11}(sp)
+            sd   t3, {t3}(sp)
+            sd   t4, {t4}(sp)
+            sd   t5, {t5}(sp)
+            sd   t6, {t6}(sp)
+
+            addi t0, sp, {size}
+            sd   t0, {sp}(sp)           # sp before the trap
+            csrr t0, sepc
+            sd   t0, {pc}(sp)
+
+            mv   a0, sp
+            call {handler}
+
+            ld   t0, {pc}(sp)
+            csrw sepc, t0               # the handler may have redirected us to a fixup
+
+            ld   ra, {ra}(sp)
+            ld   gp, {gp}(sp)
+            ld   tp, {tp}(sp)
+            ld   t1, {t1}(sp)
+            ld   t2, {t2}(sp)
+            ld   s0, {s0}(sp)
+            ld   s1, {s1}(sp)
+            ld   a0, {a0}(sp)
+            ld   a1, {a1}(sp)
+            ld   a2, {a2}(sp)
+            ld   a3, {a3}(sp)
+            ld   a4, {a4}(sp)
+            ld   a5, {a5}(sp)
+            ld   a6, {a6}(sp)
+            ld   a7, {a7}(sp)
+            ld   s2, {s2}(sp)
+            ld   s3, {s3}(sp)
+            ld   s4, {s4}(sp)
+            ld   s5, {s5}(sp)
+            ld   s6, {s6}(sp)
+            ld   s7, {s7}(sp)
+            ld   s8, {s8}(sp)
+            ld   s9, {s9}(sp)
+            ld   s10, {s10}(sp)
+            ld   s11, {s11}(sp)
+            ld   t3, {t3}(sp)
+            ld   t4, {t4}(sp)
+            ld   t5, {t5}(sp)
+            ld   t6, {t6}(sp)
+            ld   t0, {t0}(sp)
+
+            addi sp, sp, {size}
+            sret
+
+            1:
+            la   sp, {overflow_stacks}
+            addi t0, tp, 1
+            li   t1, {overflow_stack_len}
+            mul  t0, t0, t1
+            add  sp, sp, t0
+            tail {overflow}
+            ",
+            pc = const kframe_reg(Reg::PC),
+            ra = const kframe_reg(Reg::RA),
+            sp = const kframe_reg(Reg::SP),
+            gp = const kframe_reg(Reg::GP),
+            tp = const kframe_reg(Reg::TP),
+            t0 = const kframe_reg(Reg::T0),
+            t1 = const kframe_reg(Reg::T1),
+            t2 = const kframe_reg(Reg::T2),
+            s0 = const kframe_reg(Reg::S0),
+            s1 = const kframe_reg(Reg::S1),
+            a0 = const kframe_reg(Reg::A0),
+            a1 = const kframe_reg(Reg::A1),
+            a2 = const kframe_reg(Reg::A2),
+            a3 = const kframe_reg(Reg::A3),
+            a4 = const kframe_reg(Reg::A4),
+            a5 = const kframe_reg(Reg::A5),
+            a6 = const kframe_reg(Reg::A6),
+            a7 = const kframe_reg(Reg::A7),
+            s2 = const kframe_reg(Reg::S2),
+            s3 = const kframe_reg(Reg::S3),
+            s4 = const kframe_reg(Reg::S4),
+            s5 = const kframe_reg(Reg::S5),
+            s6 = const kframe_reg(Reg::S6),
+            s7 = const kframe_reg(Reg::S7),
+            s8 = const kframe_reg(Reg::S8),
+            s9 = const kframe_reg(Reg::S9),
+            s10 = const kframe_reg(Reg::S10),
+            s11 = const kframe_reg(Reg::S11),
+            t3 = const kframe_reg(Reg::T3),
+            t4 = const kframe_reg(Reg::T4),
+            t5 = const kframe_reg(Reg::T5),
+            t6 = const kframe_reg(Reg::T6),
+            size = const size_of::<KernelTrapFrame>(),
+            handler = sym handle_s_trap,
+            first_stack = const HART_FIRST_STACK.0,
+            stack_shift = const HART_STACK_LEN.trailing_zeros(),
+            overflow_stacks = sym OVERFLOW_STACKS,
+            overflow_stack_len = const OVERFLOW_STACK_LEN,
+            overflow = sym handle_stack_overflow,
+            options(noreturn),
+        );
+    }
+}
+
+extern "C" fn handle_s_trap(frame: &mut KernelTrapFrame) {
+    let cause = TrapCause::current();
+    if let Ok(cause) = cause {
+        stats::trap(cause);
+    }
+
+    match cause {
+        Ok(TrapCause::ExternalIntr) => _ = handle_external_intr(),
         Ok(TrapCause::TimerIntr) => {
-            _ = sbi::timer::set_timer(r_time() + TIMER_INTERVAL);
+            set_timer(r_time() + TIMER_INTERVAL);
+        }
+        Ok(
+            cause @ (TrapCause::LoadPageFault
+            | TrapCause::StorePageFault
+            | TrapCause::LoadAccessFault
+            | TrapCause::StoreAccessFault
+            | TrapCause::IllegalInstr),
+        ) => {
+            if let Some(fixup) = find_fixup(frame[Reg::PC]) {
+                frame[Reg::PC] = fixup;
+                return;
+            }
+
+            frame.dump(&mut EmergencyWriter);
+            panic!("Unhandled kernel fault: {cause:?}");
+        }
+        Ok(ex) => {
+            frame.dump(&mut EmergencyWriter);
+            panic!("Unhandled trap: {ex:?}");
+        }
+        Err(cause) => {
+            frame.dump(&mut EmergencyWriter);
+            panic!("Unhandled trap: unknown {cause:#x}");
         }
-        Ok(ex) => panic!("Unhandled trap: {ex:?}"),
-        Err(cause) => panic!("Unhandled trap: unknown {cause:#x}"),
     }
 }
 
-pub extern "C" fn handle_u_trap(mut sepc: usize, paddr: ProcessNode) -> ! {
+pub extern "C" fn handle_u_trap(mut sepc: usize, paddr: ProcessNode, entered_at: usize) -> ! {
     w_stvec(sv_trap_vec as usize);
+    stats::trap_entered(r_cycle().wrapping_sub(entered_at));
 
     let mut must_yield = false;
+    let mut preempted = false;
+    // a syscall hands back the lock it wrote its result with, to finish the trap with
+    let mut locked = None;
     let proc = unsafe { paddr.0.as_ref() };
-    match TrapCause::current() {
-        Ok(TrapCause::ExternalIntr) => handle_external_intr(),
+    let cause = TrapCause::current();
+    if let Ok(cause) = cause {
+        stats::trap(cause);
+    }
+
+    match cause {
+        Ok(TrapCause::ExternalIntr) => {
+            // let an interactive process waiting for this input run first
+            must_yield =
+                handle_external_intr() && proc.with(|proc| proc.class != SchedClass::Interactive);
+        }
         Ok(TrapCause::TimerIntr) => {
-            _ = sbi::timer::set_timer(r_time() + TIMER_INTERVAL);
+            set_timer(r_time() + TIMER_INTERVAL);
             must_yield = true;
+            preempted = true;
         }
         Ok(TrapCause::EcallFromUMode) => {
-            sys::handle_syscall(proc);
+            locked = Some(sys::handle_syscall(proc));
             sepc += 4;
         }
+        // the first write to untouched memory, retried once it has a page of its own
+        Ok(TrapCause::StorePageFault)
+            if proc.with(|proc| proc.pagetable().write_fault(VirtAddr(r_stval()))) => {}
         Ok(
             cause @ (TrapCause::LoadPageFault
             | TrapCause::StorePageFault
             | TrapCause::InstrPageFault),
         ) => {
             let pid = proc.with(|mut proc| {
+                fault::record(&mut proc, cause as usize, sepc, r_stval());
                 proc.kill(None);
-                proc.pid
+                proc.pid()
             });
 
-            println!(
+            klog!(
+                Warn,
+                Vmm,
                 "PID {pid} page fault ({cause:?}) on hart {} at address {:#x}",
                 r_tp(),
                 r_stval(),
@@ -229,40 +651,63 @@ pub extern "C" fn handle_u_trap(mut sepc: usize, paddr: ProcessNode) -> ! {
         }
         Ok(unk) => {
             let pid = proc.with(|mut proc| {
+                fault::record(&mut proc, unk as usize, sepc, r_stval());
                 proc.kill(None);
-                proc.pid
+                proc.pid()
             });
-            println!(
+            klog!(
+                Warn,
+                Sched,
                 "ETrap from process with PID {pid} on hart {}: exception {unk:?} raised, killing process",
                 r_tp(),
             );
         }
         Err(cause) => panic!("Unhandled trap: no match for cause {cause:#x}"),
     }
+    if let Ok(cause) = cause {
+        stats::trap_handled(cause, r_cycle().wrapping_sub(entered_at));
+    }
 
-    proc.with(|mut proc| {
-        proc.trapframe()[Reg::PC] = sepc;
-        unsafe {
-            if let Some(ecode) = proc.killed {
-                paddr.destroy(proc, ecode); // proc is invalidated here
-            } else if !must_yield && !matches!(proc.status, ProcStatus::Waiting(_)) {
-                Process::resume(proc);
-            } else if !Scheduler::take(paddr) {
-                println!("Scheduler::take failed for PID {}, OOM!", proc.pid);
-                paddr.destroy(proc, usize::MAX);
-                /* OOM */
-            }
+    let mut proc = locked.unwrap_or_else(|| proc.lock());
+    proc.trapframe()[Reg::PC] = sepc;
+    proc.charge_cpu_time();
+    unsafe {
+        if let Some(ecode) = proc.killed {
+            paddr.destroy(proc, ecode); // proc is invalidated here
+        } else if !must_yield && !proc.status.is_blocked() {
+            Process::resume(proc);
+        } else if !Scheduler::take(paddr, proc.class, preempted) {
+            klog!(
+                Error,
+                Sched,
+                "Scheduler::take failed for PID {}, OOM!",
+                proc.pid()
+            );
+            paddr.destroy(proc, usize::MAX);
+            /* OOM */
+        } else {
+            drop(proc);
         }
-    });
+    }
     Scheduler::yield_hart()
 }
 
-pub fn hart_install() {
+/// Send this hart's traps to the kernel, so faults in code with an `.ex_table` entry can be
+/// recovered from before the rest of the hart is set up. Interrupts stay off.
+pub fn install_vector() {
     w_stvec(sv_trap_vec as usize);
+}
+
+pub fn hart_install() {
+    stats::hart_online();
+    install_vector();
     w_sie(SIE_SEIE | SIE_STIE | SIE_SSIE);
     unsafe { enable_intr() };
 
-    sbi::timer::set_timer(r_time() + TIMER_INTERVAL).expect("SBI Timer support is not present");
+    assert!(
+        set_timer(r_time() + TIMER_INTERVAL),
+        "SBI Timer support is not present"
+    );
 }
 
 pub fn map_trap_code(pt: &mut PageTable) -> bool {
@@ -291,21 +736,63 @@ pub fn return_to_user(_token: InterruptToken, satp: usize) -> ! {
     __ret(satp);
 }
 
-fn handle_external_intr() {
+/// The most input handled at once. More than the 16 bytes a 16550's FIFO holds, since it keeps
+/// filling while it's drained.
+const UART_BATCH: usize = 64;
+
+/// Returns true if console input arrived
+fn handle_external_intr() -> bool {
     let irq = PLIC.hart_claim();
+    stats::irq(irq.value().map(|&num| num.get()));
     let Some(num) = irq.value() else {
-        return;
+        return false;
     };
 
     if irq.is_uart0() {
-        let ch = CONS.lock().read().unwrap();
-        // println!("UART interrupt: {ch:#04x} ({})", ch as char);
-        unsafe {
-            if !CONSOLE_DEV.get().unwrap().put(ch) {
-                CONS.lock().put(0x07); // ASCII BEL
+        // the FIFO can hold more than one byte by the time we get here, and the interrupt only
+        // clears once it's empty. a paste fills it as fast as it's drained, so everything that's
+        // there is handled in one pass instead of locking everything again for every byte.
+        let mut received = false;
+        let mut batch = [0; UART_BATCH];
+        loop {
+            // the guard is dropped before anything below can print
+            let len = {
+                let mut uart = CONS.lock();
+                let mut len = 0;
+                while len < batch.len() {
+                    let (ch, errors) = uart.read_checked();
+                    stats::uart_errors(errors);
+                    let Some(ch) = ch else {
+                        break;
+                    };
+                    batch[len] = ch;
+                    len += 1;
+                }
+                len
+            };
+            if len == 0 {
+                break;
+            }
+
+            klog!(Debug, Irq, "UART interrupt: {len} bytes");
+            let cons = unsafe { CONSOLE_DEV.get().unwrap() };
+            let dropped = cons.put_all(&batch[..len]);
+            received |= dropped < len;
+            if dropped != 0 {
+                (0..dropped).for_each(|_| stats::uart_error(UartError::Dropped));
+                if cons.bell() {
+                    CONS.lock().put(0x07); // ASCII BEL
+                }
             }
         }
+
+        if received {
+            Scheduler::console_input();
+            poll::notify();
+        }
+        received
     } else {
-        println!("PLIC interrupt with unknown irq {num:#x}");
+        klog!(Warn, Irq, "PLIC interrupt with unknown irq {num:#x}");
+        false
     }
 }
@@ -0,0 +1,68 @@
+#[cfg(feature = "procfs")]
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "procfs")]
+use shared::sys::SysError;
+
+/// What values a tunable can take, and how they're written
+#[cfg_attr(not(feature = "procfs"), allow(unused))]
+pub enum Kind {
+    /// A number between the two, inclusive
+    Range(usize, usize),
+    /// One of the names, stored as its index
+    Names(&'static [&'static str]),
+}
+
+/// A kernel parameter that can be changed at runtime through `/proc/sys`. Writes are checked
+/// against its `Kind`, so whatever reads it can rely on the value being valid.
+pub struct Tunable {
+    value: AtomicUsize,
+    /// Only checked when written through `/proc/sys`
+    #[cfg_attr(not(feature = "procfs"), allow(unused))]
+    kind: Kind,
+}
+
+impl Tunable {
+    pub const fn new(value: usize, kind: Kind) -> Self {
+        Self {
+            value: AtomicUsize::new(value),
+            kind,
+        }
+    }
+
+    pub fn get(&self) -> usize {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    /// Replace the value without parsing it, returning the previous one. `value` must be valid.
+    pub fn swap(&self, value: usize) -> usize {
+        self.value.swap(value, Ordering::Relaxed)
+    }
+
+    /// Parse and set a value as written to the tunable's file
+    #[cfg(feature = "procfs")]
+    pub fn store(&self, value: &[u8]) -> Result<(), SysError> {
+        let value = core::str::from_utf8(value)
+            .map_err(|_| SysError::BadArg)?
+            .trim();
+        let value = match self.kind {
+            Kind::Range(min, max) => value
+                .parse()
+                .ok()
+                .filter(|value| (min..=max).contains(value)),
+            Kind::Names(names) => names.iter().position(|&name| name == value),
+        };
+        self.swap(value.ok_or(SysError::BadArg)?);
+        Ok(())
+    }
+
+    #[cfg(feature = "procfs")]
+    pub fn show(&self, w: &mut dyn Write) -> fmt::Result {
+        let value = self.get();
+        match self.kind {
+            Kind::Range(..) => writeln!(w, "{value}"),
+            Kind::Names(names) => writeln!(w, "{}", names.get(value).unwrap_or(&"?")),
+        }
+    }
+}
@@ -1,6 +1,13 @@
-use core::fmt::Write;
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU16, AtomicUsize, Ordering},
+};
 
-use servos::{drivers::Ns16550a, lock::SpinLocked, sbi};
+use servos::{
+    drivers::{LineErrors, Ns16550a},
+    lock::{Guard, SpinLocked},
+    sbi,
+};
 
 pub enum DebugIo {
     Sbi(SbiConsole),
@@ -8,10 +15,12 @@ pub enum DebugIo {
 }
 
 impl DebugIo {
-    pub fn read(&mut self) -> Option<u8> {
+    /// Read a byte if there is one, along with any line errors. The SBI console never reports
+    /// any.
+    pub fn read_checked(&mut self) -> (Option<u8>, LineErrors) {
         match self {
-            DebugIo::Sbi(_) => SbiConsole::read(),
-            DebugIo::Ns16550a(c) => c.read(),
+            DebugIo::Sbi(_) => (SbiConsole::read(), LineErrors::empty()),
+            DebugIo::Ns16550a(c) => c.read_checked(),
         }
     }
 
@@ -21,6 +30,22 @@ impl DebugIo {
             DebugIo::Ns16550a(c) => c.put(byte),
         }
     }
+
+    /// The UART behind the console, if it isn't the SBI console
+    pub fn uart(&mut self) -> Option<&mut Ns16550a> {
+        match self {
+            DebugIo::Sbi(_) => None,
+            DebugIo::Ns16550a(c) => Some(c),
+        }
+    }
+
+    /// Like `put`, but translates newlines the same way `write_str` does
+    fn put_translated(&mut self, byte: u8) {
+        if byte == b'\n' && matches!(self, DebugIo::Ns16550a(_)) {
+            self.put(b'\r');
+        }
+        self.put(byte);
+    }
 }
 
 impl Write for DebugIo {
@@ -34,11 +59,121 @@ impl Write for DebugIo {
 
 pub static CONS: SpinLocked<DebugIo> = SpinLocked::new(DebugIo::Sbi(SbiConsole));
 
+/// Output from interrupt or panic contexts that couldn't acquire CONS. Flushed by the next holder
+/// of the lock taken through [`lock`].
+static PENDING: PendingQueue = PendingQueue::new();
+
+const EMERGENCY_ATTEMPTS: usize = 64;
+
+/// Lock the console, first writing out anything left in the emergency queue.
+pub fn lock() -> Guard<'static, DebugIo> {
+    let mut cons = CONS.lock();
+    PENDING.drain(|b| cons.put_translated(b));
+    cons
+}
+
+/// Write all pending emergency output without taking the lock.
+///
+/// # Safety
+/// Only meant to be called while panicking, where the current hart might be the one holding CONS
+/// and no one else would ever flush the queue. Output from other harts may be interleaved.
+pub unsafe fn force_flush() {
+    let cons = unsafe { &mut *CONS.as_ptr() };
+    PENDING.drain(|b| cons.put_translated(b));
+}
+
+/// Write `bytes` straight to the console device, bypassing the lock and the emergency queue.
+///
+/// # Safety
+/// Only meant as a last resort for nested panics, where even formatting can't be trusted.
+pub unsafe fn raw_write(bytes: &[u8]) {
+    let cons = unsafe { &mut *CONS.as_ptr() };
+    bytes.iter().for_each(|&b| cons.put_translated(b));
+}
+
+/// A console writer that never blocks indefinitely, for use in contexts where CONS may already be
+/// held by the current hart (trap handlers, panics). If the lock can't be taken after a bounded
+/// number of attempts, output is pushed to a lock-free queue instead.
+pub struct EmergencyWriter;
+
+impl Write for EmergencyWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if let Some(mut cons) = CONS.try_lock_bounded(EMERGENCY_ATTEMPTS) {
+            PENDING.drain(|b| cons.put_translated(b));
+            cons.write_str(s)
+        } else {
+            // drop whatever doesn't fit, there isn't much else we can do
+            _ = s
+                .bytes()
+                .try_for_each(|b| PENDING.push(b).then_some(()).ok_or(()));
+            Ok(())
+        }
+    }
+}
+
+struct PendingQueue {
+    buf: [AtomicU16; PendingQueue::LEN],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+impl PendingQueue {
+    const LEN: usize = 512;
+    const FULL: u16 = 1 << 8;
+
+    const fn new() -> Self {
+        Self {
+            buf: [const { AtomicU16::new(0) }; Self::LEN],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, byte: u8) -> bool {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            if head - self.tail.load(Ordering::Acquire) >= Self::LEN {
+                return false;
+            }
+
+            match self.head.compare_exchange_weak(
+                head,
+                head + 1,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(cur) => head = cur,
+            }
+        }
+
+        self.buf[head % Self::LEN].store(Self::FULL | byte as u16, Ordering::Release);
+        true
+    }
+
+    /// Must only be called by one consumer at a time (normally the holder of CONS)
+    fn drain(&self, mut f: impl FnMut(u8)) {
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            // a producer may have reserved this slot without filling it yet, in which case the
+            // remainder is picked up by the next drain
+            let slot = self.buf[tail % Self::LEN].swap(0, Ordering::Acquire);
+            if slot & Self::FULL == 0 {
+                break;
+            }
+
+            f(slot as u8);
+            tail += 1;
+            self.tail.store(tail, Ordering::Release);
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! print {
     ($($arg: tt)*) => ({
         use core::fmt::Write;
-        _ = write!($crate::uart::CONS.lock(), $($arg)*);
+        _ = write!($crate::uart::lock(), $($arg)*);
     });
 }
 
@@ -46,7 +181,7 @@ macro_rules! print {
 macro_rules! println {
     ($($arg: tt)*) => ({
         use core::fmt::Write;
-        _ = writeln!($crate::uart::CONS.lock(), $($arg)*);
+        _ = writeln!($crate::uart::lock(), $($arg)*);
     });
 }
 
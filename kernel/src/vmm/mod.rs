@@ -1,10 +1,20 @@
-use core::ptr::NonNull;
+use core::{arch::asm, ptr::NonNull};
+
+use crate::isa;
 
 pub use vaddr::*;
 pub use paging::*;
+pub use zero::*;
 
 mod paging;
 mod vaddr;
+mod zero;
+
+// the user memory layout is described in terms of Sv39
+const _: () = {
+    assert!(shared::layout::PAGE_SIZE == Page::SIZE);
+    assert!(shared::layout::USER_END == VirtAddr::MAX.0);
+};
 
 #[repr(transparent)]
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -45,3 +55,72 @@ impl<T> From<NonNull<T>> for PhysAddr {
         Self(value.as_ptr() as usize)
     }
 }
+
+/// Zero `len` bytes at `dst`, using `cbo.zero` for the cache blocks it covers if the current hart
+/// has it
+///
+/// # Safety
+/// `dst` must be valid for writes of `len` bytes.
+pub unsafe fn zero(dst: *mut u8, len: usize) {
+    let block = isa::cboz_block();
+    if block == 0 {
+        unsafe { dst.write_bytes(0, len) };
+        return;
+    }
+
+    let (start, end) = (dst as usize, dst as usize + len);
+    let first = start.next_multiple_of(block).min(end);
+    let last = (end & !(block - 1)).max(first);
+    unsafe {
+        dst.write_bytes(0, first - start);
+        for addr in (first..last).step_by(block) {
+            asm!(
+                ".option push",
+                ".option arch, +zicboz",
+                "cbo.zero ({0})",
+                ".option pop",
+                in(reg) addr,
+                options(nostack),
+            );
+        }
+        dst.add(last - start).write_bytes(0, end - last);
+    }
+}
+
+/// Copy `len` bytes from `src` to `dst`, returning false instead of panicking if any access faults.
+/// Faulting instructions are registered in the exception table, so the trap handler resumes at the
+/// failure path.
+///
+/// # Safety
+/// A successful copy must not violate any aliasing rules.
+pub unsafe fn copy_nofault(dst: *mut u8, src: *const u8, len: usize) -> bool {
+    unsafe { __copy_nofault(dst, src, len) == 0 }
+}
+
+#[naked]
+unsafe extern "C" fn __copy_nofault(_dst: *mut u8, _src: *const u8, _len: usize) -> usize {
+    unsafe {
+        asm!(
+            r#"
+            add  a2, a1, a2
+        2:  beq  a1, a2, 5f
+        3:  lbu  t0, 0(a1)
+        4:  sb   t0, 0(a0)
+            addi a0, a0, 1
+            addi a1, a1, 1
+            j    2b
+        5:  li   a0, 0
+            ret
+        6:  li   a0, 1
+            ret
+
+            .pushsection .ex_table, "a"
+            .balign 8
+            .dword 3b, 6b
+            .dword 4b, 6b
+            .popsection
+            "#,
+            options(noreturn),
+        );
+    }
+}
@@ -1,8 +1,9 @@
-use core::{alloc::AllocError, mem::MaybeUninit};
+use core::{alloc::AllocError, cell::Cell, mem::MaybeUninit};
 
 use alloc::boxed::Box;
 
 use super::{PhysAddr, VirtAddr};
+use crate::audit::{self, Resource};
 
 #[repr(C, align(0x1000))]
 pub struct Page(pub [MaybeUninit<u8>; Page::SIZE]);
@@ -10,12 +11,23 @@ pub struct Page(pub [MaybeUninit<u8>; Page::SIZE]);
 impl Page {
     pub const SIZE: usize = 0x1000;
 
+    /// Usually taken from the pool idle harts fill, so it doesn't need zeroing here
     pub fn zeroed() -> Result<Box<Page>, AllocError> {
-        Box::try_new_zeroed().map(|page| unsafe { page.assume_init() })
+        let page = super::take_zeroed();
+        super::count(page.is_some());
+        if let Some(page) = page {
+            return Ok(page);
+        }
+
+        let mut page = Self::uninit()?;
+        unsafe { super::zero(page.0.as_mut_ptr().cast(), Page::SIZE) };
+        Ok(page)
     }
 
     pub fn uninit() -> Result<Box<Page>, AllocError> {
-        Box::try_new_uninit().map(|page| unsafe { page.assume_init() })
+        Box::try_new_uninit()
+            .map(|page| unsafe { page.assume_init() })
+            .or_else(|err| super::take_zeroed().ok_or(err))
     }
 
     pub unsafe fn cast<T>(&mut self) -> &mut T {
@@ -24,6 +36,10 @@ impl Page {
     }
 }
 
+/// Mapped read only in place of untouched anonymous memory, so it doesn't need a page of its own
+/// until it's written
+static ZERO_PAGE: Page = Page([MaybeUninit::new(0); Page::SIZE]);
+
 #[inline(always)]
 pub const fn page_number(addr: usize) -> usize {
     addr & !(Page::SIZE - 1)
@@ -60,7 +76,9 @@ bitflags::bitflags! {
         /// Marks the page as owned by the page table, meaning it will be freed with the PageTable.
         /// Pages marked with this bit must have been allocated with the layout of a Page.
         const Owned = 1 << 8;
-        const Rsw1 = 1 << 9;
+        /// Maps the zero page in place of a writable page. W is clear until the first write, which
+        /// replaces it with a private page.
+        const Zero = 1 << 9;
 
         const Rw  = (1 << 1) | (1 << 2);
         const Rx  = (1 << 1) | (1 << 3);
@@ -125,8 +143,9 @@ impl PageTableEntry {
         self.0 & Pte::Owned.bits() != 0
     }
 
-    pub const fn rsw1(self) -> bool {
-        self.0 & Pte::Rsw1.bits() != 0
+    /// Is this a writable mapping that still points to the zero page
+    pub const fn is_zero(self) -> bool {
+        self.0 & Pte::Zero.bits() != 0
     }
 
     pub const fn is_leaf(self) -> bool {
@@ -149,16 +168,22 @@ impl PageTableEntry {
     }
 }
 
+/// Entries are in `Cell`s so zero pages can be replaced through a shared reference, like the ones
+/// user copies are made with
 #[repr(C, align(0x1000))] // Page::SIZE
-pub struct PageTable(pub(super) [PageTableEntry; 512]);
+pub struct PageTable(pub(super) [Cell<PageTableEntry>; 512]);
 
 impl PageTable {
     pub const fn new() -> Self {
-        PageTable([PageTableEntry(0); 512])
+        PageTable([const { Cell::new(PageTableEntry(0)) }; 512])
     }
 
     pub fn try_alloc() -> Result<Box<PageTable>, AllocError> {
-        Box::<PageTable>::try_new_zeroed().map(|ptr| unsafe { ptr.assume_init() })
+        let mut pt = Box::<PageTable>::try_new_uninit()?;
+        unsafe {
+            super::zero(pt.as_mut_ptr().cast(), size_of::<PageTable>());
+            Ok(pt.assume_init())
+        }
     }
 
     /// Map a page that will be freed when the page table is dropped
@@ -206,6 +231,59 @@ impl PageTable {
         true
     }
 
+    /// Map the zero page over every page in `va` to `va + size`. Writable pages get a page of their
+    /// own the first time they're written, until then they cost no memory.
+    pub fn map_zero_pages(&mut self, va: VirtAddr, size: usize, perms: Pte) -> bool {
+        assert!(perms.intersects(Pte::Rwx));
+        assert!(size != 0);
+
+        let [first, last] = [page_number(va.0), page_number(va.0.wrapping_add(size) - 1)];
+        if !(first < VirtAddr::MAX.0 && last < VirtAddr::MAX.0 && first <= last) {
+            return false;
+        }
+        let perms = if perms.contains(Pte::W) {
+            (perms - Pte::W) | Pte::Zero
+        } else {
+            perms
+        };
+        let pa = PhysAddr::from(&ZERO_PAGE as *const Page);
+        for page in (first..=last).step_by(Page::SIZE) {
+            if !self.map_page_raw(pa, VirtAddr(page), perms - Pte::Owned) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Give `va` a zeroed page of its own if it's mapped to the zero page, restoring W if the
+    /// mapping is writable. Returns false if `va` isn't mapped to the zero page.
+    pub fn unshare(&self, va: VirtAddr) -> Result<bool, AllocError> {
+        let Some(entry) = self.leaf(va) else {
+            return Ok(false);
+        };
+        let old = entry.get();
+        if !matches!(old.next(), PteLink::Leaf(pa) if core::ptr::eq(pa.cast(), &ZERO_PAGE)) {
+            return Ok(false);
+        }
+
+        let mut perms = old.perms() | Pte::Owned;
+        if old.is_zero() {
+            perms = (perms - Pte::Zero) | Pte::W;
+        }
+        let page = Box::into_raw(Page::zeroed()?);
+        entry.set(PageTableEntry::new(page.into(), perms.bits()));
+        Ok(true)
+    }
+
+    /// Handle a page fault from user mode writing to `va`. Returns true if it was the first write
+    /// to a writable zero page mapping, which now has its own page, so the write can be retried.
+    pub fn write_fault(&self, va: VirtAddr) -> bool {
+        va < VirtAddr::MAX
+            && self.leaf(va).is_some_and(|entry| entry.get().is_zero())
+            && self.unshare(va).unwrap_or(false)
+    }
+
     /// Identity map all pages in the contigous physical range `pa` to `pa + size`. Only intended
     /// for use by the kernel.
     pub fn map_identity(
@@ -231,69 +309,96 @@ impl PageTable {
     }
 
     pub fn unmap_page(&mut self, va: VirtAddr) -> bool {
+        let Some(entry) = self.leaf(va) else {
+            return false;
+        };
+        if let PteLink::Leaf(page) = entry.get().next() {
+            if entry.get().is_owned() {
+                drop(unsafe { Box::from_raw(page as *mut Page) });
+            }
+        }
+        entry.set(PageTableEntry(0));
+        true
+    }
+
+    /// How many pages are freed along with this table: itself, the tables below it and the owned
+    /// pages they map
+    pub fn pages_owned(&self) -> usize {
+        1 + self
+            .0
+            .iter()
+            .map(|entry| match entry.get().next() {
+                PteLink::PageTable(pt) => unsafe { &*pt }.pages_owned(),
+                PteLink::Leaf(_) => entry.get().is_owned() as usize,
+                PteLink::Invalid => 0,
+            })
+            .sum::<usize>()
+    }
+
+    pub fn make_satp(this: *const PageTable) -> usize {
+        ((SATP_MODE_SV39 as usize) << 60) | (this as usize >> 12)
+    }
+
+    /// The valid level 0 entry for `va`, if there is one
+    fn leaf(&self, va: VirtAddr) -> Option<&Cell<PageTableEntry>> {
         let mut pt = self;
         for level in (0..SV39_LEVELS).rev() {
-            let entry = &mut pt.0[va.vpn(level)];
-            match entry.next() {
-                PteLink::PageTable(next) => pt = unsafe { &mut *next },
-                PteLink::Leaf(page) => {
+            let entry = &pt.0[va.vpn(level)];
+            match entry.get().next() {
+                PteLink::PageTable(next) => pt = unsafe { &*next },
+                PteLink::Leaf(_) => {
                     assert!(level == 0, "Page table level {level} is a leaf node");
-                    if entry.is_owned() {
-                        drop(unsafe { Box::from_raw(page as *mut Page) });
-                    }
-                    *entry = PageTableEntry(0);
-                    return true;
+                    return Some(entry);
                 }
                 PteLink::Invalid => break,
             }
         }
 
-        false
-    }
-
-    pub fn make_satp(this: *const PageTable) -> usize {
-        ((SATP_MODE_SV39 as usize) << 60) | (this as usize >> 12)
+        None
     }
 
     fn map_page_raw(&mut self, pa: PhysAddr, va: VirtAddr, perms: Pte) -> bool {
         let mut pt = self;
         for level in (1..SV39_LEVELS).rev() {
-            let entry = &mut pt.0[va.vpn(level)];
-            match entry.next() {
+            let entry = &pt.0[va.vpn(level)];
+            match entry.get().next() {
                 PteLink::PageTable(next) => pt = unsafe { &mut *next },
                 PteLink::Leaf(_) => panic!("Page table {level} is a leaf node"),
                 PteLink::Invalid => {
                     let Ok(next) = Self::try_alloc().map(Box::into_raw) else {
                         return false;
                     };
-                    *entry = PageTableEntry::new(next.into(), 0);
+                    entry.set(PageTableEntry::new(next.into(), 0));
                     pt = unsafe { &mut *next };
                 }
             }
         }
 
-        let entry = &mut pt.0[va.vpn(0)];
+        let entry = &pt.0[va.vpn(0)];
         assert!(
-            matches!(entry.next(), PteLink::Invalid),
+            matches!(entry.get().next(), PteLink::Invalid),
             "remapping virtual addr (was {:?})",
-            entry.next(),
+            entry.get().next(),
         );
         // the A and D bits can be treated as secondary R and W bits on some boards
-        *entry = PageTableEntry::new(pa, (perms | Pte::D | Pte::A).bits());
+        entry.set(PageTableEntry::new(pa, (perms | Pte::D | Pte::A).bits()));
         true
     }
 }
 
 impl Drop for PageTable {
     fn drop(&mut self) {
-        for &entry in self.0.iter() {
+        let mut freed = 1;
+        for entry in self.0.iter().map(Cell::get) {
             match entry.next() {
                 PteLink::PageTable(pt) => drop(unsafe { Box::from_raw(pt) }),
                 PteLink::Leaf(page) if entry.is_owned() => {
                     drop(unsafe { Box::from_raw(page as *mut Page) });
+                    freed += 1;
                 }
                 _ => {}
             }
         }
+        audit::released(Resource::Pages, freed);
     }
 }
@@ -2,7 +2,9 @@ use core::{marker::PhantomData, mem::MaybeUninit, ops::Range};
 
 use shared::sys::SysError;
 
-use super::{page_number, page_offset, Page, PageTable, PhysAddr, Pte, PteLink, SV39_LEVELS};
+use super::{
+    copy_nofault, page_number, page_offset, Page, PageTable, PhysAddr, Pte, PteLink, SV39_LEVELS,
+};
 
 /// Sv39 Virtual Address
 ///
@@ -25,7 +27,7 @@ impl VirtAddr {
     /// bits from `perms`.
     pub fn to_phys(self, mut pt: &PageTable, perms: Pte) -> Result<PhysAddr, VirtToPhysErr> {
         for level in (0..SV39_LEVELS).rev() {
-            let entry = pt.0[self.vpn(level)];
+            let entry = pt.0[self.vpn(level)].get();
             match entry.next() {
                 PteLink::PageTable(next) => pt = unsafe { &*next },
                 PteLink::Leaf(addr) if entry.perms().contains(perms) => {
@@ -38,18 +40,25 @@ impl VirtAddr {
     }
 
     /// Copy all of `buf` into address `self` in page table `pt`. Fails if any pages are not
-    /// writable or accessible from user mode. May fail after a partial write.
+    /// writable or accessible from user mode, or if the backing memory faults when accessed. May
+    /// fail after a partial write.
     pub fn copy_to(
         self,
         pt: &PageTable,
         mut buf: &[u8],
         perms: Option<Pte>,
     ) -> Result<(), VirtToPhysErr> {
-        for phys in self.iter_phys(pt, buf.len(), perms.unwrap_or(Pte::U | Pte::W)) {
+        let phys = PhysIter {
+            unshare: true,
+            ..self.iter_phys(pt, buf.len(), perms.unwrap_or(Pte::U | Pte::W))
+        };
+        for phys in phys {
             let phys = phys?;
             unsafe {
                 let len = phys.end.sub_ptr(phys.start);
-                core::ptr::copy_nonoverlapping(buf.as_ptr(), phys.start, len);
+                if !copy_nofault(phys.start, buf.as_ptr(), len) {
+                    return Err(VirtToPhysErr);
+                }
                 buf = &buf[len..];
             }
         }
@@ -58,7 +67,8 @@ impl VirtAddr {
     }
 
     /// Copy `buf.len()` bytes from address `self` in page table `pt`. Fails if any pages are not
-    /// readable or accessible from user mode. May fail after a partial write.
+    /// readable or accessible from user mode, or if the backing memory faults when accessed. May
+    /// fail after a partial write.
     pub fn copy_from(
         self,
         pt: &PageTable,
@@ -68,7 +78,9 @@ impl VirtAddr {
             let phys = phys?;
             unsafe {
                 let len = phys.end.sub_ptr(phys.start);
-                core::ptr::copy_nonoverlapping(phys.start, buf.as_mut_ptr().cast(), len);
+                if !copy_nofault(buf.as_mut_ptr().cast(), phys.start, len) {
+                    return Err(VirtToPhysErr);
+                }
                 buf = &mut buf[len..];
             }
         }
@@ -91,12 +103,15 @@ impl VirtAddr {
         self.copy_to(pt, s, None)
     }
 
+    /// Iterate over the physical ranges backing `size` bytes at `self`. If `perms` has W, pages
+    /// mapped to the zero page are given their own first, so the ranges can be written.
     pub fn iter_phys(self, pt: &PageTable, size: usize, perms: Pte) -> PhysIter {
         PhysIter {
             va: self,
             size,
             pt,
             perms,
+            unshare: perms.contains(Pte::W),
         }
     }
 
@@ -177,14 +192,14 @@ pub struct PhysIter<'a> {
     pt: &'a PageTable,
     size: usize,
     perms: Pte,
+    unshare: bool,
 }
 
 impl PhysIter<'_> {
     pub fn zero(self) {
         for page in self {
-            unsafe {
-                core::slice::from_mut_ptr_range(page.unwrap()).fill(0);
-            }
+            let page = page.unwrap();
+            unsafe { super::zero(page.start, page.end.offset_from(page.start) as usize) };
         }
     }
 }
@@ -197,6 +212,10 @@ impl Iterator for PhysIter<'_> {
             return None;
         }
 
+        if self.unshare && self.pt.unshare(self.va).is_err() {
+            return Some(Err(VirtToPhysErr));
+        }
+
         // TODO: mega/gigapage
         let phys = match self.va.to_phys(self.pt, self.perms) {
             Ok(phys) => phys,
@@ -234,6 +253,10 @@ impl<T: Copy> User<T> {
     pub fn read_nth(self, pt: &PageTable, n: usize) -> Result<T, VirtToPhysErr> {
         (self.0 + n * core::mem::size_of::<T>()).copy_type_from(pt)
     }
+
+    pub fn write_nth(self, pt: &PageTable, n: usize, val: &T) -> Result<(), VirtToPhysErr> {
+        (self.0 + n * core::mem::size_of::<T>()).copy_type_to(pt, val)
+    }
 }
 
 impl<T: Copy> From<VirtAddr> for User<T> {
@@ -0,0 +1,230 @@
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use alloc::boxed::Box;
+use servos::{
+    lock::SpinLocked,
+    riscv::{r_time, TIMEBASE_FREQ},
+};
+
+use super::Page;
+use crate::{
+    klog,
+    tunable::{Kind, Tunable},
+};
+
+/// How many zeroed pages idle harts keep ready. They can't be used for anything else, but
+/// `Page::uninit` takes them back if the heap runs out.
+const POOL_LEN: usize = 64;
+
+struct Pool {
+    pages: [Option<Box<Page>>; POOL_LEN],
+    len: usize,
+}
+
+static POOL: SpinLocked<Pool> = SpinLocked::new(Pool {
+    pages: [const { None }; POOL_LEN],
+    len: 0,
+});
+
+/// How many times `Page::zeroed` found a page in the pool, or had to zero one itself
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Take a page from the pool, which is already zeroed
+pub(super) fn take_zeroed() -> Option<Box<Page>> {
+    let mut pool = POOL.lock();
+    let len = pool.len.checked_sub(1)?;
+    pool.len = len;
+    pool.pages[len].take()
+}
+
+/// Count a call to `Page::zeroed`
+pub(super) fn count(hit: bool) {
+    if hit {
+        HITS.fetch_add(1, Ordering::Relaxed);
+    } else {
+        MISSES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Zero one page for the pool, if it isn't full. Called by harts with nothing else to do, so
+/// zeroing pages isn't in the way of spawning processes or handling faults.
+pub fn fill_zero_pool() {
+    if POOL.lock().len == POOL_LEN {
+        return;
+    }
+    let Ok(mut page) = Page::uninit() else {
+        return;
+    };
+    // the page is zeroed without the lock held, since another hart may want a page meanwhile
+    unsafe { super::zero(page.0.as_mut_ptr().cast(), Page::SIZE) };
+
+    let mut pool = POOL.lock();
+    if pool.len < POOL_LEN {
+        let len = pool.len;
+        pool.pages[len] = Some(page);
+        pool.len += 1;
+    }
+}
+
+/// How often an idle hart checks that a page in the pool is still all zeroes, in milliseconds, or 0
+/// to never check. With `page-poison`, a few free pages are checked for their poison each time as
+/// well. Otherwise pool pages are the only free memory with known contents, so this is the only
+/// way a bit flip or a stray write to free memory is noticed before the page is handed out.
+pub static SCRUB_INTERVAL_MS: Tunable = Tunable::new(0, Kind::Range(0, 60_000));
+
+/// When the next page is due to be scrubbed, in timer ticks
+static NEXT_SCRUB: AtomicUsize = AtomicUsize::new(0);
+/// Which slot of the pool is scrubbed next, wrapped to the pool's length when it's used
+static SCRUB_CURSOR: AtomicUsize = AtomicUsize::new(0);
+static SCRUBBED: AtomicU64 = AtomicU64::new(0);
+static SCRUB_ERRORS: AtomicU64 = AtomicU64::new(0);
+/// How many poisoned free pages are checked on each turn, after the pool page
+#[cfg(feature = "page-poison")]
+const FREE_SCRUB_BATCH: usize = 8;
+
+/// Check one page of the pool, and with `page-poison` a few free pages, if scrubbing is on and it's
+/// been long enough since the last time. A page that changed is taken out and never used again,
+/// since on real hardware the same memory is likely to go bad again.
+pub fn scrub_zero_pool() {
+    let interval = SCRUB_INTERVAL_MS.get();
+    let now = r_time();
+    let next = NEXT_SCRUB.load(Ordering::Relaxed);
+    // only one hart gets each turn
+    if interval == 0
+        || now < next
+        || NEXT_SCRUB
+            .compare_exchange(
+                next,
+                now + interval * (TIMEBASE_FREQ / 1000),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_err()
+    {
+        return;
+    }
+
+    scrub_pool_page();
+    #[cfg(feature = "page-poison")]
+    scrub_free_pages();
+}
+
+fn scrub_pool_page() {
+    let (page, offset) = {
+        let mut pool = POOL.lock();
+        if pool.len == 0 {
+            return;
+        }
+
+        let i = SCRUB_CURSOR.fetch_add(1, Ordering::Relaxed) % pool.len;
+        SCRUBBED.fetch_add(1, Ordering::Relaxed);
+        let page = pool.pages[i].as_ref().unwrap();
+        // Safety: every page in the pool has been zeroed
+        let words =
+            unsafe { core::slice::from_raw_parts(page.0.as_ptr().cast::<u64>(), Page::SIZE / 8) };
+        let Some(word) = words.iter().position(|&w| w != 0) else {
+            return;
+        };
+
+        // keep the pool packed by moving the last page into the hole
+        let last = pool.len - 1;
+        pool.pages.swap(i, last);
+        pool.len = last;
+        SCRUB_ERRORS.fetch_add(1, Ordering::Relaxed);
+        (pool.pages[last].take().unwrap(), word * 8)
+    };
+
+    klog!(
+        Error,
+        Vmm,
+        "free page {:?} changed at offset {offset:#x}, retiring it",
+        page.0.as_ptr()
+    );
+    core::mem::forget(page);
+}
+
+#[cfg(feature = "page-poison")]
+fn scrub_free_pages() {
+    let result = crate::ALLOCATOR.lock().scrub_pages(FREE_SCRUB_BATCH);
+    match result {
+        Ok(checked) => {
+            SCRUBBED.fetch_add(checked as u64, Ordering::Relaxed);
+        }
+        Err((page, offset)) => {
+            SCRUBBED.fetch_add(1, Ordering::Relaxed);
+            SCRUB_ERRORS.fetch_add(1, Ordering::Relaxed);
+            // the allocator has already dropped the page, and nothing else has a pointer to it
+            klog!(
+                Error,
+                Vmm,
+                "free page {page:?} changed at offset {offset:#x}, retiring it"
+            );
+        }
+    }
+}
+
+/// Write to the page of the pool that's scrubbed next, returning its address. The pool is filled
+/// first, so idle harts leave it alone and the page stays where it is. Returns `None` if there
+/// wasn't enough memory to fill it.
+#[cfg(feature = "ktest")]
+pub fn dirty_pool_page() -> Option<*const Page> {
+    for _ in 0..POOL_LEN {
+        fill_zero_pool();
+    }
+
+    let mut pool = POOL.lock();
+    if pool.len < POOL_LEN {
+        return None;
+    }
+    let i = SCRUB_CURSOR.load(Ordering::Relaxed) % pool.len;
+    let page = pool.pages[i].as_mut().unwrap();
+    page.0[Page::SIZE - 1].write(1);
+    Some(&**page)
+}
+
+/// Run one turn of `scrub_zero_pool` now, without turning on `SCRUB_INTERVAL_MS` for idle harts
+#[cfg(feature = "ktest")]
+pub fn scrub_now() {
+    scrub_pool_page();
+    #[cfg(feature = "page-poison")]
+    scrub_free_pages();
+}
+
+/// Whether `page` is in the pool, and how many pages scrubbing has retired
+#[cfg(feature = "ktest")]
+pub fn scrub_state(page: *const Page) -> (bool, u64) {
+    let pool = POOL.lock();
+    let found = pool.pages[..pool.len]
+        .iter()
+        .any(|p| p.as_deref().is_some_and(|p| core::ptr::eq(p, page)));
+    (found, SCRUB_ERRORS.load(Ordering::Relaxed))
+}
+
+/// `/proc/meminfo`
+#[cfg(feature = "procfs")]
+pub fn show_meminfo(w: &mut dyn core::fmt::Write) -> core::fmt::Result {
+    let (heap, added) = {
+        let alloc = crate::ALLOCATOR.lock();
+        (alloc.range(), alloc.added())
+    };
+    writeln!(
+        w,
+        "heap:             {} kB",
+        (heap.end as usize - heap.start as usize + added) / 1024
+    )?;
+    writeln!(w, "reclaimed:        {} kB", added / 1024)?;
+    writeln!(w, "zero_pool:        {} pages", POOL.lock().len)?;
+    writeln!(w, "zero_pool_hits:   {}", HITS.load(Ordering::Relaxed))?;
+    writeln!(w, "zero_pool_misses: {}", MISSES.load(Ordering::Relaxed))?;
+    writeln!(
+        w,
+        "scrubbed:         {} pages",
+        SCRUBBED.load(Ordering::Relaxed)
+    )?;
+    writeln!(
+        w,
+        "scrub_errors:     {}",
+        SCRUB_ERRORS.load(Ordering::Relaxed)
+    )
+}
@@ -1,12 +1,14 @@
+import os
 import sys
 import pathlib
 import struct
 
 MAGIC        = 0xce3fdefe
 MAX_DIR_NAME = 32
+MODE_EXEC    = 1 << 0
 
 class INode:
-    def __init__(self, name: str, isdir: bool, size: int, addr: int):
+    def __init__(self, name: str, isdir: bool, size: int, addr: int, mode: int = 0):
         if len(name) > MAX_DIR_NAME:
             raise Exception(f"name `{name}`, max is {MAX_DIR_NAME} characters")
 
@@ -14,9 +16,10 @@ class INode:
         self.dir  = isdir
         self.size = size
         self.addr = addr
+        self.mode = mode
 
     def serialize_data(self):
-        return struct.pack("<HHIQ", len(self.name), int(self.dir), self.size, self.addr)
+        return struct.pack("<HBBIQ", len(self.name), int(self.dir), self.mode, self.size, self.addr)
 
 class ParentINode(INode):
     def __init__(self, parent: INode):
@@ -24,7 +27,7 @@ class ParentINode(INode):
         INode.__init__(self, "..", True, 0, 0)
 
     def serialize_data(self):
-        return struct.pack("<HHIQ", len(self.name), int(self.dir), self.parent.size, self.parent.addr)
+        return struct.pack("<HBBIQ", len(self.name), int(self.dir), 0, self.parent.size, self.parent.addr)
 
 def adddir(inodes: list[INode], data: bytearray, path: pathlib.Path, parent: int) -> int:
     def append_inode(inode: INode):
@@ -38,8 +41,9 @@ def adddir(inodes: list[INode], data: bytearray, path: pathlib.Path, parent: int
         if child.is_dir():
             children.append(adddir(inodes, data, child, ino))
         elif child.is_file():
-            buf = child.read_bytes()
-            children.append(append_inode(INode(child.name, False, len(buf), len(data))))
+            buf  = child.read_bytes()
+            mode = MODE_EXEC if os.access(child, os.X_OK) else 0
+            children.append(append_inode(INode(child.name, False, len(buf), len(data), mode)))
             data.extend(buf)
 
     inodes[ino].size = len(children)
@@ -0,0 +1,94 @@
+//! Programs for the kernel's filter VM, which can be attached to syscall entry and exit to count
+//! events, or installed as a process' syscall filter. Programs are checked when they're loaded:
+//! jumps only go forward and the last instruction returns, so every program finishes within
+//! `MAX_INSNS` steps.
+
+/// The longest program the kernel accepts
+pub const MAX_INSNS: usize = 64;
+/// Registers are 64 bits and start out zeroed. `r0` is the result when the program returns.
+pub const REGS: usize = 8;
+/// Counters in each attached filter's map, added to with `Op::MapAdd`
+pub const MAP_LEN: usize = 16;
+
+/// Where a filter runs
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Hook {
+    /// Before a syscall runs
+    SyscallEntry = 1,
+    /// After a syscall, with its result
+    SyscallExit,
+}
+
+/// What `Op::Load` can read about the event
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Field {
+    /// The `Sys` number
+    Syscall,
+    Arg0,
+    Arg1,
+    Arg2,
+    Arg3,
+    Arg4,
+    Arg5,
+    Pid,
+    /// The value returned, or 0 before the syscall has run or if it failed
+    Result,
+    /// The `SysError` returned, or 0 before the syscall has run or if it succeeded
+    Error,
+}
+
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Op {
+    /// `dst = imm`
+    LoadImm = 1,
+    /// `dst =` the `Field` numbered `imm`
+    Load,
+    /// `dst = src`
+    Mov,
+    /// `dst = dst + src`, and likewise for the rest, wrapping on overflow
+    Add,
+    Sub,
+    Mul,
+    And,
+    Or,
+    Xor,
+    /// Shifts by `src` modulo 64
+    Shl,
+    Shr,
+    /// Skip the next `imm` instructions if `dst == src`
+    Jeq,
+    Jne,
+    /// Unsigned comparisons
+    Jlt,
+    Jge,
+    /// Add `src` to counter `dst` of the map. Ignored if `dst` is out of range, or when running
+    /// as a syscall filter.
+    MapAdd,
+    /// Stop with `r0` as the result
+    Ret,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Insn {
+    pub op: u8,
+    pub dst: u8,
+    pub src: u8,
+    pub _reserved: u8,
+    pub imm: u32,
+}
+
+impl Insn {
+    pub const fn new(op: Op, dst: u8, src: u8, imm: u32) -> Self {
+        Self {
+            op: op as u8,
+            dst,
+            src,
+            _reserved: 0,
+            imm,
+        }
+    }
+}
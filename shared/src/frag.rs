@@ -0,0 +1,310 @@
+//! IPv4 reassembly, kept apart from the kernel's network stack so it can be tested on the host
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{net::Ipv4Addr, ops::Range};
+
+/// The most memory all incomplete datagrams together may use
+const REASSEMBLY_LIMIT: usize = 256 * 1024;
+/// The most datagrams that may be reassembled at once. The oldest is dropped to make room.
+const MAX_PARTIAL: usize = 16;
+/// The largest payload that fits in an IPv4 packet with a 20 byte header
+const MAX_DATAGRAM: usize = u16::MAX as usize - 20;
+
+/// Fragments are grouped by source, destination, protocol and identification
+pub type Key = (Ipv4Addr, Ipv4Addr, u8, u16);
+
+struct Partial {
+    data: Vec<u8>,
+    /// The byte ranges of `data` that have arrived, sorted and not overlapping or touching
+    received: Vec<Range<usize>>,
+    /// Known once the last fragment arrives
+    total: Option<usize>,
+    expires: usize,
+}
+
+impl Partial {
+    fn add_range(&mut self, mut new: Range<usize>) -> Option<()> {
+        // absorb every range that overlaps or touches the new one
+        let first = self.received.partition_point(|r| r.end < new.start);
+        let last = self.received.partition_point(|r| r.start <= new.end);
+        if first < last {
+            new.start = new.start.min(self.received[first].start);
+            new.end = new.end.max(self.received[last - 1].end);
+        }
+
+        self.received.try_reserve(1).ok()?;
+        self.received.splice(first..last, [new]);
+        Some(())
+    }
+
+    fn complete(&self) -> bool {
+        self.total
+            .is_some_and(|total| self.received.first() == Some(&(0..total)))
+    }
+}
+
+pub struct Reassembler {
+    partial: BTreeMap<Key, Partial>,
+    used: usize,
+    /// How long to wait for the rest of a datagram after its first fragment arrives
+    timeout: usize,
+}
+
+impl Reassembler {
+    pub const fn new(timeout: usize) -> Self {
+        Self {
+            partial: BTreeMap::new(),
+            used: 0,
+            timeout,
+        }
+    }
+
+    /// Add the fragment of datagram `key` at byte `offset`, returning the whole payload once every
+    /// fragment of the datagram is in. `more` is set on every fragment but the last. Fragments
+    /// that are malformed or don't fit under the memory limit are dropped, and a datagram whose
+    /// fragments disagree about its length is dropped entirely.
+    pub fn insert(
+        &mut self,
+        key: Key,
+        offset: usize,
+        more: bool,
+        payload: &[u8],
+        now: usize,
+    ) -> Option<Vec<u8>> {
+        let end = offset + payload.len();
+        if end > MAX_DATAGRAM || (more && !payload.len().is_multiple_of(8)) {
+            return None;
+        }
+
+        let new = !self.partial.contains_key(&key);
+        if new {
+            if self.partial.len() >= MAX_PARTIAL {
+                let oldest = self
+                    .partial
+                    .iter()
+                    .min_by_key(|(_, p)| p.expires)
+                    .map(|(&k, _)| k)?;
+                self.remove(&oldest);
+            }
+
+            // TODO: alloc failure
+            self.partial.insert(
+                key,
+                Partial {
+                    data: Vec::new(),
+                    received: Vec::new(),
+                    total: None,
+                    expires: now + self.timeout,
+                },
+            );
+        }
+
+        let partial = self.partial.get_mut(&key)?;
+        let consistent = match partial.total {
+            Some(total) => end <= total && (more || end == total),
+            None => more || partial.received.last().is_none_or(|r| r.end <= end),
+        };
+        if !consistent {
+            self.remove(&key);
+            return None;
+        }
+
+        let grow = end.saturating_sub(partial.data.len());
+        if self.used + grow > REASSEMBLY_LIMIT || partial.data.try_reserve(grow).is_err() {
+            // a datagram that couldn't store its first fragment has nothing worth keeping
+            if new {
+                self.remove(&key);
+            }
+            return None;
+        }
+        if grow != 0 {
+            partial.data.resize(end, 0);
+            self.used += grow;
+        }
+
+        partial.data[offset..end].copy_from_slice(payload);
+        if !more {
+            partial.total = Some(end);
+        }
+        partial.add_range(offset..end)?;
+        if !partial.complete() {
+            return None;
+        }
+
+        self.remove(&key)
+    }
+
+    /// Drop every datagram that has waited too long for its fragments
+    pub fn expire(&mut self, now: usize) {
+        let used = &mut self.used;
+        self.partial.retain(|_, p| {
+            if now < p.expires {
+                return true;
+            }
+            *used -= p.data.len();
+            false
+        });
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Vec<u8>> {
+        let partial = self.partial.remove(key)?;
+        self.used -= partial.data.len();
+        Some(partial.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: usize = 100;
+
+    fn key(id: u16) -> Key {
+        (
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(10, 0, 0, 2),
+            17,
+            id,
+        )
+    }
+
+    /// `len` bytes that are different at every offset, so misplaced data shows
+    fn datagram(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn in_order() {
+        let mut r = Reassembler::new(TIMEOUT);
+        let data = datagram(40);
+        assert_eq!(r.insert(key(1), 0, true, &data[..16], 0), None);
+        assert_eq!(r.insert(key(1), 16, true, &data[16..32], 0), None);
+        assert_eq!(r.insert(key(1), 32, false, &data[32..], 0), Some(data));
+        assert_eq!((r.partial.len(), r.used), (0, 0));
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut r = Reassembler::new(TIMEOUT);
+        let data = datagram(40);
+        assert_eq!(r.insert(key(1), 32, false, &data[32..], 0), None);
+        assert_eq!(r.insert(key(1), 0, true, &data[..16], 0), None);
+        assert_eq!(r.insert(key(1), 16, true, &data[16..32], 0), Some(data));
+        assert_eq!(r.used, 0);
+    }
+
+    #[test]
+    fn overlap() {
+        let mut r = Reassembler::new(TIMEOUT);
+        let data = datagram(48);
+        assert_eq!(r.insert(key(1), 0, true, &data[..24], 0), None);
+        assert_eq!(r.insert(key(1), 16, true, &data[16..40], 0), None);
+        assert_eq!(r.partial[&key(1)].received.first(), Some(&(0..40)));
+        assert_eq!(r.insert(key(1), 8, false, &data[8..], 0), Some(data));
+    }
+
+    #[test]
+    fn duplicate() {
+        let mut r = Reassembler::new(TIMEOUT);
+        let data = datagram(24);
+        assert_eq!(r.insert(key(1), 0, true, &data[..16], 0), None);
+        assert_eq!(r.insert(key(1), 0, true, &data[..16], 0), None);
+        assert_eq!(r.used, 16);
+        assert_eq!(
+            r.insert(key(1), 16, false, &data[16..], 0),
+            Some(data.clone())
+        );
+
+        // a duplicate arriving after the datagram was delivered starts over
+        assert_eq!(r.insert(key(1), 16, false, &data[16..], 0), None);
+        assert_eq!(r.partial.len(), 1);
+    }
+
+    #[test]
+    fn length_mismatch() {
+        let mut r = Reassembler::new(TIMEOUT);
+        let data = datagram(48);
+        // a fragment past the end the last one gave
+        assert_eq!(r.insert(key(1), 0, true, &data[..8], 0), None);
+        assert_eq!(r.insert(key(1), 16, false, &data[16..24], 0), None);
+        assert_eq!(r.insert(key(1), 24, true, &data[24..40], 0), None);
+        assert_eq!((r.partial.len(), r.used), (0, 0));
+
+        // a last fragment that ends before data already received
+        assert_eq!(r.insert(key(2), 16, true, &data[16..32], 0), None);
+        assert_eq!(r.insert(key(2), 0, false, &data[..8], 0), None);
+        assert_eq!((r.partial.len(), r.used), (0, 0));
+
+        // two last fragments that disagree
+        assert_eq!(r.insert(key(3), 8, false, &data[8..16], 0), None);
+        assert_eq!(r.insert(key(3), 16, false, &data[16..24], 0), None);
+        assert_eq!((r.partial.len(), r.used), (0, 0));
+    }
+
+    #[test]
+    fn malformed() {
+        let mut r = Reassembler::new(TIMEOUT);
+        let data = datagram(MAX_DATAGRAM + 8);
+        // only the last fragment may have a length that isn't a multiple of 8
+        assert_eq!(r.insert(key(1), 0, true, &data[..12], 0), None);
+        assert_eq!(r.insert(key(1), MAX_DATAGRAM, false, &data[..8], 0), None);
+        assert_eq!((r.partial.len(), r.used), (0, 0));
+    }
+
+    #[test]
+    fn expiry() {
+        let mut r = Reassembler::new(TIMEOUT);
+        let data = datagram(24);
+        assert_eq!(r.insert(key(1), 0, true, &data[..16], 10), None);
+        assert_eq!(r.insert(key(2), 0, true, &data[..16], 50), None);
+        r.expire(10 + TIMEOUT - 1);
+        assert_eq!(r.partial.len(), 2);
+        r.expire(10 + TIMEOUT);
+        assert_eq!((r.partial.len(), r.used), (1, 16));
+
+        // the rest of the expired datagram isn't enough on its own
+        assert_eq!(r.insert(key(1), 16, false, &data[16..], 10 + TIMEOUT), None);
+        assert_eq!(
+            r.insert(key(2), 16, false, &data[16..], 10 + TIMEOUT),
+            Some(data)
+        );
+    }
+
+    #[test]
+    fn oldest_dropped_for_room() {
+        let mut r = Reassembler::new(TIMEOUT);
+        let data = datagram(16);
+        for id in 0..MAX_PARTIAL as u16 {
+            assert_eq!(r.insert(key(id), 0, true, &data[..8], id as usize), None);
+        }
+        assert_eq!(r.insert(key(100), 0, true, &data[..8], 100), None);
+        assert_eq!(r.partial.len(), MAX_PARTIAL);
+        assert!(!r.partial.contains_key(&key(0)));
+        assert_eq!(r.used, MAX_PARTIAL * 8);
+    }
+
+    #[test]
+    fn memory_cap() {
+        let mut r = Reassembler::new(TIMEOUT);
+        let data = datagram(MAX_DATAGRAM);
+        let last = MAX_DATAGRAM - 8;
+        // the last fragment of each makes room for the whole datagram
+        let fits = REASSEMBLY_LIMIT / MAX_DATAGRAM;
+        for id in 0..fits as u16 {
+            assert_eq!(r.insert(key(id), last, false, &data[last..], 0), None);
+        }
+        assert_eq!(r.insert(key(50), 0, true, &data[..8], 0), None);
+        let used = fits * MAX_DATAGRAM + 8;
+        assert_eq!(r.used, used);
+
+        // no room for another one, and nothing is left behind for it
+        assert_eq!(r.insert(key(100), last, false, &data[last..], 0), None);
+        assert!(!r.partial.contains_key(&key(100)));
+        assert_eq!((r.partial.len(), r.used), (fits + 1, used));
+
+        // nor for growing one that's already there, which keeps what it had
+        assert_eq!(r.insert(key(50), last, false, &data[last..], 0), None);
+        assert_eq!(r.partial[&key(50)].received.first(), Some(&(0..8)));
+        assert_eq!((r.partial.len(), r.used), (fits + 1, used));
+    }
+}
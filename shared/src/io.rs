@@ -1,18 +1,114 @@
 use bitflags::bitflags;
 
 bitflags! {
+    /// Anything besides a plain lookup fails with `ReadOnly` on filesystems that can't be changed,
+    /// like the initrd, `/dev` and `/proc`, and on files without `Mode::Write`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct OpenFlags: u32 {
-        /// Create a directory if it doesn't exist
+        /// Create a directory if it doesn't exist. Fails with `AlreadyExists` if a file is there
+        /// instead, and with `InvalidOp` alongside `CreateFile` or `Truncate`.
         const CreateDir = 1 << 0;
-        /// Create the file if it doesn't exist
+        /// Create the file if it doesn't exist. Fails with `AlreadyExists` if a directory is there
+        /// instead.
         const CreateFile = 1 << 1;
         /// Allow reading and writing
         const ReadWrite = 1 << 2;
-        /// Truncate the file to zero when opening
+        /// Truncate the file to zero when opening. Fails with `InvalidOp` on directories. Files
+        /// that don't keep what's written to them, like devices and tunables, aren't changed.
         const Truncate = 1 << 3;
     }
 }
 
+bitflags! {
+    /// What can be done with a file besides reading it. Files are created with the mode passed to
+    /// `open`, minus the process' umask.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Mode: u32 {
+        /// Modify a file, or add and remove entries in a directory
+        const Write = 1 << 0;
+        /// Spawn a file. Ignored for directories.
+        const Exec = 1 << 1;
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MountFlags: u32 {
+        /// Don't update access times when files are read
+        const NoAtime = 1 << 0;
+    }
+}
+
+bitflags! {
+    pub struct UnmountFlags: u32 {
+        /// Unmount even if files are open, which makes any further use of them fail with `Stale`
+        const Force = 1 << 0;
+    }
+}
+
+/// Requests `Sys::Ioctl` understands for `/dev/console`. Settings are put back the way they were
+/// once the descriptor they were changed through is closed, which its process exiting does too.
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum ConsoleCtl {
+    /// Whether to ring the terminal bell when input doesn't fit in the buffer and is dropped. The
+    /// argument is 0 to turn it off, 1 to turn it on, or `usize::MAX` to leave it. Returns the
+    /// previous setting.
+    Bell = 1,
+    /// How many bytes of input were dropped since the last time this was asked. The argument is
+    /// ignored.
+    TakeDropped,
+    /// The `ModemStatus` lines of the UART. The argument is ignored. Fails with `Unsupported` if
+    /// the console isn't a UART the kernel drives itself.
+    ModemStatus,
+    /// Set the `ModemControl` lines of the UART to the argument, or leave them with `usize::MAX`.
+    /// Returns the previous lines.
+    ModemControl,
+    /// Whether to hold output while the other end deasserts CTS, with the same argument and
+    /// result as `Bell`. Output isn't held for longer than a second at a time, so a cable that was
+    /// pulled out doesn't hang the console.
+    CtsFlow,
+    /// Whether to show what's typed, with the same argument and result as `Bell`. Lines can still
+    /// be edited without it.
+    Echo,
+}
+
+bitflags! {
+    /// Lines the UART drives, in the same bits as the 16550's modem control register
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ModemControl: u8 {
+        /// Data terminal ready
+        const Dtr = 1 << 0;
+        /// Request to send, which tells the other end it can send to us
+        const Rts = 1 << 1;
+        const Out1 = 1 << 2;
+        const Out2 = 1 << 3;
+        /// Loop output back into input instead of driving the lines
+        const Loopback = 1 << 4;
+    }
+}
+
+bitflags! {
+    /// Lines the other end of the UART drives, in the same bits as the 16550's modem status
+    /// register. The delta bits say which lines changed since the status was last read.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ModemStatus: u8 {
+        const DeltaCts = 1 << 0;
+        const DeltaDsr = 1 << 1;
+        /// Ring indicator went from on to off
+        const TrailingRi = 1 << 2;
+        const DeltaDcd = 1 << 3;
+        /// Clear to send, which tells us we can send to the other end
+        const Cts = 1 << 4;
+        /// Data set ready
+        const Dsr = 1 << 5;
+        /// Ring indicator
+        const Ri = 1 << 6;
+        /// Data carrier detect
+        const Dcd = 1 << 7;
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct DirEntry {
@@ -27,4 +123,55 @@ pub struct Stat {
     pub size: usize,
     pub readonly: bool,
     pub directory: bool,
+    /// Can be spawned. Always false for directories.
+    pub executable: bool,
+    /// When the contents were last read, in microseconds since boot plus the `epoch=` boot
+    /// argument. Zero if the filesystem doesn't keep track.
+    pub atime: u64,
+    /// When the contents were last changed, in the same unit as `atime`. Zero if the filesystem
+    /// doesn't keep track.
+    pub mtime: u64,
+    /// How many bytes of storage the contents take up. Less than `size` if the file has holes,
+    /// and zero if the contents are generated when read.
+    pub allocated: usize,
+    /// Which device this is, if it's a file in `/dev`
+    pub device: Option<DeviceId>,
+}
+
+/// Identifies a device independently of its name, like a Unix major and minor number. The
+/// numbers follow Linux where there's an equivalent.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceId {
+    /// What kind of device it is
+    pub major: u16,
+    /// Which one of that kind
+    pub minor: u16,
+}
+
+impl DeviceId {
+    /// `null`, `zero` and `urandom`
+    pub const MEM: u16 = 1;
+    /// Serial ports, `ttyS0` is minor 64
+    pub const SERIAL: u16 = 4;
+    /// `console`, whichever device that is
+    pub const TTY: u16 = 5;
+    /// Devices that are the only one of their kind, like `tun0` and `pcap0`
+    pub const MISC: u16 = 10;
+
+    pub const NULL: DeviceId = DeviceId::new(Self::MEM, 3);
+    pub const ZERO: DeviceId = DeviceId::new(Self::MEM, 5);
+    pub const URANDOM: DeviceId = DeviceId::new(Self::MEM, 9);
+    pub const CONSOLE: DeviceId = DeviceId::new(Self::TTY, 1);
+    pub const TUN: DeviceId = DeviceId::new(Self::MISC, 200);
+    pub const PCAP: DeviceId = DeviceId::new(Self::MISC, 201);
+
+    pub const fn new(major: u16, minor: u16) -> Self {
+        Self { major, minor }
+    }
+
+    /// The serial port called `ttyS<index>`
+    pub const fn serial(index: u16) -> Self {
+        Self::new(Self::SERIAL, 64 + index)
+    }
 }
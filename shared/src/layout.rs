@@ -0,0 +1,61 @@
+//! The virtual memory layout of a user process. Every region is page aligned, and from the bottom
+//! of the address space up:
+//!
+//! | Region       | Mapped | Contents                                                     |
+//! |--------------|--------|--------------------------------------------------------------|
+//! | null guard   | never  | catches null pointer dereferences                            |
+//! | `IMAGE`      | U      | the program's loadable segments, then the heap up to `brk`   |
+//! | stack guard  | never  | catches heap overruns and stack overflows                    |
+//! | `STACK`      | U      | the initial stack, with `argv` at the top                    |
+//! | guard        | never  | catches stack underflows                                     |
+//! | `INFO`       | U, R   | a `SysInfo` the kernel fills in at boot, shared by everyone  |
+//! | `TRAP_FRAME` | S      | the process' saved registers                                 |
+//! | `TRAP_VEC`   | S      | the trap vector, shared with the kernel's page table         |
+//!
+//! The kernel's own page table reuses the space below `TRAP_FRAME` for the hart stacks.
+
+use core::ops::Range;
+
+pub const PAGE_SIZE: usize = 0x1000;
+
+/// One past the highest user address, the top of the lower half of the Sv39 address space
+pub const USER_END: usize = 1 << 38;
+
+/// Mapped at the same address in the kernel's page table, so the trap code is still there while
+/// satp is switched.
+pub const TRAP_VEC: Range<usize> = USER_END - PAGE_SIZE..USER_END;
+
+pub const TRAP_FRAME: Range<usize> = TRAP_VEC.start - PAGE_SIZE..TRAP_VEC.start;
+
+pub const INFO: Range<usize> = TRAP_FRAME.start - PAGE_SIZE..TRAP_FRAME.start;
+
+/// Only used by the kernel's page table, where the hart stacks grow down from here with a guard
+/// region below each one. It overlaps `STACK`, which only exists in user page tables.
+pub const KERNEL_STACKS_TOP: usize = INFO.start;
+
+pub const STACK_SIZE: usize = 1024 * 1024;
+
+pub const STACK: Range<usize> = INFO.start - PAGE_SIZE - STACK_SIZE..INFO.start - PAGE_SIZE;
+
+/// Program segments must be loaded inside this range, and `sbrk` can't grow the heap past its end.
+pub const IMAGE: Range<usize> = PAGE_SIZE..STACK.start - PAGE_SIZE;
+
+const _: () = {
+    let regions = [IMAGE, STACK, INFO, TRAP_FRAME, TRAP_VEC];
+    let mut i = 0;
+    while i < regions.len() {
+        assert!(regions[i].start < regions[i].end);
+        assert!(
+            regions[i].start.is_multiple_of(PAGE_SIZE) && regions[i].end.is_multiple_of(PAGE_SIZE)
+        );
+        assert!(i == 0 || regions[i - 1].end <= regions[i].start);
+        i += 1;
+    }
+
+    // the stack is between guard pages, everything above it is back to back
+    assert!(IMAGE.end + PAGE_SIZE == STACK.start);
+    assert!(STACK.end + PAGE_SIZE == INFO.start);
+    assert!(INFO.end == TRAP_FRAME.start);
+    assert!(TRAP_FRAME.end == TRAP_VEC.start);
+    assert!(TRAP_VEC.end == USER_END);
+};
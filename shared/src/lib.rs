@@ -1,8 +1,14 @@
 #![no_std]
 #![feature(allocator_api)]
 
-#[cfg(feature = "alloc")]
+#[cfg(any(test, feature = "alloc"))]
 extern crate alloc;
 
+pub mod filter;
+#[cfg(any(test, feature = "alloc"))]
+pub mod frag;
 pub mod io;
+pub mod layout;
+pub mod log;
+pub mod net;
 pub mod sys;
@@ -0,0 +1,21 @@
+use bitflags::bitflags;
+
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(usize)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+bitflags! {
+    /// Kernel subsystems whose debug output can be toggled at runtime
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct LogSubsystem: u32 {
+        const Sched = 1 << 0;
+        const Vmm = 1 << 1;
+        const Fs = 1 << 2;
+        const Irq = 1 << 3;
+    }
+}
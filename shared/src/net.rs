@@ -0,0 +1,95 @@
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum SocketType {
+    Udp = 1,
+    Tcp,
+}
+
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum SockOpt {
+    /// The size of the receive buffer in bytes
+    RecvBuf = 1,
+    /// The size of the send buffer in bytes. For datagram sockets, this is the largest datagram
+    /// that can be sent.
+    SendBuf,
+}
+
+/// An IPv4 address and port as passed to and from socket syscalls
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SockAddr {
+    pub addr: [u8; 4],
+    pub port: u16,
+}
+
+impl SockAddr {
+    pub const UNSPECIFIED: SockAddr = SockAddr {
+        addr: [0; 4],
+        port: 0,
+    };
+
+    pub const fn new(addr: Ipv4Addr, port: u16) -> Self {
+        Self {
+            addr: addr.octets(),
+            port,
+        }
+    }
+
+    pub const fn ip(&self) -> Ipv4Addr {
+        Ipv4Addr::new(self.addr[0], self.addr[1], self.addr[2], self.addr[3])
+    }
+}
+
+impl From<SocketAddrV4> for SockAddr {
+    fn from(value: SocketAddrV4) -> Self {
+        Self::new(*value.ip(), value.port())
+    }
+}
+
+impl From<SockAddr> for SocketAddrV4 {
+    fn from(value: SockAddr) -> Self {
+        SocketAddrV4::new(value.ip(), value.port)
+    }
+}
+
+/// A link layer (Ethernet) address
+pub type HwAddr = [u8; 6];
+
+/// The index of the loopback interface
+pub const IFACE_LO: u32 = 0;
+/// The index of the interface backed by `/dev/tun0`
+pub const IFACE_TUN: u32 = 1;
+
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum NetCtl {
+    /// Add a `RouteEntry` to the routing table
+    AddRoute = 1,
+    /// Remove the route with the destination and prefix length of a `RouteEntry`
+    DelRoute,
+    /// Add a permanent `NeighborEntry` to the ARP cache
+    AddNeighbor,
+    /// Remove the ARP cache entry with the address and interface of a `NeighborEntry`
+    DelNeighbor,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RouteEntry {
+    pub dest: [u8; 4],
+    pub prefix_len: u8,
+    /// The next hop for the destination, or the unspecified address if it is on-link
+    pub gateway: [u8; 4],
+    pub iface: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NeighborEntry {
+    pub addr: [u8; 4],
+    pub hwaddr: HwAddr,
+    pub iface: u32,
+}
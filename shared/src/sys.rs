@@ -1,5 +1,83 @@
 use core::alloc::AllocError;
 
+use bitflags::bitflags;
+
+/// How many arguments a syscall can take
+pub const SYSCALL_MAX_ARGS: usize = 6;
+
+// The syscall calling convention. Registers are given by number, so `x10` is `a0`, and `x0` is
+// never used, which lets the kernel keep the PC in its slot of the trap frame.
+//
+// - `ecall` with the `Sys` number in `a7` and the arguments in order from `a0`. Arguments a
+//   syscall doesn't take are ignored, and `a6` is never an argument.
+// - On return, `a0` holds the result and `a1` is 0, or `a0` is 0 and `a1` holds the `SysError`.
+// - Only `a0` and `a1` are clobbered. Every other register, including the number and the argument
+//   registers, is the same after the syscall as it was before.
+//
+// This doesn't change once programs depend on it: new syscalls get new numbers and fit in the
+// same registers, and `tests` checks every syscall against it.
+
+/// The register with the `Sys` number
+pub const SYSCALL_NR_REG: usize = 17;
+/// The registers with the arguments, in order
+pub const SYSCALL_ARG_REGS: [usize; SYSCALL_MAX_ARGS] = [10, 11, 12, 13, 14, 15];
+/// The register the result is returned in
+pub const SYSCALL_RET_REG: usize = 10;
+/// The register a `SysError` is returned in, or 0 on success
+pub const SYSCALL_ERR_REG: usize = 11;
+/// The registers a syscall can change
+pub const SYSCALL_CLOBBERS: [usize; 2] = [SYSCALL_RET_REG, SYSCALL_ERR_REG];
+
+/// The number and arguments of one syscall, as placed in registers by the calling convention
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallRegs {
+    pub nr: usize,
+    pub args: [usize; SYSCALL_MAX_ARGS],
+}
+
+impl SyscallRegs {
+    /// Arguments past the end of `args` are passed as 0
+    pub const fn new(nr: Sys, args: &[usize]) -> Self {
+        assert!(args.len() <= SYSCALL_MAX_ARGS, "too many syscall arguments");
+        let mut all = [0; SYSCALL_MAX_ARGS];
+        let mut i = 0;
+        while i < args.len() {
+            all[i] = args[i];
+            i += 1;
+        }
+        Self {
+            nr: nr as usize,
+            args: all,
+        }
+    }
+
+    /// Read the syscall out of a register file indexed by register number
+    pub fn read(regs: &[usize; 32]) -> Self {
+        Self {
+            nr: regs[SYSCALL_NR_REG],
+            args: SYSCALL_ARG_REGS.map(|reg| regs[reg]),
+        }
+    }
+}
+
+/// Write the outcome of a syscall into a register file indexed by register number
+pub fn set_syscall_result(regs: &mut [usize; 32], result: Result<usize, SysError>) {
+    let (ret, err) = match result {
+        Ok(res) => (res, 0),
+        Err(err) => (0, err as usize),
+    };
+    regs[SYSCALL_RET_REG] = ret;
+    regs[SYSCALL_ERR_REG] = err;
+}
+
+/// Turn what a syscall left in the result and error registers back into its outcome
+pub fn syscall_result(ret: usize, err: usize) -> Result<usize, SysError> {
+    match err {
+        0 => Ok(ret),
+        err => Err(SysError::from_repr(err).unwrap()),
+    }
+}
+
 #[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(usize)]
 pub enum Sys {
@@ -17,6 +95,300 @@ pub enum Sys {
     Sbrk,
     Waitpid,
     Exit,
+    LogCtl,
+    PidHandle,
+    SendMsg,
+    RecvMsg,
+    RegisterName,
+    UnregisterName,
+    LookupName,
+    Socket,
+    Bind,
+    Connect,
+    Listen,
+    Accept,
+    SendTo,
+    RecvFrom,
+    SetSockOpt,
+    GetSockOpt,
+    NetCtl,
+    Stats,
+    Priority,
+    GetCwd,
+    Unmount,
+    BindMount,
+    Remove,
+    GetExePath,
+    Umask,
+    FilterAttach,
+    FilterDetach,
+    FilterMap,
+    Seccomp,
+    Ioctl,
+    Acct,
+    SetHostname,
+    GetHostname,
+    PivotRoot,
+    CloseRange,
+    Poll,
+    FaultInfo,
+    WaitpidTimeout,
+}
+
+/// The maximum size of a message sent with `Sys::SendMsg`
+pub const MAX_MSG_LEN: usize = 128;
+
+/// The maximum length of a name registered with `Sys::RegisterName`
+pub const MAX_NAME_LEN: usize = 32;
+
+/// The maximum length of the name given to `Sys::SetHostname`
+pub const MAX_HOSTNAME_LEN: usize = 64;
+
+/// Sent by `Sys::Shutdown` to every other process, from the process shutting down. Whatever gets it
+/// should save its work and exit before the grace period is over.
+pub const SHUTDOWN_MSG: &[u8] = b"shutdown";
+
+/// The maximum number of entries given to `Sys::Poll`
+pub const MAX_POLL: usize = 16;
+
+/// What a `PollEntry` waits for
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum PollKind {
+    /// Descriptor `id` has something to read. Only the console can make a poll wait for this,
+    /// other files always count as readable.
+    Readable = 1,
+    /// The process with PID or handle `id` has exited
+    Exited,
+}
+
+/// One of the things `Sys::Poll` waits for
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PollEntry {
+    /// A `PollKind`
+    pub kind: usize,
+    pub id: u64,
+    /// Set to 1 by `Sys::Poll` if what the entry waits for has happened, 0 if not
+    pub ready: usize,
+    /// The exit code of an `Exited` process, `usize::MAX` if it was killed without one or exited
+    /// too long ago to be remembered
+    pub value: usize,
+}
+
+impl PollEntry {
+    pub const fn readable(fd: usize) -> Self {
+        Self::new(PollKind::Readable, fd as u64)
+    }
+
+    pub const fn exited(pid_or_handle: u64) -> Self {
+        Self::new(PollKind::Exited, pid_or_handle)
+    }
+
+    const fn new(kind: PollKind, id: u64) -> Self {
+        Self {
+            kind: kind as usize,
+            id,
+            ready: 0,
+            value: 0,
+        }
+    }
+
+    pub const fn is_ready(&self) -> bool {
+        self.ready != 0
+    }
+}
+
+/// How many return addresses `FaultInfo::backtrace` holds
+pub const FAULT_BACKTRACE_LEN: usize = 16;
+
+/// What the kernel recorded about a process it killed for an exception, from `Sys::FaultInfo`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FaultInfo {
+    pub handle: ProcHandle,
+    /// The `scause` of the exception
+    pub cause: u64,
+    /// The address of the instruction that raised it
+    pub pc: u64,
+    /// The faulting address for page and access faults, the instruction itself for illegal ones
+    pub stval: u64,
+    pub ra: u64,
+    pub sp: u64,
+    /// Return addresses found by following frame pointers out of the faulting function, innermost
+    /// first. Unused entries are 0.
+    pub backtrace: [u64; FAULT_BACKTRACE_LEN],
+    /// The absolute path of the executable, truncated to fit and padded with zeroes
+    pub exe: [u8; 128],
+}
+
+impl FaultInfo {
+    pub fn exe(&self) -> &[u8] {
+        let len = self
+            .exe
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.exe.len());
+        &self.exe[..len]
+    }
+
+    pub fn backtrace(&self) -> &[u64] {
+        let len = self
+            .backtrace
+            .iter()
+            .position(|&ra| ra == 0)
+            .unwrap_or(self.backtrace.len());
+        &self.backtrace[..len]
+    }
+
+    /// The name of the exception in `cause`, as the privileged spec gives it
+    pub const fn cause_name(&self) -> &'static str {
+        match self.cause {
+            0 => "instruction address misaligned",
+            1 => "instruction access fault",
+            2 => "illegal instruction",
+            3 => "breakpoint",
+            4 => "load address misaligned",
+            5 => "load access fault",
+            6 => "store address misaligned",
+            7 => "store access fault",
+            12 => "instruction page fault",
+            13 => "load page fault",
+            15 => "store page fault",
+            18 => "software check",
+            19 => "hardware error",
+            _ => "unknown exception",
+        }
+    }
+}
+
+bitflags! {
+    /// Counters user programs can read with `rdcycle`, `rdtime` and `rdinstret`, in the bits
+    /// `scounteren` uses for them
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Counters: u64 {
+        const Cycle = 1 << 0;
+        const Time = 1 << 1;
+        const Instret = 1 << 2;
+    }
+}
+
+/// Mapped read-only at `layout::INFO` in every process. The kernel fills it in once at boot, so it
+/// can be read without a syscall.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SysInfo {
+    /// How many times a second `time` ticks
+    pub timebase_freq: u64,
+    /// Reading a counter that isn't in here kills the process with an illegal instruction
+    pub counters: Counters,
+}
+
+/// The PLIC sources counted individually by `TrapStats`. Source 0 counts claims that returned no
+/// interrupt, sources past the end aren't counted.
+pub const MAX_IRQS: usize = 64;
+/// Exceptions are counted by their `scause` code
+pub const MAX_EXCEPTIONS: usize = 24;
+/// Syscalls are counted by number. Entry 0 counts numbers that aren't a valid `Sys`.
+pub const MAX_SYSCALLS: usize = 64;
+
+/// Input lost or corrupted on the console UART, counted by `TrapStats::uart`
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum UartError {
+    /// The receive FIFO was full, so a byte was lost
+    Overrun,
+    Parity,
+    Framing,
+    /// The line was held low for longer than a byte
+    Break,
+    /// The console's line buffer was full, so a byte that arrived fine was thrown away
+    Dropped,
+}
+
+pub const UART_ERRORS: usize = UartError::Dropped as usize + 1;
+
+/// Decides how long a process runs before it's preempted, and which processes run first
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum SchedClass {
+    /// Short time slices. Runs ahead of normal and batch processes after console input arrives.
+    Interactive = 1,
+    Normal,
+    /// Long time slices, for work nobody is waiting on
+    Batch,
+    /// Only runs when nothing else is ready
+    Idle,
+}
+
+/// Pass as the hart to `Sys::Stats` to read or reset the counters of every hart
+pub const ALL_HARTS: usize = usize::MAX;
+
+#[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum StatsOp {
+    Read = 1,
+    Reset,
+}
+
+/// Counts of interrupts, exceptions and syscalls taken by a hart since boot or the last reset
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TrapStats {
+    pub timer: u64,
+    pub software: u64,
+    pub irqs: [u64; MAX_IRQS],
+    pub exceptions: [u64; MAX_EXCEPTIONS],
+    pub syscalls: [u64; MAX_SYSCALLS],
+    pub uart: [u64; UART_ERRORS],
+}
+
+impl TrapStats {
+    pub const fn new() -> Self {
+        Self {
+            timer: 0,
+            software: 0,
+            irqs: [0; MAX_IRQS],
+            exceptions: [0; MAX_EXCEPTIONS],
+            syscalls: [0; MAX_SYSCALLS],
+            uart: [0; UART_ERRORS],
+        }
+    }
+}
+
+impl Default for TrapStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Appended to the file given to `Sys::Acct` each time a process exits
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct AcctRecord {
+    /// When the process was spawned, in the unit of `Stat::mtime`
+    pub start: u64,
+    /// How long the process was running on a hart, including syscalls and traps it caused, in
+    /// microseconds
+    pub cpu_time: u64,
+    /// The exit code, `usize::MAX` if it was killed without one
+    pub status: u64,
+    pub pid: u32,
+    /// There are no users yet, so always 0
+    pub uid: u32,
+    /// The last component of the executable's path, truncated to fit and padded with zeroes
+    pub name: [u8; 40],
+}
+
+impl AcctRecord {
+    pub fn name(&self) -> &[u8] {
+        let len = self
+            .name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.name.len());
+        &self.name[..len]
+    }
 }
 
 #[derive(strum::FromRepr, Debug, Clone, Copy, PartialEq, Eq)]
@@ -35,6 +407,77 @@ pub enum SysError {
     InvalidPerms,
     BadAddr,
     Eof,
+    WouldBlock,
+    AlreadyExists,
+    AddrInUse,
+    AddrNotAvailable,
+    NotConnected,
+    ConnRefused,
+    ConnReset,
+    Stale,
+    Busy,
+    /// The file isn't executable
+    PermissionDenied,
+    /// A blocking syscall was woken up before it could finish. `Waitpid`, `RecvMsg`, `Poll` and
+    /// `Shutdown` are the only syscalls that block, and none are restarted. Killing a process is
+    /// the only thing that interrupts them for now, so a process never sees this itself.
+    Interrupted,
+    /// The time given to `WaitpidTimeout` ran out first
+    TimedOut,
+    /// `Spawn` was given a file that isn't an ELF file
+    ExecNotElf,
+    /// The executable isn't 64-bit little endian RISC-V
+    ExecWrongArch,
+    /// The ELF file is an object file or shared library instead of an executable
+    ExecWrongType,
+    /// A header or segment of the executable is past the end of the file
+    ExecTruncated,
+    /// A segment's address and offset in the executable disagree about its alignment
+    ExecMisaligned,
+    /// Two loadable segments of the executable share a page
+    ExecOverlap,
+    /// A loadable segment is outside the address range executables are loaded at
+    ExecTooBig,
+    /// A segment of the executable has more data in the file than room in memory
+    ExecBadSegment,
+}
+
+impl SysError {
+    /// Why `Spawn` couldn't load an executable, if that's what this error is about
+    pub const fn exec_reason(self) -> Option<&'static str> {
+        Some(match self {
+            SysError::ExecNotElf => "not an ELF file",
+            SysError::ExecWrongArch => "not a 64-bit RISC-V executable",
+            SysError::ExecWrongType => "not an executable",
+            SysError::ExecTruncated => "truncated executable",
+            SysError::ExecMisaligned => "misaligned segments",
+            SysError::ExecOverlap => "overlapping segments",
+            SysError::ExecTooBig => "segments outside the user address space",
+            SysError::ExecBadSegment => "segment bigger in the file than in memory",
+            _ => return None,
+        })
+    }
+}
+
+/// A reference to one specific process. Unlike a PID, which is eventually reused after the process
+/// exits, a handle never refers to any other process. Syscalls taking a PID also accept a handle.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProcHandle(pub u64);
+
+impl ProcHandle {
+    /// Handles always have a nonzero generation, which distinguishes them from bare PIDs
+    pub const fn new(pid: u32, generation: u32) -> Self {
+        Self(((generation as u64) << 32) | pid as u64)
+    }
+
+    pub const fn pid(self) -> u32 {
+        self.0 as u32
+    }
+
+    pub const fn generation(self) -> u32 {
+        (self.0 >> 32) as u32
+    }
 }
 
 impl From<AllocError> for SysError {
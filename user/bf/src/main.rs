@@ -4,7 +4,7 @@
 use core::ffi::CStr;
 
 use bc::{Program, StepResult, Vm};
-use userstd::println;
+use userstd::{println, sys};
 
 mod bc;
 
@@ -61,8 +61,14 @@ fn main(args: &[*const u8]) -> usize {
 
     let Some((program, pcs)) = program else {
         // return repl();
-        let name = unsafe { CStr::from_ptr(args[0].cast()) };
-        println!("usage: {name:?} [-ld] <program>");
+        let mut buf = [0; 0x100];
+        let name = match sys::exe_path(&mut buf) {
+            Ok(path) => core::str::from_utf8(path).unwrap_or("bf"),
+            Err(_) => unsafe { CStr::from_ptr(args[0].cast()) }
+                .to_str()
+                .unwrap_or("bf"),
+        };
+        println!("usage: {name} [-ld] <program>");
         return 1;
     };
 
@@ -4,37 +4,126 @@
 use core::ffi::CStr;
 
 use userstd::{
-    io::OpenFlags,
-    println,
+    alloc::vec::Vec,
+    fd::{AsRawFd, OwnedFd},
+    io::{OpenFlags, STDIN, STDOUT},
+    print, println,
     sys::{self, RawFd, SysError},
 };
 
+const USAGE: &str = "usage: cat [-n] [file...]";
+
+/// The console can't signal the end of input, so a line containing ^D does instead
+const EOT: u8 = 0x04;
+
+struct Output {
+    number: bool,
+    line: usize,
+    line_start: bool,
+}
+
+impl Output {
+    fn write(&mut self, mut buf: &[u8]) {
+        if !self.number {
+            _ = sys::write(STDOUT, None, buf);
+            return;
+        }
+
+        while !buf.is_empty() {
+            if self.line_start {
+                self.line += 1;
+                print!("{:>6}\t", self.line);
+            }
+
+            let end = buf
+                .iter()
+                .position(|&c| c == b'\n')
+                .map_or(buf.len(), |i| i + 1);
+            _ = sys::write(STDOUT, None, &buf[..end]);
+            self.line_start = buf[end - 1] == b'\n';
+            buf = &buf[end..];
+        }
+    }
+}
+
+/// Copy `fd` to stdout until the end of the file, or ^D on the console
+fn copy(out: &mut Output, fd: RawFd, buf: &mut [u8]) -> Result<(), SysError> {
+    loop {
+        match sys::read(fd, None, buf) {
+            // the console has nothing to read yet
+            Ok(0) if fd == STDIN => continue,
+            Ok(0) | Err(SysError::Eof) => return Ok(()),
+            Ok(n) if fd == STDIN && buf[..n].contains(&EOT) => {
+                let end = buf[..n].iter().position(|&c| c == EOT).unwrap();
+                out.write(&buf[..end]);
+                return Ok(());
+            }
+            Ok(n) => out.write(&buf[..n]),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
 #[no_mangle]
 fn main(args: &[*const u8]) -> usize {
+    let args = args[1..]
+        .iter()
+        .map(|arg| unsafe { CStr::from_ptr(arg.cast()).to_bytes() });
+
+    let mut out = Output {
+        number: false,
+        line: 0,
+        line_start: true,
+    };
+    for arg in args.clone().filter(|arg| arg.len() > 1 && arg[0] == b'-') {
+        for &opt in &arg[1..] {
+            match opt {
+                b'n' => out.number = true,
+                _ => {
+                    println!("cat: unknown option '{}'\n{USAGE}", opt as char);
+                    return 1;
+                }
+            }
+        }
+    }
+
     let mut buf = [0; 0x4000];
     let mut ecode = 0;
-    for arg in args[1..]
-        .iter()
-        .map(|arg| unsafe { CStr::from_ptr(arg.cast()).to_bytes() })
-    {
-        let strname = core::str::from_utf8(arg).unwrap();
-        let fd = match sys::open(arg, OpenFlags::empty()) {
+    let mut files: Vec<&[u8]> = args
+        .filter(|arg| arg.len() <= 1 || arg[0] != b'-')
+        .collect();
+    if files.is_empty() {
+        files.push(b"-");
+    }
+
+    for file in files {
+        let name = core::str::from_utf8(file).unwrap_or("?");
+        if file == b"-" {
+            if let Err(err) = copy(&mut out, STDIN, &mut buf) {
+                println!("cat: stdin: read error: {err:?}");
+                ecode = 1;
+            }
+            continue;
+        }
+
+        let fd = match OwnedFd::open(file, OpenFlags::empty()) {
             Ok(fd) => fd,
             Err(SysError::PathNotFound) => {
-                println!("'{strname}': doesn't exist");
+                println!("cat: '{name}': doesn't exist");
                 ecode = 1;
                 continue;
             }
             Err(err) => {
-                println!("'{strname}': read error: {err:?}");
+                println!("cat: '{name}': open error: {err:?}");
                 ecode = 1;
                 continue;
             }
         };
-        while let Ok(n) = sys::read(fd, None, &mut buf) {
-            _ = sys::write(RawFd(0), None, &buf[..n]);
+
+        if let Err(err) = copy(&mut out, fd.as_raw_fd(), &mut buf) {
+            println!("cat: '{name}': read error: {err:?}");
+            ecode = 1;
         }
-        _ = sys::close(fd);
     }
 
     ecode
@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+
+use core::ffi::CStr;
+
+use userstd::{
+    log::{LogLevel, LogSubsystem},
+    println,
+    sys::{self},
+};
+
+fn parse_level(arg: &[u8]) -> Option<LogLevel> {
+    match arg {
+        b"error" | b"0" => Some(LogLevel::Error),
+        b"warn" | b"1" => Some(LogLevel::Warn),
+        b"info" | b"2" => Some(LogLevel::Info),
+        b"debug" | b"3" => Some(LogLevel::Debug),
+        _ => None,
+    }
+}
+
+fn parse_subsystems(arg: &[u8]) -> Option<LogSubsystem> {
+    let mut subsys = LogSubsystem::empty();
+    for name in arg.split(|&c| c == b',').filter(|n| !n.is_empty()) {
+        subsys |= match name {
+            b"sched" => LogSubsystem::Sched,
+            b"vmm" => LogSubsystem::Vmm,
+            b"fs" => LogSubsystem::Fs,
+            b"irq" => LogSubsystem::Irq,
+            b"all" => LogSubsystem::all(),
+            _ => return None,
+        };
+    }
+    Some(subsys)
+}
+
+#[no_mangle]
+fn main(args: &[*const u8]) -> usize {
+    let mut args = args[1..]
+        .iter()
+        .map(|arg| unsafe { CStr::from_ptr(arg.cast()).to_bytes() });
+
+    let mut level = None;
+    let mut enable = LogSubsystem::empty();
+    let mut disable = LogSubsystem::empty();
+    while let Some(arg) = args.next() {
+        let Some(value) = args.next() else {
+            println!("usage: dmesg [-n <level>] [-e <subsystems>] [-d <subsystems>]");
+            return 1;
+        };
+
+        let strval = core::str::from_utf8(value).unwrap_or("?");
+        match arg {
+            b"-n" => {
+                let Some(l) = parse_level(value) else {
+                    println!("dmesg: invalid log level '{strval}'");
+                    return 1;
+                };
+                level = Some(l);
+            }
+            b"-e" | b"-d" => {
+                let Some(subsys) = parse_subsystems(value) else {
+                    println!("dmesg: invalid subsystem list '{strval}'");
+                    return 1;
+                };
+                if arg == b"-e" {
+                    enable |= subsys;
+                } else {
+                    disable |= subsys;
+                }
+            }
+            _ => {
+                println!(
+                    "dmesg: unknown option '{}'",
+                    core::str::from_utf8(arg).unwrap_or("?")
+                );
+                return 1;
+            }
+        }
+    }
+
+    match sys::logctl(level, enable, disable) {
+        Ok(prev) if level.is_some() => println!("log level: {prev:?} -> {:?}", level.unwrap()),
+        Ok(prev) => println!("log level: {prev:?}"),
+        Err(err) => {
+            println!("dmesg: error: {err:?}");
+            return 1;
+        }
+    }
+    0
+}
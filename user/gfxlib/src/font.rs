@@ -0,0 +1,191 @@
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_LEN: usize = 32;
+const PSF1_MODE512: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    BadMagic,
+    Truncated,
+    BadHeader,
+}
+
+/// A bitmap font in PSF version 1 or 2 format. Glyphs are borrowed from the font file.
+pub struct Font<'a> {
+    pub width: usize,
+    pub height: usize,
+    glyphs: &'a [u8],
+    glyph_len: usize,
+    count: usize,
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    buf.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+impl<'a> Font<'a> {
+    pub fn parse(file: &'a [u8]) -> Result<Self, FontError> {
+        if file.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(file)
+        } else if file.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(file)
+        } else {
+            Err(FontError::BadMagic)
+        }
+    }
+
+    fn parse_psf1(file: &'a [u8]) -> Result<Self, FontError> {
+        let (&mode, &height) = file.get(2).zip(file.get(3)).ok_or(FontError::Truncated)?;
+        let count = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+        let height = height as usize;
+        Self::new(file, 4, 8, height, height, count)
+    }
+
+    fn parse_psf2(file: &'a [u8]) -> Result<Self, FontError> {
+        let field = |i: usize| read_u32(file, 8 + i * 4).ok_or(FontError::Truncated);
+        let header_len = field(0)? as usize;
+        let count = field(2)? as usize;
+        let glyph_len = field(3)? as usize;
+        let height = field(4)? as usize;
+        let width = field(5)? as usize;
+        let min_glyph_len = height.checked_mul(width.div_ceil(8));
+        if header_len < PSF2_HEADER_LEN || min_glyph_len.is_none_or(|min| glyph_len < min) {
+            return Err(FontError::BadHeader);
+        }
+        Self::new(file, header_len, width, height, glyph_len, count)
+    }
+
+    fn new(
+        file: &'a [u8],
+        offset: usize,
+        width: usize,
+        height: usize,
+        glyph_len: usize,
+        count: usize,
+    ) -> Result<Self, FontError> {
+        if width == 0 || height == 0 || count == 0 {
+            return Err(FontError::BadHeader);
+        }
+
+        let len = glyph_len.checked_mul(count).ok_or(FontError::BadHeader)?;
+        let glyphs = file
+            .get(offset..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or(FontError::Truncated)?;
+        Ok(Self {
+            width,
+            height,
+            glyphs,
+            glyph_len,
+            count,
+        })
+    }
+
+    /// The bitmap for `ch`, or for '?' if the font doesn't have it. Each row is `width` bits
+    /// rounded up to a whole byte, most significant bit first.
+    pub fn glyph(&self, ch: char) -> &'a [u8] {
+        let idx = match ch as usize {
+            idx if idx < self.count => idx,
+            _ => b'?' as usize % self.count,
+        };
+        &self.glyphs[idx * self.glyph_len..][..self.glyph_len]
+    }
+
+    pub fn row_len(&self) -> usize {
+        self.width.div_ceil(8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// A PSF2 header followed by `count` glyphs, each filled with its index
+    fn psf2(width: u32, height: u32, glyph_len: u32, count: u32) -> Vec<u8> {
+        let header_len = PSF2_HEADER_LEN as u32;
+        let mut file = PSF2_MAGIC.to_vec();
+        for field in [0, header_len, 0, count, glyph_len, height, width] {
+            file.extend_from_slice(&field.to_le_bytes());
+        }
+        for i in 0..count {
+            file.extend((0..glyph_len).map(|_| i as u8));
+        }
+        file
+    }
+
+    #[test]
+    fn psf1() {
+        let mut file = [PSF1_MAGIC[0], PSF1_MAGIC[1], 0, 16].to_vec();
+        file.extend((0..256).flat_map(|i| [i as u8; 16]));
+        let font = Font::parse(&file).unwrap();
+        assert_eq!((font.width, font.height, font.row_len()), (8, 16, 1));
+        assert_eq!(font.glyph('A'), &[b'A'; 16]);
+
+        // a 512 glyph font that only has 256
+        file[2] = PSF1_MODE512;
+        assert_eq!(Font::parse(&file).err(), Some(FontError::Truncated));
+        assert_eq!(Font::parse(&file[..3]).err(), Some(FontError::Truncated));
+    }
+
+    #[test]
+    fn psf2_glyphs() {
+        let file = psf2(10, 3, 6, 128);
+        let font = Font::parse(&file).unwrap();
+        assert_eq!((font.width, font.height, font.row_len()), (10, 3, 2));
+        assert_eq!(font.glyph('x'), &[b'x'; 6]);
+        // missing characters are drawn as '?'
+        assert_eq!(font.glyph('é'), &[b'?'; 6]);
+    }
+
+    #[test]
+    fn bad_magic() {
+        assert_eq!(Font::parse(b"").err(), Some(FontError::BadMagic));
+        assert_eq!(
+            Font::parse(&[0x72, 0xb5, 0x4a, 0x87, 0, 0]).err(),
+            Some(FontError::BadMagic)
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let file = psf2(8, 8, 8, 4);
+        for len in [4, 12, PSF2_HEADER_LEN - 1, PSF2_HEADER_LEN, file.len() - 1] {
+            assert_eq!(
+                Font::parse(&file[..len]).err(),
+                Some(FontError::Truncated),
+                "{len} bytes"
+            );
+        }
+
+        // glyphs that start past the end of the file
+        let mut file = file;
+        file[8..12].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(Font::parse(&file).err(), Some(FontError::Truncated));
+    }
+
+    #[test]
+    fn bad_header() {
+        // rows of 9 pixels take 2 bytes each
+        assert_eq!(
+            Font::parse(&psf2(9, 8, 15, 4)).err(),
+            Some(FontError::BadHeader)
+        );
+        assert!(Font::parse(&psf2(9, 8, 16, 4)).is_ok());
+
+        for file in [
+            psf2(0, 8, 8, 4),
+            psf2(8, 0, 8, 4),
+            psf2(8, 8, 8, 0),
+            psf2(8, u32::MAX, 8, 1),
+        ] {
+            assert_eq!(Font::parse(&file).err(), Some(FontError::BadHeader));
+        }
+
+        // a header that claims to be shorter than the fixed fields
+        let mut file = psf2(8, 8, 8, 4);
+        file[8..12].copy_from_slice(&16u32.to_le_bytes());
+        assert_eq!(Font::parse(&file).err(), Some(FontError::BadHeader));
+    }
+}
@@ -0,0 +1,23 @@
+#![no_std]
+
+//! Drawing helpers for graphical programs: PSF fonts, filled rectangles and glyph blitting into a
+//! 32-bit pixel buffer, and double buffering over a framebuffer.
+
+pub mod font;
+pub mod surface;
+
+extern crate alloc;
+
+/// A pixel in 0x00RRGGBB format
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color(pub u32);
+
+impl Color {
+    pub const BLACK: Color = Color(0x000000);
+    pub const WHITE: Color = Color(0xffffff);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color((r as u32) << 16 | (g as u32) << 8 | b as u32)
+    }
+}
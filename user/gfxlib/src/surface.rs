@@ -0,0 +1,302 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::{font::Font, Color};
+
+/// A rectangle of pixels in memory, such as a framebuffer. `stride` is the distance between rows
+/// in pixels and may be larger than `width`.
+pub struct Surface<'a> {
+    buf: &'a mut [u32],
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+}
+
+impl<'a> Surface<'a> {
+    /// Returns `None` if `buf` is too small for the given dimensions
+    pub fn new(buf: &'a mut [u32], width: usize, height: usize, stride: usize) -> Option<Self> {
+        if stride < width || buf.len() < stride.checked_mul(height)? {
+            return None;
+        }
+
+        Some(Self {
+            buf,
+            width,
+            height,
+            stride,
+        })
+    }
+
+    pub fn pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x < self.width && y < self.height {
+            self.buf[y * self.stride + x] = color.0;
+        }
+    }
+
+    /// Fill a rectangle, clipped to the surface
+    pub fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: Color) {
+        let Some((xs, ys)) = self.clip(x, y, w, h) else {
+            return;
+        };
+
+        for row in ys {
+            self.buf[row * self.stride..][xs.clone()].fill(color.0);
+        }
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+
+    /// Draw `ch` with its top left corner at `x`, `y`. If `bg` is `None`, unset pixels are left as
+    /// they are.
+    pub fn draw_glyph(
+        &mut self,
+        font: &Font,
+        x: usize,
+        y: usize,
+        ch: char,
+        fg: Color,
+        bg: Option<Color>,
+    ) {
+        let glyph = font.glyph(ch);
+        let Some((xs, ys)) = self.clip(x, y, font.width, font.height) else {
+            return;
+        };
+
+        for row in ys {
+            let bits = &glyph[(row - y) * font.row_len()..];
+            let line = &mut self.buf[row * self.stride..];
+            for col in xs.clone() {
+                let gx = col - x;
+                if bits[gx / 8] & (0x80 >> (gx % 8)) != 0 {
+                    line[col] = fg.0;
+                } else if let Some(bg) = bg {
+                    line[col] = bg.0;
+                }
+            }
+        }
+    }
+
+    /// Draw a single line of text. Returns the x coordinate after the last character.
+    pub fn draw_str(
+        &mut self,
+        font: &Font,
+        mut x: usize,
+        y: usize,
+        s: &str,
+        fg: Color,
+        bg: Option<Color>,
+    ) -> usize {
+        for ch in s.chars() {
+            self.draw_glyph(font, x, y, ch, fg, bg);
+            x += font.width;
+        }
+        x
+    }
+
+    /// Copy rows `rows` of `src` into the same rows of this surface
+    pub fn copy_rows_from(&mut self, src: &Surface, rows: Range<usize>) {
+        let width = self.width.min(src.width);
+        for row in rows.start..rows.end.min(self.height).min(src.height) {
+            self.buf[row * self.stride..][..width]
+                .copy_from_slice(&src.buf[row * src.stride..][..width]);
+        }
+    }
+
+    fn clip(&self, x: usize, y: usize, w: usize, h: usize) -> Option<(Range<usize>, Range<usize>)> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let xs = x..x.saturating_add(w).min(self.width);
+        let ys = y..y.saturating_add(h).min(self.height);
+        Some((xs, ys))
+    }
+}
+
+/// Draws into an off-screen buffer and copies only the rows that changed to the front surface on
+/// `present`, to avoid tearing and redundant framebuffer writes.
+pub struct DoubleBuffer<'a> {
+    front: Surface<'a>,
+    back: Vec<u32>,
+    dirty: Option<Range<usize>>,
+}
+
+impl<'a> DoubleBuffer<'a> {
+    pub fn new(front: Surface<'a>) -> Option<Self> {
+        let len = front.width * front.height;
+        let mut back = Vec::new();
+        back.try_reserve_exact(len).ok()?;
+        back.resize(len, 0);
+        Some(Self {
+            front,
+            back,
+            dirty: None,
+        })
+    }
+
+    /// The rows `rows` of the back buffer, which will be copied to the front buffer on the next
+    /// `present`. Coordinates in the returned surface are relative to `rows.start`, and drawing is
+    /// clipped to `rows`.
+    pub fn draw(&mut self, rows: Range<usize>) -> Surface<'_> {
+        let (width, height) = (self.front.width, self.front.height);
+        let end = rows.end.min(height);
+        let rows = rows.start.min(end)..end;
+        if !rows.is_empty() {
+            self.dirty = Some(match self.dirty.take() {
+                Some(dirty) => dirty.start.min(rows.start)..dirty.end.max(rows.end),
+                None => rows.clone(),
+            });
+        }
+
+        Surface {
+            buf: &mut self.back[rows.start * width..rows.end * width],
+            width,
+            height: rows.len(),
+            stride: width,
+        }
+    }
+
+    /// The whole back buffer, marking every row dirty
+    pub fn draw_all(&mut self) -> Surface<'_> {
+        self.draw(0..self.front.height)
+    }
+
+    pub fn present(&mut self) {
+        let Some(rows) = self.dirty.take() else {
+            return;
+        };
+
+        let (width, height) = (self.front.width, self.front.height);
+        let back = Surface {
+            buf: &mut self.back,
+            width,
+            height,
+            stride: width,
+        };
+        self.front.copy_rows_from(&back, rows);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const FG: Color = Color::WHITE;
+    const BG: Color = Color::rgb(1, 2, 3);
+
+    /// Pixels set by the caller are 1, untouched ones 0
+    fn ones(buf: &[u32], stride: usize) -> Vec<Vec<u8>> {
+        buf.chunks(stride)
+            .map(|row| row.iter().map(|&p| (p != 0) as u8).collect())
+            .collect()
+    }
+
+    #[test]
+    fn new_checks_size() {
+        let mut buf = vec![0; 4 * 3];
+        assert!(Surface::new(&mut buf, 4, 3, 3).is_none());
+        assert!(Surface::new(&mut buf, 3, 4, 3).is_some());
+        assert!(Surface::new(&mut buf, 3, 3, 5).is_none());
+        assert!(Surface::new(&mut buf, 1, 2, usize::MAX).is_none());
+        assert!(Surface::new(&mut buf, 2, 3, 4).is_some());
+    }
+
+    #[test]
+    fn fill_rect_clips() {
+        // one column of padding past the width that must never be written
+        let mut buf = vec![0; 5 * 3];
+        let mut s = Surface::new(&mut buf, 4, 3, 5).unwrap();
+        s.fill_rect(2, 1, 10, 10, Color(1));
+        s.fill_rect(4, 0, 1, 1, Color(1));
+        s.fill_rect(0, 3, 1, 1, Color(1));
+        s.fill_rect(0, 0, usize::MAX, 0, Color(1));
+        s.pixel(4, 0, Color(1));
+        assert_eq!(
+            ones(&buf, 5),
+            [[0, 0, 0, 0, 0], [0, 0, 1, 1, 0], [0, 0, 1, 1, 0]]
+        );
+    }
+
+    #[test]
+    fn glyph_clips() {
+        // a 10x2 glyph: a checkerboard on the first row and a solid second row
+        let mut file = vec![0x72, 0xb5, 0x4a, 0x86];
+        for field in [0u32, 32, 0, 1, 4, 2, 10] {
+            file.extend_from_slice(&field.to_le_bytes());
+        }
+        file.extend_from_slice(&[0xaa, 0x80, 0xff, 0xc0]);
+        let font = Font::parse(&file).unwrap();
+
+        let mut buf = vec![0; 9 * 3];
+        let mut s = Surface::new(&mut buf, 9, 3, 9).unwrap();
+        s.draw_glyph(&font, 1, 2, '?', FG, None);
+        s.draw_glyph(&font, 9, 0, '?', FG, None);
+        s.draw_glyph(&font, 0, 3, '?', FG, None);
+        assert_eq!(
+            ones(&buf, 9),
+            [
+                [0, 0, 0, 0, 0, 0, 0, 0, 0],
+                [0, 0, 0, 0, 0, 0, 0, 0, 0],
+                [0, 1, 0, 1, 0, 1, 0, 1, 0],
+            ]
+        );
+
+        let mut s = Surface::new(&mut buf, 9, 3, 9).unwrap();
+        s.clear(Color::BLACK);
+        s.draw_glyph(&font, 0, 0, '?', FG, Some(BG));
+        assert_eq!(
+            buf[..9],
+            [FG.0, BG.0, FG.0, BG.0, FG.0, BG.0, FG.0, BG.0, FG.0]
+        );
+        assert_eq!(buf[9..18], [FG.0; 9]);
+        assert_eq!(buf[18..], [0; 9]);
+    }
+
+    #[test]
+    fn copy_rows() {
+        let mut src_buf: Vec<u32> = (0..4 * 3).collect();
+        let src = Surface::new(&mut src_buf, 3, 3, 4).unwrap();
+        let mut buf = vec![0; 2 * 4];
+        let mut dst = Surface::new(&mut buf, 2, 4, 2).unwrap();
+        dst.copy_rows_from(&src, 1..10);
+        assert_eq!(buf, [0, 0, 4, 5, 8, 9, 0, 0]);
+    }
+
+    #[test]
+    fn dirty_rows() {
+        let mut front = vec![0; 2 * 6];
+        let mut db = DoubleBuffer::new(Surface::new(&mut front, 2, 6, 2).unwrap()).unwrap();
+        db.present();
+
+        // drawing is relative to and clipped to the rows asked for
+        let mut s = db.draw(1..2);
+        assert_eq!((s.width, s.height), (2, 1));
+        s.clear(Color(1));
+        s.fill_rect(0, 1, 2, 1, Color(2));
+        db.draw(4..5).pixel(1, 0, Color(3));
+        assert_eq!(db.dirty, Some(1..5));
+        assert_eq!(db.back, [0, 0, 1, 1, 0, 0, 0, 0, 0, 3, 0, 0]);
+
+        // rows between the two are copied as well, but nothing else
+        db.back[0] = 9;
+        db.back[5] = 7;
+        db.back[10] = 9;
+        db.present();
+        assert_eq!(db.dirty, None);
+        assert_eq!(db.front.buf, [0, 0, 1, 1, 0, 7, 0, 0, 0, 3, 0, 0]);
+
+        // rows past the bottom are dropped
+        assert_eq!(db.draw(5..100).height, 1);
+        assert_eq!(db.draw(7..9).height, 0);
+        assert_eq!(db.dirty, Some(5..6));
+        db.present();
+        assert_eq!(db.front.buf[10..], [9, 0]);
+
+        db.draw_all();
+        db.present();
+        assert_eq!(db.front.buf, db.back);
+    }
+}
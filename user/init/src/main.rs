@@ -2,10 +2,76 @@
 #![no_main]
 
 use userstd::{
+    alloc::vec::Vec,
+    crash, io,
+    log::{LogLevel, LogSubsystem},
     println,
-    sys::{self},
+    sys::{self, KString},
 };
 
+/// Call `f` on each line of `/etc/<name>` that isn't blank or a comment, trimmed. A missing file
+/// is treated as empty, so a system without `/etc` still boots.
+fn config(name: &str, mut f: impl FnMut(&[u8])) {
+    let mut path = Vec::from(b"/etc/");
+    path.extend_from_slice(name.as_bytes());
+    let Ok(data) = io::read_file(&path) else {
+        return;
+    };
+
+    data.split(|&c| c == b'\n')
+        .map(|line| line.trim_ascii())
+        .filter(|line| !line.is_empty() && !line.starts_with(b"#"))
+        .for_each(&mut f);
+}
+
+fn parse_level(level: &[u8]) -> Option<LogLevel> {
+    match level {
+        b"error" => Some(LogLevel::Error),
+        b"warn" => Some(LogLevel::Warn),
+        b"info" => Some(LogLevel::Info),
+        b"debug" => Some(LogLevel::Debug),
+        _ => None,
+    }
+}
+
+fn apply_console(line: &[u8]) {
+    let str = core::str::from_utf8(line).unwrap_or("?");
+    let mut kv = line.splitn(2, |&c| c == b'=');
+    let res = match (kv.next(), kv.next()) {
+        (Some(b"bell"), Some(b"on")) => sys::console_bell(io::STDIN, Some(true)).map(|_| ()),
+        (Some(b"bell"), Some(b"off")) => sys::console_bell(io::STDIN, Some(false)).map(|_| ()),
+        (Some(b"loglevel"), Some(level)) => {
+            let Some(level) = parse_level(level) else {
+                println!("init: /etc/console: invalid log level in '{str}'");
+                return;
+            };
+            sys::logctl(Some(level), LogSubsystem::empty(), LogSubsystem::empty()).map(|_| ())
+        }
+        _ => {
+            println!("init: /etc/console: unknown setting '{str}'");
+            return;
+        }
+    };
+
+    if let Err(err) = res {
+        println!("init: /etc/console: couldn't apply '{str}': {err:?}");
+    }
+}
+
+fn start_service(line: &[u8]) {
+    let mut words = line.split(|&c| c == b' ').filter(|w| !w.is_empty());
+    let Some(path) = words.next() else {
+        return;
+    };
+    let args: Vec<KString> = words.map(KString::new).collect();
+    if let Err(err) = sys::spawn(path, &args) {
+        println!(
+            "init: couldn't start '{}': {err:?}",
+            core::str::from_utf8(line).unwrap_or("?")
+        );
+    }
+}
+
 #[no_mangle]
 fn main(_args: &[*const u8]) -> usize {
     if sys::getpid() != 0 {
@@ -13,8 +79,28 @@ fn main(_args: &[*const u8]) -> usize {
         return 1;
     }
 
+    // only the first line is used
+    let mut hostname_set = false;
+    config("hostname", |name| {
+        if !core::mem::replace(&mut hostname_set, true) {
+            if let Err(err) = sys::sethostname(name) {
+                println!(
+                    "init: invalid hostname '{}': {err:?}",
+                    core::str::from_utf8(name).unwrap_or("?")
+                );
+            }
+        }
+    });
+    config("console", apply_console);
+    config("services", start_service);
+
     println!("\n\nServos has booted sucessfully!");
     let sh = sys::spawn("/bin/sh", &[]).expect("init: couldn't spawn the shell!");
-    let _ = sys::waitpid(sh);
+    if sys::waitpid(sh) == Ok(usize::MAX) {
+        match crash::write_report(sh as u64) {
+            Ok(path) => println!("init: the shell crashed, report written to {path}"),
+            Err(err) => println!("init: the shell was killed ({err:?})"),
+        }
+    }
     panic!("init: shell process returned!");
 }
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+use core::{ffi::CStr, mem::size_of};
+
+use userstd::{
+    alloc::{
+        format,
+        string::{String, ToString},
+        vec::Vec,
+    },
+    io, println,
+    sys::{self, AcctRecord},
+};
+
+/// Used if no file is given
+const DEFAULT_FILE: &[u8] = b"/acct";
+
+/// Microseconds as seconds with millisecond precision
+fn secs(us: u64) -> String {
+    format!("{}.{:03}", us / 1_000_000, us / 1000 % 1000)
+}
+
+/// Print the records in `path`, most recent first
+fn show(path: &[u8]) -> usize {
+    let data = match io::read_file(path) {
+        Ok(data) => data,
+        Err(err) => {
+            println!("lastcomm: error: {err:?}");
+            return 1;
+        }
+    };
+
+    println!(
+        "{:<16} {:>5} {:>8} {:>10} {:>10}",
+        "COMMAND", "PID", "STATUS", "CPU", "STARTED"
+    );
+    for rec in data.chunks_exact(size_of::<AcctRecord>()).rev() {
+        let rec = unsafe { rec.as_ptr().cast::<AcctRecord>().read_unaligned() };
+        let status = if rec.status == usize::MAX as u64 {
+            "killed".to_string()
+        } else {
+            rec.status.to_string()
+        };
+        println!(
+            "{:<16} {:>5} {status:>8} {:>10} {:>10}",
+            core::str::from_utf8(rec.name()).unwrap_or("?"),
+            rec.pid,
+            secs(rec.cpu_time),
+            secs(rec.start),
+        );
+    }
+    0
+}
+
+#[no_mangle]
+fn main(args: &[*const u8]) -> usize {
+    let args: Vec<&[u8]> = args[1..]
+        .iter()
+        .map(|arg| unsafe { CStr::from_ptr(arg.cast()).to_bytes() })
+        .collect();
+
+    let res = match args[..] {
+        [] => return show(DEFAULT_FILE),
+        [b"-f", path] => return show(path),
+        [b"-e"] => sys::acct(Some(DEFAULT_FILE)),
+        [b"-e", path] => sys::acct(Some(path)),
+        [b"-d"] => sys::acct(None),
+        _ => {
+            println!("usage: lastcomm [-f <file>] | lastcomm -e [<file>] | lastcomm -d");
+            return 1;
+        }
+    };
+
+    match res {
+        Ok(()) => 0,
+        Err(err) => {
+            println!("lastcomm: error: {err:?}");
+            1
+        }
+    }
+}
@@ -3,7 +3,14 @@
 
 use core::ffi::CStr;
 
-use userstd::{io::OpenFlags, println, sys};
+use userstd::{
+    alloc::{format, vec::Vec},
+    fd::{AsRawFd, OwnedFd},
+    io::{DirEntry, OpenFlags, Stat},
+    print, println, sys,
+};
+
+const USAGE: &str = "usage: ls [-alrS] [path...]";
 
 struct Size(usize);
 
@@ -42,44 +49,138 @@ impl core::fmt::Display for Size {
     }
 }
 
-fn printdir(dir: impl AsRef<[u8]>, name: bool, all: bool) -> bool {
+/// A modification time. There's no real time clock, so it's the time since boot unless the kernel
+/// was given an `epoch=`, and only the time of day is shown.
+struct Time(u64);
+
+impl core::fmt::Display for Time {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "{:>8}", "-");
+        }
+
+        let secs = self.0 / 1_000_000;
+        write!(
+            f,
+            "{:02}:{:02}:{:02}",
+            secs / 3600 % 24,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+}
+
+/// The console can't tell us its size, so assume the usual
+const TERM_WIDTH: usize = 80;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    Name,
+    Size,
+}
+
+struct Options {
+    all: bool,
+    long: bool,
+    sort: SortBy,
+    reverse: bool,
+}
+
+fn long_entry(name: &str, stat: &Stat) {
+    if stat.directory {
+        println!("dr--@ {:>4}  {}  {name}", "-", Time(stat.mtime));
+    } else if let Some(dev) = stat.device {
+        // where the size would be, like ls shows major and minor numbers
+        println!(
+            "cr{}-@ {:>4}  {}  {name}",
+            if stat.readonly { "-" } else { "w" },
+            format!("{},{}", dev.major, dev.minor),
+            Time(stat.mtime)
+        );
+    } else {
+        println!(
+            ".r{}{}@ {}  {}  {name}",
+            if stat.readonly { "-" } else { "w" },
+            if stat.executable { "x" } else { "-" },
+            Size(stat.size),
+            Time(stat.mtime)
+        );
+    }
+}
+
+/// Print `names` in as many columns as fit, filling each column before the next like ls does
+fn columns(names: &[&str]) {
+    let width = names.iter().map(|n| n.len()).max().unwrap_or(0) + 2;
+    let cols = (TERM_WIDTH / width).max(1);
+    let rows = names.len().div_ceil(cols);
+    for row in 0..rows {
+        for col in 0..cols {
+            let Some(name) = names.get(col * rows + row) else {
+                break;
+            };
+            if (col + 1) * rows + row < names.len() {
+                print!("{name:<width$}");
+            } else {
+                print!("{name}");
+            }
+        }
+        println!();
+    }
+}
+
+fn entry_name(ent: &DirEntry) -> &str {
+    core::str::from_utf8(&ent.name[..ent.name_len]).unwrap_or("?")
+}
+
+fn printdir(dir: impl AsRef<[u8]>, name: bool, opts: &Options) -> bool {
     let dir = dir.as_ref();
-    let Ok(fd) = sys::open(dir, OpenFlags::empty()) else {
-        println!("'{}': doesn't exist", core::str::from_utf8(dir).unwrap());
+    let dirname = core::str::from_utf8(dir).unwrap_or("?");
+    let Ok(fd) = OwnedFd::open(dir, OpenFlags::empty()) else {
+        println!("'{dirname}': doesn't exist");
         return false;
     };
 
     if name {
-        println!("'{}': ", core::str::from_utf8(dir).unwrap());
+        println!("'{dirname}': ");
     }
 
-    if let Ok(stat) = sys::stat(fd) {
+    if let Ok(stat) = sys::stat(fd.as_raw_fd()) {
         if !stat.directory {
-            println!(
-                ".r{}x@ {}  {}",
-                if stat.readonly { "-" } else { "w" },
-                Size(stat.size),
-                core::str::from_utf8(dir).unwrap()
-            );
+            if opts.long {
+                long_entry(dirname, &stat);
+            } else {
+                println!("{dirname}");
+            }
             return true;
         }
     }
 
-    while let Ok(Some(ent)) = sys::readdir(fd, None) {
-        let name = core::str::from_utf8(&ent.name[..ent.name_len]).unwrap();
-        if name.starts_with(".") && !all {
+    let mut entries = Vec::new();
+    while let Ok(Some(ent)) = sys::readdir(fd.as_raw_fd(), None) {
+        if entry_name(&ent).starts_with('.') && !opts.all {
             continue;
         }
+        entries.push(ent);
+    }
 
-        if ent.stat.directory {
-            println!("dr--@ {:>4}  {name}", "-");
-        } else {
-            println!(
-                ".r{}x@ {}  {name}",
-                if ent.stat.readonly { "-" } else { "w" },
-                Size(ent.stat.size)
-            );
+    entries.sort_by(|a, b| {
+        let by_name = entry_name(a).cmp(entry_name(b));
+        match opts.sort {
+            SortBy::Name => by_name,
+            // largest first, like ls
+            SortBy::Size => b.stat.size.cmp(&a.stat.size).then(by_name),
+        }
+    });
+    if opts.reverse {
+        entries.reverse();
+    }
+
+    if opts.long {
+        for ent in entries.iter() {
+            long_entry(entry_name(ent), &ent.stat);
         }
+    } else {
+        columns(&entries.iter().map(entry_name).collect::<Vec<_>>());
     }
 
     true
@@ -91,14 +192,28 @@ fn main(args: &[*const u8]) -> usize {
         .iter()
         .map(|arg| unsafe { CStr::from_ptr(arg.cast()).to_bytes() });
 
-    let mut all = false;
+    let mut opts = Options {
+        all: false,
+        long: false,
+        sort: SortBy::Name,
+        reverse: false,
+    };
     for arg in args.clone() {
         if !arg.starts_with(b"-") {
             continue;
         }
 
-        if arg.contains(&b'a') {
-            all = true;
+        for &opt in &arg[1..] {
+            match opt {
+                b'a' => opts.all = true,
+                b'l' => opts.long = true,
+                b'S' => opts.sort = SortBy::Size,
+                b'r' => opts.reverse = true,
+                _ => {
+                    println!("ls: unknown option '{}'\n{USAGE}", opt as char);
+                    return 1;
+                }
+            }
         }
     }
 
@@ -112,13 +227,13 @@ fn main(args: &[*const u8]) -> usize {
         if printed {
             println!();
         }
-        if !printdir(path, printed, all) {
+        if !printdir(path, printed, &opts) {
             ecode = 1;
         }
         printed = true;
     }
 
-    if !printed && !printdir(".", false, all) {
+    if !printed && !printdir(".", false, &opts) {
         ecode = 1;
     }
 
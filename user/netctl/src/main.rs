@@ -0,0 +1,139 @@
+#![no_std]
+#![no_main]
+
+use core::ffi::CStr;
+
+use userstd::{
+    fd::{AsRawFd, OwnedFd},
+    io::OpenFlags,
+    net::{HwAddr, Ipv4Addr, NeighborEntry, RouteEntry, IFACE_LO, IFACE_TUN},
+    println,
+    sys::{self, RawFd, SysError},
+};
+
+const USAGE: &str = "usage: netctl route [add <dest>/<prefix> [via <gateway>] [dev <iface>]]
+       netctl route del <dest>/<prefix>
+       netctl arp [add <addr> <hwaddr> [dev <iface>]]
+       netctl arp del <addr> [dev <iface>]";
+
+fn show(path: &[u8]) -> Result<(), SysError> {
+    let fd = OwnedFd::open(path, OpenFlags::empty())?;
+    let mut buf = [0; 0x400];
+    while let Ok(n) = sys::read(fd.as_raw_fd(), None, &mut buf) {
+        _ = sys::write(RawFd(0), None, &buf[..n]);
+    }
+    fd.close()
+}
+
+fn parse_addr(arg: &[u8]) -> Option<Ipv4Addr> {
+    core::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+fn parse_network(arg: &[u8]) -> Option<(Ipv4Addr, u8)> {
+    let (addr, prefix) = core::str::from_utf8(arg).ok()?.split_once('/')?;
+    Some((addr.parse().ok()?, prefix.parse().ok()?))
+}
+
+fn parse_hwaddr(arg: &[u8]) -> Option<HwAddr> {
+    let mut hwaddr = [0; 6];
+    let mut parts = core::str::from_utf8(arg).ok()?.split(':');
+    for byte in hwaddr.iter_mut() {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    parts.next().is_none().then_some(hwaddr)
+}
+
+fn parse_iface(arg: &[u8]) -> Option<u32> {
+    match arg {
+        b"lo" => Some(IFACE_LO),
+        b"tun0" => Some(IFACE_TUN),
+        _ => None,
+    }
+}
+
+fn route(args: &[&[u8]]) -> Option<Result<(), SysError>> {
+    let (&op, args) = args.split_first()?;
+    let (dest, prefix_len) = parse_network(args.first()?)?;
+    let mut route = RouteEntry {
+        dest: dest.octets(),
+        prefix_len,
+        gateway: [0; 4],
+        iface: IFACE_LO,
+    };
+
+    for opt in args[1..].chunks(2) {
+        match opt {
+            [b"via", value] if op == b"add" => route.gateway = parse_addr(value)?.octets(),
+            [b"dev", value] if op == b"add" => route.iface = parse_iface(value)?,
+            _ => return None,
+        }
+    }
+
+    match op {
+        b"add" => Some(sys::add_route(&route)),
+        b"del" => Some(sys::del_route(&route)),
+        _ => None,
+    }
+}
+
+fn arp(args: &[&[u8]]) -> Option<Result<(), SysError>> {
+    let (&op, args) = args.split_first()?;
+    let mut neighbor = NeighborEntry {
+        addr: parse_addr(args.first()?)?.octets(),
+        hwaddr: [0; 6],
+        iface: IFACE_LO,
+    };
+
+    let opts = match op {
+        b"add" => {
+            neighbor.hwaddr = parse_hwaddr(args.get(1)?)?;
+            &args[2..]
+        }
+        b"del" => &args[1..],
+        _ => return None,
+    };
+    match opts {
+        [] => {}
+        [b"dev", iface] => neighbor.iface = parse_iface(iface)?,
+        _ => return None,
+    }
+
+    if op == b"add" {
+        Some(sys::add_neighbor(&neighbor))
+    } else {
+        Some(sys::del_neighbor(&neighbor))
+    }
+}
+
+#[no_mangle]
+fn main(args: &[*const u8]) -> usize {
+    let mut argv = [&b""[..]; 8];
+    if args.len() > argv.len() + 1 {
+        println!("{USAGE}");
+        return 1;
+    }
+    for (dst, arg) in argv.iter_mut().zip(&args[1..]) {
+        *dst = unsafe { CStr::from_ptr(arg.cast()).to_bytes() };
+    }
+
+    let argv = &argv[..args.len() - 1];
+    let result = match argv {
+        [b"route"] => Some(show(b"/proc/net/route")),
+        [b"arp"] => Some(show(b"/proc/net/arp")),
+        [b"route", rest @ ..] => route(rest),
+        [b"arp", rest @ ..] => arp(rest),
+        _ => None,
+    };
+
+    match result {
+        Some(Ok(())) => 0,
+        Some(Err(err)) => {
+            println!("netctl: error: {err:?}");
+            1
+        }
+        None => {
+            println!("{USAGE}");
+            1
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Runs synthetic workloads to make changes to the scheduler measurable: CPU bound spinners, pairs
+//! of processes bouncing messages back and forth, and sleepers that stay blocked for the whole run,
+//! which the scheduler still has to skip over in its queues. Reports how fast the work got done and
+//! what `/proc/sched` saw meanwhile. Before that, a single process calls `getpid` in a loop, to
+//! measure what the round trip through the kernel costs for a syscall that never blocks, and sends
+//! keystrokes and pasted lines to itself through the console UART in loopback, to measure how long
+//! input takes to become readable and how much of it gets through.
+//!
+//! Boot with `init=/bin/schedbench` to run it on an otherwise idle system, which is shut down once
+//! the results are printed.
+
+#![no_std]
+#![no_main]
+
+use core::{ffi::CStr, hint::black_box, time::Duration};
+
+use userstd::{
+    alloc::{string::ToString, vec::Vec},
+    io::{self, ModemControl},
+    print, println,
+    sys::{self, KString, PollEntry},
+    time::Instant,
+};
+
+/// Iterations of the spin loop per round
+const SPIN_ROUND: usize = 1 << 16;
+
+/// Used for whatever isn't given on the command line
+const DEFAULT_SPINNERS: usize = 4;
+const DEFAULT_PAIRS: usize = 2;
+const DEFAULT_SLEEPERS: usize = 8;
+const DEFAULT_ROUNDS: usize = 1000;
+const DEFAULT_CALLS: usize = 100000;
+const DEFAULT_KEYS: usize = 200;
+const DEFAULT_LINES: usize = 100;
+
+/// How much a 16550 can receive before it's drained. In loopback, writing more at once overruns
+/// it, since the interrupt can't be handled until the write is done with the UART.
+const FIFO_LEN: usize = 16;
+/// Input that hasn't come back by then was lost
+const CONSOLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn spin(rounds: usize) -> usize {
+    for _ in 0..rounds {
+        for i in 0..SPIN_ROUND {
+            black_box(i);
+        }
+    }
+    0
+}
+
+/// Send `rounds` messages to `peer` and wait for each to be answered
+fn ping(rounds: usize, peer: u32) -> usize {
+    let mut buf = [0; 8];
+    for i in 0..rounds {
+        if sys::sendmsg(peer, &i.to_le_bytes()).is_err() || sys::recvmsg(&mut buf).is_err() {
+            return 1;
+        }
+    }
+    0
+}
+
+/// Answer `rounds` messages from whoever sends them
+fn pong(rounds: usize) -> usize {
+    let mut buf = [0; 8];
+    for _ in 0..rounds {
+        let Ok((len, sender)) = sys::recvmsg(&mut buf) else {
+            return 1;
+        };
+        if sys::sendmsg_handle(sender, &buf[..len]).is_err() {
+            return 1;
+        }
+    }
+    0
+}
+
+fn spawn(mode: &str, args: &[&str]) -> u32 {
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let mut argv = Vec::from([KString::new("schedbench"), KString::new(mode)]);
+    argv.extend(args.iter().map(KString::new));
+    sys::spawn_argv(&*path, &argv, None).unwrap()
+}
+
+/// The number after `key` on each line of `/proc/sched` that has one
+fn fields<'a>(sched: &'a [u8], key: &'a str) -> impl Iterator<Item = u64> + 'a {
+    sched.split(|&c| c == b'\n').filter_map(move |line| {
+        let mut words = line.split(|&c| c == b' ');
+        words.find(|&word| word == key.as_bytes())?;
+        core::str::from_utf8(words.next()?).ok()?.parse().ok()
+    })
+}
+
+/// How many times something happened per second, over `us` microseconds
+fn per_sec(count: usize, us: u64) -> u64 {
+    count as u64 * 1_000_000 / us.max(1)
+}
+
+/// The longest any hart has been counting for, according to `/proc/sched`
+fn elapsed_us() -> Option<u64> {
+    fields(&io::read_file(b"/proc/sched").ok()?, "elapsed_us").max()
+}
+
+fn bench_syscalls(calls: usize) -> usize {
+    if let Err(err) = sys::reset_trap_stats(sys::ALL_HARTS) {
+        println!("schedbench: couldn't reset the counters: {err:?}");
+        return 1;
+    }
+    for _ in 0..calls {
+        black_box(sys::getpid());
+    }
+    let Some(elapsed) = elapsed_us() else {
+        println!("schedbench: couldn't read /proc/sched");
+        return 1;
+    };
+    println!("getpid:      {} calls/s", per_sec(calls, elapsed));
+    0
+}
+
+/// Send `line`, which ends with a carriage return, through the console and wait until it can be
+/// read back. Returns false if it doesn't all come back.
+fn loop_line(line: &[u8], buf: &mut [u8]) -> bool {
+    for chunk in line.chunks(FIFO_LEN) {
+        if sys::write(io::STDOUT, None, chunk).is_err() {
+            return false;
+        }
+    }
+
+    // spin instead of blocking, so a lost byte can't hang the benchmark
+    let start = Instant::now();
+    let mut got = 0;
+    while got < line.len() {
+        let mut entry = [PollEntry::readable(io::STDIN.0)];
+        match sys::try_poll(&mut entry) {
+            Ok(_) if entry[0].is_ready() => {}
+            Ok(_) if start.elapsed() < CONSOLE_TIMEOUT => continue,
+            _ => return false,
+        }
+        match sys::read(io::STDIN, None, &mut buf[got..]) {
+            Ok(read) => got += read,
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+fn bench_console(keys: usize, lines: usize) -> usize {
+    let Ok(modem) = sys::console_modem_control(io::STDIN, None) else {
+        println!("schedbench: the console isn't a UART, skipping the console benchmark");
+        return 0;
+    };
+    // nothing can be printed until loopback is off again, or it would come back as input
+    let echo = sys::console_echo(io::STDIN, Some(false)).unwrap_or(true);
+    _ = sys::console_dropped(io::STDIN);
+    _ = sys::console_modem_control(io::STDIN, Some(modem | ModemControl::Loopback));
+
+    let mut buf = [0; 64];
+    let (mut total, mut worst, mut lost) = (Duration::ZERO, Duration::ZERO, 0);
+    for _ in 0..keys {
+        let start = Instant::now();
+        if !loop_line(b"k\r", &mut buf) {
+            lost += 1;
+            continue;
+        }
+        let latency = start.elapsed();
+        total += latency;
+        worst = worst.max(latency);
+    }
+
+    let mut line = [b'x'; 64];
+    line[63] = b'\r';
+    let start = Instant::now();
+    for _ in 0..lines {
+        if !loop_line(&line, &mut buf) {
+            lost += 1;
+        }
+    }
+    let paste = start.elapsed();
+
+    _ = sys::console_modem_control(io::STDIN, Some(modem));
+    _ = sys::console_echo(io::STDIN, Some(echo));
+    let dropped = sys::console_dropped(io::STDIN).unwrap_or(0);
+
+    let received = keys.saturating_sub(lost).max(1) as u128;
+    println!(
+        "keystroke:   {} us average, {} us worst",
+        total.as_micros() / received,
+        worst.as_micros()
+    );
+    println!(
+        "paste:       {} bytes/s",
+        per_sec(lines * line.len(), paste.as_micros() as u64)
+    );
+    if lost != 0 || dropped != 0 {
+        println!("schedbench: {lost} lines lost, {dropped} bytes dropped");
+        return 1;
+    }
+    0
+}
+
+fn bench(spinners: usize, pairs: usize, sleepers: usize, rounds: usize) -> usize {
+    println!(
+        "schedbench: {spinners} spinners, {pairs} ping pong pairs, {sleepers} sleepers, {rounds} rounds each"
+    );
+
+    let sleeping: Vec<_> = (0..sleepers).map(|_| spawn("sleep", &[])).collect();
+    if let Err(err) = sys::reset_trap_stats(sys::ALL_HARTS) {
+        println!("schedbench: couldn't reset the counters: {err:?}");
+        return 1;
+    }
+
+    let rounds_arg = rounds.to_string();
+    let mut workers: Vec<_> = (0..spinners)
+        .map(|_| spawn("spin", &[&rounds_arg]))
+        .collect();
+    for _ in 0..pairs {
+        let pong = spawn("pong", &[&rounds_arg]);
+        workers.push(spawn("ping", &[&rounds_arg, &pong.to_string()]));
+        workers.push(pong);
+    }
+
+    let mut failed = 0;
+    for pid in workers {
+        if sys::waitpid(pid) != Ok(0) {
+            failed += 1;
+        }
+    }
+    let sched = io::read_file(b"/proc/sched");
+
+    for pid in sleeping {
+        _ = sys::sendmsg(pid, &[0]);
+        _ = sys::waitpid(pid);
+    }
+
+    let sched = match sched {
+        Ok(sched) => sched,
+        Err(err) => {
+            println!("schedbench: couldn't read /proc/sched: {err:?}");
+            return 1;
+        }
+    };
+    let elapsed = fields(&sched, "elapsed_us").max().unwrap_or(0);
+    println!("elapsed:     {} ms", elapsed / 1000);
+    println!(
+        "spinning:    {} rounds/s",
+        per_sec(spinners * rounds, elapsed)
+    );
+    println!(
+        "ping pong:   {} round trips/s",
+        per_sec(pairs * rounds, elapsed)
+    );
+    let busy = || fields(&sched, "busy_us");
+    if let (Some(min), Some(max)) = (busy().min(), busy().max()) {
+        println!(
+            "balance:     {}% (least / most busy hart)",
+            min * 100 / max.max(1)
+        );
+    }
+    print!("{}", core::str::from_utf8(&sched).unwrap_or("?"));
+
+    if failed != 0 {
+        println!("schedbench: {failed} workers failed");
+        return 1;
+    }
+    0
+}
+
+#[no_mangle]
+fn main(args: &[*const u8]) -> usize {
+    let args: Vec<_> = args[1..]
+        .iter()
+        .map(|arg| unsafe { CStr::from_ptr(arg.cast()) }.to_str().unwrap_or(""))
+        .collect();
+    let num = |arg: Option<&&str>| arg.and_then(|arg| arg.parse().ok());
+
+    match args.first().copied() {
+        Some("spin") => return num(args.get(1)).map_or(1, spin),
+        Some("ping") => {
+            return match (num(args.get(1)), num(args.get(2))) {
+                (Some(rounds), Some(peer)) => ping(rounds, peer as u32),
+                _ => 1,
+            }
+        }
+        Some("pong") => return num(args.get(1)).map_or(1, pong),
+        Some("sleep") => return sys::recvmsg(&mut [0; 8]).map_or(1, |_| 0),
+        _ => {}
+    }
+
+    let (mut spinners, mut pairs, mut sleepers, mut rounds, mut calls, mut keys, mut lines) = (
+        DEFAULT_SPINNERS,
+        DEFAULT_PAIRS,
+        DEFAULT_SLEEPERS,
+        DEFAULT_ROUNDS,
+        DEFAULT_CALLS,
+        DEFAULT_KEYS,
+        DEFAULT_LINES,
+    );
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        let value = match arg {
+            "-s" => &mut spinners,
+            "-p" => &mut pairs,
+            "-k" => &mut sleepers,
+            "-n" => &mut rounds,
+            "-g" => &mut calls,
+            "-t" => &mut keys,
+            "-l" => &mut lines,
+            _ => return usage(),
+        };
+        let Some(num) = num(args.next()) else {
+            return usage();
+        };
+        *value = num;
+    }
+
+    let res = bench_syscalls(calls)
+        | bench_console(keys, lines)
+        | bench(spinners, pairs, sleepers, rounds);
+    // started as init, so there's nothing else to go back to
+    if sys::getpid() == 0 {
+        _ = sys::shutdown(false);
+    }
+    res
+}
+
+fn usage() -> usize {
+    println!(
+        "usage: schedbench [-s spinners] [-p pairs] [-k sleepers] [-n rounds] [-g calls] [-t keys] [-l lines]"
+    );
+    1
+}
@@ -3,36 +3,172 @@
 
 use userstd::{
     alloc::vec::Vec,
+    crash,
+    io::Mode,
     print, println,
-    sys::{self, KString, RawFd, SysError},
+    sys::{self, KString, PollEntry, RawFd, SchedClass, SysError},
 };
 
 static PATH: &[&[u8]] = &[b"/bin", b"/sbin"];
 
-fn read_buf(buf: &mut [u8]) -> usize {
+enum Event {
+    /// This much was read from the console
+    Input(usize),
+    /// Background jobs finished and were reported
+    JobsDone,
+}
+
+/// Wait for console input or for background jobs to finish, whichever comes first
+fn wait_event(buf: &mut [u8], jobs: &mut Vec<u32>) -> Event {
     loop {
+        // if there are too many jobs to watch at once, the rest are noticed once these finish
+        let mut entries: Vec<PollEntry> = core::iter::once(PollEntry::readable(1))
+            .chain(
+                jobs.iter()
+                    .take(sys::MAX_POLL - 1)
+                    .map(|&pid| PollEntry::exited(pid as u64)),
+            )
+            .collect();
+        if sys::poll(&mut entries).is_err() {
+            continue;
+        }
+
+        let mut done = false;
+        for entry in entries[1..].iter().filter(|e| e.is_ready()) {
+            let pid = entry.id as u32;
+            jobs.retain(|&job| job != pid);
+            match entry.value {
+                usize::MAX => {
+                    println!("\n[{pid}] done");
+                    report_crash(pid);
+                }
+                code => println!("\n[{pid}] done, exit code {code}"),
+            }
+            done = true;
+        }
+        if done {
+            return Event::JobsDone;
+        }
+
         match sys::read(RawFd(1), None, buf) {
-            Ok(n) if n != 0 => return n,
+            Ok(n) if n != 0 => return Event::Input(n),
             _ => continue,
         }
     }
 }
 
-fn try_spawn_in_path(path: &[&[u8]], cmd: &[u8], args: &[KString]) -> Option<u32> {
+/// Write a crash report if `pid` was killed for an exception, and say where it went
+fn report_crash(pid: u32) {
+    match crash::write_report(pid as u64) {
+        Ok(path) => println!("sh: PID {pid} crashed, report written to {path}"),
+        Err(SysError::NotFound) => {}
+        Err(err) => println!("sh: couldn't write a crash report for PID {pid}: {err:?}"),
+    }
+}
+
+/// `argv[0]` is the command as it was typed, wherever it's found
+fn try_spawn_in_path(
+    path: &[&[u8]],
+    cmd: &[u8],
+    argv: &[KString],
+    cwd: Option<&[u8]>,
+) -> Result<u32, SysError> {
     for dir in path {
         let mut buf = dir.to_vec();
         buf.push(b'/');
         buf.extend(cmd);
-        if let Ok(pid) = sys::spawn(buf, args) {
-            return Some(pid);
+        match sys::spawn_argv(&buf, argv, cwd) {
+            Err(SysError::PathNotFound) => continue,
+            res => return res,
+        }
+    }
+
+    Err(SysError::PathNotFound)
+}
+
+/// `umask` prints the mask as letters (`w` for write, `x` for exec, `-` for nothing), `umask wx`
+/// sets it
+fn umask(cmd: &[&[u8]]) {
+    let mask = match cmd {
+        [_] => None,
+        [_, b"-"] => Some(Mode::empty()),
+        [_, letters] => {
+            let mut mask = Mode::empty();
+            for letter in letters.iter() {
+                match letter {
+                    b'w' => mask |= Mode::Write,
+                    b'x' => mask |= Mode::Exec,
+                    _ => {
+                        println!("umask: expected some of 'wx', or '-'");
+                        return;
+                    }
+                }
+            }
+            Some(mask)
+        }
+        _ => {
+            println!("umask: too many arguments");
+            return;
+        }
+    };
+
+    match sys::umask(mask) {
+        Ok(prev) if mask.is_none() => {
+            if prev.is_empty() {
+                println!("-");
+            } else {
+                let w = if prev.contains(Mode::Write) { "w" } else { "" };
+                let x = if prev.contains(Mode::Exec) { "x" } else { "" };
+                println!("{w}{x}");
+            }
         }
+        Ok(_) => {}
+        Err(err) => println!("umask: error: {err:?}"),
     }
+}
+
+/// Run `(cd dir && cmd...)`, the only kind of subshell we support, without changing our own
+/// working directory
+fn parse_subshell(raw: &str, cmd: &[&[u8]], jobs: &mut Vec<u32>) -> usize {
+    let mut inner = cmd.to_vec();
+    inner[0] = &inner[0][1..];
+    let last = inner.len() - 1;
+    inner[last] = &inner[last][..inner[last].len() - 1];
+    inner.retain(|arg| !arg.is_empty());
 
-    None
+    match &inner[..] {
+        [cd, dir, and, cmd @ ..] if cd == b"cd" && and == b"&&" && !cmd.is_empty() => {
+            run_cmd(raw, cmd, Some(dir), jobs)
+        }
+        _ => {
+            println!("sh: only '(cd dir && cmd)' is supported in parentheses");
+            0
+        }
+    }
 }
 
-fn parse_cmd(raw: &str, cmd: &[&[u8]]) -> usize {
-    if cmd[0] == b"cd" {
+fn bell(cmd: &[&[u8]]) {
+    let on = match cmd {
+        [_] => None,
+        [_, b"on"] => Some(true),
+        [_, b"off"] => Some(false),
+        _ => {
+            println!("bell: expected 'on' or 'off'");
+            return;
+        }
+    };
+
+    match sys::console_bell(RawFd(1), on) {
+        Ok(prev) if on.is_none() => println!("{}", if prev { "on" } else { "off" }),
+        Ok(_) => {}
+        Err(err) => println!("bell: error: {err:?}"),
+    }
+}
+
+fn parse_cmd(raw: &str, cmd: &[&[u8]], jobs: &mut Vec<u32>) -> usize {
+    if cmd[0].starts_with(b"(") && cmd[cmd.len() - 1].ends_with(b")") {
+        parse_subshell(raw, cmd, jobs)
+    } else if cmd[0] == b"cd" {
         if cmd.len() > 2 {
             println!("cd: too many arguments");
         } else {
@@ -42,56 +178,114 @@ fn parse_cmd(raw: &str, cmd: &[&[u8]]) -> usize {
             }
         }
 
+        0
+    } else if cmd[0] == b"umask" {
+        umask(cmd);
+        0
+    } else if cmd[0] == b"bell" {
+        bell(cmd);
         0
     } else {
-        let mut args = Vec::new();
-        let mut bg = false;
-        for arg in cmd[1..].iter() {
-            if arg == b"&" {
-                bg = true;
-            } else {
-                args.push(KString::new(arg));
-            }
+        run_cmd(raw, cmd, None, jobs)
+    }
+}
+
+fn run_cmd(raw: &str, cmd: &[&[u8]], cwd: Option<&[u8]>, jobs: &mut Vec<u32>) -> usize {
+    let mut args = Vec::from([KString::new(cmd[0])]);
+    let mut bg = false;
+    for arg in cmd[1..].iter() {
+        if arg == b"&" {
+            bg = true;
+        } else {
+            args.push(KString::new(arg));
         }
+    }
 
-        let pid = match sys::spawn(cmd[0], &args) {
-            Ok(pid) => pid,
-            Err(err @ SysError::PathNotFound) if !cmd[0].contains(&b'/') => {
-                if let Some(pid) = try_spawn_in_path(PATH, cmd[0], &args) {
-                    pid
-                } else {
-                    println!("spawn error for '{raw}': {err:?}");
-                    return 0;
-                }
-            }
-            Err(err) => {
-                println!("spawn error for '{raw}': {err:?}");
-                return 0;
+    let pid = match sys::spawn_argv(cmd[0], &args, cwd) {
+        Err(SysError::PathNotFound) if !cmd[0].contains(&b'/') => {
+            try_spawn_in_path(PATH, cmd[0], &args, cwd)
+        }
+        res => res,
+    };
+    let pid = match pid {
+        Ok(pid) => pid,
+        Err(SysError::PermissionDenied) => {
+            println!(
+                "sh: '{}': not executable",
+                core::str::from_utf8(cmd[0]).unwrap_or("?")
+            );
+            return 0;
+        }
+        Err(err) => {
+            match err.exec_reason() {
+                Some(reason) => println!("spawn error for '{raw}': {reason} ({err:?})"),
+                None => println!("spawn error for '{raw}': {err:?}"),
             }
-        };
+            return 0;
+        }
+    };
 
-        if bg {
-            println!("spawned background task with PID {pid}");
-            0
-        } else {
-            sys::waitpid(pid).unwrap_or(0)
+    if bg {
+        println!("spawned background task with PID {pid}");
+        jobs.push(pid);
+        0
+    } else {
+        let code = sys::waitpid(pid).unwrap_or(0);
+        if code == usize::MAX {
+            report_crash(pid);
         }
+        code
     }
 }
 
 #[no_mangle]
 fn main(_args: &[*const u8]) -> usize {
+    _ = sys::priority(sys::getpid(), Some(SchedClass::Interactive));
+
     let mut buf = [0; 0x1000];
+    // the console can hand over part of a line when a lot is pasted at once, so commands only run
+    // once their newline arrives
+    let mut pending = Vec::new();
+    let mut jobs = Vec::new();
     let mut last = 0;
     loop {
-        match last {
-            usize::MAX => print!("[\x1b[1;31m-1\x1b[0m] "),
-            n if n != 0 => print!("[\x1b[1;31m{n}\x1b[0m] "),
-            _ => {}
-        }
-        print!("\x1b[1;32m$ \x1b[0m");
-        let n = read_buf(&mut buf);
-        for cmd in buf[..n].split(|&c| c == b'\n') {
+        if pending.is_empty() {
+            // shown even with the bell off, so a paste that didn't fit doesn't go unnoticed
+            if let Ok(n @ 1..) = sys::console_dropped(RawFd(1)) {
+                println!("\x1b[1;33m[{n} bytes of input dropped]\x1b[0m");
+            }
+            match last {
+                usize::MAX => print!("[\x1b[1;31m-1\x1b[0m] "),
+                n if n != 0 => print!("[\x1b[1;31m{n}\x1b[0m] "),
+                _ => {}
+            }
+            let mut hostname = [0; sys::MAX_HOSTNAME_LEN];
+            if let Ok(hostname) = sys::gethostname(&mut hostname) {
+                print!(
+                    "\x1b[1;35m{}\x1b[0m:",
+                    core::str::from_utf8(hostname).unwrap_or("?")
+                );
+            }
+            let mut cwd = [0; 0x100];
+            if let Ok(cwd) = sys::getcwd(&mut cwd) {
+                print!(
+                    "\x1b[1;34m{}\x1b[0m ",
+                    core::str::from_utf8(cwd).unwrap_or("?")
+                );
+            }
+            print!("\x1b[1;32m$ \x1b[0m");
+        }
+
+        let n = match wait_event(&mut buf, &mut jobs) {
+            Event::Input(n) => n,
+            Event::JobsDone => continue,
+        };
+        pending.extend_from_slice(&buf[..n]);
+        let Some(end) = pending.iter().rposition(|&c| c == b'\n') else {
+            continue;
+        };
+        let lines: Vec<u8> = pending.drain(..=end).collect();
+        for cmd in lines.split(|&c| c == b'\n') {
             let args: Vec<&[u8]> = cmd
                 .split(|&c| c == b' ')
                 .filter(|a| !a.is_empty())
@@ -99,7 +293,7 @@ fn main(_args: &[*const u8]) -> usize {
             if args.is_empty() {
                 continue;
             }
-            last = parse_cmd(core::str::from_utf8(cmd).unwrap(), &args);
+            last = parse_cmd(core::str::from_utf8(cmd).unwrap(), &args, &mut jobs);
         }
     }
 }
@@ -1,12 +1,29 @@
 #![no_std]
 #![no_main]
 
+use core::{ffi::CStr, mem::size_of, time::Duration};
+
 use userstd::{
-    io::OpenFlags,
+    alloc::{self, format, string::ToString, vec, vec::Vec},
+    crash,
+    fd::{AsFd, AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
+    filter::{Field, Hook, Insn, Op},
+    io::{self, ConsoleCtl, DeviceId, Mode, ModemControl, ModemStatus, OpenFlags, UnmountFlags},
+    log::{LogLevel, LogSubsystem},
+    net::{
+        self, dns, IpAddr, Ipv4Addr, NeighborEntry, RouteEntry, SockOpt, SocketAddrV4, SocketType,
+        TcpListener, TcpStream, UdpSocket, IFACE_LO,
+    },
     print, println,
-    sys::{self, SysError},
+    sys::{
+        self, AcctRecord, Counters, KString, PollEntry, SchedClass, Sys, SysError, SYSCALL_ERR_REG,
+        SYSCALL_MAX_ARGS, SYSCALL_NR_REG, SYSCALL_RET_REG,
+    },
+    time::Instant,
 };
 
+mod tcp;
+
 static mut GLOBAL_STATIC: usize = 5;
 
 #[link_section = ".bss"]
@@ -61,11 +78,1809 @@ fn test_fd_cursor() {
     _ = sys::close(fd);
 }
 
+fn test_loopback_udp() {
+    print!("loopback udp test: ");
+    let a = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 7000)).unwrap();
+    let b = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+
+    let dst = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 7000);
+    assert_eq!(b.send_to(b"hello", dst), Ok(5));
+
+    let mut buf = [0; 16];
+    let (n, from) = a.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    assert_eq!(a.send_to(b"world", from), Ok(5));
+    let (n, _) = b.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"world");
+    assert_eq!(a.try_recv_from(&mut buf), Err(SysError::WouldBlock));
+
+    println!("GOOD");
+}
+
+fn test_loopback_tcp() {
+    print!("loopback tcp test: ");
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8000);
+    let listener = TcpListener::bind(addr).unwrap();
+    let client = TcpStream::connect(addr).unwrap();
+    let (server, _) = listener.accept().unwrap();
+
+    // larger than one segment, then larger than the receive buffer
+    let data = [b'x'; 4000];
+    for _ in 0..2 {
+        client.write_all(&data).unwrap();
+        let mut buf = [0; 4000];
+        let mut total = 0;
+        while total < data.len() {
+            total += server.read(&mut buf[total..]).unwrap();
+        }
+        assert_eq!(buf, data);
+
+        sys::setsockopt(server.fd(), SockOpt::RecvBuf, 0).unwrap();
+    }
+    assert_eq!(sys::getsockopt(server.fd(), SockOpt::RecvBuf), Ok(2048));
+
+    server.write_all(b"bye").unwrap();
+    drop(server);
+    let mut buf = [0; 8];
+    assert_eq!(client.read(&mut buf), Ok(3));
+    assert_eq!(&buf[..3], b"bye");
+    assert_eq!(client.read(&mut buf), Ok(0));
+
+    assert_eq!(
+        TcpStream::connect(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8001)).err(),
+        Some(SysError::ConnRefused)
+    );
+    println!("GOOD");
+}
+
+fn test_routes() {
+    print!("route table test: ");
+    let route = RouteEntry {
+        dest: [10, 0, 0, 0],
+        prefix_len: 8,
+        gateway: [127, 0, 0, 2],
+        iface: IFACE_LO,
+    };
+    assert_eq!(sys::add_route(&route), Ok(()));
+    assert_eq!(sys::add_route(&route), Err(SysError::AlreadyExists));
+
+    let fd = sys::open(b"/proc/net/route", OpenFlags::empty()).unwrap();
+    let mut buf = [0; 0x200];
+    let n = sys::read(fd, None, &mut buf).unwrap();
+    _ = sys::close(fd);
+    let table = core::str::from_utf8(&buf[..n]).unwrap();
+    assert!(table
+        .lines()
+        .any(|l| l.starts_with("10.0.0.0 ") && l.contains("127.0.0.2")));
+    assert!(table.lines().any(|l| l.starts_with("127.0.0.0 ")));
+
+    // the gateway has to be on-link, and host bits must be clear
+    let offlink = RouteEntry {
+        dest: [192, 168, 0, 0],
+        gateway: [10, 0, 0, 1],
+        ..route
+    };
+    assert_eq!(sys::add_route(&offlink), Err(SysError::AddrNotAvailable));
+    let hostbits = RouteEntry {
+        dest: [10, 0, 0, 1],
+        ..route
+    };
+    assert_eq!(sys::add_route(&hostbits), Err(SysError::BadArg));
+
+    assert_eq!(sys::del_route(&route), Ok(()));
+    assert_eq!(sys::del_route(&route), Err(SysError::NotFound));
+
+    // lo has no link layer, so there are no neighbors to add
+    let neighbor = NeighborEntry {
+        addr: [127, 0, 0, 2],
+        hwaddr: [2, 0, 0, 0, 0, 1],
+        iface: IFACE_LO,
+    };
+    assert_eq!(sys::add_neighbor(&neighbor), Err(SysError::Unsupported));
+    assert_eq!(sys::del_neighbor(&neighbor), Err(SysError::NotFound));
+
+    println!("GOOD");
+}
+
+fn test_pcap() {
+    print!("packet capture test: ");
+    let fd = sys::open(b"/dev/pcap0", OpenFlags::empty()).unwrap();
+    let mut hdr = [0; 24];
+    assert_eq!(sys::read(fd, None, &mut hdr), Ok(24));
+    assert_eq!(hdr[..4], 0xa1b2c3d4u32.to_le_bytes());
+    assert_eq!(hdr[20..], 101u32.to_le_bytes());
+
+    // discard whatever earlier tests captured
+    let mut buf = [0; 0x400];
+    while sys::read(fd, None, &mut buf).is_ok() {}
+
+    let sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 7001)).unwrap();
+    let dst = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 7001);
+    assert_eq!(sock.send_to(b"hello", dst), Ok(5));
+
+    // one record: a 16 byte header, then the 20 byte IP header, 8 byte UDP header and payload
+    assert_eq!(sys::read(fd, None, &mut buf), Ok(16 + 33));
+    assert_eq!(buf[8..12], 33u32.to_le_bytes());
+    assert_eq!(buf[12..16], 33u32.to_le_bytes());
+    assert_eq!(&buf[16 + 28..16 + 33], b"hello");
+    assert_eq!(sys::read(fd, None, &mut buf), Err(SysError::Eof));
+    _ = sys::close(fd);
+
+    println!("GOOD");
+}
+
+fn test_fragmentation() {
+    print!("ip fragmentation test: ");
+    let a = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 7002)).unwrap();
+    let b = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let dst = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 7002);
+
+    // a few fragments, then enough to nearly fill the receive buffer
+    let mut buf = alloc::vec![0u8; 60000];
+    for len in [20000, 60000] {
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        assert_eq!(b.send_to(&data, dst), Ok(len));
+        let (n, _) = a.recv_from(&mut buf).unwrap();
+        assert_eq!(n, len);
+        assert!(buf[..n] == data[..]);
+    }
+
+    println!("GOOD");
+}
+
+fn test_dns() {
+    print!("dns test: ");
+    let conf =
+        b"# local\nnameserver 10.0.2.3\nsearch example\nnameserver bogus\n nameserver 1.1.1.1 # cf\n";
+    assert!(dns::nameservers(conf).eq([Ipv4Addr::new(10, 0, 2, 3), Ipv4Addr::new(1, 1, 1, 1)]));
+    let hosts = b"127.0.0.1 localhost\n10.0.0.5  Build build.lan # ci\n";
+    assert_eq!(
+        net::lookup_hosts(hosts, "build.lan"),
+        Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5)))
+    );
+    assert_eq!(net::lookup_hosts(hosts, "ci"), None);
+
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let me = sys::getpid().to_string();
+    let argv = [
+        KString::new("tests"),
+        KString::new("dns-child"),
+        KString::new(&*me),
+    ];
+    let pid = sys::spawn_argv(&*path, &argv, None).unwrap();
+    sys::recvmsg(&mut [0; 1]).unwrap();
+
+    let server = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5353);
+    assert_eq!(
+        dns::query("servos.test.", server),
+        Ok(Ipv4Addr::new(10, 1, 2, 3))
+    );
+    assert_eq!(dns::query("missing.test", server), Err(SysError::NotFound));
+    assert_eq!(dns::query("bad..name", server), Err(SysError::BadArg));
+    assert_eq!(sys::waitpid(pid), Ok(0));
+
+    // query IDs come from here, so two reads shouldn't match
+    let urandom = OwnedFd::open("/dev/urandom", OpenFlags::empty()).unwrap();
+    let (mut a, mut b) = ([0; 16], [0; 16]);
+    assert_eq!(sys::read(urandom.as_raw_fd(), None, &mut a), Ok(16));
+    assert_eq!(sys::read(urandom.as_raw_fd(), None, &mut b), Ok(16));
+    assert_ne!(a, b);
+    println!("GOOD");
+}
+
+fn test_trap_stats() {
+    print!("trap stats test: ");
+    assert_eq!(sys::reset_trap_stats(sys::ALL_HARTS), Ok(()));
+    for _ in 0..10 {
+        sys::getpid();
+    }
+
+    let stats = sys::trap_stats(sys::ALL_HARTS).unwrap();
+    assert!(stats.syscalls[sys::Sys::GetPid as usize] >= 10);
+    assert_eq!(stats.syscalls[sys::Sys::Stats as usize], 1);
+    assert_eq!(
+        sys::trap_stats(usize::MAX - 1).err(),
+        Some(SysError::NotFound)
+    );
+
+    let fd = sys::open(b"/proc/syscalls", OpenFlags::empty()).unwrap();
+    let mut buf = [0; 0x400];
+    let n = sys::read(fd, None, &mut buf).unwrap();
+    _ = sys::close(fd);
+    let table = core::str::from_utf8(&buf[..n]).unwrap();
+    assert!(table.lines().next().unwrap().contains("hart"));
+    assert!(table.lines().any(|l| l.starts_with("GetPid ")));
+
+    let fd = sys::open(b"/proc/interrupts", OpenFlags::empty()).unwrap();
+    let n = sys::read(fd, None, &mut buf).unwrap();
+    _ = sys::close(fd);
+    let table = core::str::from_utf8(&buf[..n]).unwrap();
+    assert!(table.lines().any(|l| l.starts_with("timer ")));
+    assert!(table.lines().any(|l| l.starts_with("EcallFromUMode ")));
+
+    println!("GOOD");
+}
+
+fn test_priority() {
+    print!("scheduling class test: ");
+    let pid = sys::getpid();
+    let prev = sys::priority(pid, Some(SchedClass::Batch)).unwrap();
+    assert_eq!(sys::priority(pid, None), Ok(SchedClass::Batch));
+
+    // a batch process still gets to run, just in longer slices
+    for _ in 0..1000 {
+        sys::getpid();
+    }
+
+    assert_eq!(sys::priority(pid, Some(prev)), Ok(SchedClass::Batch));
+    assert_eq!(sys::priority(pid, None), Ok(prev));
+    assert_eq!(sys::priority(u32::MAX - 1, None), Err(SysError::NotFound));
+    println!("GOOD");
+}
+
+fn test_spawn_cwd() {
+    print!("spawn with a working directory test: ");
+    assert_eq!(sys::spawn("echo", &[]), Err(SysError::PathNotFound));
+    let pid = sys::spawn_in("echo", &[], "/bin").unwrap();
+    assert_eq!(sys::waitpid(pid), Ok(0));
+    assert_eq!(
+        sys::spawn_in("echo", &[], "/1001_A.txt"),
+        Err(SysError::BadArg)
+    );
+    assert_eq!(
+        sys::spawn_in("echo", &[], "/nonexistent"),
+        Err(SysError::PathNotFound)
+    );
+    println!("GOOD");
+}
+
+fn test_owned_fd() {
+    print!("owned fd test: ");
+    let fd = OwnedFd::open("/1001_A.txt", OpenFlags::empty()).unwrap();
+    let raw = fd.as_raw_fd();
+    assert_eq!(sys::stat(raw).map(|stat| stat.size), Ok(0x1001));
+    drop(fd);
+    assert_eq!(sys::stat(raw).err(), Some(SysError::BadFd));
+
+    let raw = OwnedFd::open("/1001_A.txt", OpenFlags::empty())
+        .unwrap()
+        .into_raw_fd();
+    let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+    assert_eq!(
+        userstd::io::read_fd(fd.as_fd()).map(|buf| buf.len()),
+        Ok(0x1001)
+    );
+    assert_eq!(fd.close(), Ok(()));
+    assert_eq!(sys::close(raw), Err(SysError::BadFd));
+    println!("GOOD");
+}
+
+fn test_close_range() {
+    print!("close range test: ");
+    let open = || sys::open("/1001_A.txt", OpenFlags::empty()).unwrap();
+    let fds = [open(), open(), open(), open()];
+    assert_eq!(sys::close_range(fds[1], fds[2]), Ok(2));
+    assert_eq!(sys::stat(fds[1]).err(), Some(SysError::BadFd));
+    assert_eq!(sys::stat(fds[2]).err(), Some(SysError::BadFd));
+    assert_eq!(sys::close_range(fds[1], fds[2]), Ok(0));
+    assert_eq!(sys::close_range(fds[2], fds[1]), Err(SysError::BadArg));
+
+    assert!(sys::close_all_except(&[io::STDIN, fds[3], io::STDOUT]).is_ok_and(|n| n >= 1));
+    assert_eq!(sys::stat(fds[0]).err(), Some(SysError::BadFd));
+    assert!(sys::stat(fds[3]).is_ok());
+    assert!(sys::stat(io::STDOUT).is_ok());
+    assert_eq!(sys::close(fds[3]), Ok(()));
+    println!("GOOD");
+}
+
+fn test_cwd() {
+    print!("working directory test: ");
+    let mut buf = [0; 0x100];
+    let prev = sys::getcwd(&mut buf).unwrap().to_vec();
+
+    sys::chdir("/bin").unwrap();
+    assert_eq!(sys::getcwd(&mut buf).map(|p| &*p), Ok(&b"/bin"[..]));
+    sys::chdir("./../bin/.//..").unwrap();
+    assert_eq!(sys::getcwd(&mut buf).map(|p| &*p), Ok(&b"/"[..]));
+
+    // .. leaves a mount point
+    sys::chdir("/dev").unwrap();
+    sys::chdir("..").unwrap();
+    assert_eq!(sys::getcwd(&mut buf).map(|p| &*p), Ok(&b"/"[..]));
+
+    assert_eq!(sys::chdir("/1001_A.txt"), Err(SysError::BadArg));
+    assert_eq!(sys::chdir("/dev/console"), Err(SysError::BadArg));
+    assert_eq!(sys::getcwd(&mut buf[..0]).err(), Some(SysError::BadArg));
+
+    sys::chdir(&prev).unwrap();
+    println!("GOOD");
+}
+
+fn test_unmount() {
+    print!("unmount test: ");
+    assert_eq!(
+        sys::unmount("/nothing", UnmountFlags::empty()),
+        Err(SysError::PathNotFound)
+    );
+    // /dev and /proc are mounted inside it
+    assert_eq!(
+        sys::unmount("/", UnmountFlags::empty()),
+        Err(SysError::Busy)
+    );
+
+    let fd = OwnedFd::open("/proc", OpenFlags::empty()).unwrap();
+    assert_eq!(
+        sys::unmount("/proc", UnmountFlags::empty()),
+        Err(SysError::Busy)
+    );
+    drop(fd);
+
+    let mut buf = [0; 0x100];
+    let prev = sys::getcwd(&mut buf).unwrap().to_vec();
+    sys::chdir("/proc").unwrap();
+    assert_eq!(
+        sys::unmount(".", UnmountFlags::empty()),
+        Err(SysError::Busy)
+    );
+    sys::chdir(&prev).unwrap();
+    println!("GOOD");
+}
+
+fn test_bind_mount() {
+    print!("bind mount test: ");
+    sys::bind_mount("/bin", "/sandbox/bin").unwrap();
+    sys::bind_mount("/dev", "/sandbox/dev").unwrap();
+    assert_eq!(
+        sys::bind_mount("/bin", "/sandbox/bin"),
+        Err(SysError::AlreadyExists)
+    );
+    assert_eq!(sys::bind_mount("/bin", "/bin/again"), Err(SysError::BadArg));
+    assert_eq!(
+        sys::bind_mount("/1001_A.txt", "/sandbox/a"),
+        Err(SysError::BadArg)
+    );
+
+    let stat = sys::stat(
+        OwnedFd::open("/bin/tests", OpenFlags::empty())
+            .unwrap()
+            .as_raw_fd(),
+    );
+    let bound = OwnedFd::open("/sandbox/bin/tests", OpenFlags::empty()).unwrap();
+    assert_eq!(
+        sys::stat(bound.as_raw_fd()).map(|s| s.size),
+        stat.map(|s| s.size)
+    );
+    assert!(OwnedFd::open("/sandbox/dev/console", OpenFlags::empty()).is_ok());
+    // .. can't climb out of the bind, not even from inside it
+    assert_eq!(
+        OwnedFd::open("/sandbox/bin/../1001_A.txt", OpenFlags::empty()).err(),
+        Some(SysError::PathNotFound)
+    );
+    let mut buf = [0; 0x100];
+    let prev = sys::getcwd(&mut buf).unwrap().to_vec();
+    sys::chdir("/sandbox/bin").unwrap();
+    assert_eq!(
+        OwnedFd::open("../1001_A.txt", OpenFlags::empty()).err(),
+        Some(SysError::PathNotFound)
+    );
+    sys::chdir(&prev).unwrap();
+
+    assert_eq!(
+        sys::unmount("/sandbox/bin", UnmountFlags::empty()),
+        Err(SysError::Busy)
+    );
+    drop(bound);
+    sys::unmount("/sandbox/bin", UnmountFlags::empty()).unwrap();
+    sys::unmount("/sandbox/dev", UnmountFlags::empty()).unwrap();
+    assert_eq!(
+        OwnedFd::open("/sandbox/bin/tests", OpenFlags::empty()).err(),
+        Some(SysError::PathNotFound)
+    );
+    println!("GOOD");
+}
+
+fn test_pivot_root() {
+    print!("pivot_root test: ");
+    let mut buf = [0; 0x100];
+    let prev = sys::getcwd(&mut buf).unwrap().to_vec();
+    sys::bind_mount("/bin", "/pivot").unwrap();
+    assert_eq!(sys::pivot_root("/pivot", "/old"), Err(SysError::BadArg));
+    assert_eq!(sys::pivot_root("/", "/old"), Err(SysError::BadArg));
+    assert_eq!(
+        sys::pivot_root("/nothing", "/nothing/old"),
+        Err(SysError::PathNotFound)
+    );
+    // /dev stays where it is, so the old root can't go there
+    assert_eq!(
+        sys::pivot_root("/pivot", "/pivot/dev"),
+        Err(SysError::AlreadyExists)
+    );
+
+    sys::chdir("/etc").unwrap();
+    sys::pivot_root("/pivot", "/pivot/old").unwrap();
+    assert!(OwnedFd::open("/tests", OpenFlags::empty()).is_ok());
+    assert!(OwnedFd::open("/old/1001_A.txt", OpenFlags::empty()).is_ok());
+    assert!(OwnedFd::open("/dev/console", OpenFlags::empty()).is_ok());
+    assert_eq!(
+        OwnedFd::open("/1001_A.txt", OpenFlags::empty()).err(),
+        Some(SysError::PathNotFound)
+    );
+    assert_eq!(sys::getcwd(&mut buf).unwrap(), b"/old/etc");
+    assert!(OwnedFd::open("hostname", OpenFlags::empty()).is_ok());
+
+    // and back again, which leaves the bind mount where the old root was
+    sys::pivot_root("/old", "/old/pivot").unwrap();
+    assert_eq!(sys::getcwd(&mut buf).unwrap(), b"/etc");
+    assert!(OwnedFd::open("/1001_A.txt", OpenFlags::empty()).is_ok());
+    sys::unmount("/pivot", UnmountFlags::empty()).unwrap();
+    sys::chdir(&prev).unwrap();
+    println!("GOOD");
+}
+
+fn test_overlay() {
+    fn listed(dir: &str, name: &[u8]) -> bool {
+        let dir = OwnedFd::open(dir, OpenFlags::empty()).unwrap();
+        let mut found = false;
+        while let Some(entry) = sys::readdir(dir.as_raw_fd(), None).unwrap() {
+            found |= &entry.name[..entry.name_len] == name;
+        }
+        found
+    }
+
+    print!("overlay test: ");
+
+    // a new directory and file in the upper layer
+    OwnedFd::open("/scratch", OpenFlags::CreateDir).unwrap();
+    let file = OwnedFd::open(
+        "/scratch/file",
+        OpenFlags::CreateFile | OpenFlags::ReadWrite,
+    )
+    .unwrap();
+    assert_eq!(sys::write(file.as_raw_fd(), 0, b"hello"), Ok(5));
+    assert_eq!(sys::write(file.as_raw_fd(), 7, b"!"), Ok(1));
+    assert_eq!(io::read_fd(file.as_fd()).as_deref(), Ok(&b"hello\0\0!"[..]));
+    drop(file);
+    assert!(listed("/", b"scratch"));
+    assert_eq!(sys::remove("/scratch"), Err(SysError::InvalidOp));
+    sys::remove("/scratch/file").unwrap();
+    sys::remove("/scratch").unwrap();
+    assert!(!listed("/", b"scratch"));
+
+    // files from the initrd are copied up before they're written
+    let original = io::read_file(b"/test.txt").unwrap();
+    let ro = OwnedFd::open("/test.txt", OpenFlags::empty()).unwrap();
+    assert_eq!(sys::write(ro.as_raw_fd(), 0, b"x"), Err(SysError::ReadOnly));
+    let file = OwnedFd::open("/test.txt", OpenFlags::ReadWrite).unwrap();
+    assert_eq!(
+        sys::write(file.as_raw_fd(), 0, &original),
+        Ok(original.len())
+    );
+    assert_eq!(io::read_fd(ro.as_fd()), Ok(original));
+
+    // removing them leaves a whiteout that hides the initrd copy
+    let original = io::read_file(b"/bfp/hw.bf").unwrap();
+    sys::remove("/bfp/hw.bf").unwrap();
+    assert_eq!(
+        OwnedFd::open("/bfp/hw.bf", OpenFlags::empty()).err(),
+        Some(SysError::PathNotFound)
+    );
+    assert!(!listed("/bfp", b"hw.bf"));
+    assert!(listed("/bfp", b"rot13.bf"));
+    let file = OwnedFd::open("/bfp/hw.bf", OpenFlags::CreateFile | OpenFlags::ReadWrite).unwrap();
+    assert_eq!(
+        sys::write(file.as_raw_fd(), 0, &original),
+        Ok(original.len())
+    );
+    assert!(listed("/bfp", b"hw.bf"));
+    println!("GOOD");
+}
+
+fn test_exec_permission() {
+    print!("exec permission test: ");
+    let stat = |path| sys::stat(OwnedFd::open(path, OpenFlags::empty()).unwrap().as_raw_fd());
+    assert!(stat("/bin/echo").unwrap().executable);
+    assert!(!stat("/1001_A.txt").unwrap().executable);
+    assert!(!stat("/bin").unwrap().executable);
+    assert_eq!(
+        sys::spawn("/1001_A.txt", &[]),
+        Err(SysError::PermissionDenied)
+    );
+    assert_eq!(sys::spawn("/bin", &[]), Err(SysError::PermissionDenied));
+
+    // copying it into the overlay's upper layer keeps the bit
+    drop(OwnedFd::open("/bin/echo", OpenFlags::ReadWrite).unwrap());
+    assert!(stat("/bin/echo").unwrap().executable);
+    let pid = sys::spawn("/bin/echo", &[]).unwrap();
+    assert_eq!(sys::waitpid(pid), Ok(0));
+    println!("GOOD");
+}
+
+fn test_open_flags() {
+    print!("open flags test: ");
+    let open = |path: &str, flags| OwnedFd::open(path, flags).map(|_| ());
+    let (dir, file) = ("/open-flags-dir", "/open-flags-file");
+    assert_eq!(open(file, OpenFlags::empty()), Err(SysError::PathNotFound));
+    assert_eq!(
+        open(dir, OpenFlags::CreateDir | OpenFlags::CreateFile),
+        Err(SysError::InvalidOp)
+    );
+    assert_eq!(
+        open(dir, OpenFlags::CreateDir | OpenFlags::Truncate),
+        Err(SysError::InvalidOp)
+    );
+    assert_eq!(open(dir, OpenFlags::empty()), Err(SysError::PathNotFound));
+
+    open(dir, OpenFlags::CreateDir).unwrap();
+    open(dir, OpenFlags::CreateDir).unwrap();
+    assert_eq!(open(dir, OpenFlags::CreateFile), Err(SysError::AlreadyExists));
+    assert_eq!(
+        open(dir, OpenFlags::ReadWrite | OpenFlags::Truncate),
+        Err(SysError::InvalidOp)
+    );
+
+    let fd = OwnedFd::open(file, OpenFlags::CreateFile | OpenFlags::ReadWrite).unwrap();
+    assert_eq!(sys::write(fd.as_raw_fd(), None, b"hello"), Ok(5));
+    drop(fd);
+    assert_eq!(open(file, OpenFlags::CreateDir), Err(SysError::AlreadyExists));
+    open(file, OpenFlags::CreateFile).unwrap();
+    assert_eq!(io::read_file(file.as_bytes()).unwrap(), b"hello");
+    open(file, OpenFlags::ReadWrite | OpenFlags::Truncate).unwrap();
+    assert_eq!(io::read_file(file.as_bytes()).unwrap(), b"");
+
+    // filesystems that can't be changed say so, whatever the entry is
+    assert_eq!(
+        open("/proc/open-flags", OpenFlags::CreateFile),
+        Err(SysError::ReadOnly)
+    );
+    assert_eq!(
+        open("/dev/open-flags", OpenFlags::CreateDir),
+        Err(SysError::ReadOnly)
+    );
+    assert_eq!(
+        open("/proc/cpuinfo", OpenFlags::ReadWrite),
+        Err(SysError::ReadOnly)
+    );
+    assert_eq!(
+        open("/proc/missing", OpenFlags::empty()),
+        Err(SysError::PathNotFound)
+    );
+
+    sys::remove(file).unwrap();
+    sys::remove(dir).unwrap();
+    println!("GOOD");
+}
+
+fn test_exec_errors() {
+    print!("exec error test: ");
+    let path = "/exec-error-test";
+    let spawn_with = |data: &[u8]| {
+        let file = sys::open_mode(
+            path,
+            OpenFlags::CreateFile | OpenFlags::ReadWrite | OpenFlags::Truncate,
+            Mode::Write | Mode::Exec,
+        )
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+        .unwrap();
+        let mut buf = data;
+        while !buf.is_empty() {
+            buf = &buf[sys::write(file.as_raw_fd(), None, buf).unwrap()..];
+        }
+        drop(file);
+        sys::spawn(path, &[])
+    };
+    let patched = |offset: usize, bytes: &[u8]| {
+        let mut elf = io::read_file(b"/bin/echo").unwrap();
+        elf[offset..][..bytes.len()].copy_from_slice(bytes);
+        spawn_with(&elf)
+    };
+
+    assert_eq!(spawn_with(b"#!/bin/sh"), Err(SysError::ExecNotElf));
+    // 32 bit, then x86-64, then a shared library
+    assert_eq!(patched(4, &[1]), Err(SysError::ExecWrongArch));
+    assert_eq!(
+        patched(0x12, &62u16.to_le_bytes()),
+        Err(SysError::ExecWrongArch)
+    );
+    assert_eq!(
+        patched(0x10, &3u16.to_le_bytes()),
+        Err(SysError::ExecWrongType)
+    );
+    // program headers past the end of the file
+    assert_eq!(
+        patched(0x20, &u64::MAX.to_le_bytes()),
+        Err(SysError::ExecTruncated)
+    );
+    let elf = io::read_file(b"/bin/echo").unwrap();
+    assert_eq!(spawn_with(&elf[..0x40]), Err(SysError::ExecTruncated));
+    assert_eq!(
+        SysError::ExecWrongArch.exec_reason(),
+        Some("not a 64-bit RISC-V executable")
+    );
+    assert_eq!(SysError::BadArg.exec_reason(), None);
+
+    let pid = spawn_with(&elf).unwrap();
+    assert_eq!(sys::waitpid(pid), Ok(0));
+    sys::remove(path).unwrap();
+    println!("GOOD");
+}
+
+fn test_exe_path() {
+    print!("exe path test: ");
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    assert!(path.starts_with(b"/") && path.ends_with(b"/tests"));
+    assert_eq!(sys::exe_path(&mut buf[..1]).err(), Some(SysError::BadArg));
+
+    let argv = [KString::new("not echo")];
+    let pid = sys::spawn_argv("/bin/echo", &argv, None).unwrap();
+    assert_eq!(sys::waitpid(pid), Ok(0));
+    println!("GOOD");
+}
+
+fn test_umask() {
+    print!("umask test: ");
+    assert_eq!(sys::umask(Some(Mode::Write)), Ok(Mode::empty()));
+    assert_eq!(sys::umask(None), Ok(Mode::Write));
+
+    // the creator can still write to it the first time
+    let path = "/umask-test";
+    let file = sys::open_mode(
+        path,
+        OpenFlags::CreateFile | OpenFlags::ReadWrite,
+        Mode::Write | Mode::Exec,
+    )
+    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+    .unwrap();
+    assert_eq!(sys::write(file.as_raw_fd(), None, b"hi"), Ok(2));
+    let stat = sys::stat(file.as_raw_fd()).unwrap();
+    assert!(stat.readonly && stat.executable);
+    drop(file);
+    assert_eq!(
+        OwnedFd::open(path, OpenFlags::ReadWrite).err(),
+        Some(SysError::ReadOnly)
+    );
+    sys::remove(path).unwrap();
+
+    assert_eq!(sys::umask(Some(Mode::Exec)), Ok(Mode::Write));
+    let file = sys::open_mode(path, OpenFlags::CreateFile, Mode::Write | Mode::Exec)
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+        .unwrap();
+    let stat = sys::stat(file.as_raw_fd()).unwrap();
+    assert!(!stat.readonly && !stat.executable);
+    drop(file);
+    sys::remove(path).unwrap();
+
+    assert_eq!(sys::umask(Some(Mode::empty())), Ok(Mode::Exec));
+    println!("GOOD");
+}
+
+fn test_file_times() {
+    print!("file times test: ");
+    let file = OwnedFd::open("/times-test", OpenFlags::CreateFile | OpenFlags::ReadWrite).unwrap();
+    let stat = || sys::stat(file.as_raw_fd()).unwrap();
+    let created = stat();
+    assert!(created.mtime != 0 && created.atime == created.mtime);
+
+    // there's no way to sleep, so keep writing until the clock moves
+    let mut buf = [0; 1];
+    for _ in 0..100_000 {
+        sys::write(file.as_raw_fd(), 0, b"x").unwrap();
+        if stat().mtime > created.mtime {
+            break;
+        }
+    }
+    let written = stat();
+    assert!(written.mtime > created.mtime && written.atime == created.atime);
+
+    // the first read after a write always updates the access time...
+    for _ in 0..100_000 {
+        sys::read(file.as_raw_fd(), 0, &mut buf).unwrap();
+        if stat().atime > written.mtime {
+            break;
+        }
+    }
+    let read = stat();
+    assert!(read.atime > read.mtime && read.mtime == written.mtime);
+
+    // ...but later ones don't, until it's a day old
+    sys::read(file.as_raw_fd(), 0, &mut buf).unwrap();
+    assert_eq!(stat().atime, read.atime);
+
+    drop(file);
+    sys::remove("/times-test").unwrap();
+    println!("GOOD");
+}
+
+fn test_sparse_file() {
+    // the root filesystem allocates files a page at a time
+    const BLOCK: u64 = 0x1000;
+
+    print!("sparse file test: ");
+    let file = OwnedFd::open("/sparse-test", OpenFlags::CreateFile | OpenFlags::ReadWrite).unwrap();
+    let stat = || sys::stat(file.as_raw_fd()).unwrap();
+
+    // writing past the end leaves a hole instead of allocating it
+    assert_eq!(sys::write(file.as_raw_fd(), 3 * BLOCK + 5, b"!"), Ok(1));
+    assert_eq!(stat().size, 3 * BLOCK as usize + 6);
+    assert_eq!(stat().allocated, BLOCK as usize);
+
+    let mut buf = [0xff; 16];
+    assert_eq!(sys::read(file.as_raw_fd(), BLOCK - 8, &mut buf), Ok(16));
+    assert_eq!(buf, [0; 16]);
+    assert_eq!(sys::read(file.as_raw_fd(), 3 * BLOCK + 5, &mut buf), Ok(1));
+    assert_eq!(buf[0], b'!');
+    assert_eq!(
+        sys::read(file.as_raw_fd(), 3 * BLOCK + 6, &mut buf),
+        Err(SysError::Eof)
+    );
+
+    // filling in part of a hole allocates just that block
+    assert_eq!(sys::write(file.as_raw_fd(), 1, b"x"), Ok(1));
+    assert_eq!(stat().size, 3 * BLOCK as usize + 6);
+    assert_eq!(stat().allocated, 2 * BLOCK as usize);
+
+    drop(file);
+    sys::remove("/sparse-test").unwrap();
+    println!("GOOD");
+}
+
+fn test_big_directory() {
+    // enough entries that the directory gets indexed
+    const FILES: usize = 100;
+
+    print!("big directory test: ");
+    let path = |i: usize| {
+        let mut path = b"/bigdir/file".to_vec();
+        path.extend(i.to_string().as_bytes());
+        path
+    };
+
+    OwnedFd::open("/bigdir", OpenFlags::CreateDir).unwrap();
+    for i in 0..FILES {
+        let file = OwnedFd::open(path(i), OpenFlags::CreateFile | OpenFlags::ReadWrite).unwrap();
+        sys::write(file.as_raw_fd(), 0, i.to_string().as_bytes()).unwrap();
+    }
+
+    // every file is found, and is the right one
+    for i in 0..FILES {
+        assert_eq!(io::read_file(&path(i)), Ok(i.to_string().into_bytes()));
+    }
+    assert_eq!(
+        OwnedFd::open("/bigdir/file100", OpenFlags::empty()).err(),
+        Some(SysError::PathNotFound)
+    );
+
+    // the index keeps up with removals and new files
+    for i in (0..FILES).step_by(2) {
+        sys::remove(path(i)).unwrap();
+    }
+    for i in 0..FILES {
+        let found = OwnedFd::open(path(i), OpenFlags::empty()).is_ok();
+        assert_eq!(found, i % 2 == 1);
+    }
+    OwnedFd::open(path(0), OpenFlags::CreateFile).unwrap();
+    assert_eq!(io::read_file(&path(0)), Ok(Vec::new()));
+
+    for i in (1..FILES).step_by(2) {
+        sys::remove(path(i)).unwrap();
+    }
+    sys::remove(path(0)).unwrap();
+    sys::remove("/bigdir").unwrap();
+    println!("GOOD");
+}
+
+fn test_readdir_stability() {
+    fn names(dir: &OwnedFd, pos: usize) -> Vec<Vec<u8>> {
+        let mut names = Vec::new();
+        let mut pos = pos;
+        while let Some((entry, next)) = sys::readdir_at(dir.as_raw_fd(), pos).unwrap() {
+            names.push(entry.name[..entry.name_len].to_vec());
+            pos = next;
+        }
+        names
+    }
+
+    print!("readdir stability test: ");
+    let path = |i: usize| {
+        let mut path = b"/rddir/file".to_vec();
+        path.extend(i.to_string().as_bytes());
+        path
+    };
+    let dir = OwnedFd::open("/rddir", OpenFlags::CreateDir).unwrap();
+    for i in 0..8 {
+        OwnedFd::open(path(i), OpenFlags::CreateFile).unwrap();
+    }
+
+    // removing entries before and after the cursor doesn't skip or repeat the rest
+    let mut seen = Vec::new();
+    for _ in 0..3 {
+        let entry = sys::readdir(dir.as_raw_fd(), None).unwrap().unwrap();
+        seen.push(entry.name[..entry.name_len].to_vec());
+    }
+    sys::remove(path(0)).unwrap();
+    sys::remove(path(1)).unwrap();
+    sys::remove(path(5)).unwrap();
+    while let Some(entry) = sys::readdir(dir.as_raw_fd(), None).unwrap() {
+        seen.push(entry.name[..entry.name_len].to_vec());
+    }
+    for i in [2, 3, 4, 6, 7] {
+        let name = &path(i)[b"/rddir/".len()..];
+        assert_eq!(seen.iter().filter(|n| &n[..] == name).count(), 1);
+    }
+
+    // a position stays valid after the entries before it are removed
+    let (_, pos) = sys::readdir_at(dir.as_raw_fd(), 0).unwrap().unwrap();
+    let rest = names(&dir, pos);
+    sys::remove(path(2)).unwrap();
+    assert_eq!(names(&dir, pos), rest);
+
+    // copying a file up from the lower layer of an overlay doesn't list it twice, or not at all
+    let root = OwnedFd::open("/", OpenFlags::empty()).unwrap();
+    let (first, pos) = sys::readdir_at(root.as_raw_fd(), 0).unwrap().unwrap();
+    OwnedFd::open("/1001_A.txt", OpenFlags::ReadWrite).unwrap();
+    let mut seen = names(&root, pos);
+    seen.push(first.name[..first.name_len].to_vec());
+    assert_eq!(seen.iter().filter(|n| &n[..] == b"1001_A.txt").count(), 1);
+
+    for i in [3, 4, 6, 7] {
+        sys::remove(path(i)).unwrap();
+    }
+    drop(dir);
+    sys::remove("/rddir").unwrap();
+    println!("GOOD");
+}
+
+fn test_lookup_cache() {
+    fn hits() -> usize {
+        let stats = io::read_file(b"/proc/lookups").unwrap();
+        core::str::from_utf8(&stats)
+            .unwrap()
+            .lines()
+            .find_map(|line| line.strip_prefix("hits:"))
+            .and_then(|hits| hits.trim().parse().ok())
+            .unwrap()
+    }
+
+    print!("lookup cache test: ");
+    let path = "/cache-test";
+    let open = || OwnedFd::open(path, OpenFlags::empty()).err();
+    assert_eq!(open(), Some(SysError::PathNotFound));
+
+    // reading the stats is a lookup too
+    let before = hits();
+    let own = hits() - before;
+    assert_eq!(open(), Some(SysError::PathNotFound));
+    assert_eq!(hits() - before, 2 * own + 1);
+
+    // creating and removing the file isn't hidden by what was cached
+    OwnedFd::open(path, OpenFlags::CreateFile).unwrap();
+    assert_eq!(open(), None);
+    assert_eq!(open(), None);
+    sys::remove(path).unwrap();
+    assert_eq!(open(), Some(SysError::PathNotFound));
+    println!("GOOD");
+}
+
+fn test_filters() {
+    print!("filter test: ");
+    let pid = sys::getpid();
+    let count_getpid = [
+        Insn::new(Op::Load, 1, 0, Field::Pid as u32),
+        Insn::new(Op::LoadImm, 2, 0, pid),
+        Insn::new(Op::Jne, 1, 2, 5),
+        Insn::new(Op::Load, 1, 0, Field::Syscall as u32),
+        Insn::new(Op::LoadImm, 2, 0, Sys::GetPid as u32),
+        Insn::new(Op::Jne, 1, 2, 2),
+        Insn::new(Op::LoadImm, 3, 0, 1),
+        Insn::new(Op::MapAdd, 0, 3, 0),
+        Insn::new(Op::Ret, 0, 0, 0),
+    ];
+    let id = sys::filter_attach(Hook::SyscallEntry, &count_getpid).unwrap();
+    for _ in 0..10 {
+        sys::getpid();
+    }
+    assert_eq!(sys::filter_map(id).unwrap()[0], 10);
+    sys::filter_detach(id).unwrap();
+    assert_eq!(sys::filter_map(id), Err(SysError::NotFound));
+
+    let ret = Insn::new(Op::Ret, 0, 0, 0);
+    for bad in [
+        &[][..],
+        &[Insn::new(Op::LoadImm, 0, 0, 1)],
+        &[Insn::new(Op::Jeq, 0, 0, 1), ret],
+        &[Insn::new(Op::Mov, 8, 0, 0), ret],
+        &[Insn::new(Op::Load, 0, 0, 100), ret],
+        &[Insn { op: 0, ..ret }],
+        &[ret; 65],
+    ] {
+        assert_eq!(
+            sys::filter_attach(Hook::SyscallExit, bad),
+            Err(SysError::BadArg)
+        );
+    }
+
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let argv = [KString::new("tests"), KString::new("seccomp-child")];
+    let pid = sys::spawn_argv(&*path, &argv, None).unwrap();
+    assert_eq!(sys::waitpid(pid), Ok(0));
+    println!("GOOD");
+}
+
+fn test_syscall_abi() {
+    print!("syscall convention test: ");
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let argv = [KString::new("tests"), KString::new("syscall-abi-child")];
+    let pid = sys::spawn_argv(&*path, &argv, None).unwrap();
+    assert_eq!(sys::waitpid(pid), Ok(0));
+    println!("GOOD");
+}
+
+fn test_cpuinfo() {
+    print!("cpuinfo test: ");
+    let info = io::read_file(b"/proc/cpuinfo").unwrap();
+    let info = core::str::from_utf8(&info).unwrap();
+    let mut online = 0;
+    for hart in info.split_terminator("\n\n") {
+        let field = |name| {
+            hart.lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .map(str::trim)
+        };
+        assert!(field("hart").unwrap().parse::<usize>().is_ok());
+        assert!(field("isa").unwrap().starts_with("rv64"));
+        assert!(field("timebase").unwrap().parse::<u64>().unwrap() > 0);
+        if field("online") == Some("yes") {
+            assert!(field("vendor").is_some());
+            online += 1;
+        }
+    }
+    // at least the hart running this
+    assert!(online > 0);
+    println!("GOOD");
+}
+
+fn test_console_ctl() {
+    print!("console ioctl test: ");
+    let prev = sys::console_bell(io::STDIN, Some(false)).unwrap();
+    assert!(!sys::console_bell(io::STDIN, None).unwrap());
+    assert!(!sys::console_bell(io::STDIN, Some(prev)).unwrap());
+    assert_eq!(sys::console_bell(io::STDIN, None), Ok(prev));
+
+    // taking the count resets it
+    sys::console_dropped(io::STDIN).unwrap();
+    assert_eq!(sys::console_dropped(io::STDIN), Ok(0));
+    assert_eq!(
+        sys::ioctl(io::STDIN, usize::MAX, 0),
+        Err(SysError::InvalidOp)
+    );
+
+    // in loopback the status lines follow our own: CTS is RTS and DSR is DTR. nothing can be
+    // written until it's turned off, or it would come back as input.
+    let prev = sys::console_modem_control(io::STDIN, None).unwrap();
+    sys::console_modem_control(io::STDIN, Some(ModemControl::Loopback | ModemControl::Rts))
+        .unwrap();
+    let rts = sys::console_modem_status(io::STDIN).unwrap();
+    sys::console_modem_control(io::STDIN, Some(ModemControl::Loopback | ModemControl::Dtr))
+        .unwrap();
+    let dtr = sys::console_modem_status(io::STDIN).unwrap();
+    sys::console_modem_control(io::STDIN, Some(prev)).unwrap();
+    assert!(rts.contains(ModemStatus::Cts) && !rts.contains(ModemStatus::Dsr));
+    assert!(dtr.contains(ModemStatus::Dsr) && !dtr.contains(ModemStatus::Cts));
+    assert_eq!(
+        sys::ioctl(io::STDIN, ConsoleCtl::ModemControl as usize, 0x100),
+        Err(SysError::InvalidOp)
+    );
+
+    let prev = sys::console_cts_flow(io::STDIN, Some(true)).unwrap();
+    assert!(sys::console_cts_flow(io::STDIN, Some(prev)).unwrap());
+    let prev = sys::console_echo(io::STDIN, Some(false)).unwrap();
+    assert!(!sys::console_echo(io::STDIN, Some(prev)).unwrap());
+
+    let fd = sys::open("/1001_A.txt", OpenFlags::empty()).unwrap();
+    assert_eq!(sys::console_dropped(fd), Err(SysError::Unsupported));
+    _ = sys::close(fd);
+    println!("GOOD");
+}
+
+fn test_console_restore() {
+    print!("console settings restore test: ");
+    let console = || OwnedFd::open("/dev/console", OpenFlags::empty()).unwrap();
+    let echo = || sys::console_echo(io::STDIN, None).unwrap();
+    let bell = || sys::console_bell(io::STDIN, None).unwrap();
+    assert!(echo());
+
+    let fd = console();
+    sys::console_echo(fd.as_raw_fd(), Some(false)).unwrap();
+    assert!(!echo());
+    drop(fd);
+    assert!(echo());
+
+    // the first one closed hands its settings to the one that changed things after it
+    let prev = bell();
+    let (first, second) = (console(), console());
+    sys::console_bell(first.as_raw_fd(), Some(!prev)).unwrap();
+    sys::console_echo(second.as_raw_fd(), Some(false)).unwrap();
+    drop(first);
+    assert_eq!((bell(), echo()), (!prev, false));
+    drop(second);
+    assert_eq!((bell(), echo()), (prev, true));
+
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let argv = [KString::new("tests"), KString::new("echo-off-child")];
+    let pid = sys::spawn_argv(&*path, &argv, None).unwrap();
+    assert_eq!(sys::waitpid(pid), Ok(0));
+    assert!(echo());
+    println!("GOOD");
+}
+
+fn test_acct() {
+    print!("process accounting test: ");
+    let spawn_child = || {
+        let mut buf = [0; 0x100];
+        let path = sys::exe_path(&mut buf).unwrap();
+        let argv = [KString::new("tests"), KString::new("acct-child")];
+        let pid = sys::spawn_argv(&*path, &argv, None).unwrap();
+        assert_eq!(sys::waitpid(pid), Ok(7));
+        pid
+    };
+
+    _ = sys::remove("/acct-test");
+    sys::acct(Some(b"/acct-test")).unwrap();
+    let pid = spawn_child();
+    sys::acct(None).unwrap();
+    spawn_child();
+
+    let data = io::read_file(b"/acct-test").unwrap();
+    assert_eq!(data.len() % size_of::<AcctRecord>(), 0);
+    let records: Vec<AcctRecord> = data
+        .chunks_exact(size_of::<AcctRecord>())
+        .map(|rec| unsafe { rec.as_ptr().cast::<AcctRecord>().read_unaligned() })
+        .collect();
+    // nothing else exited in between, and nothing is recorded once it's off
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].pid, pid);
+    assert_eq!(records[0].status, 7);
+    assert_eq!(records[0].name(), b"tests");
+    _ = sys::remove("/acct-test");
+    println!("GOOD");
+}
+
+fn test_hostname() {
+    print!("hostname test: ");
+    let mut buf = [0; sys::MAX_HOSTNAME_LEN];
+    let prev = sys::gethostname(&mut buf).unwrap().to_vec();
+    assert!(!prev.is_empty());
+
+    sys::sethostname("test-host.local").unwrap();
+    assert_eq!(sys::gethostname(&mut buf).unwrap(), b"test-host.local");
+    assert_eq!(sys::gethostname(&mut [0; 4]), Err(SysError::BadArg));
+    for bad in ["", "-test", "test-", "a..b", "with space", "under_score"] {
+        assert_eq!(sys::sethostname(bad), Err(SysError::BadArg));
+    }
+    assert_eq!(
+        sys::sethostname([b'a'; sys::MAX_HOSTNAME_LEN + 1]),
+        Err(SysError::BadArg)
+    );
+    assert_eq!(sys::gethostname(&mut buf).unwrap(), b"test-host.local");
+
+    let path = b"/proc/sys/kernel/hostname";
+    assert_eq!(io::read_file(path).unwrap(), b"test-host.local\n");
+    let fd = sys::open(path, OpenFlags::ReadWrite).unwrap();
+    assert_eq!(sys::write(fd, 0, b"proc-host\n"), Ok(10));
+    assert_eq!(sys::write(fd, 0, b"bad host"), Err(SysError::InvalidOp));
+    _ = sys::close(fd);
+    assert_eq!(sys::gethostname(&mut buf).unwrap(), b"proc-host");
+
+    let fd = sys::open(path, OpenFlags::empty()).unwrap();
+    assert_eq!(sys::write(fd, 0, b"other"), Err(SysError::InvalidOp));
+    _ = sys::close(fd);
+
+    sys::sethostname(&prev).unwrap();
+    println!("GOOD");
+}
+
+fn test_sysctl() {
+    fn write(path: &str, value: &str) -> Result<usize, SysError> {
+        let fd = OwnedFd::open(path, OpenFlags::ReadWrite)?;
+        sys::write(fd.as_raw_fd(), 0, value.as_bytes())
+    }
+
+    print!("/proc/sys test: ");
+    let level = "/proc/sys/kernel/log_level";
+    let prev = io::read_file(level.as_bytes()).unwrap();
+    write(level, "warn\n").unwrap();
+    assert_eq!(
+        sys::logctl(None, LogSubsystem::empty(), LogSubsystem::empty()),
+        Ok(LogLevel::Warn)
+    );
+    assert_eq!(io::read_file(level.as_bytes()).unwrap(), b"warn\n");
+    assert_eq!(write(level, "loud"), Err(SysError::InvalidOp));
+    write(level, core::str::from_utf8(&prev).unwrap()).unwrap();
+
+    let quantum = "/proc/sys/kernel/sched/batch_quantum_ms";
+    let prev = io::read_file(quantum.as_bytes()).unwrap();
+    for bad in ["0", "10001", "-5", "fast", ""] {
+        assert_eq!(write(quantum, bad), Err(SysError::InvalidOp));
+    }
+    assert_eq!(io::read_file(quantum.as_bytes()).unwrap(), prev);
+    write(quantum, "250").unwrap();
+    assert_eq!(io::read_file(quantum.as_bytes()).unwrap(), b"250\n");
+    write(quantum, core::str::from_utf8(&prev).unwrap()).unwrap();
+
+    let rcvbuf = "/proc/sys/net/tcp_rcvbuf";
+    let prev = io::read_file(rcvbuf.as_bytes()).unwrap();
+    assert_eq!(write(rcvbuf, "1024"), Err(SysError::InvalidOp));
+    write(rcvbuf, "4096").unwrap();
+    let fd = sys::socket(SocketType::Tcp).unwrap();
+    assert_eq!(sys::getsockopt(fd, SockOpt::RecvBuf), Ok(4096));
+    _ = sys::close(fd);
+    write(rcvbuf, core::str::from_utf8(&prev).unwrap()).unwrap();
+
+    let grace = "/proc/sys/kernel/shutdown_grace_ms";
+    assert_eq!(io::read_file(grace.as_bytes()).unwrap(), b"3000\n");
+    assert_eq!(write(grace, "60001"), Err(SysError::InvalidOp));
+
+    let scrub = "/proc/sys/vm/scrub_interval_ms";
+    assert_eq!(io::read_file(scrub.as_bytes()).unwrap(), b"0\n");
+    assert_eq!(write(scrub, "60001"), Err(SysError::InvalidOp));
+    write(scrub, "1").unwrap();
+    write(scrub, "0").unwrap();
+
+    // read-only files stay read-only
+    assert_eq!(write("/proc/cpuinfo", "x"), Err(SysError::ReadOnly));
+    println!("GOOD");
+}
+
+fn test_kill_blocked() {
+    print!("kill blocked process test: ");
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let me = sys::getpid().to_string();
+    let argv = [
+        KString::new("tests"),
+        KString::new("wait-child"),
+        KString::new(&*me),
+    ];
+    let waiter = sys::spawn_argv(&*path, &argv, None).unwrap();
+
+    // the waiter sends the PID of the child it's about to wait for, which sits in recvmsg forever
+    let mut msg = [0; 4];
+    let (len, _) = sys::recvmsg(&mut msg).unwrap();
+    assert_eq!(len, msg.len());
+    let receiver = u32::from_le_bytes(msg);
+
+    // neither would ever wake up on its own, so these only return if kill interrupts them
+    sys::kill(waiter).unwrap();
+    assert_eq!(sys::waitpid(waiter), Ok(usize::MAX));
+    sys::kill(receiver).unwrap();
+    assert_eq!(sys::waitpid(receiver), Ok(usize::MAX));
+    println!("GOOD");
+}
+
+fn test_poll() {
+    print!("poll test: ");
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let spawn = |arg| sys::spawn_argv(&*path, &[KString::new("tests"), KString::new(arg)], None);
+    let receiver = spawn("recv-child").unwrap();
+    let exiter = spawn("acct-child").unwrap();
+
+    // the receiver sits in recvmsg forever, so only the other child wakes us up
+    let mut entries = [
+        PollEntry::exited(receiver as u64),
+        PollEntry::exited(exiter as u64),
+    ];
+    assert_eq!(sys::poll(&mut entries), Ok(1));
+    assert!(!entries[0].is_ready());
+    assert!(entries[1].is_ready());
+    assert_eq!(entries[1].value, 7);
+
+    let mut entries = [PollEntry::exited(receiver as u64)];
+    assert_eq!(sys::try_poll(&mut entries), Ok(0));
+    sys::kill(receiver).unwrap();
+    assert_eq!(sys::poll(&mut entries), Ok(1));
+    assert_eq!(entries[0].value, usize::MAX);
+
+    // files other than the console can always be read
+    let file = OwnedFd::open("/1001_A.txt", OpenFlags::empty()).unwrap();
+    let mut entries = [PollEntry::readable(file.as_raw_fd().0)];
+    assert_eq!(sys::try_poll(&mut entries), Ok(1));
+    drop(file);
+    assert_eq!(sys::try_poll(&mut entries), Err(SysError::BadFd));
+    assert_eq!(
+        sys::try_poll(&mut [PollEntry::exited(sys::getpid() as u64)]),
+        Err(SysError::BadArg)
+    );
+    println!("GOOD");
+}
+
+fn test_recvmsg_bad_buffer() {
+    print!("recvmsg bad buffer test: ");
+    sys::sendmsg(sys::getpid(), b"kept").unwrap();
+
+    // the null guard page is never mapped, so the message can't be copied out and stays queued
+    let bad = unsafe { core::slice::from_raw_parts_mut(0x10 as *mut u8, 4) };
+    assert_eq!(sys::try_recvmsg(bad), Err(SysError::BadAddr));
+
+    let mut buf = [0; 8];
+    let (len, sender) = sys::try_recvmsg(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"kept");
+    assert_eq!(sender, sys::pidhandle(sys::getpid()).unwrap());
+    assert_eq!(sys::try_recvmsg(&mut buf), Err(SysError::WouldBlock));
+    println!("GOOD");
+}
+
+fn test_waitpid_timeout() {
+    print!("waitpid timeout test: ");
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let spawn = |arg| sys::spawn_argv(&*path, &[KString::new("tests"), KString::new(arg)], None);
+    let receiver = spawn("recv-child").unwrap();
+    assert_eq!(
+        sys::waitpid_timeout(receiver, Duration::ZERO),
+        Err(SysError::TimedOut)
+    );
+    assert_eq!(
+        sys::waitpid_timeout(receiver, Duration::from_millis(10)),
+        Err(SysError::TimedOut)
+    );
+    sys::kill(receiver).unwrap();
+    assert_eq!(
+        sys::waitpid_timeout(receiver, Duration::from_secs(10)),
+        Ok(usize::MAX)
+    );
+
+    // a child that exits in time beats the timeout
+    let exiter = spawn("acct-child").unwrap();
+    assert_eq!(sys::waitpid_timeout(exiter, Duration::from_secs(10)), Ok(7));
+    assert_eq!(
+        sys::waitpid_timeout(sys::getpid(), Duration::ZERO),
+        Err(SysError::BadArg)
+    );
+    println!("GOOD");
+}
+
+fn test_counters() {
+    print!("user counters test: ");
+    let info = sys::info();
+    assert!(info.counters.contains(Counters::Time));
+    let cpuinfo = io::read_file(b"/proc/cpuinfo").unwrap();
+    let timebase = format!("timebase: {}\n", info.timebase_freq);
+    assert!(core::str::from_utf8(&cpuinfo).unwrap().contains(&timebase));
+
+    // a child that doesn't exit holds up the wait for the whole timeout
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let child = sys::spawn_argv(
+        &*path,
+        &[KString::new("tests"), KString::new("recv-child")],
+        None,
+    )
+    .unwrap();
+    let start = Instant::now();
+    assert_eq!(
+        sys::waitpid_timeout(child, Duration::from_millis(20)),
+        Err(SysError::TimedOut)
+    );
+    let elapsed = start.elapsed();
+    assert!(Instant::now() >= start);
+    assert!(elapsed >= Duration::from_millis(20), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    sys::kill(child).unwrap();
+    _ = sys::waitpid(child);
+    println!("GOOD");
+}
+
+fn test_crash_report() {
+    print!("crash report test: ");
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let spawn = |arg| sys::spawn_argv(&*path, &[KString::new("tests"), KString::new(arg)], None);
+    let pid = spawn("fault-child").unwrap();
+    assert_eq!(sys::waitpid(pid), Ok(usize::MAX));
+
+    let info = sys::fault_info(pid as u64).unwrap();
+    assert_eq!(info.handle.pid(), pid);
+    assert_eq!(info.cause_name(), "store page fault");
+    assert_eq!(info.stval, 0x10);
+    assert_eq!(info.exe(), &*path);
+    assert!(!info.backtrace().is_empty());
+
+    let report_path = crash::write_report(pid as u64).unwrap();
+    assert_eq!(report_path, format!("/tmp/crash-{pid}.txt"));
+    let report = io::read_file(report_path.as_bytes()).unwrap();
+    let report = core::str::from_utf8(&report).unwrap();
+    assert!(report.contains("store page fault"));
+    // symbolized against our own binary
+    assert!(report.contains("tests::fault_child"));
+    sys::remove(&report_path).unwrap();
+
+    // being killed isn't a fault
+    let receiver = spawn("recv-child").unwrap();
+    sys::kill(receiver).unwrap();
+    assert_eq!(sys::waitpid(receiver), Ok(usize::MAX));
+    assert_eq!(
+        sys::fault_info(receiver as u64).map(|_| ()),
+        Err(SysError::NotFound)
+    );
+    println!("GOOD");
+}
+
+fn test_kill_holder() {
+    print!("kill resource holder test: ");
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let me = sys::getpid().to_string();
+    let argv = [
+        KString::new("tests"),
+        KString::new("holder-child"),
+        KString::new(&*me),
+    ];
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8010);
+    let holder = sys::spawn_argv(&*path, &argv, None).unwrap();
+
+    // wait until it has everything, then kill it while it's blocked
+    assert_eq!(sys::recvmsg(&mut [0; 4]).map(|(len, _)| len), Ok(1));
+    assert!(sys::lookup_name("kill-test").is_ok());
+    assert!(TcpListener::bind(addr).is_err());
+    sys::kill(holder).unwrap();
+    assert_eq!(sys::waitpid(holder), Ok(usize::MAX));
+
+    // everything is released by the time waitpid returns
+    assert_eq!(sys::lookup_name("kill-test"), Err(SysError::NotFound));
+    drop(TcpListener::bind(addr).unwrap());
+    println!("GOOD");
+}
+
+fn test_zero_pages() {
+    print!("zero page test: ");
+    // start on a fresh page, the current one may have been used before
+    let brk = sys::sbrk(0).unwrap() as usize;
+    let pad = brk.next_multiple_of(0x1000) - brk;
+    let len = 3 * 0x1000;
+    let start = sys::sbrk((pad + len) as isize).unwrap() as usize - len;
+    let mem = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, len) };
+    assert!(mem.iter().all(|&b| b == 0));
+
+    // written from user mode, then by the kernel
+    mem[0] = 1;
+    let fd = sys::open("/1001_A.txt", OpenFlags::empty()).unwrap();
+    assert_eq!(sys::read(fd, 0, &mut mem[0x1000..0x1010]), Ok(0x10));
+    _ = sys::close(fd);
+
+    // neither write went anywhere else, like the page the rest of the memory still shares
+    assert_eq!(mem[0], 1);
+    assert!(mem[1..0x1000].iter().all(|&b| b == 0));
+    assert!(mem[0x1000..0x1010].iter().all(|&b| b == b'A'));
+    assert!(mem[0x1010..].iter().all(|&b| b == 0));
+    assert!(ZEROED.iter().all(|&b| b == 0));
+    sys::sbrk(-((pad + len) as isize)).unwrap();
+    println!("GOOD");
+}
+
+fn test_meminfo() {
+    print!("meminfo test: ");
+    let field = |name: &str| {
+        let info = io::read_file(b"/proc/meminfo").unwrap();
+        let info = core::str::from_utf8(&info).unwrap();
+        info.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.split_whitespace().next()?.parse::<u64>().ok())
+            .unwrap()
+    };
+    assert!(field("heap") > 0);
+    assert!(field("zero_pool") <= 64);
+    // scrubbing is off unless turned on, and nothing should have gone bad anyway
+    assert_eq!(field("scrub_errors"), 0);
+
+    // every page a process is spawned with comes from the pool or is counted as a miss
+    let before = field("zero_pool_hits") + field("zero_pool_misses");
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let argv = [KString::new("tests"), KString::new("acct-child")];
+    let pid = sys::spawn_argv(&*path, &argv, None).unwrap();
+    assert_eq!(sys::waitpid(pid), Ok(7));
+    assert!(field("zero_pool_hits") + field("zero_pool_misses") > before);
+    println!("GOOD");
+}
+
+fn test_boot_info() {
+    print!("boot info test: ");
+    let cmdline = io::read_file(b"/proc/cmdline").unwrap();
+    assert_eq!(cmdline.iter().filter(|&&c| c == b'\n').count(), 1);
+    assert!(!cmdline.starts_with(b" ") && !cmdline.contains(&b'\t'));
+
+    let uptime = io::read_file(b"/proc/uptime").unwrap();
+    let uptime = core::str::from_utf8(&uptime).unwrap();
+    let (up, boot) = uptime.trim_end().split_once(' ').unwrap();
+    let (secs, hundredths) = up.split_once('.').unwrap();
+    assert!(secs.parse::<u64>().is_ok() && hundredths.len() == 2);
+    let boot = boot.parse::<u64>().unwrap();
+
+    // files are stamped with the same clock
+    let file = OwnedFd::open("/boot-info-test", OpenFlags::CreateFile).unwrap();
+    assert!(sys::stat(file.as_raw_fd()).unwrap().mtime > boot);
+    drop(file);
+    sys::remove("/boot-info-test").unwrap();
+
+    let features = io::read_file(b"/proc/features").unwrap();
+    let features = core::str::from_utf8(&features).unwrap();
+    assert!(features.lines().any(|name| name == "procfs"));
+    println!("GOOD");
+}
+
+fn test_lock_stats() {
+    print!("lock stats test: ");
+    let locks = io::read_file(b"/proc/locks").unwrap();
+    let locks = core::str::from_utf8(&locks).unwrap();
+    for line in locks.lines() {
+        let (_, count) = line.split_once(": contended ").unwrap();
+        count.parse::<u64>().unwrap();
+    }
+    for name in ["alloc", "proc_list", "scheduler", "vfs", "console"] {
+        assert!(locks
+            .lines()
+            .any(|line| line.starts_with(&format!("{name}: "))));
+    }
+    println!("GOOD");
+}
+
+fn test_sched_stats() {
+    print!("sched stats test: ");
+    let field = |name: &str| {
+        let sched = io::read_file(b"/proc/sched").unwrap();
+        let sched = core::str::from_utf8(&sched).unwrap();
+        sched
+            .lines()
+            .filter_map(|line| {
+                let mut words = line.split_whitespace().skip_while(|&word| word != name);
+                words.nth(1)?.parse::<u64>().ok()
+            })
+            .sum::<u64>()
+    };
+
+    sys::reset_trap_stats(sys::ALL_HARTS).unwrap();
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let argv = [KString::new("tests"), KString::new("acct-child")];
+    let pid = sys::spawn_argv(&*path, &argv, None).unwrap();
+    assert_eq!(sys::waitpid(pid), Ok(7));
+    // the child had to be picked from a run queue, and so did we once it woke us up
+    assert!(field("runs") >= 2);
+    assert!(field("wait_samples:") >= 2);
+    println!("GOOD");
+}
+
+fn test_latency() {
+    print!("latency histogram test: ");
+    let total = |name: &str| {
+        let latency = io::read_file(b"/proc/latency").unwrap();
+        let latency = core::str::from_utf8(&latency).unwrap();
+        latency
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .map_or(0, |counts| {
+                counts
+                    .split_whitespace()
+                    .map(|count| count.parse::<u64>().unwrap())
+                    .sum::<u64>()
+            })
+    };
+
+    sys::reset_trap_stats(sys::ALL_HARTS).unwrap();
+    assert_eq!(total("sys GetPid"), 0);
+    for _ in 0..10 {
+        sys::getpid();
+    }
+    assert!(total("sys GetPid") >= 10);
+    assert!(total("EcallFromUMode") >= 10);
+    assert!(total("entry") >= total("EcallFromUMode"));
+    println!("GOOD");
+}
+
+fn test_device_ids() {
+    print!("device id test: ");
+    let device = |path| {
+        let fd = OwnedFd::open(path, OpenFlags::empty()).unwrap();
+        sys::stat(fd.as_raw_fd()).unwrap().device
+    };
+    assert_eq!(device("/dev/null"), Some(DeviceId::NULL));
+    assert_eq!(device("/dev/zero"), Some(DeviceId::ZERO));
+    assert_eq!(device("/dev/urandom"), Some(DeviceId::URANDOM));
+    assert_eq!(device("/dev/console"), Some(DeviceId::CONSOLE));
+    assert_eq!(device("/1001_A.txt"), None);
+    assert_eq!(device("/dev"), None);
+
+    // every device has its own id, and the console is also a serial port under its own name
+    let dir = OwnedFd::open("/dev", OpenFlags::empty()).unwrap();
+    let mut ids = Vec::new();
+    let mut serial = false;
+    while let Some(entry) = sys::readdir(dir.as_raw_fd(), None).unwrap() {
+        let id = entry.stat.device.unwrap();
+        assert!(!ids.contains(&id));
+        ids.push(id);
+        if id.major == DeviceId::SERIAL {
+            let name = core::str::from_utf8(&entry.name[..entry.name_len]).unwrap();
+            assert_eq!(DeviceId::serial(name[4..].parse().unwrap()), id);
+            serial = true;
+        }
+    }
+    assert!(serial);
+    println!("GOOD");
+}
+
+/// Run by `test_filters` in a child, since the filter can't be removed
+fn seccomp_child() -> usize {
+    let deny_open = [
+        Insn::new(Op::Load, 1, 0, Field::Syscall as u32),
+        Insn::new(Op::LoadImm, 2, 0, Sys::Open as u32),
+        Insn::new(Op::Jne, 1, 2, 1),
+        Insn::new(Op::LoadImm, 0, 0, 1),
+        Insn::new(Op::Ret, 0, 0, 0),
+    ];
+    sys::seccomp(&deny_open).unwrap();
+    assert_eq!(
+        sys::open("/1001_A.txt", OpenFlags::empty()),
+        Err(SysError::PermissionDenied)
+    );
+    assert_eq!(sys::seccomp(&deny_open), Err(SysError::AlreadyExists));
+    0
+}
+
+/// The top byte of every argument `syscall_abi_child` passes, so its filters can pick them out
+const SENTINEL: u32 = 0xa5;
+/// The register number of `a0`
+const A0: usize = 10;
+
+/// What `syscall_abi_child` puts in argument register `i` (`a6` for `SYSCALL_MAX_ARGS`) of syscall
+/// `nr`. Every register gets a different value, so one read from the wrong place stands out.
+fn sentinel(nr: usize, i: usize) -> usize {
+    (SENTINEL as usize) << 56 | nr << 8 | i
+}
+
+/// Run by `test_syscall_abi` in a child, since the filter that keeps its syscalls from running
+/// can't be removed. Makes every syscall with sentinels in the argument registers, and checks
+/// the kernel read each argument from the register the convention in `shared::sys` says and
+/// changed nothing but the result and error registers.
+fn syscall_abi_child() -> usize {
+    // deny anything with a sentinel in any argument, so nothing actually runs
+    let mut deny_marked = vec![
+        Insn::new(Op::LoadImm, 2, 0, 56),
+        Insn::new(Op::LoadImm, 3, 0, SENTINEL),
+    ];
+    let deny_at = 2 + 3 * SYSCALL_MAX_ARGS + 1;
+    for i in 0..SYSCALL_MAX_ARGS {
+        let jump = deny_at - deny_marked.len() - 3;
+        deny_marked.extend([
+            Insn::new(Op::Load, 1, 0, Field::Arg0 as u32 + i as u32),
+            Insn::new(Op::Shr, 1, 2, 0),
+            Insn::new(Op::Jeq, 1, 3, jump as u32),
+        ]);
+    }
+    deny_marked.extend([
+        Insn::new(Op::Ret, 0, 0, 0),
+        Insn::new(Op::LoadImm, 0, 0, 1),
+        Insn::new(Op::Ret, 0, 0, 0),
+    ]);
+
+    // counter `i` counts the syscalls the kernel saw the right sentinel in argument `i` of
+    let mut count_args = vec![
+        Insn::new(Op::Load, 1, 0, Field::Pid as u32),
+        Insn::new(Op::LoadImm, 2, 0, sys::getpid()),
+        // to the last instruction, once it's known where that is
+        Insn::new(Op::Jne, 1, 2, 0),
+        Insn::new(Op::LoadImm, 1, 0, SENTINEL),
+        Insn::new(Op::LoadImm, 2, 0, 56),
+        Insn::new(Op::Shl, 1, 2, 0),
+        Insn::new(Op::Load, 3, 0, Field::Syscall as u32),
+        Insn::new(Op::LoadImm, 2, 0, 8),
+        Insn::new(Op::Shl, 3, 2, 0),
+        Insn::new(Op::Or, 1, 3, 0),
+        Insn::new(Op::LoadImm, 6, 0, 1),
+    ];
+    for i in 0..SYSCALL_MAX_ARGS as u32 {
+        count_args.extend([
+            Insn::new(Op::Load, 3, 0, Field::Arg0 as u32 + i),
+            Insn::new(Op::LoadImm, 4, 0, i),
+            Insn::new(Op::Or, 4, 1, 0),
+            Insn::new(Op::Jne, 3, 4, 2),
+            Insn::new(Op::LoadImm, 5, 0, i),
+            Insn::new(Op::MapAdd, 5, 6, 0),
+        ]);
+    }
+    count_args.push(Insn::new(Op::Ret, 0, 0, 0));
+    count_args[2].imm = (count_args.len() - 4) as u32;
+
+    let id = sys::filter_attach(Hook::SyscallEntry, &count_args).unwrap();
+    sys::seccomp(&deny_marked).unwrap();
+
+    let mut issued = 0;
+    for nr in (1..).map_while(Sys::from_repr) {
+        let nr = nr as usize;
+        let mut regs: [usize; 8] = core::array::from_fn(|i| sentinel(nr, i));
+        regs[SYSCALL_NR_REG - A0] = nr;
+        let before = regs;
+        unsafe {
+            core::arch::asm!(
+                "ecall",
+                inout("a0") regs[0],
+                inout("a1") regs[1],
+                inout("a2") regs[2],
+                inout("a3") regs[3],
+                inout("a4") regs[4],
+                inout("a5") regs[5],
+                inout("a6") regs[6],
+                inout("a7") regs[7],
+            );
+        }
+
+        for (i, (&after, &before)) in regs.iter().zip(&before).enumerate() {
+            let expected = match i + A0 {
+                SYSCALL_RET_REG => 0,
+                SYSCALL_ERR_REG => SysError::PermissionDenied as usize,
+                _ => before,
+            };
+            assert_eq!(after, expected, "a{i} after {:?}", Sys::from_repr(nr));
+        }
+        issued += 1;
+    }
+
+    let counts = sys::filter_map(id).unwrap();
+    sys::filter_detach(id).unwrap();
+    for (i, &count) in counts[..SYSCALL_MAX_ARGS].iter().enumerate() {
+        assert_eq!(
+            count, issued,
+            "argument {i} was read from the wrong register"
+        );
+    }
+    0
+}
+
+/// Run by `test_dns` in a child. Answers two queries on port 5353, the first with an address and
+/// the second with an error. The first answer is preceded by a forged one from another port.
+fn dns_child(parent: u32) -> usize {
+    let sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5353)).unwrap();
+    let forger = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5354)).unwrap();
+    sys::sendmsg(parent, &[1]).unwrap();
+
+    let mut buf = [0; 512];
+    for rcode in [0, 3] {
+        let (len, from) = sock.recv_from(&mut buf).unwrap();
+        let mut reply = buf[..len].to_vec();
+        reply[2] |= 0x80; // QR
+        reply[3] |= rcode;
+        if rcode == 0 {
+            // one answer: a pointer back to the question's name, then A, IN, the TTL, and the
+            // address
+            reply[7] = 1;
+            reply.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 1, 2, 3]);
+
+            // right ID, wrong server
+            let mut forged = reply.clone();
+            let addr = forged.len() - 4;
+            forged[addr..].copy_from_slice(&[6, 6, 6, 6]);
+            forger.send_to(&forged, from).unwrap();
+        }
+        sock.send_to(&reply, from).unwrap();
+    }
+    0
+}
+
+/// Run by `test_kill_blocked` in a child, which it kills while this is in `waitpid`
+fn wait_child(parent: u32) -> usize {
+    let mut buf = [0; 0x100];
+    let path = sys::exe_path(&mut buf).unwrap();
+    let argv = [KString::new("tests"), KString::new("recv-child")];
+    let pid = sys::spawn_argv(&*path, &argv, None).unwrap();
+    sys::sendmsg(parent, &pid.to_le_bytes()).unwrap();
+    _ = sys::waitpid(pid);
+    0
+}
+
+/// Run by `test_kill_holder` in a child, which it kills once this has told it everything is set up
+/// Lands in the null guard page, which is never mapped
+#[inline(never)]
+fn fault_child() -> usize {
+    unsafe { (0x10 as *mut u8).write_volatile(1) };
+    0
+}
+
+fn holder_child(parent: u32) -> usize {
+    let _listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8010)).unwrap();
+    sys::register_name("kill-test").unwrap();
+    sys::sendmsg(parent, &[1]).unwrap();
+    _ = sys::recvmsg(&mut [0; 4]);
+    0
+}
+
 #[no_mangle]
-fn main(_args: &[*const u8]) -> usize {
+fn main(args: &[*const u8]) -> usize {
+    let arg = |i: usize| {
+        args.get(i)
+            .map(|arg| unsafe { CStr::from_ptr(arg.cast()) }.to_bytes())
+    };
+    // children that report back are given their parent's PID after their name
+    let parent = || -> u32 {
+        core::str::from_utf8(arg(2).unwrap())
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    match arg(1) {
+        Some(b"seccomp-child") => return seccomp_child(),
+        Some(b"syscall-abi-child") => return syscall_abi_child(),
+        Some(b"acct-child") => return 7,
+        Some(b"echo-off-child") => {
+            // and exits without turning it back on
+            return sys::console_echo(io::STDIN, Some(false)).map_or(1, |_| 0);
+        }
+        Some(b"wait-child") => return wait_child(parent()),
+        Some(b"holder-child") => return holder_child(parent()),
+        Some(b"recv-child") => {
+            _ = sys::recvmsg(&mut [0; 4]);
+            return 0;
+        }
+        Some(b"fault-child") => return fault_child(),
+        Some(b"dns-child") => return dns_child(parent()),
+        _ => {}
+    }
+
     test_global_static();
     test_file_read();
     test_fd_cursor();
+    test_loopback_udp();
+    test_loopback_tcp();
+    test_routes();
+    test_pcap();
+    test_fragmentation();
+    test_dns();
+    tcp::test_tcp_peer();
+    test_trap_stats();
+    test_priority();
+    test_spawn_cwd();
+    test_owned_fd();
+    test_close_range();
+    test_cwd();
+    test_unmount();
+    test_bind_mount();
+    test_overlay();
+    test_exec_permission();
+    test_exec_errors();
+    test_open_flags();
+    test_exe_path();
+    test_umask();
+    test_file_times();
+    test_sparse_file();
+    test_big_directory();
+    test_readdir_stability();
+    test_lookup_cache();
+    test_filters();
+    test_syscall_abi();
+    test_cpuinfo();
+    test_console_ctl();
+    test_console_restore();
+    test_acct();
+    test_hostname();
+    test_sysctl();
+    test_kill_blocked();
+    test_kill_holder();
+    test_poll();
+    test_recvmsg_bad_buffer();
+    test_crash_report();
+    test_waitpid_timeout();
+    test_counters();
+    test_zero_pages();
+    test_meminfo();
+    test_boot_info();
+    test_device_ids();
+    test_pivot_root();
+    test_sched_stats();
+    test_lock_stats();
+    test_latency();
 
     println!("testing sbrk: ");
     let brk = sys::sbrk(0).unwrap() as usize;
@@ -0,0 +1,258 @@
+//! A scripted TCP peer on the far end of `tun0`. It writes segments a well-behaved stack would
+//! never send (out of order, past the window, resets) and checks every segment the kernel replies
+//! with, since TCP bugs otherwise only show up as hangs.
+
+use userstd::{
+    alloc::vec::Vec,
+    io::OpenFlags,
+    net::{Ipv4Addr, SockOpt, SocketAddrV4, TcpListener, TcpStream},
+    print, println,
+    sys::{self, RawFd, SysError},
+};
+
+const FIN: u8 = 1 << 0;
+const SYN: u8 = 1 << 1;
+const RST: u8 = 1 << 2;
+const PSH: u8 = 1 << 3;
+const ACK: u8 = 1 << 4;
+
+const HOST: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+const PEER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 2);
+const HOST_PORT: u16 = 9000;
+const PEER_PORT: u16 = 5000;
+
+#[derive(Debug)]
+struct Segment {
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    payload: Vec<u8>,
+}
+
+struct Peer {
+    fd: RawFd,
+    next_id: u16,
+}
+
+impl Peer {
+    fn open() -> Self {
+        let fd = sys::open(b"/dev/tun0", OpenFlags::ReadWrite).unwrap();
+        // throw away anything left from before
+        let mut buf = [0; 1500];
+        while sys::read(fd, None, &mut buf).is_ok() {}
+        Self { fd, next_id: 0 }
+    }
+
+    fn send(&mut self, seq: u32, ack: u32, flags: u8, window: u16, payload: &[u8]) {
+        let len = 40 + payload.len();
+        let mut pkt = userstd::alloc::vec![0; len];
+        pkt[0] = 0x45;
+        pkt[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        pkt[4..6].copy_from_slice(&self.next_id.to_be_bytes());
+        pkt[8] = 64;
+        pkt[9] = 6;
+        pkt[12..16].copy_from_slice(&PEER.octets());
+        pkt[16..20].copy_from_slice(&HOST.octets());
+        let sum = checksum(&pkt[..20], 0);
+        pkt[10..12].copy_from_slice(&sum.to_be_bytes());
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let seg = &mut pkt[20..];
+        seg[0..2].copy_from_slice(&PEER_PORT.to_be_bytes());
+        seg[2..4].copy_from_slice(&HOST_PORT.to_be_bytes());
+        seg[4..8].copy_from_slice(&seq.to_be_bytes());
+        seg[8..12].copy_from_slice(&ack.to_be_bytes());
+        seg[12] = 5 << 4;
+        seg[13] = flags;
+        seg[14..16].copy_from_slice(&window.to_be_bytes());
+        seg[20..].copy_from_slice(payload);
+
+        let (src, dst) = (PEER.octets(), HOST.octets());
+        let pseudo = [
+            src[0], src[1], src[2], src[3], dst[0], dst[1], dst[2], dst[3], 0, 6,
+        ]
+        .chunks(2)
+        .map(|w| u16::from_be_bytes([w[0], w[1]]) as u32)
+        .sum::<u32>()
+            + seg.len() as u32;
+        let sum = checksum(seg, pseudo);
+        seg[16..18].copy_from_slice(&sum.to_be_bytes());
+
+        assert_eq!(sys::write(self.fd, None, &pkt), Ok(len));
+    }
+
+    /// The next segment the kernel sent, if there is one
+    fn recv(&mut self) -> Option<Segment> {
+        let mut buf = [0; 1500];
+        let n = match sys::read(self.fd, None, &mut buf) {
+            Ok(n) => n,
+            Err(SysError::WouldBlock) => return None,
+            Err(err) => panic!("reading tun0: {err:?}"),
+        };
+
+        let pkt = &buf[..n];
+        assert_eq!(pkt[9], 6);
+        assert_eq!(pkt[12..16], HOST.octets());
+        assert_eq!(pkt[16..20], PEER.octets());
+        let seg = &pkt[(pkt[0] & 0xf) as usize * 4..];
+        assert_eq!(u16::from_be_bytes([seg[0], seg[1]]), HOST_PORT);
+        assert_eq!(u16::from_be_bytes([seg[2], seg[3]]), PEER_PORT);
+
+        let be32 = |i: usize| u32::from_be_bytes([seg[i], seg[i + 1], seg[i + 2], seg[i + 3]]);
+        Some(Segment {
+            seq: be32(4),
+            ack: be32(8),
+            flags: seg[13],
+            window: u16::from_be_bytes([seg[14], seg[15]]),
+            payload: seg[(seg[12] >> 4) as usize * 4..].to_vec(),
+        })
+    }
+
+    /// The next segment, which must be a bare ACK
+    fn recv_ack(&mut self) -> Segment {
+        let seg = self.recv().expect("expected an ack");
+        assert_eq!(seg.flags, ACK, "{seg:?}");
+        assert!(seg.payload.is_empty());
+        seg
+    }
+}
+
+impl Drop for Peer {
+    fn drop(&mut self) {
+        _ = sys::close(self.fd);
+    }
+}
+
+fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|w| u16::from_be_bytes([w[0], *w.get(1).unwrap_or(&0)]) as u32)
+        .fold(initial, |sum, w| sum + w);
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn read_now(stream: &TcpStream, buf: &mut [u8]) -> Result<usize, SysError> {
+    sys::read(stream.fd(), None, buf)
+}
+
+pub fn test_tcp_peer() {
+    print!("tcp scripted peer test: ");
+    let mut peer = Peer::open();
+    let listener = TcpListener::bind(SocketAddrV4::new(HOST, HOST_PORT)).unwrap();
+
+    // handshake
+    peer.send(1000, 0, SYN, 8192, &[]);
+    let synack = peer.recv().unwrap();
+    assert_eq!(synack.flags, SYN | ACK);
+    assert_eq!(synack.ack, 1001);
+    assert_eq!(listener.try_accept().err(), Some(SysError::WouldBlock));
+
+    // a peer that missed the SYN-ACK sends its SYN again, and gets the same SYN-ACK back
+    peer.send(1000, 0, SYN, 8192, &[]);
+    let again = peer.recv().unwrap();
+    assert_eq!(
+        (again.seq, again.ack, again.flags),
+        (synack.seq, 1001, SYN | ACK)
+    );
+    assert!(peer.recv().is_none());
+
+    let mut snd = synack.seq.wrapping_add(1);
+    let mut rcv = 1001u32;
+    peer.send(rcv, snd, ACK, 8192, &[]);
+    assert!(peer.recv().is_none());
+    let (stream, from) = listener.try_accept().unwrap();
+    assert_eq!(from, SocketAddrV4::new(PEER, PEER_PORT));
+
+    // once the connection is up, a late retransmission of the SYN is only acked
+    peer.send(1000, 0, SYN, 8192, &[]);
+    let seg = peer.recv_ack();
+    assert_eq!((seg.seq, seg.ack), (snd, rcv));
+    assert!(peer.recv().is_none());
+
+    let mut buf = [0; 4096];
+
+    // a segment past a hole is dropped, and the ack repeats what's expected next
+    peer.send(rcv + 5, snd, ACK | PSH, 8192, b"world");
+    assert_eq!(peer.recv_ack().ack, rcv);
+    assert_eq!(read_now(&stream, &mut buf), Err(SysError::WouldBlock));
+
+    peer.send(rcv, snd, ACK | PSH, 8192, b"hello");
+    assert_eq!(peer.recv_ack().ack, rcv + 5);
+    peer.send(rcv + 5, snd, ACK | PSH, 8192, b"world");
+    assert_eq!(peer.recv_ack().ack, rcv + 10);
+    assert_eq!(read_now(&stream, &mut buf), Ok(10));
+    assert_eq!(&buf[..10], b"helloworld");
+    rcv += 10;
+
+    // a duplicate of data already received is acked again, not delivered twice
+    peer.send(rcv - 5, snd, ACK | PSH, 8192, b"world");
+    assert_eq!(peer.recv_ack().ack, rcv);
+    assert_eq!(read_now(&stream, &mut buf), Err(SysError::WouldBlock));
+
+    // data from the kernel
+    stream.write_all(b"ping").unwrap();
+    let seg = peer.recv().unwrap();
+    assert_eq!((seg.seq, seg.ack, seg.flags), (snd, rcv, ACK | PSH));
+    assert_eq!(seg.payload, b"ping");
+    snd += 4;
+    peer.send(rcv, snd, ACK, 8192, &[]);
+    assert!(peer.recv().is_none());
+
+    // fill the kernel's receive window until it advertises zero
+    sys::setsockopt(stream.fd(), SockOpt::RecvBuf, 2048).unwrap();
+    while peer.recv().is_some() {}
+    peer.send(rcv, snd, ACK | PSH, 8192, b"a");
+    let seg = peer.recv_ack();
+    rcv += 1;
+    assert_eq!((seg.ack, seg.window), (rcv, 2047));
+    peer.send(rcv, snd, ACK | PSH, 8192, &[b'b'; 1024]);
+    rcv += 1024;
+    assert_eq!(peer.recv_ack().ack, rcv);
+    peer.send(rcv, snd, ACK | PSH, 8192, &[b'c'; 1023]);
+    rcv += 1023;
+    let seg = peer.recv_ack();
+    assert_eq!((seg.ack, seg.window), (rcv, 0));
+
+    // nothing past a zero window is accepted
+    peer.send(rcv, snd, ACK | PSH, 8192, b"d");
+    let seg = peer.recv_ack();
+    assert_eq!((seg.ack, seg.window), (rcv, 0));
+
+    // reading everything reopens the window
+    assert_eq!(read_now(&stream, &mut buf), Ok(2048));
+    let seg = peer.recv_ack();
+    assert_eq!((seg.ack, seg.window), (rcv, 2048));
+
+    // the kernel holds data back while the peer's window is zero
+    peer.send(rcv, snd, ACK, 0, &[]);
+    stream.write_all(b"data").unwrap();
+    assert!(peer.recv().is_none());
+    peer.send(rcv, snd, ACK, 8192, &[]);
+    let seg = peer.recv().unwrap();
+    assert_eq!((seg.seq, seg.payload.as_slice()), (snd, &b"data"[..]));
+    snd += 4;
+    peer.send(rcv, snd, ACK, 8192, &[]);
+
+    // a reset that isn't exactly at the next expected sequence number is ignored
+    peer.send(rcv + 100, 0, RST, 0, &[]);
+    assert!(peer.recv().is_none());
+    assert_eq!(read_now(&stream, &mut buf), Err(SysError::WouldBlock));
+
+    peer.send(rcv, 0, RST, 0, &[]);
+    assert!(peer.recv().is_none());
+    assert_eq!(read_now(&stream, &mut buf), Err(SysError::ConnReset));
+    assert_eq!(stream.write_all(b"x"), Err(SysError::ConnReset));
+
+    // a segment for a connection that doesn't exist gets a reset
+    peer.send(rcv, snd, ACK | FIN, 8192, &[]);
+    let seg = peer.recv().unwrap();
+    assert_eq!((seg.seq, seg.flags), (snd, RST));
+
+    // the kernel never retransmits (see the TODO on `TcpSocket`), so only the peer's
+    // retransmissions are covered here
+    println!("GOOD");
+}
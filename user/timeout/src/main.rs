@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+
+use core::{ffi::CStr, time::Duration};
+
+use userstd::{
+    alloc::vec::Vec,
+    println,
+    sys::{self, KString, SysError},
+};
+
+/// Directories searched for a command without a `/`, like the shell does
+const PATH: &[&[u8]] = &[b"/bin", b"/sbin"];
+
+/// The exit code when the command was killed, which no program here exits with on its own
+const TIMED_OUT: usize = 124;
+
+fn spawn(cmd: &[u8], argv: &[KString]) -> Result<u32, SysError> {
+    if cmd.contains(&b'/') {
+        return sys::spawn_argv(cmd, argv, None);
+    }
+
+    for dir in PATH {
+        let mut buf = dir.to_vec();
+        buf.push(b'/');
+        buf.extend(cmd);
+        match sys::spawn_argv(&buf, argv, None) {
+            Err(SysError::PathNotFound) => continue,
+            res => return res,
+        }
+    }
+    Err(SysError::PathNotFound)
+}
+
+/// `timeout <secs> <cmd> [args...]` runs `cmd` and kills it if it's still running after `secs`
+/// seconds. Exits with the command's exit code, or 124 if it had to be killed.
+#[no_mangle]
+fn main(args: &[*const u8]) -> usize {
+    let args: Vec<_> = args
+        .iter()
+        .map(|arg| unsafe { CStr::from_ptr(arg.cast()) }.to_bytes())
+        .collect();
+    let [_, secs, cmd, ..] = args[..] else {
+        println!("usage: timeout <secs> <cmd> [args...]");
+        return 1;
+    };
+    let Some(secs) = core::str::from_utf8(secs)
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+    else {
+        println!("timeout: invalid number of seconds");
+        return 1;
+    };
+
+    let argv: Vec<_> = args[2..].iter().map(KString::new).collect();
+    let pid = match spawn(cmd, &argv) {
+        Ok(pid) => pid,
+        Err(err) => {
+            println!("timeout: couldn't spawn process: {err:?}");
+            return 1;
+        }
+    };
+
+    match sys::waitpid_timeout(pid, Duration::from_secs(secs)) {
+        Ok(ecode) => ecode,
+        Err(SysError::TimedOut) => {
+            _ = sys::kill(pid);
+            _ = sys::waitpid(pid);
+            println!("timeout: killed PID {pid} after {secs}s");
+            TIMED_OUT
+        }
+        Err(err) => {
+            println!("timeout: error: {err:?}");
+            1
+        }
+    }
+}
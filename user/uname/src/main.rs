@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+use core::ffi::CStr;
+
+use userstd::{
+    print, println,
+    sys::{self, MAX_HOSTNAME_LEN},
+};
+
+#[no_mangle]
+fn main(args: &[*const u8]) -> usize {
+    let (mut sysname, mut nodename, mut machine) = (false, false, false);
+    for arg in args[1..]
+        .iter()
+        .map(|arg| unsafe { CStr::from_ptr(arg.cast()).to_bytes() })
+    {
+        match arg {
+            b"-s" => sysname = true,
+            b"-n" => nodename = true,
+            b"-m" => machine = true,
+            b"-a" => (sysname, nodename, machine) = (true, true, true),
+            _ => {
+                println!("usage: uname [-a] [-s] [-n] [-m]");
+                return 1;
+            }
+        }
+    }
+    if !nodename && !machine {
+        sysname = true;
+    }
+
+    let mut fields = 0;
+    let mut field = |value: &str| {
+        if fields != 0 {
+            print!(" ");
+        }
+        print!("{value}");
+        fields += 1;
+    };
+    if sysname {
+        field("Servos");
+    }
+    if nodename {
+        let mut buf = [0; MAX_HOSTNAME_LEN];
+        match sys::gethostname(&mut buf) {
+            Ok(name) => field(core::str::from_utf8(name).unwrap_or("?")),
+            Err(err) => {
+                println!("uname: error: {err:?}");
+                return 1;
+            }
+        }
+    }
+    if machine {
+        field("riscv64");
+    }
+    println!();
+    0
+}
@@ -0,0 +1,236 @@
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Write;
+
+use shared::{
+    io::OpenFlags,
+    sys::{FaultInfo, SysError},
+};
+
+use crate::{
+    fd::{AsRawFd, OwnedFd},
+    io,
+    sys::{self, RawFd},
+};
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+/// The functions in an ELF file's symbol table, sorted by address. Binaries in the initrd are
+/// built without stripping, so they all have one.
+struct Symbols {
+    elf: Vec<u8>,
+    /// (address, size, offset of the name in `elf`)
+    funcs: Vec<(u64, u64, usize)>,
+}
+
+fn bytes<const N: usize>(buf: &[u8], at: usize) -> Option<[u8; N]> {
+    buf.get(at..)?.get(..N)?.try_into().ok()
+}
+
+fn read_u16(buf: &[u8], at: usize) -> Option<u16> {
+    bytes(buf, at).map(u16::from_le_bytes)
+}
+
+fn read_u32(buf: &[u8], at: usize) -> Option<u32> {
+    bytes(buf, at).map(u32::from_le_bytes)
+}
+
+fn read_u64(buf: &[u8], at: usize) -> Option<u64> {
+    bytes(buf, at).map(u64::from_le_bytes)
+}
+
+impl Symbols {
+    /// Read the symbol table of a 64-bit little endian ELF file. Fields are read a byte at a time,
+    /// so the file doesn't need to be aligned in memory.
+    fn parse(elf: Vec<u8>) -> Option<Self> {
+        if elf.get(..6)? != b"\x7fELF\x02\x01" {
+            return None;
+        }
+
+        let shoff = read_u64(&elf, 0x28)? as usize;
+        let shentsize = read_u16(&elf, 0x3a)? as usize;
+        let shnum = read_u16(&elf, 0x3c)? as usize;
+        let section = |i: usize| shoff.checked_add(i.checked_mul(shentsize)?);
+        let symtab = (0..shnum)
+            .filter_map(section)
+            .find(|&sh| read_u32(&elf, sh + 4) == Some(SHT_SYMTAB))?;
+
+        let offset = read_u64(&elf, symtab + 0x18)? as usize;
+        let size = read_u64(&elf, symtab + 0x20)? as usize;
+        let entsize = read_u64(&elf, symtab + 0x38)? as usize;
+        let strtab = section(read_u32(&elf, symtab + 0x28)? as usize)?;
+        let stroff = read_u64(&elf, strtab + 0x18)? as usize;
+        if entsize == 0 {
+            return None;
+        }
+
+        let mut funcs: Vec<_> = (0..size / entsize)
+            .filter_map(|i| {
+                let sym = offset + i * entsize;
+                if *elf.get(sym + 4)? & 0xf != STT_FUNC {
+                    return None;
+                }
+                let name = stroff.checked_add(read_u32(&elf, sym)? as usize)?;
+                Some((read_u64(&elf, sym + 8)?, read_u64(&elf, sym + 16)?, name))
+            })
+            .collect();
+        funcs.sort_unstable_by_key(|&(addr, ..)| addr);
+        Some(Self { elf, funcs })
+    }
+
+    /// The name of the function containing `addr`, and how far into it `addr` is
+    fn lookup(&self, addr: u64) -> Option<(&[u8], u64)> {
+        let i = self.funcs.partition_point(|&(start, ..)| start <= addr);
+        let &(start, size, name) = self.funcs.get(i.checked_sub(1)?)?;
+        if addr - start >= size.max(1) {
+            return None;
+        }
+
+        let name = self.elf.get(name..)?;
+        let len = name.iter().position(|&c| c == 0)?;
+        Some((&name[..len], addr - start))
+    }
+}
+
+/// Escapes legacy Rust mangling uses for characters that can't appear in a symbol
+const ESCAPES: &[(&str, &str)] = &[
+    ("$LT$", "<"),
+    ("$GT$", ">"),
+    ("$RF$", "&"),
+    ("$BP$", "*"),
+    ("$SP$", "@"),
+    ("$C$", ","),
+    ("$u20$", " "),
+    ("$u27$", "'"),
+    ("$u5b$", "["),
+    ("$u5d$", "]"),
+    ("$u7b$", "{"),
+    ("$u7d$", "}"),
+    ("$u7e$", "~"),
+    ("..", "::"),
+];
+
+/// Turn a legacy Rust symbol like `_ZN7userstd5_start17h0123456789abcdefE` back into the path
+/// `userstd::_start`. Anything else is written as is.
+fn demangle(name: &[u8], out: &mut String) {
+    let raw = core::str::from_utf8(name).unwrap_or("?");
+    let Some(mut rest) = raw.strip_prefix("_ZN").and_then(|r| r.strip_suffix('E')) else {
+        out.push_str(raw);
+        return;
+    };
+
+    let start = out.len();
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let ident = rest[..digits]
+            .parse::<usize>()
+            .ok()
+            .and_then(|len| rest.get(digits..digits.checked_add(len)?));
+        let Some(ident) = ident else {
+            out.truncate(start);
+            out.push_str(raw);
+            return;
+        };
+        rest = &rest[digits + ident.len()..];
+        // the last component is a hash that only tells apart symbols with the same path
+        if rest.is_empty() && ident.len() == 17 && ident.starts_with('h') {
+            break;
+        }
+
+        if out.len() != start {
+            out.push_str("::");
+        }
+        // an identifier can't start with `$`, so one that would is prefixed with `_`
+        let mut ident = ident
+            .strip_prefix('_')
+            .filter(|rest| rest.starts_with('$'))
+            .unwrap_or(ident);
+        while let Some(c) = ident.chars().next() {
+            match ESCAPES.iter().find(|(escape, _)| ident.starts_with(escape)) {
+                Some((escape, text)) => {
+                    out.push_str(text);
+                    ident = &ident[escape.len()..];
+                }
+                None => {
+                    out.push(c);
+                    ident = &ident[c.len_utf8()..];
+                }
+            }
+        }
+    }
+}
+
+/// Write `addr` and the function it's in. A return address points after the call, which can be
+/// the start of the next function, so those are looked up a byte earlier.
+fn write_addr(out: &mut String, syms: Option<&Symbols>, addr: u64, is_return: bool) {
+    _ = write!(out, "{addr:#018x}");
+    let lookup = addr.saturating_sub(is_return as u64);
+    if let Some((name, off)) = syms.and_then(|s| s.lookup(lookup)) {
+        out.push(' ');
+        demangle(name, out);
+        _ = write!(out, "+{:#x}", off + addr - lookup);
+    }
+    out.push('\n');
+}
+
+/// Format a readable report of `info`. Addresses are symbolized against the executable the
+/// process was spawned from, if it can still be read.
+pub fn format_report(info: &FaultInfo) -> String {
+    let syms = io::read_file(info.exe()).ok().and_then(Symbols::parse);
+    let syms = syms.as_ref();
+
+    let mut out = String::new();
+    _ = writeln!(
+        out,
+        "PID {} ({}) was killed for an exception: {} (scause {})",
+        info.handle.pid(),
+        core::str::from_utf8(info.exe()).unwrap_or("?"),
+        info.cause_name(),
+        info.cause,
+    );
+    _ = writeln!(out, "handle: {:#x}", info.handle.0);
+    _ = writeln!(out, "stval: {:#018x}", info.stval);
+    if syms.is_none() {
+        _ = writeln!(out, "(no symbols, the executable couldn't be read)");
+    }
+    _ = writeln!(out, "sp: {:#018x}", info.sp);
+    out.push_str("ra: ");
+    write_addr(&mut out, syms, info.ra, true);
+
+    // the faulting function is frame 0, like in kernel backtraces
+    out.push_str("backtrace:\n  0: ");
+    write_addr(&mut out, syms, info.pc, false);
+    for (i, &ra) in info.backtrace().iter().enumerate() {
+        _ = write!(out, "{:>3}: ", i + 1);
+        write_addr(&mut out, syms, ra, true);
+    }
+    out
+}
+
+/// If `pid_or_handle` was killed for an exception, write a report of it to `/tmp/crash-<pid>.txt`,
+/// creating `/tmp` if needed, and return the path. Fails with `NotFound` if the kernel didn't
+/// record a fault, either because the process exited some other way or because it was too long
+/// ago. The kernel doesn't write core files, so the report is all that's left of the process.
+pub fn write_report(pid_or_handle: u64) -> Result<String, SysError> {
+    let info = sys::fault_info(pid_or_handle)?;
+    let report = format_report(&info);
+
+    OwnedFd::open("/tmp", OpenFlags::CreateDir)?;
+    let path = format!("/tmp/crash-{}.txt", info.handle.pid());
+    let file = OwnedFd::open(
+        &path,
+        OpenFlags::CreateFile | OpenFlags::ReadWrite | OpenFlags::Truncate,
+    )?;
+    write_all(file.as_raw_fd(), report.as_bytes())?;
+    Ok(path)
+}
+
+fn write_all(fd: RawFd, mut buf: &[u8]) -> Result<(), SysError> {
+    while !buf.is_empty() {
+        match sys::write(fd, None, buf)? {
+            0 => return Err(SysError::Eof),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
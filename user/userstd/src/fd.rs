@@ -0,0 +1,95 @@
+use core::marker::PhantomData;
+
+use shared::{io::OpenFlags, sys::SysError};
+
+use crate::sys::{self, RawFd};
+
+/// A file descriptor that is closed when it's dropped
+#[derive(Debug, PartialEq, Eq)]
+pub struct OwnedFd(RawFd);
+
+impl OwnedFd {
+    pub fn open(path: impl AsRef<[u8]>, flags: OpenFlags) -> Result<Self, SysError> {
+        sys::open(path, flags).map(OwnedFd)
+    }
+
+    /// Close the descriptor now, to see if it fails
+    pub fn close(self) -> Result<(), SysError> {
+        sys::close(self.into_raw_fd())
+    }
+}
+
+impl Drop for OwnedFd {
+    fn drop(&mut self) {
+        _ = sys::close(self.0);
+    }
+}
+
+/// A file descriptor that stays open for at least `'a`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BorrowedFd<'a>(RawFd, PhantomData<&'a OwnedFd>);
+
+impl BorrowedFd<'_> {
+    /// # Safety
+    /// `fd` must stay open for the returned lifetime.
+    pub const unsafe fn borrow_raw(fd: RawFd) -> Self {
+        Self(fd, PhantomData)
+    }
+}
+
+pub trait AsRawFd {
+    fn as_raw_fd(&self) -> RawFd;
+}
+
+pub trait AsFd {
+    fn as_fd(&self) -> BorrowedFd<'_>;
+}
+
+pub trait IntoRawFd {
+    /// Give up ownership without closing the descriptor
+    fn into_raw_fd(self) -> RawFd;
+}
+
+pub trait FromRawFd {
+    /// # Safety
+    /// `fd` must be open, and not owned by anything else.
+    unsafe fn from_raw_fd(fd: RawFd) -> Self;
+}
+
+impl AsRawFd for OwnedFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl AsRawFd for BorrowedFd<'_> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl AsFd for OwnedFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        BorrowedFd(self.0, PhantomData)
+    }
+}
+
+impl AsFd for BorrowedFd<'_> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        *self
+    }
+}
+
+impl IntoRawFd for OwnedFd {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.0;
+        core::mem::forget(self);
+        fd
+    }
+}
+
+impl FromRawFd for OwnedFd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self(fd)
+    }
+}
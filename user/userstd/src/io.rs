@@ -1,16 +1,23 @@
 use core::fmt::Write;
 
-use crate::sys::{self, RawFd};
+use crate::{
+    fd::{AsFd, AsRawFd, OwnedFd},
+    sys::{self, RawFd},
+};
 
 use alloc::vec::Vec;
 pub use shared::io::*;
 use shared::sys::SysError;
 
+/// Opened by `_start` before main runs. Both refer to the console.
+pub const STDOUT: RawFd = RawFd(0);
+pub const STDIN: RawFd = RawFd(1);
+
 pub struct Stdout;
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        _ = sys::write(RawFd(0), None, s.as_bytes());
+        _ = sys::write(STDOUT, None, s.as_bytes());
         Ok(())
     }
 }
@@ -32,16 +39,26 @@ macro_rules! println {
 }
 
 pub fn read_file(path: &[u8]) -> Result<Vec<u8>, SysError> {
-    let fd = sys::open(path, OpenFlags::empty())?;
-    let buf = read_fd(fd)?;
-    _ = sys::close(fd);
-    Ok(buf)
+    read_fd(OwnedFd::open(path, OpenFlags::empty())?)
 }
 
-pub fn read_fd(fd: RawFd) -> Result<Vec<u8>, SysError> {
-    let stat = sys::stat(fd)?;
-    let mut buf = alloc::vec![0; stat.size];
-    let n = sys::read(fd, None, &mut buf)?;
-    buf.truncate(n);
-    Ok(buf)
+pub fn read_fd(fd: impl AsFd) -> Result<Vec<u8>, SysError> {
+    let fd = fd.as_fd().as_raw_fd();
+    // generated files like those in /proc have a size of 0, so read until the end regardless
+    let mut buf = Vec::with_capacity(sys::stat(fd)?.size.max(0x400));
+    loop {
+        let start = buf.len();
+        if start == buf.capacity() {
+            buf.reserve(start);
+        }
+        buf.resize(buf.capacity(), 0);
+        match sys::read(fd, None, &mut buf[start..]) {
+            Ok(0) | Err(SysError::Eof) => {
+                buf.truncate(start);
+                return Ok(buf);
+            }
+            Ok(n) => buf.truncate(start + n),
+            Err(err) => return Err(err),
+        }
+    }
 }
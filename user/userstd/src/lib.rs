@@ -1,10 +1,15 @@
 #![no_std]
 
+pub mod crash;
+pub mod fd;
 pub mod io;
 pub mod mem;
+pub mod net;
 pub mod sys;
+pub mod time;
 
 use shared::io::OpenFlags;
+pub use shared::{filter, layout, log};
 
 pub extern crate alloc;
 
@@ -28,11 +33,15 @@ extern "Rust" {
 #[no_mangle]
 extern "C" fn _start(argc: usize, argv: *const *const u8) {
     // open stdout and stdin
-    _ = sys::open("/dev/uart0", OpenFlags::ReadWrite).unwrap();
-    _ = sys::open("/dev/uart0", OpenFlags::empty()).unwrap();
+    _ = sys::open("/dev/console", OpenFlags::ReadWrite).unwrap();
+    _ = sys::open("/dev/console", OpenFlags::empty()).unwrap();
 
     let bottom = sys::sbrk(0).unwrap();
     let top = sys::sbrk(1024 * 512).expect("sbrk failed");
+    assert!(
+        layout::IMAGE.contains(&(bottom as usize)) && top as usize <= layout::IMAGE.end,
+        "heap is outside of the image region"
+    );
 
     unsafe {
         mem::init(bottom, top);
@@ -0,0 +1,217 @@
+use core::{
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    time::Duration,
+};
+
+use shared::{io::OpenFlags, sys::SysError};
+
+use super::UdpSocket;
+use crate::{
+    fd::{AsRawFd, OwnedFd},
+    io::read_file,
+    sys,
+    time::Instant,
+};
+
+pub const DNS_PORT: u16 = 53;
+pub const RESOLV_CONF_PATH: &[u8] = b"/etc/resolv.conf";
+const URANDOM_PATH: &[u8] = b"/dev/urandom";
+
+/// How long to wait for an answer before asking again, and how many times to ask each server
+const RETRY_TIMEOUT: Duration = Duration::from_secs(1);
+const ATTEMPTS: usize = 3;
+/// The largest message sent over UDP without EDNS
+const MAX_MSG_LEN: usize = 512;
+
+const HEADER_LEN: usize = 12;
+const FLAG_RD: u16 = 1 << 8;
+const FLAG_QR: u16 = 1 << 15;
+const RCODE_MASK: u16 = 0xf;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+const MAX_LABEL_LEN: usize = 63;
+
+/// Look up the A record for `name`, asking each server in `/etc/resolv.conf` in turn until one
+/// answers. Fails with `NotFound` if there are no servers or one says the name doesn't exist.
+pub fn resolve(name: &str) -> Result<IpAddr, SysError> {
+    let conf = read_file(RESOLV_CONF_PATH).map_err(|_| SysError::NotFound)?;
+    let mut res = Err(SysError::NotFound);
+    for server in nameservers(&conf) {
+        res = query(name, SocketAddrV4::new(server, DNS_PORT)).map(IpAddr::V4);
+        if !matches!(res, Err(SysError::TimedOut)) {
+            break;
+        }
+    }
+    res
+}
+
+/// The addresses on the `nameserver` lines of a resolv.conf file. Everything after a '#' is
+/// ignored.
+pub fn nameservers(conf: &[u8]) -> impl Iterator<Item = Ipv4Addr> + '_ {
+    conf.split(|&c| c == b'\n').filter_map(|line| {
+        let line = line.split(|&c| c == b'#').next().unwrap_or_default();
+        let mut fields = line
+            .split(|c| c.is_ascii_whitespace())
+            .filter(|f| !f.is_empty());
+        if fields.next()? != b"nameserver" {
+            return None;
+        }
+        core::str::from_utf8(fields.next()?).ok()?.parse().ok()
+    })
+}
+
+/// Ask `server` for the A record of `name`, sending the query again if no answer comes in time.
+/// Fails with `NotFound` if the server answers without one, and `TimedOut` if it never answers.
+///
+/// An answer is only accepted from `server` and with the query's ID, which is random like the
+/// port the query is sent from, so someone who can't see the query has to guess both to forge one.
+pub fn query(name: &str, server: SocketAddrV4) -> Result<Ipv4Addr, SysError> {
+    let id = random_id();
+    let mut query = [0; MAX_MSG_LEN];
+    let len = encode_query(id, name, &mut query).ok_or(SysError::BadArg)?;
+    let query = &query[..len];
+
+    let sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+    sock.connect(server)?;
+    let mut reply = [0; MAX_MSG_LEN];
+    for _ in 0..ATTEMPTS {
+        sock.send(query)?;
+        let sent = Instant::now();
+        while sent.elapsed() < RETRY_TIMEOUT {
+            let len = match sock.try_recv_from(&mut reply) {
+                Ok((len, from)) if from == server => len,
+                Ok(_) => continue,
+                Err(SysError::WouldBlock) => continue,
+                Err(err) => return Err(err),
+            };
+
+            // anything that isn't an answer to this query is ignored
+            let reply = &reply[..len];
+            if reply.get(..2) == Some(&id.to_be_bytes()[..]) {
+                return parse_response(id, reply).ok_or(SysError::NotFound);
+            }
+        }
+    }
+    Err(SysError::TimedOut)
+}
+
+/// A query ID from `/dev/urandom`, or from the clock if it can't be read
+fn random_id() -> u16 {
+    let mut id = [0; 2];
+    let read = OwnedFd::open(URANDOM_PATH, OpenFlags::empty())
+        .and_then(|fd| sys::read(fd.as_raw_fd(), None, &mut id));
+    match read {
+        Ok(2) => u16::from_ne_bytes(id),
+        _ => Instant::now().ticks() as u16,
+    }
+}
+
+/// Write a recursive query for the A record of `name` into `buf`. Returns the length of the query,
+/// or `None` if the name is invalid or `buf` is too small.
+pub fn encode_query(id: u16, name: &str, buf: &mut [u8]) -> Option<usize> {
+    let mut w = Writer { buf, pos: 0 };
+    w.u16(id)?;
+    w.u16(FLAG_RD)?;
+    w.u16(1)?; // questions
+    w.u16(0)?;
+    w.u16(0)?;
+    w.u16(0)?;
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            return None;
+        }
+        w.u8(label.len() as u8)?;
+        w.bytes(label.as_bytes())?;
+    }
+    w.u8(0)?;
+    w.u16(TYPE_A)?;
+    w.u16(CLASS_IN)?;
+    Some(w.pos)
+}
+
+/// Find the first A record in the response to query `id`.
+pub fn parse_response(id: u16, msg: &[u8]) -> Option<Ipv4Addr> {
+    let mut r = Reader { buf: msg, pos: 0 };
+    if r.u16()? != id {
+        return None;
+    }
+
+    let flags = r.u16()?;
+    if flags & FLAG_QR == 0 || flags & RCODE_MASK != 0 {
+        return None;
+    }
+
+    let questions = r.u16()?;
+    let answers = r.u16()?;
+    r.pos = HEADER_LEN;
+    for _ in 0..questions {
+        r.skip_name()?;
+        r.pos += 4; // type and class
+    }
+
+    for _ in 0..answers {
+        r.skip_name()?;
+        let (typ, class) = (r.u16()?, r.u16()?);
+        r.pos += 4; // ttl
+        let len = r.u16()? as usize;
+        let data = r.bytes(len)?;
+        if typ == TYPE_A && class == CLASS_IN && len == 4 {
+            return Some(Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+        }
+    }
+    None
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        self.buf
+            .get_mut(self.pos..self.pos + bytes.len())?
+            .copy_from_slice(bytes);
+        self.pos += bytes.len();
+        Some(())
+    }
+
+    fn u8(&mut self, v: u8) -> Option<()> {
+        self.bytes(&[v])
+    }
+
+    fn u16(&mut self, v: u16) -> Option<()> {
+        self.bytes(&v.to_be_bytes())
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.buf.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// Skip a possibly compressed name. Compression pointers always end the name, so there is no
+    /// need to follow them.
+    fn skip_name(&mut self) -> Option<()> {
+        loop {
+            let len = *self.bytes(1)?.first()?;
+            match len {
+                0 => return Some(()),
+                len if len & 0xc0 == 0xc0 => return self.bytes(1).map(|_| ()),
+                len => _ = self.bytes(len as usize)?,
+            }
+        }
+    }
+}
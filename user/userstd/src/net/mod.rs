@@ -0,0 +1,163 @@
+pub mod dns;
+
+pub use core::net::{IpAddr, Ipv4Addr, SocketAddrV4};
+pub use shared::net::{
+    HwAddr, NeighborEntry, RouteEntry, SockAddr, SockOpt, SocketType, IFACE_LO, IFACE_TUN,
+};
+
+use crate::{
+    fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    io::read_file,
+    sys::{self, RawFd},
+};
+use shared::sys::SysError;
+
+pub const HOSTS_PATH: &[u8] = b"/etc/hosts";
+
+/// Resolve `name` to an address. Numeric addresses are returned as-is, then `/etc/hosts` is
+/// checked, and finally the name is looked up with DNS.
+pub fn resolve(name: &str) -> Result<IpAddr, SysError> {
+    if let Ok(addr) = name.parse() {
+        return Ok(addr);
+    }
+
+    if let Some(addr) = read_file(HOSTS_PATH)
+        .ok()
+        .and_then(|hosts| lookup_hosts(&hosts, name))
+    {
+        return Ok(addr);
+    }
+
+    dns::resolve(name)
+}
+
+/// Find `name` in the contents of a hosts file. Each line is an address followed by one or more
+/// names, and everything after a '#' is ignored.
+pub fn lookup_hosts(hosts: &[u8], name: &str) -> Option<IpAddr> {
+    hosts.split(|&c| c == b'\n').find_map(|line| {
+        let line = line.split(|&c| c == b'#').next().unwrap_or_default();
+        let mut fields = line
+            .split(|c| c.is_ascii_whitespace())
+            .filter(|f| !f.is_empty());
+        let addr = core::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+        fields
+            .any(|f| f.eq_ignore_ascii_case(name.as_bytes()))
+            .then_some(addr)
+    })
+}
+
+/// Sockets are nonblocking, so retry until the operation doesn't fail with `WouldBlock`
+fn blocking<T>(mut f: impl FnMut() -> Result<T, SysError>) -> Result<T, SysError> {
+    loop {
+        match f() {
+            Err(SysError::WouldBlock) => continue,
+            res => return res,
+        }
+    }
+}
+
+fn socket(typ: SocketType) -> Result<OwnedFd, SysError> {
+    sys::socket(typ).map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Implement the fd traits for a socket type that wraps an `OwnedFd`
+macro_rules! socket_fd {
+    ($($ty: ty),*) => {
+        $(
+            impl AsRawFd for $ty {
+                fn as_raw_fd(&self) -> RawFd {
+                    self.0.as_raw_fd()
+                }
+            }
+
+            impl AsFd for $ty {
+                fn as_fd(&self) -> BorrowedFd<'_> {
+                    self.0.as_fd()
+                }
+            }
+        )*
+    };
+}
+
+socket_fd!(UdpSocket, TcpListener, TcpStream);
+
+pub struct UdpSocket(OwnedFd);
+
+impl UdpSocket {
+    pub fn bind(addr: SocketAddrV4) -> Result<Self, SysError> {
+        let sock = Self(socket(SocketType::Udp)?);
+        sys::bind(sock.0.as_raw_fd(), &addr.into())?;
+        Ok(sock)
+    }
+
+    /// Only receive from and send to `addr` by default
+    pub fn connect(&self, addr: SocketAddrV4) -> Result<(), SysError> {
+        sys::connect(self.0.as_raw_fd(), &addr.into())
+    }
+
+    pub fn send_to(&self, buf: &[u8], addr: SocketAddrV4) -> Result<usize, SysError> {
+        sys::sendto(self.0.as_raw_fd(), buf, Some(&addr.into()))
+    }
+
+    pub fn send(&self, buf: &[u8]) -> Result<usize, SysError> {
+        sys::sendto(self.0.as_raw_fd(), buf, None)
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddrV4), SysError> {
+        blocking(|| sys::recvfrom(self.0.as_raw_fd(), buf)).map(|(len, from)| (len, from.into()))
+    }
+
+    pub fn try_recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddrV4), SysError> {
+        sys::recvfrom(self.0.as_raw_fd(), buf).map(|(len, from)| (len, from.into()))
+    }
+}
+
+pub struct TcpListener(OwnedFd);
+
+impl TcpListener {
+    pub fn bind(addr: SocketAddrV4) -> Result<Self, SysError> {
+        let sock = Self(socket(SocketType::Tcp)?);
+        sys::bind(sock.0.as_raw_fd(), &addr.into())?;
+        sys::listen(sock.0.as_raw_fd(), 16)?;
+        Ok(sock)
+    }
+
+    pub fn accept(&self) -> Result<(TcpStream, SocketAddrV4), SysError> {
+        blocking(|| self.try_accept())
+    }
+
+    pub fn try_accept(&self) -> Result<(TcpStream, SocketAddrV4), SysError> {
+        sys::accept(self.0.as_raw_fd())
+            .map(|(fd, peer)| (TcpStream(unsafe { OwnedFd::from_raw_fd(fd) }), peer.into()))
+    }
+}
+
+pub struct TcpStream(OwnedFd);
+
+impl TcpStream {
+    pub fn connect(addr: SocketAddrV4) -> Result<Self, SysError> {
+        let sock = Self(socket(SocketType::Tcp)?);
+        blocking(|| sys::connect(sock.0.as_raw_fd(), &addr.into()))?;
+        Ok(sock)
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+
+    /// Read at least one byte. Returns `Ok(0)` once the peer has closed the connection.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, SysError> {
+        match blocking(|| sys::read(self.0.as_raw_fd(), None, buf)) {
+            Err(SysError::Eof) => Ok(0),
+            res => res,
+        }
+    }
+
+    pub fn write_all(&self, mut buf: &[u8]) -> Result<(), SysError> {
+        while !buf.is_empty() {
+            let n = blocking(|| sys::write(self.0.as_raw_fd(), None, buf))?;
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+}
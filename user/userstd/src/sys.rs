@@ -1,200 +1,145 @@
-use core::{convert::Infallible, marker::PhantomData, mem::MaybeUninit};
+use alloc::vec::Vec;
+use core::{convert::Infallible, marker::PhantomData, mem::MaybeUninit, time::Duration};
 
 pub use shared::sys::*;
 
-use shared::io::{DirEntry, OpenFlags, Stat};
+use shared::{
+    filter::{Hook, Insn, MAP_LEN},
+    io::{ConsoleCtl, DirEntry, Mode, ModemControl, ModemStatus, OpenFlags, Stat, UnmountFlags},
+    layout,
+    log::{LogLevel, LogSubsystem},
+    net::{NeighborEntry, NetCtl, RouteEntry, SockAddr, SockOpt, SocketType},
+};
 
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RawFd(pub usize);
 
 macro_rules! syscall {
-    ($no: expr $(,)?) => {
-        {
-            let no: Sys = $no;
-            let (result, err): (usize, usize);
-            unsafe {
-                core::arch::asm!(
-                    "ecall",
-                    in("a7") no as usize,
-                    lateout("a0") result,
-                    lateout("a1") err,
-                );
-            }
-            sys_result(result, err)
-        }
-    };
-    ($no: expr, $a0: expr $(,)?) => {
-        {
-            let no: Sys = $no;
-            let (result, err): (usize, usize);
-            unsafe {
-                core::arch::asm!(
-                    "ecall",
-                    in("a7") no as usize,
-                    in("a0") $a0,
-                    lateout("a0") result,
-                    lateout("a1") err,
-                );
-            }
-            sys_result(result, err)
-        }
-    };
-    ($no: expr, $a0: expr, $a1: expr $(,)?) => {
-        {
-            let no: Sys = $no;
-            let (result, err): (usize, usize);
-            unsafe {
-                core::arch::asm!(
-                    "ecall",
-                    in("a7") no as usize,
-                    in("a0") $a0,
-                    in("a1") $a1,
-                    lateout("a0") result,
-                    lateout("a1") err,
-                );
-            }
-            sys_result(result, err)
-        }
-    };
-    ($no: expr, $a0: expr, $a1: expr, $a2: expr $(,)?) => {
-        {
-            let no: Sys = $no;
-            let (result, err): (usize, usize);
-            unsafe {
-                core::arch::asm!(
-                    "ecall",
-                    in("a7") no as usize,
-                    in("a0") $a0,
-                    in("a1") $a1,
-                    in("a2") $a2,
-                    lateout("a0") result,
-                    lateout("a1") err,
-                );
-            }
-            sys_result(result, err)
-        }
-    };
-    ($no: expr, $a0: expr, $a1: expr, $a2: expr, $a3: expr $(,)?) => {
-        {
-            let no: Sys = $no;
-            let (result, err): (usize, usize);
-            unsafe {
-                core::arch::asm!(
-                    "ecall",
-                    in("a7") no as usize,
-                    in("a0") $a0,
-                    in("a1") $a1,
-                    in("a2") $a2,
-                    in("a3") $a3,
-                    lateout("a0") result,
-                    lateout("a1") err,
-                );
-            }
-            sys_result(result, err)
-        }
-    };
-    ($no: expr, $a0: expr, $a1: expr, $a2: expr, $a3: expr, $a4: expr $(,)?) => {
-        {
-            let no: Sys = $no;
-            let (result, err): (usize, usize);
-            unsafe {
-                core::arch::asm!(
-                    "ecall",
-                    in("a7") no as usize,
-                    in("a0") $a0,
-                    in("a1") $a1,
-                    in("a2") $a2,
-                    in("a3") $a3,
-                    in("a4") $a4,
-                    lateout("a0") result,
-                    lateout("a1") err,
-                );
-            }
-            sys_result(result, err)
-        }
-    };
-    ($no: expr, $a0: expr, $a1: expr, $a2: expr, $a3: expr, $a4: expr, $a5: expr $(,)?) => {
-        {
-            let no: Sys = $no;
-            let (result, err): (usize, usize);
-            unsafe {
-                core::arch::asm!(
-                    "ecall",
-                    in("a7") no as usize,
-                    in("a0") $a0,
-                    in("a1") $a1,
-                    in("a2") $a2,
-                    in("a3") $a3,
-                    in("a4") $a4,
-                    in("a5") $a5,
-                    lateout("a0") result,
-                    lateout("a1") err,
-                );
-            }
-            sys_result(result, err)
-        }
-    };
-    ($no: expr, $a0: expr, $a1: expr, $a2: expr, $a3: expr, $a4: expr, $a5: expr, $a6: expr $(,)?) => {
-        {
-            let no: Sys = $no;
-            let (result, err): (usize, usize);
-            unsafe {
-                core::arch::asm!(
-                    "ecall",
-                    in("a7") no as usize,
-                    in("a0") $a0,
-                    in("a1") $a1,
-                    in("a2") $a2,
-                    in("a3") $a3,
-                    in("a4") $a4,
-                    in("a5") $a5,
-                    in("a6") $a6,
-                    lateout("a0") result,
-                    lateout("a1") err,
-                );
-            }
-            sys_result(result, err)
-        }
+    ($no: expr $(, $arg: expr)* $(,)?) => {
+        ecall(SyscallRegs::new($no, &[$($arg),*]))
     };
 }
 
+/// Make a syscall following the convention in `shared::sys`. Every stub here goes through this,
+/// so it's the only place the registers are named.
 #[inline(always)]
-fn sys_result(result: usize, err: usize) -> Result<usize, SysError> {
-    if err != 0 {
-        Err(SysError::from_repr(err).unwrap())
-    } else {
-        Ok(result)
+fn ecall(call: SyscallRegs) -> Result<usize, SysError> {
+    let [a0, a1, a2, a3, a4, a5] = call.args;
+    let (ret, err): (usize, usize);
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a7") call.nr,
+            inlateout("a0") a0 => ret,
+            inlateout("a1") a1 => err,
+            in("a2") a2,
+            in("a3") a3,
+            in("a4") a4,
+            in("a5") a5,
+        );
     }
+    syscall_result(ret, err)
 }
 
+/// Send `SHUTDOWN_MSG` to every other process and wait for them to exit, for up to
+/// `/proc/sys/kernel/shutdown_grace_ms`. Anything still running then is killed, and the machine
+/// powers off or restarts.
 pub fn shutdown(restart: bool) -> Result<Infallible, SysError> {
-    Err(syscall!(Sys::Shutdown, restart as usize).unwrap_err())
+    loop {
+        // the kernel returns WouldBlock each time a process exits, and we wait again
+        match syscall!(Sys::Shutdown, restart as usize).unwrap_err() {
+            SysError::WouldBlock => continue,
+            err => return Err(err),
+        }
+    }
+}
+
+/// Remove a file or an empty directory
+pub fn remove(path: impl AsRef<[u8]>) -> Result<(), SysError> {
+    let path = path.as_ref();
+    syscall!(Sys::Remove, path.as_ptr() as usize, path.len()).map(|_| ())
 }
 
 pub fn close(fd: RawFd) -> Result<(), SysError> {
     syscall!(Sys::Close, fd.0).map(|_| ())
 }
 
+/// Close every descriptor from `first` to `last`, inclusive. Returns how many were open.
+pub fn close_range(first: RawFd, last: RawFd) -> Result<usize, SysError> {
+    syscall!(Sys::CloseRange, first.0, last.0)
+}
+
+/// Close every descriptor except the ones in `keep`. Returns how many were closed.
+pub fn close_all_except(keep: &[RawFd]) -> Result<usize, SysError> {
+    let mut closed = 0;
+    let mut first = 0;
+    while let Some(next) = keep.iter().map(|fd| fd.0).filter(|&fd| fd >= first).min() {
+        if next > first {
+            closed += close_range(RawFd(first), RawFd(next - 1))?;
+        }
+        let Some(after) = next.checked_add(1) else {
+            return Ok(closed);
+        };
+        first = after;
+    }
+    Ok(closed + close_range(RawFd(first), RawFd(usize::MAX))?)
+}
+
 pub fn kill(pid: u32) -> Result<(), SysError> {
     syscall!(Sys::Kill, pid as usize).map(|_| ())
 }
 
+pub fn kill_handle(handle: ProcHandle) -> Result<(), SysError> {
+    syscall!(Sys::Kill, handle.0 as usize).map(|_| ())
+}
+
 pub fn getpid() -> u32 {
     syscall!(Sys::GetPid).unwrap() as u32
 }
 
+pub fn pidhandle(pid: u32) -> Result<ProcHandle, SysError> {
+    syscall!(Sys::PidHandle, pid as usize).map(|h| ProcHandle(h as u64))
+}
+
+/// Change the scheduling class of process `pid` (if `class` is `Some`). Returns the previous class.
+pub fn priority(pid: u32, class: Option<SchedClass>) -> Result<SchedClass, SysError> {
+    syscall!(
+        Sys::Priority,
+        pid as usize,
+        class.map(|c| c as usize).unwrap_or(usize::MAX)
+    )
+    .map(|class| SchedClass::from_repr(class).unwrap())
+}
+
+/// Open `path`. Anything created is writable, unless the umask says otherwise.
 pub fn open(path: impl AsRef<[u8]>, flags: OpenFlags) -> Result<RawFd, SysError> {
+    open_mode(path, flags, Mode::Write)
+}
+
+/// Open `path`, creating it with `mode` minus the umask if `flags` ask for it
+pub fn open_mode(path: impl AsRef<[u8]>, flags: OpenFlags, mode: Mode) -> Result<RawFd, SysError> {
     let path = path.as_ref();
     syscall!(
         Sys::Open,
         path.as_ptr() as usize,
         path.len(),
         flags.bits() as usize,
+        mode.bits() as usize,
     )
     .map(RawFd)
 }
 
+/// Change the permissions removed from anything we create (if `mask` is `Some`). Returns the
+/// previous mask. Inherited by spawned processes.
+pub fn umask(mask: Option<Mode>) -> Result<Mode, SysError> {
+    syscall!(
+        Sys::Umask,
+        mask.map(|m| m.bits() as usize).unwrap_or(usize::MAX)
+    )
+    .map(|mask| Mode::from_bits_truncate(mask as u32))
+}
+
 pub fn read(fd: RawFd, pos: impl Into<Option<u64>>, buf: &mut [u8]) -> Result<usize, SysError> {
     syscall!(
         Sys::Read,
@@ -215,15 +160,20 @@ pub fn write(fd: RawFd, pos: impl Into<Option<u64>>, buf: &[u8]) -> Result<usize
     )
 }
 
+/// Read the next entry of the directory `fd`, or the one at `pos`. An entry that stays in the
+/// directory while it's read is returned exactly once. Entries created or removed in the meantime
+/// may or may not be.
 pub fn readdir(fd: RawFd, pos: impl Into<Option<usize>>) -> Result<Option<DirEntry>, SysError> {
+    readdir_at(fd, pos.into().unwrap_or(usize::MAX)).map(|res| res.map(|(entry, _)| entry))
+}
+
+/// Read the entry of the directory `fd` at `pos`, returning the position of the next one.
+/// Positions aren't indices: 0 is the start, and otherwise only positions returned by this are
+/// meaningful. They stay valid if the directory changes, like the cursor `readdir` uses.
+pub fn readdir_at(fd: RawFd, pos: usize) -> Result<Option<(DirEntry, usize)>, SysError> {
     let mut entry = MaybeUninit::<DirEntry>::uninit();
-    let res = syscall!(
-        Sys::Readdir,
-        fd.0,
-        pos.into().unwrap_or(usize::MAX),
-        entry.as_mut_ptr() as usize,
-    );
-    res.map(|res| (res != 0).then(|| unsafe { entry.assume_init() }))
+    let next = syscall!(Sys::Readdir, fd.0, pos, entry.as_mut_ptr() as usize)?;
+    Ok((next != 0).then(|| (unsafe { entry.assume_init() }, next)))
 }
 
 pub fn stat(fd: RawFd) -> Result<Stat, SysError> {
@@ -237,31 +187,462 @@ pub fn chdir(path: impl AsRef<[u8]>) -> Result<(), SysError> {
     syscall!(Sys::Chdir, path.as_ptr() as usize, path.len()).map(|_| ())
 }
 
+/// Copy the absolute path of the working directory into `buf`. Fails with `BadArg` if it doesn't
+/// fit.
+pub fn getcwd(buf: &mut [u8]) -> Result<&mut [u8], SysError> {
+    syscall!(Sys::GetCwd, buf.as_mut_ptr() as usize, buf.len()).map(|len| &mut buf[..len])
+}
+
+/// Unmount the filesystem mounted at `path`. Fails with `Busy` if files are still open on it, unless
+/// `UnmountFlags::Force` is given.
+pub fn unmount(path: impl AsRef<[u8]>, flags: UnmountFlags) -> Result<(), SysError> {
+    let path = path.as_ref();
+    syscall!(
+        Sys::Unmount,
+        path.as_ptr() as usize,
+        path.len(),
+        flags.bits() as usize
+    )
+    .map(|_| ())
+}
+
+/// Make the directory at `src` reachable from `dst` as well. Filesystems mounted inside `src` don't
+/// appear under `dst`. Fails with `BadArg` if `dst` is inside `src`.
+pub fn bind_mount(src: impl AsRef<[u8]>, dst: impl AsRef<[u8]>) -> Result<(), SysError> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    syscall!(
+        Sys::BindMount,
+        src.as_ptr() as usize,
+        src.len(),
+        dst.as_ptr() as usize,
+        dst.len()
+    )
+    .map(|_| ())
+}
+
+/// Make the filesystem mounted at `new_root` the root, and move the old root to `put_old`, which
+/// must be inside `new_root`. Other mounts inside `new_root` move with it, and the rest, like
+/// `/dev`, stay where they are. Open files and working directories aren't affected.
+pub fn pivot_root(new_root: impl AsRef<[u8]>, put_old: impl AsRef<[u8]>) -> Result<(), SysError> {
+    let (new_root, put_old) = (new_root.as_ref(), put_old.as_ref());
+    syscall!(
+        Sys::PivotRoot,
+        new_root.as_ptr() as usize,
+        new_root.len(),
+        put_old.as_ptr() as usize,
+        put_old.len()
+    )
+    .map(|_| ())
+}
+
 pub fn sbrk(inc: isize) -> Result<*mut u8, SysError> {
     syscall!(Sys::Sbrk, inc as usize).map(|addr| addr as *mut u8)
 }
 
+/// Spawn `path`, with `path` as `argv[0]` and then `args`
 pub fn spawn(path: impl AsRef<[u8]>, args: &[KString]) -> Result<u32, SysError> {
     let path = path.as_ref();
+    spawn_argv(path, &with_argv0(path, args), None)
+}
+
+/// Spawn a child that starts in `cwd` instead of our working directory. A relative `path` is
+/// looked up from `cwd`.
+pub fn spawn_in(
+    path: impl AsRef<[u8]>,
+    args: &[KString],
+    cwd: impl AsRef<[u8]>,
+) -> Result<u32, SysError> {
+    let path = path.as_ref();
+    spawn_argv(path, &with_argv0(path, args), Some(cwd.as_ref()))
+}
+
+/// Spawn `path` with exactly the arguments in `argv`, so `argv[0]` can differ from `path`. The
+/// child inherits our working directory if `cwd` is `None`.
+pub fn spawn_argv(
+    path: impl AsRef<[u8]>,
+    argv: &[KString],
+    cwd: Option<&[u8]>,
+) -> Result<u32, SysError> {
+    let path = path.as_ref();
+    let (cwd, cwdlen) = cwd.map_or((0, 0), |cwd| (cwd.as_ptr() as usize, cwd.len()));
     syscall!(
         Sys::Spawn,
         path.as_ptr() as usize,
         path.len(),
-        args.as_ptr() as usize,
-        args.len(),
+        argv.as_ptr() as usize,
+        argv.len(),
+        cwd,
+        cwdlen,
     )
     .map(|pid| pid as u32)
 }
 
+fn with_argv0<'a>(path: &'a [u8], args: &[KString<'a>]) -> Vec<KString<'a>> {
+    let mut argv = Vec::with_capacity(args.len() + 1);
+    argv.push(KString::new(path));
+    argv.extend_from_slice(args);
+    argv
+}
+
+/// Copy the absolute path of the executable we were spawned from into `buf`. Fails with `BadArg`
+/// if it doesn't fit.
+pub fn exe_path(buf: &mut [u8]) -> Result<&mut [u8], SysError> {
+    syscall!(Sys::GetExePath, buf.as_mut_ptr() as usize, buf.len()).map(|len| &mut buf[..len])
+}
+
 pub fn waitpid(pid: u32) -> Result<usize, SysError> {
     syscall!(Sys::Waitpid, pid as usize)
 }
 
+pub fn wait_handle(handle: ProcHandle) -> Result<usize, SysError> {
+    syscall!(Sys::Waitpid, handle.0 as usize)
+}
+
+/// Like `waitpid`, but fails with `TimedOut` if `pid` is still running after `timeout`. A zero
+/// timeout checks without blocking.
+pub fn waitpid_timeout(pid: u32, timeout: Duration) -> Result<usize, SysError> {
+    let ns = u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX);
+    syscall!(Sys::WaitpidTimeout, pid as usize, ns as usize)
+}
+
+/// What the kernel shares with every process. Reading it doesn't take a syscall.
+pub fn info() -> &'static SysInfo {
+    unsafe { &*(layout::INFO.start as *const SysInfo) }
+}
+
 pub fn exit(ecode: usize) -> Result<Infallible, SysError> {
     Err(syscall!(Sys::Exit, ecode).unwrap_err())
 }
 
+/// Change the kernel console log level (if `level` is `Some`) and toggle per-subsystem debug
+/// output. Returns the previous log level.
+pub fn logctl(
+    level: Option<LogLevel>,
+    enable: LogSubsystem,
+    disable: LogSubsystem,
+) -> Result<LogLevel, SysError> {
+    syscall!(
+        Sys::LogCtl,
+        level.map(|l| l as usize).unwrap_or(usize::MAX),
+        enable.bits() as usize,
+        disable.bits() as usize,
+    )
+    .map(|level| LogLevel::from_repr(level).unwrap())
+}
+
+/// Send up to `MAX_MSG_LEN` bytes to the mailbox of process `pid`. Fails with `WouldBlock` if the
+/// mailbox is full.
+pub fn sendmsg(pid: u32, buf: &[u8]) -> Result<(), SysError> {
+    syscall!(Sys::SendMsg, pid as usize, buf.as_ptr() as usize, buf.len()).map(|_| ())
+}
+
+pub fn sendmsg_handle(handle: ProcHandle, buf: &[u8]) -> Result<(), SysError> {
+    syscall!(
+        Sys::SendMsg,
+        handle.0 as usize,
+        buf.as_ptr() as usize,
+        buf.len()
+    )
+    .map(|_| ())
+}
+
+/// Receive the next message, or fail with `WouldBlock` if there isn't one. Messages longer than
+/// `buf` are truncated. Returns the message length and the sender.
+pub fn try_recvmsg(buf: &mut [u8]) -> Result<(usize, ProcHandle), SysError> {
+    let mut sender = ProcHandle(0);
+    let len = syscall!(
+        Sys::RecvMsg,
+        buf.as_mut_ptr() as usize,
+        buf.len(),
+        &mut sender as *mut _ as usize,
+        1,
+    )?;
+    Ok((len, sender))
+}
+
+/// Like `try_recvmsg`, but blocks until a message arrives.
+pub fn recvmsg(buf: &mut [u8]) -> Result<(usize, ProcHandle), SysError> {
+    loop {
+        let mut sender = ProcHandle(0);
+        // the kernel returns WouldBlock after waking us up, and we try again
+        match syscall!(
+            Sys::RecvMsg,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+            &mut sender as *mut _ as usize,
+            0,
+        ) {
+            Ok(len) => return Ok((len, sender)),
+            Err(SysError::WouldBlock) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Check which of `entries` are ready without blocking, marking them. Returns how many are.
+pub fn try_poll(entries: &mut [PollEntry]) -> Result<usize, SysError> {
+    syscall!(Sys::Poll, entries.as_mut_ptr() as usize, entries.len(), 1)
+}
+
+/// Like `try_poll`, but blocks until at least one of `entries` is ready.
+pub fn poll(entries: &mut [PollEntry]) -> Result<usize, SysError> {
+    loop {
+        // the kernel returns WouldBlock after waking us up, and we try again
+        match syscall!(Sys::Poll, entries.as_mut_ptr() as usize, entries.len(), 0) {
+            Err(SysError::WouldBlock) => continue,
+            res => return res,
+        }
+    }
+}
+
+/// What the kernel recorded about the exception `pid_or_handle` was killed for. Fails with
+/// `NotFound` if it exited any other way, or so long ago that the fault has been forgotten.
+pub fn fault_info(pid_or_handle: u64) -> Result<FaultInfo, SysError> {
+    let mut info = MaybeUninit::<FaultInfo>::uninit();
+    syscall!(
+        Sys::FaultInfo,
+        pid_or_handle as usize,
+        info.as_mut_ptr() as usize
+    )?;
+    Ok(unsafe { info.assume_init() })
+}
+
+/// Register `name` so other processes can find this one with `lookup_name`. The name is removed
+/// when this process exits.
+pub fn register_name(name: impl AsRef<[u8]>) -> Result<(), SysError> {
+    let name = name.as_ref();
+    syscall!(Sys::RegisterName, name.as_ptr() as usize, name.len()).map(|_| ())
+}
+
+pub fn unregister_name(name: impl AsRef<[u8]>) -> Result<(), SysError> {
+    let name = name.as_ref();
+    syscall!(Sys::UnregisterName, name.as_ptr() as usize, name.len()).map(|_| ())
+}
+
+pub fn lookup_name(name: impl AsRef<[u8]>) -> Result<ProcHandle, SysError> {
+    let name = name.as_ref();
+    syscall!(Sys::LookupName, name.as_ptr() as usize, name.len()).map(|h| ProcHandle(h as u64))
+}
+
+pub fn socket(typ: SocketType) -> Result<RawFd, SysError> {
+    syscall!(Sys::Socket, typ as usize).map(RawFd)
+}
+
+pub fn bind(fd: RawFd, addr: &SockAddr) -> Result<(), SysError> {
+    syscall!(Sys::Bind, fd.0, addr as *const _ as usize).map(|_| ())
+}
+
+/// For stream sockets, fails with `WouldBlock` until the connection is established
+pub fn connect(fd: RawFd, addr: &SockAddr) -> Result<(), SysError> {
+    syscall!(Sys::Connect, fd.0, addr as *const _ as usize).map(|_| ())
+}
+
+pub fn listen(fd: RawFd, backlog: usize) -> Result<(), SysError> {
+    syscall!(Sys::Listen, fd.0, backlog).map(|_| ())
+}
+
+pub fn accept(fd: RawFd) -> Result<(RawFd, SockAddr), SysError> {
+    let mut peer = SockAddr::UNSPECIFIED;
+    let fd = syscall!(Sys::Accept, fd.0, &mut peer as *mut _ as usize)?;
+    Ok((RawFd(fd), peer))
+}
+
+pub fn sendto(fd: RawFd, buf: &[u8], addr: Option<&SockAddr>) -> Result<usize, SysError> {
+    syscall!(
+        Sys::SendTo,
+        fd.0,
+        buf.as_ptr() as usize,
+        buf.len(),
+        addr.map(|a| a as *const _ as usize).unwrap_or(0),
+    )
+}
+
+pub fn recvfrom(fd: RawFd, buf: &mut [u8]) -> Result<(usize, SockAddr), SysError> {
+    let mut from = SockAddr::UNSPECIFIED;
+    let len = syscall!(
+        Sys::RecvFrom,
+        fd.0,
+        buf.as_mut_ptr() as usize,
+        buf.len(),
+        &mut from as *mut _ as usize,
+    )?;
+    Ok((len, from))
+}
+
+pub fn setsockopt(fd: RawFd, opt: SockOpt, val: usize) -> Result<(), SysError> {
+    syscall!(Sys::SetSockOpt, fd.0, opt as usize, val).map(|_| ())
+}
+
+pub fn getsockopt(fd: RawFd, opt: SockOpt) -> Result<usize, SysError> {
+    syscall!(Sys::GetSockOpt, fd.0, opt as usize)
+}
+
+pub fn add_route(route: &RouteEntry) -> Result<(), SysError> {
+    syscall!(
+        Sys::NetCtl,
+        NetCtl::AddRoute as usize,
+        route as *const _ as usize
+    )
+    .map(|_| ())
+}
+
+pub fn del_route(route: &RouteEntry) -> Result<(), SysError> {
+    syscall!(
+        Sys::NetCtl,
+        NetCtl::DelRoute as usize,
+        route as *const _ as usize
+    )
+    .map(|_| ())
+}
+
+pub fn add_neighbor(neighbor: &NeighborEntry) -> Result<(), SysError> {
+    syscall!(
+        Sys::NetCtl,
+        NetCtl::AddNeighbor as usize,
+        neighbor as *const _ as usize
+    )
+    .map(|_| ())
+}
+
+pub fn del_neighbor(neighbor: &NeighborEntry) -> Result<(), SysError> {
+    syscall!(
+        Sys::NetCtl,
+        NetCtl::DelNeighbor as usize,
+        neighbor as *const _ as usize
+    )
+    .map(|_| ())
+}
+
+/// Read the trap counters of one hart, or the sum over all of them with `ALL_HARTS`
+pub fn trap_stats(hart: usize) -> Result<TrapStats, SysError> {
+    let mut stats = TrapStats::new();
+    syscall!(
+        Sys::Stats,
+        StatsOp::Read as usize,
+        hart,
+        &mut stats as *mut _ as usize
+    )?;
+    Ok(stats)
+}
+
+pub fn reset_trap_stats(hart: usize) -> Result<(), SysError> {
+    syscall!(Sys::Stats, StatsOp::Reset as usize, hart).map(|_| ())
+}
+
+/// Run `insns` every time `hook` is reached, for every process. Returns an ID for
+/// `filter_map` and `filter_detach`.
+pub fn filter_attach(hook: Hook, insns: &[Insn]) -> Result<u32, SysError> {
+    syscall!(
+        Sys::FilterAttach,
+        hook as usize,
+        insns.as_ptr() as usize,
+        insns.len()
+    )
+    .map(|id| id as u32)
+}
+
+pub fn filter_detach(id: u32) -> Result<(), SysError> {
+    syscall!(Sys::FilterDetach, id as usize).map(|_| ())
+}
+
+/// Read the counters of an attached filter
+pub fn filter_map(id: u32) -> Result<[u64; MAP_LEN], SysError> {
+    let mut map = [0; MAP_LEN];
+    syscall!(Sys::FilterMap, id as usize, map.as_mut_ptr() as usize)?;
+    Ok(map)
+}
+
+/// Deny every syscall that `insns` returns nonzero for from now on, with
+/// `SysError::PermissionDenied`. Can only be done once, and is inherited by spawned processes.
+pub fn seccomp(insns: &[Insn]) -> Result<(), SysError> {
+    syscall!(Sys::Seccomp, insns.as_ptr() as usize, insns.len()).map(|_| ())
+}
+
+/// Make a device specific request of the device `fd` refers to
+pub fn ioctl(fd: RawFd, cmd: usize, arg: usize) -> Result<usize, SysError> {
+    syscall!(Sys::Ioctl, fd.0, cmd, arg)
+}
+
+/// Turn the bell the console rings when it drops input on or off, or leave it as is with `None`.
+/// Returns the previous setting.
+pub fn console_bell(fd: RawFd, on: Option<bool>) -> Result<bool, SysError> {
+    ioctl(
+        fd,
+        ConsoleCtl::Bell as usize,
+        on.map_or(usize::MAX, |on| on as usize),
+    )
+    .map(|prev| prev != 0)
+}
+
+/// How many bytes of input the console dropped since the last call
+pub fn console_dropped(fd: RawFd) -> Result<usize, SysError> {
+    ioctl(fd, ConsoleCtl::TakeDropped as usize, 0)
+}
+
+/// The lines the other end of the console's UART drives. Fails with `Unsupported` if the console
+/// isn't a UART.
+pub fn console_modem_status(fd: RawFd) -> Result<ModemStatus, SysError> {
+    ioctl(fd, ConsoleCtl::ModemStatus as usize, 0)
+        .map(|bits| ModemStatus::from_bits_retain(bits as u8))
+}
+
+/// Drive the console's UART lines with `lines`, or leave them as is with `None`. Returns the
+/// previous lines.
+pub fn console_modem_control(
+    fd: RawFd,
+    lines: Option<ModemControl>,
+) -> Result<ModemControl, SysError> {
+    ioctl(
+        fd,
+        ConsoleCtl::ModemControl as usize,
+        lines.map_or(usize::MAX, |lines| lines.bits() as usize),
+    )
+    .map(|bits| ModemControl::from_bits_retain(bits as u8))
+}
+
+/// Turn RTS/CTS flow control of console output on or off, or leave it as is with `None`. Returns
+/// the previous setting.
+pub fn console_cts_flow(fd: RawFd, on: Option<bool>) -> Result<bool, SysError> {
+    ioctl(
+        fd,
+        ConsoleCtl::CtsFlow as usize,
+        on.map_or(usize::MAX, |on| on as usize),
+    )
+    .map(|prev| prev != 0)
+}
+
+/// Turn echoing typed input on or off, or leave it with `None`. Returns the previous setting.
+pub fn console_echo(fd: RawFd, on: Option<bool>) -> Result<bool, SysError> {
+    ioctl(
+        fd,
+        ConsoleCtl::Echo as usize,
+        on.map_or(usize::MAX, |on| on as usize),
+    )
+    .map(|prev| prev != 0)
+}
+
+/// Fails with `BadArg` unless `name` is dot separated labels of letters, digits and `-`, that
+/// don't start or end with `-`
+pub fn sethostname(name: impl AsRef<[u8]>) -> Result<(), SysError> {
+    let name = name.as_ref();
+    syscall!(Sys::SetHostname, name.as_ptr() as usize, name.len()).map(|_| ())
+}
+
+/// Copy the hostname into `buf`. Fails with `BadArg` if it doesn't fit, which it always does if
+/// `buf` is `MAX_HOSTNAME_LEN` long.
+pub fn gethostname(buf: &mut [u8]) -> Result<&mut [u8], SysError> {
+    syscall!(Sys::GetHostname, buf.as_mut_ptr() as usize, buf.len()).map(|len| &mut buf[..len])
+}
+
+/// Append an `AcctRecord` to the file at `path` every time a process exits, creating it if it
+/// doesn't exist, or stop with `None`
+pub fn acct(path: Option<&[u8]>) -> Result<(), SysError> {
+    let path = path.unwrap_or_default();
+    syscall!(Sys::Acct, path.as_ptr() as usize, path.len()).map(|_| ())
+}
+
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct KString<'a> {
     buf: *const u8,
     len: usize,
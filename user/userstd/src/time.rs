@@ -0,0 +1,49 @@
+//! Measuring time without syscalls, from the counters the kernel lets every program read
+
+use core::{arch::asm, time::Duration};
+
+use shared::sys::Counters;
+
+use crate::sys;
+
+/// A reading of the `time` counter, which counts up at `SysInfo::timebase_freq` and never goes
+/// backwards. It's shared by every hart, so readings from before and after a process moves to
+/// another one can still be compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        let ticks: u64;
+        unsafe { asm!("csrr {ticks}, time", ticks = out(reg) ticks, options(nomem, nostack)) };
+        Self(ticks)
+    }
+
+    /// How long after `earlier` this is, or zero if it's before it
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        let freq = sys::info().timebase_freq.max(1);
+        let ticks = self.0.saturating_sub(earlier.0);
+        Duration::new(ticks / freq, ((ticks % freq) * 1_000_000_000 / freq) as u32)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    /// The raw reading, in ticks of the timebase
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+}
+
+/// The `cycle` counter of whichever hart this runs on, or `None` if the kernel wasn't booted with
+/// `rdcycle=on`. Harts count separately, so only readings from the same one can be compared.
+pub fn cycles() -> Option<u64> {
+    if !sys::info().counters.contains(Counters::Cycle) {
+        return None;
+    }
+
+    let cycles: u64;
+    unsafe { asm!("csrr {cycles}, cycle", cycles = out(reg) cycles, options(nomem, nostack)) };
+    Some(cycles)
+}